@@ -0,0 +1,269 @@
+//! Rewrites zip-based containers entry by entry, replacing the entries that
+//! can be shrunk and copying everything else byte for byte.
+
+use image::ImageFormat;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{info, warn};
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::checksum;
+use crate::compressor::{self, CompressionResult};
+use crate::error::{Error, Result};
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::presets::Preset;
+use crate::settings::Settings;
+
+/// New contents for an entry, streamed into the rebuilt archive.
+pub type Replacement = Box<dyn Read>;
+
+/// Compresses the images and videos inside a zip, keeping every entry's
+/// name so the archive's layout is unchanged. The result's sizes are those
+/// of the whole archive, i.e. the combined savings.
+pub async fn compress_archive(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+
+    let original_size = fs::metadata(input)?.len();
+    let output_dir = compressor::prepare_output_dir(input, output_path, settings)?;
+
+    let file_name = input.file_stem().unwrap().to_str().unwrap();
+    let output_file = output_dir.join(format!(
+        "{}.zip",
+        settings.output_file_name(file_name, "archive")
+    ));
+
+    // Videos go through ffmpeg, which needs real files, so they are
+    // extracted and compressed before the archive is rebuilt
+    let work_dir = output_dir.join(format!(".{}.work", file_name));
+    let videos = compress_videos(input, &work_dir, settings, preset, ctx).await;
+    let repacked = videos.and_then(|videos| {
+        repack(input, &output_file, ctx, |entry| {
+            if let Some(path) = videos.get(entry.name()) {
+                let file = File::open(path).map_err(|e| Error::io("Failed to read video", e))?;
+                return Ok(Some(Box::new(file) as Replacement));
+            }
+            recompress_image_entry(entry, preset)
+        })
+    });
+    fs::remove_dir_all(&work_dir).ok();
+    let replaced = repacked?;
+
+    let compressed_size = fs::metadata(&output_file)?.len();
+    info!(
+        "Archive {}: {} entries recompressed, {} -> {} bytes",
+        input_path, replaced, original_size, compressed_size
+    );
+
+    let mut result = if compressed_size >= original_size {
+        fs::copy(input, &output_file)?;
+        let mut result =
+            CompressionResult::new(original_size, original_size, &output_file, started);
+        result.kept_original = true;
+        result
+    } else {
+        CompressionResult::new(original_size, compressed_size, &output_file, started)
+    };
+
+    result.sha256 = checksum::write(&output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+/// Extracts and compresses every video in the archive, returning the entry
+/// names that shrank mapped to their compressed files.
+async fn compress_videos(
+    input: &Path,
+    work_dir: &Path,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<HashMap<String, PathBuf>> {
+    let extracted = extract_videos(input, work_dir)?;
+    let output_dir = work_dir.join("compressed");
+
+    let mut compressed = HashMap::new();
+    for (name, path) in extracted {
+        let path = path.to_string_lossy();
+        let output = Some(output_dir.to_string_lossy().to_string());
+        match compressor::compress_video(&path, output, settings, preset, ctx).await {
+            Ok(result) if !result.kept_original => {
+                compressed.insert(name, PathBuf::from(result.output_path));
+            }
+            Ok(_) => {}
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            // One bad clip shouldn't stop the rest of the archive
+            Err(e) => warn!("Keeping {} unchanged: {}", name, e),
+        }
+    }
+    Ok(compressed)
+}
+
+fn extract_videos(input: &Path, work_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let file = File::open(input).map_err(|e| Error::io("Failed to open archive", e))?;
+    let mut archive = ZipArchive::new(file).map_err(|e| read_error(input, e))?;
+
+    let mut extracted = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| read_error(input, e))?;
+        let name = entry.name().to_string();
+        if entry.is_dir() || MediaType::from_path(Path::new(&name)) != Some(MediaType::Video) {
+            continue;
+        }
+
+        // Entry names may contain anything, so extract under the index
+        let extension = Path::new(&name).extension().unwrap_or_default();
+        let path = work_dir.join(i.to_string()).with_extension(extension);
+        fs::create_dir_all(work_dir)
+            .map_err(|e| Error::io("Failed to create working directory", e))?;
+        let mut out = File::create(&path).map_err(|e| Error::io("Failed to extract video", e))?;
+        io::copy(&mut entry, &mut out).map_err(|e| Error::io("Failed to extract video", e))?;
+
+        extracted.push((name, path));
+    }
+    Ok(extracted)
+}
+
+/// Re-encodes a JPEG or PNG entry in its own format, `None` when that
+/// doesn't make it smaller.
+pub(crate) fn recompress_image_entry(
+    entry: &mut ZipFile,
+    preset: &Preset,
+) -> Result<Option<Replacement>> {
+    let Ok(format) = ImageFormat::from_path(entry.name()) else {
+        return Ok(None);
+    };
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png) {
+        return Ok(None);
+    }
+
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry
+        .read_to_end(&mut data)
+        .map_err(|e| Error::io("Failed to read archive entry", e))?;
+
+    Ok(compressor::recompress_image_data(&data, format, preset)
+        .map(|smaller| Box::new(Cursor::new(smaller)) as Replacement))
+}
+
+/// Copies the archive at `input` to `output`, asking `replace` for new
+/// contents for each file entry. Entries it returns `None` for are copied
+/// without being decompressed. Returns how many entries were replaced.
+pub fn repack(
+    input: &Path,
+    output: &Path,
+    ctx: &JobContext,
+    replace: impl FnMut(&mut ZipFile) -> Result<Option<Replacement>>,
+) -> Result<usize> {
+    if is_same_file(input, output) {
+        return Err(Error::InvalidRequest {
+            message: "Output would overwrite the input archive".to_string(),
+        });
+    }
+
+    let result = write_repacked(input, output, ctx, replace);
+    if result.is_err() {
+        fs::remove_file(output).ok();
+    }
+    result
+}
+
+fn write_repacked(
+    input: &Path,
+    output: &Path,
+    ctx: &JobContext,
+    mut replace: impl FnMut(&mut ZipFile) -> Result<Option<Replacement>>,
+) -> Result<usize> {
+    let file = File::open(input).map_err(|e| Error::io("Failed to open archive", e))?;
+    let mut archive = ZipArchive::new(file).map_err(|e| read_error(input, e))?;
+    ctx.writing(output);
+    let out = File::create(output).map_err(|e| Error::io("Failed to create archive", e))?;
+    let mut writer = ZipWriter::new(out);
+    let mut replaced = 0;
+
+    for i in 0..archive.len() {
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let replacement = {
+            let mut entry = archive.by_index(i).map_err(|e| read_error(input, e))?;
+            if entry.is_dir() {
+                None
+            } else {
+                replace(&mut entry)?
+            }
+        };
+
+        // `replace` may have read the entry, so copy from a fresh handle
+        let entry = archive.by_index_raw(i).map_err(|e| read_error(input, e))?;
+        match replacement {
+            Some(mut contents) => {
+                // Recompressed media doesn't deflate any further
+                let mut options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .large_file(entry.size() > u32::MAX as u64);
+                if let Some(time) = entry.last_modified() {
+                    options = options.last_modified_time(time);
+                }
+                if let Some(mode) = entry.unix_mode() {
+                    options = options.unix_permissions(mode);
+                }
+                writer
+                    .start_file(entry.name(), options)
+                    .map_err(write_error)?;
+                io::copy(&mut contents, &mut writer)
+                    .map_err(|e| Error::io("Failed to write archive", e))?;
+                replaced += 1;
+            }
+            None => writer.raw_copy_file(entry).map_err(write_error)?,
+        }
+    }
+
+    writer.finish().map_err(write_error)?;
+    Ok(replaced)
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn read_error(input: &Path, error: ZipError) -> Error {
+    match error {
+        ZipError::Io(error) => Error::io("Failed to read archive", error),
+        // Not a zip at all, or a damaged one
+        _ => Error::UnsupportedFormat {
+            path: input.to_string_lossy().to_string(),
+        },
+    }
+}
+
+fn write_error(error: ZipError) -> Error {
+    match error {
+        ZipError::Io(error) => Error::io("Failed to write archive", error),
+        error => Error::Io {
+            message: format!("Failed to write archive: {}", error),
+        },
+    }
+}
@@ -0,0 +1,68 @@
+//! Packs a finished batch's outputs into a single zip for sharing,
+//! optionally AES-256 encrypted.
+
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{AesMode, CompressionMethod, ZipWriter};
+
+use crate::error::{Error, Result};
+
+/// Writes `outputs` to `batch-<unix time>_compressed.zip` in the deepest directory
+/// containing all of them, keeping their paths below it, and returns the
+/// zip's path. Outputs that no longer exist are left out.
+pub fn package(outputs: &[PathBuf], password: Option<&str>) -> Result<PathBuf> {
+    let outputs: Vec<&PathBuf> = outputs.iter().filter(|output| output.is_file()).collect();
+    let root = common_dir(&outputs).ok_or_else(|| Error::InvalidRequest {
+        message: "There are no outputs to zip".to_string(),
+    })?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let zip_path = root.join(format!("batch-{}_compressed.zip", timestamp));
+    let file = File::create(&zip_path).map_err(|e| Error::io("Failed to create zip", e))?;
+    let mut zip = ZipWriter::new(file);
+
+    for output in outputs {
+        let name = output.strip_prefix(&root).unwrap_or(output);
+        let size = output.metadata()?.len();
+        // Compressed media doesn't deflate any further
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(size > u32::MAX as u64);
+        if let Some(password) = password.filter(|password| !password.is_empty()) {
+            options = options.with_aes_encryption(AesMode::Aes256, password);
+        }
+
+        zip.start_file(name.to_string_lossy().replace('\\', "/"), options)
+            .map_err(zip_error)?;
+        let mut contents = File::open(output).map_err(|e| Error::io("Failed to read output", e))?;
+        io::copy(&mut contents, &mut zip).map_err(|e| Error::io("Failed to write zip", e))?;
+    }
+
+    zip.finish().map_err(zip_error)?;
+    Ok(zip_path)
+}
+
+pub(crate) fn common_dir(paths: &[&PathBuf]) -> Option<PathBuf> {
+    let mut dir = paths.first()?.parent()?.to_path_buf();
+    while !paths.iter().all(|path| path.starts_with(&dir)) {
+        if !dir.pop() {
+            return None;
+        }
+    }
+    Some(dir)
+}
+
+fn zip_error(error: zip::result::ZipError) -> Error {
+    match error {
+        zip::result::ZipError::Io(error) => Error::io("Failed to write zip", error),
+        error => Error::Io {
+            message: format!("Failed to write zip: {}", error),
+        },
+    }
+}
@@ -0,0 +1,363 @@
+use clap::Parser;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use media_compressor_core::compressor;
+use media_compressor_core::dry_run::{self, PlannedAction};
+use media_compressor_core::encryption;
+use media_compressor_core::ffmpeg_manager::FFmpegManager;
+use media_compressor_core::job::JobContext;
+use media_compressor_core::media::{MediaFilter, MediaType};
+use media_compressor_core::presets;
+use media_compressor_core::queue::{JobRequest, Priority};
+use media_compressor_core::settings::{Settings, SettingsManager};
+use media_compressor_core::stdio;
+use media_compressor_core::units::format_size;
+
+/// Passphrase for --decrypt, so it needn't appear in the command line.
+const PASSPHRASE_VAR: &str = "MEDIA_COMPRESSOR_PASSPHRASE";
+
+/// Batch-compress images and videos from scripts and cron jobs, using the
+/// same settings, presets, and ffmpeg install as the desktop app.
+#[derive(Parser)]
+#[command(name = "media-compressor-cli", version)]
+struct Cli {
+    /// Files or directories to compress
+    #[arg(required_unless_present_any = ["list_presets", "download_ffmpeg", "stdio"])]
+    inputs: Vec<PathBuf>,
+
+    /// Output directory (defaults to the app setting, then `<input dir>/compressed`)
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Preset name, as printed by --list-presets
+    #[arg(short, long)]
+    preset: Option<String>,
+
+    /// Descend into subdirectories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Only pick these media types from directories, e.g. `image,video`
+    #[arg(long, value_delimiter = ',')]
+    only: Vec<MediaType>,
+
+    /// Only pick files with these extensions from directories, e.g. `jpg,heic`
+    #[arg(long, value_delimiter = ',')]
+    extensions: Vec<String>,
+
+    /// Print the available presets and exit
+    #[arg(long)]
+    list_presets: bool,
+
+    /// Print what would happen to each file, and roughly how big its output
+    /// would be, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Download ffmpeg if it isn't installed yet, then exit
+    #[arg(long)]
+    download_ffmpeg: bool,
+
+    /// Read JSON-lines requests on stdin and write JSON events to stdout
+    #[arg(long, conflicts_with = "inputs")]
+    stdio: bool,
+
+    /// Decrypt .age outputs instead of compressing, with the key from
+    /// --identity, else $MEDIA_COMPRESSOR_PASSPHRASE, else the passphrase in
+    /// settings
+    #[arg(long)]
+    decrypt: bool,
+
+    /// File holding an age secret key (as written by age-keygen), for --decrypt
+    #[arg(long, requires = "decrypt")]
+    identity: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    if cli.stdio {
+        return match stdio::run().await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let settings = SettingsManager::new().load();
+
+    if cli.list_presets {
+        for preset in presets::all_presets(&settings) {
+            println!("{}", preset.name);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if cli.download_ffmpeg {
+        return match FFmpegManager::new().ensure_ffmpeg().await {
+            Ok(path) => {
+                println!("ffmpeg available at {}", path.display());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if cli.decrypt {
+        return decrypt(&cli, &settings);
+    }
+
+    let preset = match presets::resolve_preset(cli.preset.as_deref(), &settings) {
+        Ok(preset) => preset,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let filter = MediaFilter {
+        media_types: cli.only,
+        extensions: cli.extensions,
+    };
+    let mut files = Vec::new();
+    for input in &cli.inputs {
+        if let Err(e) = collect_files(input, cli.recursive, &filter, &mut files) {
+            eprintln!("error: {}: {}", input.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if cli.dry_run {
+        return dry_run(&files, cli.output, cli.preset, &settings).await;
+    }
+
+    let mut failures = 0;
+    let mut skipped = 0;
+    let mut output_size = 0;
+    for file in &files {
+        let input_path = file.to_string_lossy();
+        let original_size = fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+
+        let ctx = JobContext::default();
+        let result = match settings.check_output_budget(output_size, &input_path) {
+            Ok(()) => {
+                compressor::compress_file(&input_path, cli.output.clone(), &settings, &preset, &ctx)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(result) => {
+                println!(
+                    "{} -> {} ({} -> {}){}",
+                    input_path,
+                    result.output_path,
+                    format_size(original_size),
+                    format_size(result.compressed_size),
+                    match result.lossless {
+                        Some(true) => " [lossless]",
+                        Some(false) => " [lossy]",
+                        None => "",
+                    }
+                );
+                let before = output_size;
+                output_size += result.compressed_size;
+                if let Some(budget) = settings.output_budget_bytes() {
+                    if before < budget && output_size >= budget {
+                        eprintln!(
+                            "warning: outputs take {}, over the {} budget",
+                            format_size(output_size),
+                            format_size(budget)
+                        );
+                    }
+                }
+            }
+            Err(e) if e.is_skip() => {
+                skipped += 1;
+                println!("{}", e);
+            }
+            Err(e) => {
+                failures += 1;
+                eprintln!("{}: {}", input_path, e);
+            }
+        }
+    }
+
+    println!(
+        "{} of {} files compressed",
+        files.len() - failures - skipped,
+        files.len()
+    );
+    if skipped > 0 {
+        println!("{} skipped", skipped);
+    }
+
+    if failures > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+async fn dry_run(
+    files: &[PathBuf],
+    output: Option<String>,
+    preset: Option<String>,
+    settings: &Settings,
+) -> ExitCode {
+    let requests: Vec<JobRequest> = files
+        .iter()
+        .map(|file| JobRequest {
+            input_path: file.to_string_lossy().to_string(),
+            output_path: output.clone(),
+            preset: preset.clone(),
+            priority: Priority::default(),
+        })
+        .collect();
+    let plan = dry_run::plan(&requests, settings).await;
+
+    for file in &plan.files {
+        match file.action {
+            PlannedAction::Compress | PlannedAction::Remux => println!(
+                "{}: {} with {} into {} ({} -> {})",
+                file.input_path,
+                if file.action == PlannedAction::Remux {
+                    "remux"
+                } else {
+                    "compress"
+                },
+                file.preset.as_deref().unwrap_or_default(),
+                file.output_dir.as_deref().unwrap_or_default(),
+                file.original_size.map_or("?".to_string(), format_size),
+                file.estimated_size
+                    .map_or("?".to_string(), |size| format!("~{}", format_size(size)))
+            ),
+            PlannedAction::Skip => println!(
+                "{}: skip: {}",
+                file.input_path,
+                file.reason.as_deref().unwrap_or_default()
+            ),
+            PlannedAction::Fail => eprintln!(
+                "{}: would fail: {}",
+                file.input_path,
+                file.reason.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+
+    let summary = &plan.summary;
+    println!(
+        "{} of {} files would be written, {} -> ~{}",
+        summary.written,
+        summary.files,
+        format_size(summary.original_size),
+        format_size(summary.estimated_size)
+    );
+    if summary.skipped > 0 {
+        println!("{} skipped", summary.skipped);
+    }
+    if summary.failing > 0 {
+        println!("{} would fail", summary.failing);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Decrypts each input next to itself, or into `--output`.
+fn decrypt(cli: &Cli, settings: &Settings) -> ExitCode {
+    let secret = match &cli.identity {
+        Some(path) => match fs::read_to_string(path) {
+            // Key files may carry `# created:` and `# public key:` comments
+            Ok(contents) => contents
+                .lines()
+                .find(|line| !line.starts_with('#') && !line.trim().is_empty())
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            Err(e) => {
+                eprintln!("error: {}: {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => match std::env::var(PASSPHRASE_VAR)
+            .ok()
+            .or_else(|| settings.encryption.passphrase.clone())
+        {
+            Some(passphrase) if !passphrase.is_empty() => passphrase,
+            _ => {
+                eprintln!(
+                    "error: no key given; pass --identity or set {}",
+                    PASSPHRASE_VAR
+                );
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let mut failures = 0;
+    for input in &cli.inputs {
+        let output = cli.output.as_ref().map(|dir| {
+            Path::new(dir).join(input.with_extension("").file_name().unwrap_or_default())
+        });
+        match encryption::decrypt_file(input, &secret, output.as_deref()) {
+            Ok(output) => println!("{} -> {}", input.display(), output.display()),
+            Err(e) => {
+                failures += 1;
+                eprintln!("{}: {}", input.display(), e);
+            }
+        }
+    }
+
+    if failures > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Expands `input` into the supported media files it refers to that pass
+/// `filter`. Explicitly named files are passed through so unsupported ones
+/// get reported.
+fn collect_files(
+    input: &Path,
+    recursive: bool,
+    filter: &MediaFilter,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !input.is_dir() {
+        if !input.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No such file or directory",
+            ));
+        }
+        files.push(input.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(input)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+
+        if path.is_dir() {
+            // Don't re-compress the outputs of a previous run
+            if recursive && entry.file_name() != "compressed" {
+                collect_files(&path, recursive, filter, files)?;
+            }
+        } else if filter.matches(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
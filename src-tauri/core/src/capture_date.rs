@@ -0,0 +1,74 @@
+//! When a photo or video was taken, for sorting outputs into `YYYY/MM`
+//! folders.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use exif::{In, Tag, Value};
+
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::media::MediaType;
+use crate::probe;
+
+/// Folder for files without a usable date.
+const UNDATED_FOLDER: &str = "undated";
+
+/// Container tags holding a creation date, best first. Apple's is in local
+/// time; `creation_time` is UTC and can land in the neighbouring month.
+const DATE_TAGS: &[&str] = &["com.apple.quicktime.creationdate", "creation_time"];
+
+/// `YYYY/MM` for the month `path` was captured in, or `undated`.
+pub fn month_folder(path: &Path) -> PathBuf {
+    match capture_month(path) {
+        Some((year, month)) => PathBuf::from(format!("{:04}", year)).join(format!("{:02}", month)),
+        None => PathBuf::from(UNDATED_FOLDER),
+    }
+}
+
+/// Year and month from the EXIF capture date for images, or the container's
+/// creation time for videos and audio (which needs ffmpeg).
+pub fn capture_month(path: &Path) -> Option<(u16, u8)> {
+    match MediaType::from_path(path)? {
+        MediaType::Image => exif_month(path),
+        MediaType::Video | MediaType::Audio => tag_month(path),
+        MediaType::Document | MediaType::Archive => None,
+    }
+}
+
+/// `DateTimeOriginal`, falling back to `DateTime`, which editors rewrite
+/// but scanners and screenshots often only have.
+fn exif_month(path: &Path) -> Option<(u16, u8)> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+
+    [Tag::DateTimeOriginal, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => parse_month(std::str::from_utf8(values.first()?).ok()?),
+            _ => None,
+        })
+}
+
+fn tag_month(path: &Path) -> Option<(u16, u8)> {
+    let ffmpeg_path = FFmpegManager::new().installed_ffmpeg_path()?;
+    let tags = probe::probe_tags(&ffmpeg_path, path).ok()?;
+    DATE_TAGS
+        .iter()
+        .find_map(|key| parse_month(tags.get(*key)?))
+}
+
+/// Reads the year and month from the start of an EXIF (`2023:05:01 12:00:00`)
+/// or ISO 8601 (`2023-05-01T12:00:00Z`) date. Cameras without a clock write
+/// zeros, which count as no date.
+fn parse_month(date: &str) -> Option<(u16, u8)> {
+    let bytes = date.as_bytes();
+    if bytes.len() < 7 || !matches!(bytes[4], b':' | b'-') {
+        return None;
+    }
+    let year: u16 = date.get(..4)?.parse().ok()?;
+    let month: u8 = date.get(5..7)?.parse().ok()?;
+    (year > 0 && (1..=12).contains(&month)).then_some((year, month))
+}
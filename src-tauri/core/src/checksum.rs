@@ -0,0 +1,73 @@
+//! File hashes: SHA-256 checksums for compressed outputs, in the
+//! `sha256sum` format so `sha256sum -c` can verify them later, and BLAKE3
+//! content hashes for recognizing duplicate inputs.
+
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::{Error, Result};
+use crate::settings::ChecksumMode;
+
+/// Name of the per-directory manifest written in `ChecksumMode::Manifest`.
+pub const MANIFEST_FILE: &str = "SHA256SUMS";
+
+/// Serializes manifest updates from concurrent workers.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Fast content hash identifying an input regardless of its name or location.
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hashes `output` and records it as `mode` asks, returning the hex digest.
+pub fn write(output: &Path, mode: ChecksumMode) -> Result<Option<String>> {
+    if mode == ChecksumMode::None {
+        return Ok(None);
+    }
+
+    let hash = sha256_file(output).map_err(|e| Error::io("Failed to compute checksum", e))?;
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let line = format!("{}  {}\n", hash, file_name);
+
+    match mode {
+        ChecksumMode::None => {}
+        ChecksumMode::Sidecar => {
+            let mut sidecar = output.as_os_str().to_owned();
+            sidecar.push(".sha256");
+            fs::write(&sidecar, line).map_err(|e| Error::io("Failed to write checksum", e))?;
+        }
+        ChecksumMode::Manifest => {
+            let manifest = output.with_file_name(MANIFEST_FILE);
+            let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+            // Replace any entry from an earlier run that wrote the same file
+            let suffix = format!("  {}", file_name);
+            let mut contents: String = fs::read_to_string(&manifest)
+                .unwrap_or_default()
+                .lines()
+                .filter(|entry| !entry.ends_with(&suffix))
+                .map(|entry| format!("{}\n", entry))
+                .collect();
+            contents.push_str(&line);
+
+            fs::write(&manifest, contents)
+                .map_err(|e| Error::io("Failed to write checksum manifest", e))?;
+        }
+    }
+
+    Ok(Some(hash))
+}
@@ -0,0 +1,2241 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageFormat, ImageReader};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::archive;
+use crate::capture_date;
+use crate::checksum;
+use crate::custom_args;
+use crate::destination;
+use crate::encoders::{self, ExternalEncoder};
+use crate::encryption;
+use crate::error::{Error, Result};
+use crate::fat32;
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::filter_graph::{Filter, FilterGraph};
+use crate::gpu;
+use crate::history::{HistoryStore, NewJob};
+use crate::job::{self, JobContext, JobLog};
+use crate::live_photo;
+use crate::media::MediaType;
+use crate::office;
+use crate::paths;
+use crate::presets::{self, BurnSubtitles, ColorRange, ColorSpace, Preset, ResizeFilter};
+use crate::probe::{self, Crop, MediaInfo};
+use crate::s3;
+use crate::script;
+use crate::settings::{HardwareDecoding, HevcPolicy, OutputLayout, Settings};
+use crate::streaming;
+use crate::svg;
+
+/// Largest image decoded at full size, about 360 MB as RGB. Bigger JPEGs are
+/// scaled down while decoding; other formats are refused rather than risk
+/// running out of memory.
+const MAX_DECODE_PIXELS: u64 = 120_000_000;
+/// ffmpeg's JPEG decoder can decode at 1/2, 1/4 or 1/8 scale.
+const MAX_JPEG_LOWRES: u32 = 3;
+/// Decoded through ffmpeg, as `image` can't.
+const HEIF_EXTENSIONS: &[&str] = &["heic", "heif"];
+/// Blur radius of the unsharp mask; small, so only fine detail is boosted.
+const SHARPEN_SIGMA: f32 = 1.0;
+/// rav1e speed (1-10) for AVIF stills: 6 is several times faster than the
+/// slowest settings for a few percent larger files.
+const AVIF_SPEED: u8 = 6;
+
+/// Encoder used when the preset's encoder fails in a recoverable way.
+const FALLBACK_VIDEO_CODEC: &str = "libx264";
+const FALLBACK_ENCODER_PRESET: &str = "medium";
+const TWO_PASS_ENCODERS: &[&str] = &["libx264", "libvpx-vp9"];
+/// Numbers two-pass stats logs, so concurrent encodes don't share one.
+static NEXT_PASS_LOG: AtomicU64 = AtomicU64::new(0);
+
+/// Single-pass EBU R128 normalization to the usual -16 LUFS speech target.
+const LOUDNORM_FILTER: &str = "loudnorm=I=-16:TP=-1.5:LRA=11";
+/// Drops leading silence, and any pause over a second anywhere else. -50 dB
+/// is quiet enough to keep breaths and soft speech.
+const SILENCEREMOVE_FILTER: &str = "silenceremove=start_periods=1:start_threshold=-50dB:\
+     stop_periods=-1:stop_duration=1:stop_threshold=-50dB";
+/// Sidecar subtitle files looked for next to a video, in order.
+const SUBTITLE_EXTENSIONS: &[&str] = &["ass", "ssa", "srt", "vtt"];
+
+/// Audio containers that can embed a cover image.
+const COVER_ART_CONTAINERS: &[&str] = &["m4a", "mp3", "flac"];
+/// Longest side of re-embedded cover art; players never show it bigger.
+const MAX_COVER_ART_DIMENSION: u32 = 1000;
+/// `loudnorm` resamples to 192 kHz internally, so the output rate is pinned.
+const NORMALIZED_SAMPLE_RATE: u32 = 48_000;
+
+/// ffmpeg messages for failures caused by the encoder setup, typically a
+/// hardware encoder or decoder without a usable device, or a pixel format
+/// the encoder rejects.
+const RECOVERABLE_FAILURES: &[&str] = &[
+    "Unknown encoder",
+    "Error while opening encoder",
+    "Error initializing output stream",
+    "Incompatible pixel format",
+    "Unsupported pixel format",
+    "not supported by the encoder",
+    "Failed to create hardware device",
+    "Device creation failed",
+    "No capable devices found",
+    "No NVENC capable devices found",
+    "Cannot load",
+    "OpenEncodeSessionEx failed",
+    "hwaccel initialisation returned error",
+    "No device available for decoder",
+    "Failed to initialise VAAPI connection",
+    "InitializeEncoder failed",
+    "Provided device doesn't support required NVENC features",
+    "Driver does not support the required nvenc API version",
+    "cannot create compression session",
+    "Error initializing the encoder",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressionResult {
+    pub original_size: u64,
+    pub compressed_size: u64,
+    /// `compressed_size / original_size`; below 1.0 means the file shrank.
+    pub ratio: f64,
+    pub output_path: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Encoder of the output, e.g. "h264" or "jpeg".
+    pub codec: Option<String>,
+    /// Output duration in seconds, for videos.
+    pub duration: Option<f64>,
+    pub elapsed_ms: u64,
+    /// True when the output is a copy of the input because compressing made
+    /// it larger.
+    pub kept_original: bool,
+    /// True when the preset's encoder or hardware decoding failed and the
+    /// video was encoded in software instead.
+    pub fallback_used: bool,
+    /// The encoder that failed and ffmpeg's message, e.g. `h264_nvenc: No
+    /// NVENC capable devices found`, when `fallback_used`.
+    pub fallback_reason: Option<String>,
+    /// Hex SHA-256 of the output, when checksums are enabled.
+    pub sha256: Option<String>,
+    /// For audio, whether the output codec is lossless (FLAC, ALAC, PCM), so
+    /// archival copies can be told apart from lossy ones. For video, set once
+    /// a lossless encode was verified against the source.
+    pub lossless: Option<bool>,
+    /// Playlist or manifest of the streaming package, when the preset asks
+    /// for one.
+    pub manifest_path: Option<String>,
+    /// Extra sizes of a responsive image set, smallest first.
+    pub variants: Vec<ImageVariant>,
+    /// Pages after the first of a multi-page TIFF, written next to the
+    /// output as `<name>-pageNNN`. The first page is the output itself.
+    pub pages: Vec<ImageVariant>,
+    /// `<name>-partNNN` files a video too big for a FAT32 output drive was
+    /// split into, in order. `output_path` is the first of them.
+    pub parts: Vec<String>,
+    /// URL of the uploaded output, when uploads are on.
+    pub remote_url: Option<String>,
+}
+
+/// An image file written alongside the main output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub output_path: String,
+    pub size: u64,
+}
+
+impl CompressionResult {
+    /// Every file the job wrote: the output, then any variants, pages and
+    /// parts.
+    pub fn output_files(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.output_path.as_str())
+            .chain(
+                self.variants
+                    .iter()
+                    .map(|variant| variant.output_path.as_str()),
+            )
+            .chain(self.pages.iter().map(|page| page.output_path.as_str()))
+            .chain(self.parts.iter().skip(1).map(String::as_str))
+    }
+
+    pub(crate) fn new(
+        original_size: u64,
+        compressed_size: u64,
+        output_path: &Path,
+        started: Instant,
+    ) -> Self {
+        let ratio = if original_size == 0 {
+            1.0
+        } else {
+            compressed_size as f64 / original_size as f64
+        };
+
+        Self {
+            original_size,
+            compressed_size,
+            ratio,
+            output_path: output_path.to_string_lossy().to_string(),
+            width: None,
+            height: None,
+            codec: None,
+            duration: None,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            kept_original: false,
+            fallback_used: false,
+            fallback_reason: None,
+            sha256: None,
+            lossless: None,
+            manifest_path: None,
+            variants: Vec::new(),
+            pages: Vec::new(),
+            parts: Vec::new(),
+            remote_url: None,
+        }
+    }
+}
+
+/// Fails with the reason `input_path` is left out of the batch by settings,
+/// if any. Checked before any decoding.
+pub fn check_skips(input_path: &str, settings: &Settings) -> Result<()> {
+    let input = Path::new(input_path);
+    if let Some(min_kb) = settings.min_input_size_kb {
+        let size = fs::metadata(input)?.len();
+        if size < min_kb * 1024 {
+            return Err(Error::BelowMinimumSize {
+                path: input_path.to_string(),
+                size,
+            });
+        }
+    }
+    if settings.skip_compressed_outputs && has_output_name(input, settings) {
+        return Err(Error::CompressedOutput {
+            path: input_path.to_string(),
+        });
+    }
+    // Both history lookups go by contents; hash the file once for them
+    let hash = if settings.skip_compressed_outputs || settings.skip_processed_inputs {
+        checksum::content_hash(input).ok()
+    } else {
+        None
+    };
+    if let Some(hash) = &hash {
+        if settings.skip_compressed_outputs && is_compressed_output(hash) {
+            return Err(Error::CompressedOutput {
+                path: input_path.to_string(),
+            });
+        }
+        if settings.skip_processed_inputs && already_processed(hash) {
+            return Err(Error::AlreadyProcessed {
+                path: input_path.to_string(),
+            });
+        }
+    }
+    if settings.drop_live_photo_motion && live_photo::is_motion(input) {
+        return Err(Error::LivePhotoMotion {
+            path: input_path.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Compresses one file with the pipeline matching its media type and records
+/// the outcome in the job history.
+pub async fn compress_file(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let media_type =
+        MediaType::from_path(Path::new(input_path)).ok_or_else(|| Error::UnsupportedFormat {
+            path: input_path.to_string(),
+        })?;
+
+    check_skips(input_path, settings)?;
+    let (preset, ctx) = script::apply(input_path, media_type, settings, preset, ctx).await?;
+    let (preset, ctx) = (&preset, &ctx);
+    // A hand-edited settings file skips the check on save
+    custom_args::validate(&preset.extra_ffmpeg_args)?;
+
+    let started = Instant::now();
+    let result = match media_type {
+        MediaType::Video => compress_video(input_path, output_path, settings, preset, ctx).await,
+        MediaType::Audio => compress_audio(input_path, output_path, settings, preset, ctx).await,
+        MediaType::Image => compress_image(input_path, output_path, settings, preset, ctx),
+        MediaType::Document => {
+            office::compress_document(input_path, output_path, settings, preset, ctx)
+        }
+        MediaType::Archive => {
+            archive::compress_archive(input_path, output_path, settings, preset, ctx).await
+        }
+    };
+    finish_job(
+        input_path,
+        media_type.as_str(),
+        preset,
+        settings,
+        started,
+        result,
+    )
+}
+
+pub async fn compress_video(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+
+    // Ensure FFmpeg is available
+    let ffmpeg_manager = FFmpegManager::new();
+    let ffmpeg_path = ffmpeg_manager.ensure_ffmpeg().await?;
+
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+
+    let original_size = fs::metadata(input)?.len();
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+
+    let file_name = input.file_stem().unwrap().to_str().unwrap();
+    let lossless = is_lossless_video_codec(&preset.video_codec);
+    let container = video_container(&preset.video_codec);
+    let extension = container.unwrap_or_else(|| {
+        input
+            .extension()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or("mp4")
+    });
+    let mut output_file = output_dir.join(format!(
+        "{}.{}",
+        settings.output_file_name(file_name, "video"),
+        extension
+    ));
+
+    if settings.hevc_policy != HevcPolicy::Reencode
+        && probe::probe_media(&ffmpeg_path, input)?
+            .video_codec
+            .as_deref()
+            == Some("hevc")
+    {
+        if settings.hevc_policy == HevcPolicy::Skip {
+            return Err(Error::AlreadyHevc {
+                path: input_path.to_string(),
+            });
+        }
+        // The source's own container, whatever the preset's codec implies
+        let output_file = output_file.with_extension(input.extension().unwrap_or_default());
+        return remux_video(&ffmpeg_path, input, &output_file, settings, ctx);
+    }
+
+    // Size-capped presets need a bitrate derived from the clip length;
+    // presets without one or a fixed bitrate use constant quality
+    let target_bitrate = match preset.target_size_mb {
+        Some(_) => {
+            let duration = probe::probe_duration(&ffmpeg_path, input)?;
+            preset.target_video_bitrate(duration)
+        }
+        None => preset.average_video_bitrate(),
+    };
+
+    if let Some(watermark) = &preset.watermark {
+        if !Path::new(&watermark.path).exists() {
+            return Err(Error::invalid_settings(format!(
+                "Watermark image not found: {}",
+                watermark.path
+            )));
+        }
+    }
+
+    // Detection is best-effort; without it the video is encoded uncropped
+    let crop = if preset.crop_black_bars {
+        probe::detect_crop(&ffmpeg_path, input).ok().flatten()
+    } else {
+        None
+    };
+
+    // A drive that can't hold 4 GB files gets the encode once it is known
+    // to fit, or split into parts; until then it is staged on the system drive
+    let fat32 = fat32::has_4gb_limit(&output_dir);
+    let mut encode_file = if fat32 {
+        let staging_dir = paths::staging_dir();
+        fs::create_dir_all(&staging_dir)
+            .map_err(|e| Error::io("Failed to create staging directory", e))?;
+        staging_dir.join(format!(
+            "{}-{}",
+            std::process::id(),
+            output_file.file_name().unwrap().to_string_lossy()
+        ))
+    } else {
+        output_file.clone()
+    };
+
+    // Best-effort; an unreadable source just isn't sharpened or tone mapped
+    let source = probe::probe_media(&ffmpeg_path, input).unwrap_or_default();
+    let filters = video_filters(preset, input, crop, &source, ctx.filters());
+    let device = ctx
+        .gpu()
+        .or_else(|| gpu::default_device(&settings.gpu, &preset.video_codec));
+    // Decoder options go before the source's `-i`, which starts `video_args`
+    let hardware_decoding = settings.gpu.decoding != HardwareDecoding::Off;
+    let mut args = gpu::decode_args(settings.gpu.decoding, device);
+    args.extend(video_args(
+        input_path,
+        &encode_file,
+        preset,
+        target_bitrate,
+        &filters,
+        device,
+    ));
+    let mut encode = encode_video(&ffmpeg_path, &args, preset, input_path, &encode_file, ctx);
+
+    // Hardware decoders and encoders, exotic pixel formats and encoders
+    // missing from the ffmpeg build fail on some machines and inputs before
+    // anything is encoded. The job then goes down a chain of software
+    // retries: the same codec without hardware decoding, the codec's
+    // software encoder, then plain x264. All keep the container: x264 can't
+    // go in WebM, and a lossless encode must not silently become lossy.
+    let mut fallbacks = fallback_encoders(&preset.video_codec);
+    if hardware_decoding {
+        fallbacks.insert(0, preset.video_codec.clone());
+    }
+    let mut fallbacks = fallbacks.into_iter();
+    let mut attempted = preset.video_codec.clone();
+    let mut fallback_reason = None;
+    while let Err(Error::EncodeFailed { stderr_tail, .. }) = &encode {
+        let Some(reason) = recoverable_failure(stderr_tail) else {
+            break;
+        };
+        let Some(codec) = fallbacks.next() else {
+            break;
+        };
+        warn!(
+            "Encoding {} with {} failed ({}), retrying in software with {}",
+            input_path, attempted, reason, codec
+        );
+        // The first failure is the one worth reporting; later ones only
+        // say the software encoders are missing too
+        fallback_reason.get_or_insert_with(|| format!("{}: {}", attempted, reason));
+        let fallback = Preset {
+            encoder_preset: if codec == preset.video_codec {
+                preset.encoder_preset.clone()
+            } else {
+                FALLBACK_ENCODER_PRESET.to_string()
+            },
+            video_codec: codec.clone(),
+            // The requested format may be what the encoder rejected
+            pixel_format: None,
+            ..preset.clone()
+        };
+        // Only the hardware decoder is dropped when the codec stays, so the
+        // encode keeps the device the queue reserved for it
+        let args = video_args(
+            input_path,
+            &encode_file,
+            &fallback,
+            target_bitrate,
+            &filters,
+            device.filter(|_| codec == preset.video_codec),
+        );
+        encode = encode_video(
+            &ffmpeg_path,
+            &args,
+            &fallback,
+            input_path,
+            &encode_file,
+            ctx,
+        );
+        attempted = codec;
+    }
+    encode?;
+
+    let mut compressed_size = fs::metadata(&encode_file)?.len();
+
+    // Same rule as images: never hand back something bigger than the input.
+    // Except for archives: the original is no substitute for a lossless
+    // copy, which of a lossy source is nearly always bigger
+    let kept_original = !lossless && compressed_size >= original_size;
+    if kept_original {
+        // The original keeps its own container, so it gets its own extension
+        fs::remove_file(&encode_file).ok();
+        let extension = input.extension().unwrap_or_default();
+        output_file.set_extension(extension);
+        encode_file.set_extension(extension);
+        fs::copy(input, &encode_file)?;
+        compressed_size = original_size;
+    }
+
+    // Only an unfiltered encode is expected to reproduce the source frames
+    let unaltered =
+        filters.is_empty() && preset.max_frame_rate.is_none() && preset.pixel_format.is_none();
+    let verified = lossless && unaltered;
+    if verified && !frames_match(&ffmpeg_path, input, &encode_file)? {
+        fs::remove_file(&encode_file).ok();
+        return Err(Error::VerificationFailed {
+            path: output_file.to_string_lossy().to_string(),
+        });
+    }
+
+    let mut parts = Vec::new();
+    if fat32 {
+        if compressed_size > fat32::MAX_FILE_SIZE {
+            let split = fat32::split(&ffmpeg_path, &encode_file, &output_file, ctx);
+            fs::remove_file(&encode_file).ok();
+            parts = split?;
+        } else {
+            // Across drives, so a copy rather than a rename
+            fs::copy(&encode_file, &output_file)?;
+            fs::remove_file(&encode_file).ok();
+        }
+    }
+    let output_file = parts.first().cloned().unwrap_or(output_file);
+
+    let mut result = CompressionResult::new(original_size, compressed_size, &output_file, started);
+    result.kept_original = kept_original;
+    result.fallback_used = fallback_reason.is_some();
+    result.fallback_reason = fallback_reason;
+    if verified {
+        result.lossless = Some(true);
+    }
+
+    // Describing the output is best-effort; the file itself is done
+    if let Ok(info) = probe::probe_media(&ffmpeg_path, &output_file) {
+        result.width = info.width;
+        result.height = info.height;
+        result.codec = info.video_codec;
+        result.duration = info.duration;
+    }
+
+    if let Some(format) = preset.streaming {
+        let manifest = streaming::package(
+            &ffmpeg_path,
+            &output_file,
+            format,
+            result.codec.as_deref(),
+            ctx,
+        )?;
+        result.manifest_path = Some(manifest.to_string_lossy().to_string());
+    }
+
+    for part in parts.iter().skip(1) {
+        checksum::write(part, settings.checksum_mode)?;
+    }
+    result.parts = parts
+        .iter()
+        .map(|part| part.to_string_lossy().to_string())
+        .collect();
+    result.sha256 = checksum::write(&output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+/// Copies a video's streams into `output_file` without re-encoding. Only
+/// the container is rewritten: the index moves to the front for streaming,
+/// and camera data tracks, which MP4 often can't hold, are left out.
+fn remux_video(
+    ffmpeg_path: &Path,
+    input: &Path,
+    output_file: &Path,
+    settings: &Settings,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+    let input_path = input.to_string_lossy();
+    let original_size = fs::metadata(input)?.len();
+
+    let mut args: Vec<String> = vec!["-i".into(), input_path.to_string()];
+    args.extend(
+        [
+            "-map",
+            "0:v",
+            "-map",
+            "0:a?",
+            "-c",
+            "copy",
+            "-map_metadata",
+            "0",
+        ]
+        .map(String::from),
+    );
+    let extension = output_file
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if matches!(extension.as_str(), "mp4" | "mov" | "m4v") {
+        // hvc1 is the tag Apple players require for HEVC
+        args.extend(["-tag:v", "hvc1", "-movflags", "+faststart"].map(String::from));
+    }
+    args.extend(["-y".into(), output_file.to_string_lossy().to_string()]);
+    run_encode(ffmpeg_path, &args, &input_path, output_file, ctx)?;
+
+    let mut compressed_size = fs::metadata(output_file)?.len();
+    let kept_original = compressed_size >= original_size;
+    if kept_original {
+        fs::copy(input, output_file)?;
+        compressed_size = original_size;
+    }
+
+    let mut result = CompressionResult::new(original_size, compressed_size, output_file, started);
+    result.kept_original = kept_original;
+    if let Ok(info) = probe::probe_media(ffmpeg_path, output_file) {
+        result.width = info.width;
+        result.height = info.height;
+        result.codec = info.video_codec;
+        result.duration = info.duration;
+    }
+    result.sha256 = checksum::write(output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+/// Encodes the `(start, end)` seconds of a video on their own, with the same
+/// arguments as a full encode.
+pub(crate) fn encode_range(
+    ffmpeg_path: &Path,
+    input_path: &str,
+    output_file: &Path,
+    preset: &Preset,
+    crop: Option<Crop>,
+    (start, end): (f64, f64),
+    ctx: &JobContext,
+) -> Result<()> {
+    let target_bitrate = preset
+        .target_video_bitrate(end - start)
+        .or_else(|| preset.average_video_bitrate());
+    let input = Path::new(input_path);
+    let source = probe::probe_media(ffmpeg_path, input).unwrap_or_default();
+    let filters = video_filters(preset, input, crop, &source, ctx.filters());
+
+    // Input seeking, so each clip decodes only its own part of the source
+    let mut args = vec![
+        "-ss".to_string(),
+        format!("{:.3}", start),
+        "-t".to_string(),
+        format!("{:.3}", end - start),
+    ];
+    args.extend(video_args(
+        input_path,
+        output_file,
+        preset,
+        target_bitrate,
+        &filters,
+        ctx.gpu(),
+    ));
+    encode_video(ffmpeg_path, &args, preset, input_path, output_file, ctx)
+}
+
+fn video_args(
+    input_path: &str,
+    output_file: &Path,
+    preset: &Preset,
+    target_bitrate: Option<u64>,
+    filters: &FilterGraph,
+    device: Option<&str>,
+) -> Vec<String> {
+    let mut args: Vec<String> = vec!["-i".into(), input_path.to_string()];
+    if let Some(watermark) = &preset.watermark {
+        args.extend(["-i".into(), watermark.path.clone()]);
+    }
+    args.extend(["-c:v".into(), preset.video_codec.clone()]);
+    if let Some(device) = device {
+        args.extend(gpu::device_args(&preset.video_codec, device));
+    }
+
+    // These profiles are x264's; other encoders reject them
+    if preset.video_codec == "libx264" {
+        args.extend(h264_profile_args(preset));
+    }
+
+    let lossless = is_lossless_video_codec(&preset.video_codec);
+    let vp9 = preset.video_codec == "libvpx-vp9";
+    if lossless {
+        // FFV1 version 3 with per-slice CRCs, so damage in storage is
+        // detectable and confined to one slice
+        args.extend(
+            ["-level", "3", "-g", "1", "-slices", "16", "-slicecrc", "1"].map(String::from),
+        );
+    }
+
+    args.extend(filters.args());
+
+    if let Some(max_frame_rate) = preset.max_frame_rate {
+        args.extend(["-fpsmax".into(), max_frame_rate.to_string()]);
+    }
+
+    // Lossless keeps the source's pixel format unless told otherwise;
+    // converting it would lose chroma or bit depth
+    match &preset.pixel_format {
+        Some(format) => args.extend(["-pix_fmt".into(), format.clone()]),
+        None if !lossless => args.extend(["-pix_fmt".into(), "yuv420p".into()]),
+        None => {}
+    }
+    args.extend(color_tag_args(preset));
+
+    let peak_bitrate = preset.peak_video_bitrate();
+    match target_bitrate {
+        Some(bitrate) => {
+            args.extend(["-b:v".into(), bitrate.to_string()]);
+            // An average is only that; a target size also caps the peaks
+            let cap = match preset.target_size_mb {
+                Some(_) => Some(peak_bitrate.map_or(bitrate, |peak| peak.min(bitrate))),
+                None => peak_bitrate,
+            };
+            if let Some(cap) = cap {
+                args.extend(rate_cap_args(cap));
+            }
+        }
+        None if lossless => {}
+        // libvpx treats -b:v as the cap on -crf, and zeroing it leaves
+        // plain constant quality
+        None if vp9 => args.extend([
+            "-crf".into(),
+            preset.video_crf.to_string(),
+            "-b:v".into(),
+            peak_bitrate.unwrap_or(0).to_string(),
+        ]),
+        None => {
+            args.extend(["-crf".into(), preset.video_crf.to_string()]);
+            if let Some(peak) = peak_bitrate {
+                args.extend(rate_cap_args(peak));
+            }
+        }
+    }
+
+    if let Some(tune) = &preset.tune {
+        if matches!(preset.video_codec.as_str(), "libx264" | "libx265") {
+            args.extend(["-tune".into(), tune.clone()]);
+        }
+    }
+
+    // FFV1 is all keyframes already
+    if !lossless {
+        args.extend(keyframe_args(preset));
+    }
+
+    args.extend(speed_args(preset));
+    if let (Some(grain), "libsvtav1") = (preset.film_grain, preset.video_codec.as_str()) {
+        args.extend(["-svtav1-params".into(), format!("film-grain={}", grain)]);
+    }
+    if preset.drops_audio() {
+        args.push("-an".into());
+    } else {
+        let audio_codec = match preset.video_codec.as_str() {
+            "ffv1" => "flac",
+            "libvpx-vp9" => "libopus",
+            _ => "aac",
+        };
+        args.extend(["-c:a".into(), audio_codec.into()]);
+        if !is_lossless_audio_codec(audio_codec) {
+            args.extend(["-b:a".into(), preset.audio_bitrate.clone()]);
+        }
+        args.extend(audio_filter_args(preset, false));
+    }
+    // faststart is an MP4/MOV muxer option
+    if video_container(&preset.video_codec).is_none() {
+        args.extend(["-movflags".into(), "+faststart".into()]);
+    }
+    args.extend(preset.extra_ffmpeg_args.iter().cloned());
+    args.extend(["-y".into(), output_file.to_string_lossy().to_string()]);
+
+    args
+}
+
+/// `-profile:v` and `-level` for x264. A preset that picks no profile gets
+/// baseline at level 3.0, what the oldest phones and browsers play, unless
+/// its pixel format is beyond baseline. x264 would reject the encode then,
+/// so it gets the profile the format needs and x264 picks the level.
+fn h264_profile_args(preset: &Preset) -> Vec<String> {
+    let required = preset
+        .pixel_format
+        .as_deref()
+        .and_then(presets::required_h264_profile);
+    let (profile, level) = match (&preset.h264_profile, required) {
+        (Some(profile), _) => (profile.as_str(), preset.h264_level.as_deref()),
+        (None, Some(required)) => (required, preset.h264_level.as_deref()),
+        (None, None) => (
+            "baseline",
+            Some(preset.h264_level.as_deref().unwrap_or("3.0")),
+        ),
+    };
+    let mut args = vec!["-profile:v".to_string(), profile.to_string()];
+    if let Some(level) = level {
+        args.extend(["-level".to_string(), level.to_string()]);
+    }
+    args
+}
+
+/// The encoder's speed setting for the preset's x264-style speed name.
+pub(crate) fn speed_args(preset: &Preset) -> Vec<String> {
+    match preset.video_codec.as_str() {
+        // libvpx is single-threaded per row without -row-mt and far slower
+        // than x264 at its default speed
+        "libvpx-vp9" => vec![
+            "-row-mt".into(),
+            "1".into(),
+            "-deadline".into(),
+            "good".into(),
+            "-cpu-used".into(),
+            vp9_cpu_used(&preset.encoder_preset).to_string(),
+        ],
+        "libsvtav1" => vec![
+            "-preset".into(),
+            svtav1_preset(&preset.encoder_preset).to_string(),
+        ],
+        codec if is_lossless_video_codec(codec) => Vec::new(),
+        _ => vec!["-preset".into(), preset.encoder_preset.clone()],
+    }
+}
+
+/// VBV limits holding the bitrate under `peak`, smoothed over two seconds.
+pub(crate) fn rate_cap_args(peak: u64) -> [String; 4] {
+    [
+        "-maxrate".into(),
+        peak.to_string(),
+        "-bufsize".into(),
+        (peak * 2).to_string(),
+    ]
+}
+
+/// Time-based rather than a `-g` frame count, so the interval holds at any
+/// source frame rate.
+pub(crate) fn keyframe_args(preset: &Preset) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(interval) = preset.keyframe_interval_secs {
+        args.extend([
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{})", interval),
+        ]);
+    }
+    if preset.fixed_keyframes {
+        match preset.video_codec.as_str() {
+            "libx264" => args.extend(["-sc_threshold".into(), "0".into()]),
+            "libx265" => args.extend(["-x265-params".into(), "scenecut=0".into()]),
+            _ => {}
+        }
+    }
+    args
+}
+
+/// Tags matching the conversion `color_filter` does, so players don't have
+/// to guess.
+fn color_tag_args(preset: &Preset) -> Vec<String> {
+    let mut args = Vec::new();
+    if preset.color_space == ColorSpace::Bt709 {
+        for option in ["-colorspace", "-color_primaries", "-color_trc"] {
+            args.extend([option.to_string(), "bt709".to_string()]);
+        }
+    }
+    match preset.color_range {
+        ColorRange::Limited => args.extend(["-color_range".into(), "tv".into()]),
+        ColorRange::Full => args.extend(["-color_range".into(), "pc".into()]),
+        ColorRange::Source => {}
+    }
+    args
+}
+
+/// The preset's filters as a graph, in the order they must run, with the
+/// script's `extra` filters added at the output size and the watermark,
+/// always input 1, overlaid on the result. `source` decides the rest: only
+/// videos `max_video_height` actually shrinks get sharpened, and HDR ones
+/// are tone mapped unless the output stays HDR.
+fn video_filters(
+    preset: &Preset,
+    input: &Path,
+    crop: Option<Crop>,
+    source: &MediaInfo,
+    extra: &[String],
+) -> FilterGraph {
+    let mut graph = FilterGraph::new();
+    let downscaled = preset
+        .max_video_height
+        .zip(source.height)
+        .is_some_and(|(max_height, height)| height > max_height);
+
+    if let Some(crop) = crop {
+        graph.push(Filter::Crop(crop));
+    }
+
+    if let Some(max_height) = preset.max_video_height {
+        graph.push(Filter::MaxHeight(max_height));
+    }
+
+    // At the output size, and before any conversion that assumes SDR
+    if source.is_hdr() && !keeps_hdr(preset) {
+        graph.push(Filter::Tonemap);
+    }
+
+    // Before denoising and subtitles, so both work in the output colors
+    if preset.color_space != ColorSpace::Source || preset.color_range != ColorRange::Source {
+        graph.push(Filter::Color {
+            space: preset.color_space,
+            range: preset.color_range,
+        });
+    }
+
+    // After scaling, so the (slow) denoiser has fewer pixels to work on
+    if let Some(denoise) = preset.denoise {
+        graph.push(Filter::Denoise(denoise));
+    }
+
+    // After denoising, which would otherwise have its noise boosted
+    if let (Some(amount), true) = (preset.sharpen, downscaled) {
+        graph.push(Filter::Sharpen(amount));
+    }
+
+    // At the output size, under any subtitles
+    for filter in extra {
+        graph.push(Filter::Custom(filter.clone()));
+    }
+
+    // Last, so text is drawn at the output size and never denoised
+    match preset.burn_subtitles {
+        Some(BurnSubtitles::Sidecar) => match find_sidecar_subtitles(input) {
+            Some(path) => graph.push(Filter::Subtitles { path, track: None }),
+            None => warn!("No subtitle file found next to {}", input.display()),
+        },
+        Some(BurnSubtitles::Embedded { track }) => graph.push(Filter::Subtitles {
+            path: input.to_path_buf(),
+            track: Some(track),
+        }),
+        None => {}
+    }
+
+    // Retime after everything that relies on the source timestamps
+    if let Some(speed) = preset.speed {
+        graph.push(Filter::Speed(speed));
+    }
+
+    if let Some(watermark) = &preset.watermark {
+        graph.overlay(1, watermark.clone());
+    }
+    graph
+}
+
+/// Whether an HDR source stays HDR: it keeps its colors and a pixel format
+/// deep enough for them, as lossless encodes do by default. Anything else
+/// ends up 8-bit or BT.709, where untouched PQ or HLG looks flat and gray.
+fn keeps_hdr(preset: &Preset) -> bool {
+    if preset.color_space != ColorSpace::Source {
+        return false;
+    }
+    match &preset.pixel_format {
+        Some(format) => format.contains("10") || format.contains("12"),
+        None => is_lossless_video_codec(&preset.video_codec),
+    }
+}
+
+fn find_sidecar_subtitles(input: &Path) -> Option<PathBuf> {
+    SUBTITLE_EXTENSIONS
+        .iter()
+        .map(|extension| input.with_extension(extension))
+        .find(|path| path.exists())
+}
+
+/// Filters, channel layout and sample rate for the preset's audio options.
+/// Silence trimming only applies when there is no picture to keep in sync.
+fn audio_filter_args(preset: &Preset, audio_only: bool) -> Vec<String> {
+    let mut filters = Vec::new();
+    if preset.trim_silence && audio_only {
+        filters.push(SILENCEREMOVE_FILTER.to_string());
+    }
+    // Keeps the soundtrack in sync with the retimed video
+    if let Some(speed) = preset.speed.filter(|_| !audio_only) {
+        filters.push(format!("atempo={}", speed));
+    }
+    // Normalize last so trimmed silence doesn't skew the measurement
+    if preset.normalize_loudness {
+        filters.push(LOUDNORM_FILTER.to_string());
+    }
+
+    let mut args = Vec::new();
+    if !filters.is_empty() {
+        args.extend(["-af".to_string(), filters.join(",")]);
+    }
+
+    // ffmpeg's default downmix matrix handles 5.1 to stereo and stereo to mono
+    if let Some(channels) = preset.audio_channels {
+        args.extend(["-ac".to_string(), channels.to_string()]);
+    }
+
+    let sample_rate = preset
+        .audio_sample_rate
+        .or(preset.normalize_loudness.then_some(NORMALIZED_SAMPLE_RATE));
+    if let Some(rate) = sample_rate {
+        args.extend(["-ar".to_string(), rate.to_string()]);
+    }
+    args
+}
+
+/// Re-encodes an audio file with the preset's audio codec, in the container
+/// that codec usually ships in.
+pub async fn compress_audio(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+
+    let original_size = fs::metadata(input)?.len();
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+
+    let file_name = input.file_stem().unwrap().to_str().unwrap();
+    let output_name = settings.output_file_name(file_name, "audio");
+    let mut output_file = output_dir.join(format!(
+        "{}.{}",
+        output_name,
+        audio_extension(&preset.audio_codec)
+    ));
+
+    let cover = shrink_cover_art(&ffmpeg_path, input, &output_file, preset, ctx);
+    let args = audio_args(input_path, &output_file, preset, cover.as_deref());
+    let encode = run_encode(&ffmpeg_path, &args, input_path, &output_file, ctx);
+    if let Some(cover) = &cover {
+        fs::remove_file(cover).ok();
+    }
+    encode?;
+
+    let mut compressed_size = fs::metadata(&output_file)?.len();
+
+    // The original keeps its own container, so it gets its own extension
+    let kept_original = compressed_size >= original_size;
+    if kept_original {
+        fs::remove_file(&output_file).ok();
+        let extension = input.extension().unwrap_or_default().to_string_lossy();
+        output_file = output_dir.join(format!("{}.{}", output_name, extension));
+        fs::copy(input, &output_file)?;
+        compressed_size = original_size;
+    }
+
+    let mut result = CompressionResult::new(original_size, compressed_size, &output_file, started);
+    result.kept_original = kept_original;
+
+    if let Ok(info) = probe::probe_media(&ffmpeg_path, &output_file) {
+        result.codec = info.audio_codec;
+        result.duration = info.duration;
+    }
+    result.lossless = result.codec.as_deref().map(is_lossless_audio_codec);
+
+    result.sha256 = checksum::write(&output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+/// Extracts the input's cover art and re-encodes it as a smaller JPEG for
+/// embedding. `None` when there is no cover to keep or it can't be shrunk,
+/// in which case the original cover is copied as-is.
+fn shrink_cover_art(
+    ffmpeg_path: &Path,
+    input: &Path,
+    output_file: &Path,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Option<PathBuf> {
+    let extension = output_file.extension()?.to_str()?;
+    if !preset.keep_cover_art || !COVER_ART_CONTAINERS.contains(&extension) {
+        return None;
+    }
+    if !probe::probe_media(ffmpeg_path, input).ok()?.has_cover_art {
+        return None;
+    }
+
+    let extracted = output_file.with_extension("cover.img");
+    let args: Vec<String> = vec![
+        "-i".into(),
+        input.to_string_lossy().to_string(),
+        "-map".into(),
+        "0:v:0".into(),
+        "-c:v".into(),
+        "copy".into(),
+        "-frames:v".into(),
+        "1".into(),
+        "-f".into(),
+        "image2".into(),
+        "-y".into(),
+        extracted.to_string_lossy().to_string(),
+    ];
+    let status = job::run_ffmpeg(ffmpeg_path, &args, ctx, None).map(|output| output.status);
+    let original = fs::read(&extracted).ok();
+    fs::remove_file(&extracted).ok();
+    if !status.ok()?.success() {
+        return None;
+    }
+
+    let original = original?;
+    let img = image::load_from_memory(&original).ok()?;
+    let resized = resize_to_fit(img, Some(MAX_COVER_ART_DIMENSION), ResizeFilter::default());
+    let encoded = encode_jpeg(&resized.to_rgb8(), preset.image_quality).ok()?;
+    if encoded.len() >= original.len() {
+        return None;
+    }
+
+    let cover = output_file.with_extension("cover.jpg");
+    fs::write(&cover, encoded).ok()?;
+    Some(cover)
+}
+
+fn audio_args(
+    input_path: &str,
+    output_file: &Path,
+    preset: &Preset,
+    cover: Option<&Path>,
+) -> Vec<String> {
+    let extension = output_file
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let mut args: Vec<String> = vec!["-i".into(), input_path.to_string()];
+
+    // Cover art is a still-image video stream; a shrunk copy replaces the
+    // original, otherwise the original is copied when wanted
+    if let Some(cover) = cover {
+        args.extend([
+            "-i".into(),
+            cover.to_string_lossy().to_string(),
+            "-map".into(),
+            "0:a".into(),
+            "-map".into(),
+            "1:v".into(),
+            "-c:v".into(),
+            "copy".into(),
+            "-disposition:v".into(),
+            "attached_pic".into(),
+        ]);
+    } else if preset.keep_cover_art && COVER_ART_CONTAINERS.contains(&extension.as_str()) {
+        args.extend(
+            [
+                "-map",
+                "0:a",
+                "-map",
+                "0:v?",
+                "-c:v",
+                "copy",
+                "-disposition:v",
+                "attached_pic",
+            ]
+            .map(String::from),
+        );
+    } else {
+        args.push("-vn".into());
+    }
+
+    // Ogg and Opus keep their tags on the audio stream, which other
+    // containers don't read, so lift them to the file level
+    let input_extension = Path::new(input_path)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let metadata_source = match input_extension.as_str() {
+        "ogg" | "opus" => "0:s:a:0",
+        _ => "0",
+    };
+    args.extend([
+        "-map_metadata".into(),
+        metadata_source.into(),
+        "-c:a".into(),
+        preset.audio_codec.clone(),
+    ]);
+    if is_lossless_audio_codec(&preset.audio_codec) {
+        if preset.audio_codec == "flac" {
+            args.extend(["-compression_level".into(), "8".into()]);
+        }
+    } else {
+        args.extend(["-b:a".into(), preset.audio_bitrate.clone()]);
+    }
+    args.extend(audio_filter_args(preset, true));
+    match extension.as_str() {
+        "m4a" => args.extend(["-movflags".into(), "+faststart".into()]),
+        // ID3v2.4 tags are invisible to Windows Explorer and older players
+        "mp3" => args.extend(["-id3v2_version".into(), "3".into()]),
+        _ => {}
+    }
+    args.extend(preset.extra_ffmpeg_args.iter().cloned());
+    args.extend(["-y".into(), output_file.to_string_lossy().to_string()]);
+    args
+}
+
+/// The container an encoder's output has to go in, when the input's own
+/// container can't hold it. `None` keeps the input's container.
+pub(crate) fn video_container(codec: &str) -> Option<&'static str> {
+    match codec {
+        "ffv1" => Some("mkv"),
+        "libvpx-vp9" => Some("webm"),
+        _ => None,
+    }
+}
+
+/// libvpx `-cpu-used` (0 slowest to 5 fastest for `good`) for an x264-style
+/// speed preset name.
+fn vp9_cpu_used(encoder_preset: &str) -> u8 {
+    match encoder_preset {
+        "ultrafast" | "superfast" | "veryfast" => 5,
+        "faster" | "fast" => 4,
+        "slow" => 2,
+        "slower" | "veryslow" | "placebo" => 1,
+        _ => 3,
+    }
+}
+
+/// SVT-AV1 `-preset` (0 slowest to 13 fastest) for a numeric or x264-style
+/// speed preset.
+fn svtav1_preset(encoder_preset: &str) -> u8 {
+    if let Ok(preset) = encoder_preset.parse::<u8>() {
+        return preset.min(13);
+    }
+    match encoder_preset {
+        "ultrafast" | "superfast" => 12,
+        "veryfast" | "faster" => 10,
+        "fast" => 9,
+        "slow" => 6,
+        "slower" => 5,
+        "veryslow" | "placebo" => 4,
+        _ => 8,
+    }
+}
+
+/// Whether the video encoder is lossless and meant for archival copies.
+pub fn is_lossless_video_codec(codec: &str) -> bool {
+    codec == "ffv1"
+}
+
+/// Compares the per-frame MD5s of the decoded video streams, which is the
+/// proof that a lossless encode reproduced every frame.
+fn frames_match(ffmpeg_path: &Path, input: &Path, output: &Path) -> Result<bool> {
+    let source = probe::frame_md5s(ffmpeg_path, input)?;
+    Ok(!source.is_empty() && source == probe::frame_md5s(ffmpeg_path, output)?)
+}
+
+/// Whether `codec` (an encoder or a probed codec name) stores audio
+/// losslessly.
+pub fn is_lossless_audio_codec(codec: &str) -> bool {
+    matches!(codec, "flac" | "alac" | "wavpack" | "tta") || codec.starts_with("pcm_")
+}
+
+fn audio_extension(codec: &str) -> &'static str {
+    match codec {
+        "libopus" | "opus" => "opus",
+        "libvorbis" | "vorbis" => "ogg",
+        "libmp3lame" => "mp3",
+        "flac" => "flac",
+        _ => "m4a",
+    }
+}
+
+/// Whether ffmpeg can drive the encoder's two-pass mode with `-pass`.
+pub fn supports_two_pass(codec: &str) -> bool {
+    TWO_PASS_ENCODERS.contains(&codec)
+}
+
+/// Runs a video encode built by `video_args`, in two passes when the preset
+/// asks for them. The first pass only writes ffmpeg's stats log, which is
+/// kept in the staging directory and removed however the encode ends.
+fn encode_video(
+    ffmpeg_path: &Path,
+    args: &[String],
+    preset: &Preset,
+    input_path: &str,
+    output_file: &Path,
+    ctx: &JobContext,
+) -> Result<()> {
+    if !preset.two_pass || !supports_two_pass(&preset.video_codec) {
+        return run_encode(ffmpeg_path, args, input_path, output_file, ctx);
+    }
+
+    let staging_dir = paths::staging_dir();
+    fs::create_dir_all(&staging_dir)
+        .map_err(|e| Error::io("Failed to create staging directory", e))?;
+    let prefix = format!(
+        "{}-{}-pass",
+        std::process::id(),
+        NEXT_PASS_LOG.fetch_add(1, Ordering::Relaxed)
+    );
+    let log = staging_dir.join(&prefix);
+    let pass = |number: &str| {
+        [
+            "-pass".to_string(),
+            number.to_string(),
+            "-passlogfile".to_string(),
+            log.to_string_lossy().to_string(),
+        ]
+    };
+
+    // `video_args` ends with `-y <output>`. The analysis needs neither audio
+    // nor a file, and the null muxer has no use for mp4's faststart.
+    let (options, output) = args.split_at(args.len() - 2);
+    let mut first_pass = Vec::new();
+    let mut options_iter = options.iter();
+    while let Some(option) = options_iter.next() {
+        if option == "-movflags" {
+            options_iter.next();
+        } else {
+            first_pass.push(option.clone());
+        }
+    }
+    first_pass.extend(pass("1"));
+    first_pass.extend(["-an", "-f", "null", "-"].map(String::from));
+    let mut second_pass = options.to_vec();
+    second_pass.extend(pass("2"));
+    second_pass.extend_from_slice(output);
+
+    let result = run_encode(ffmpeg_path, &first_pass, input_path, &log, ctx)
+        .and_then(|()| run_encode(ffmpeg_path, &second_pass, input_path, output_file, ctx));
+
+    // x264 writes `<log>-0.log` and `<log>-0.log.mbtree`
+    if let Ok(entries) = fs::read_dir(&staging_dir) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                fs::remove_file(entry.path()).ok();
+            }
+        }
+    }
+    result
+}
+
+/// Runs one ffmpeg encode, logging it to its own job log.
+pub(crate) fn run_encode(
+    ffmpeg_path: &Path,
+    args: &[String],
+    input_path: &str,
+    output_file: &Path,
+    ctx: &JobContext,
+) -> Result<()> {
+    let log = JobLog::create(ffmpeg_path, args, input_path);
+
+    ctx.writing(output_file);
+    match job::run_ffmpeg(ffmpeg_path, args, ctx, log.as_ref()) {
+        Ok(result) if result.status.success() => Ok(()),
+        Ok(result) => {
+            let stderr = result.stderr_tail;
+
+            if stderr.contains("ffmpeg: not found") || stderr.contains("command not found") {
+                return Err(Error::FfmpegMissing);
+            }
+            if stderr.contains("No space left on device") {
+                return Err(Error::DiskFull);
+            }
+
+            Err(Error::EncodeFailed {
+                stderr_tail: stderr,
+                log_path: log.map(|log| log.path().to_string_lossy().to_string()),
+            })
+        }
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                // Don't leave a truncated video behind
+                fs::remove_file(output_file).ok();
+                Err(Error::Cancelled)
+            } else if e.kind() == std::io::ErrorKind::NotFound {
+                Err(Error::FfmpegMissing)
+            } else {
+                Err(Error::io("Failed to run ffmpeg", e))
+            }
+        }
+    }
+}
+
+/// The line of ffmpeg's output blaming the encoder, decoder or pixel format
+/// rather than the input, without its `[hevc_nvenc @ 0x...]` prefix. `None`
+/// when the input itself is the problem and no retry would help.
+fn recoverable_failure(stderr: &str) -> Option<&str> {
+    let line = stderr.lines().find(|line| {
+        RECOVERABLE_FAILURES
+            .iter()
+            .any(|pattern| line.contains(pattern))
+    })?;
+    let line = line.trim();
+    Some(match line.split_once("] ") {
+        Some((_, message)) if line.starts_with('[') => message,
+        _ => line,
+    })
+}
+
+/// Software encoders to retry a failed `codec` with, closest first, each
+/// writing the same container.
+fn fallback_encoders(codec: &str) -> Vec<String> {
+    // Hardware encoders are named after their format, like `hevc_nvenc`
+    let equivalent = match codec.split('_').next() {
+        Some("hevc") => "libx265",
+        Some("av1") => "libsvtav1",
+        Some("vp9") => "libvpx-vp9",
+        _ => FALLBACK_VIDEO_CODEC,
+    };
+    let mut encoders: Vec<String> = Vec::new();
+    for encoder in [equivalent, FALLBACK_VIDEO_CODEC] {
+        if encoder != codec
+            && video_container(encoder) == video_container(codec)
+            && !encoders.iter().any(|known| known == encoder)
+        {
+            encoders.push(encoder.to_string());
+        }
+    }
+    encoders
+}
+
+pub fn compress_image(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    if ctx.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    let started = Instant::now();
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+
+    // Vector images are minified, not rasterized
+    if input
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+    {
+        return svg::compress_svg(input_path, output_path, settings, ctx);
+    }
+
+    if preset.jpeg_xl {
+        return compress_jxl(input, output_path, settings, preset, started);
+    }
+
+    // Get original file size
+    let original_size = fs::metadata(input_path)?.len();
+
+    let img = open_image(input)?;
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+
+    let file_name = input.file_stem().unwrap().to_str().unwrap();
+    let original_extension = input
+        .extension()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or("jpg");
+
+    let original_dimensions = img.dimensions();
+
+    // Variants are resized from the full-size source
+    let source = (!preset.responsive_widths.is_empty()).then(|| img.clone());
+    let resized = resize_for_preset(img, preset);
+
+    let (output_format, encoded) = encode_for_preset(&resized, original_extension, preset)?;
+    let output_extension = output_format.extensions_str()[0];
+
+    let mut variants = Vec::new();
+    if let Some(source) = &source {
+        let mut widths = preset.responsive_widths.clone();
+        widths.sort_unstable();
+        widths.dedup();
+        for width in widths
+            .into_iter()
+            .filter(|&width| width < original_dimensions.0)
+        {
+            if ctx.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let height = (original_dimensions.1 as f64 * width as f64
+                / original_dimensions.0 as f64)
+                .round()
+                .max(1.0) as u32;
+            let mut variant = resize_exact(source, width, height, preset.resize_filter);
+            if let Some(amount) = preset.sharpen {
+                variant = sharpen(&variant, amount);
+            }
+            let variant_file = output_dir.join(format!(
+                "{}.{}",
+                settings.responsive_file_name(file_name, width),
+                output_extension
+            ));
+            write_image(&variant, output_format, preset, &variant_file)?;
+            checksum::write(&variant_file, settings.checksum_mode)?;
+            variants.push(ImageVariant {
+                width: variant.width(),
+                height: variant.height(),
+                output_path: variant_file.to_string_lossy().to_string(),
+                size: fs::metadata(&variant_file)?.len(),
+            });
+        }
+    }
+
+    let output_file = output_dir.join(format!(
+        "{}.{}",
+        settings
+            .output_file_name(file_name, "image")
+            .replace("{width}", &resized.width().to_string()),
+        output_extension
+    ));
+    fs::write(&output_file, encoded)?;
+
+    let mut pages = Vec::new();
+    if matches!(original_extension.to_lowercase().as_str(), "tif" | "tiff") {
+        for index in 1..tiff_page_count(input)? {
+            if ctx.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let page = resize_for_preset(decode_tiff_page(input, index)?, preset);
+            let page_file = output_dir.join(format!(
+                "{}-page{:03}.{}",
+                settings
+                    .output_file_name(file_name, "image")
+                    .replace("{width}", &page.width().to_string()),
+                index + 1,
+                output_extension
+            ));
+            write_image(&page, output_format, preset, &page_file)?;
+            pages.push(ImageVariant {
+                width: page.width(),
+                height: page.height(),
+                output_path: page_file.to_string_lossy().to_string(),
+                size: fs::metadata(&page_file)?.len(),
+            });
+        }
+    }
+
+    // All pages together replace the original
+    let compressed_size =
+        fs::metadata(&output_file)?.len() + pages.iter().map(|page| page.size).sum::<u64>();
+
+    // If compressed is larger than original, just copy the original. For a
+    // multi-page TIFF the copy holds every page, so the page files go.
+    let mut result = if compressed_size >= original_size {
+        for page in pages.drain(..) {
+            fs::remove_file(&page.output_path).ok();
+        }
+        fs::copy(input_path, &output_file)?;
+        let final_size = fs::metadata(&output_file)?.len();
+        let mut result = CompressionResult::new(original_size, final_size, &output_file, started);
+        (result.width, result.height) = (Some(original_dimensions.0), Some(original_dimensions.1));
+        result.codec = ImageFormat::from_extension(original_extension)
+            .map(|format| image_codec_name(format).to_string());
+        result.kept_original = true;
+        result
+    } else {
+        let mut result =
+            CompressionResult::new(original_size, compressed_size, &output_file, started);
+        let (width, height) = resized.dimensions();
+        (result.width, result.height) = (Some(width), Some(height));
+        result.codec = Some(image_codec_name(output_format).to_string());
+        result
+    };
+
+    for page in &pages {
+        checksum::write(Path::new(&page.output_path), settings.checksum_mode)?;
+    }
+    result.variants = variants;
+    result.pages = pages;
+    result.sha256 = checksum::write(&output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+/// JPEG XL through cjxl. A JPEG the preset leaves at its size is repacked
+/// rather than re-encoded, keeping every pixel.
+fn compress_jxl(
+    input: &Path,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    started: Instant,
+) -> Result<CompressionResult> {
+    let cjxl = encoders::find(ExternalEncoder::Cjxl).ok_or_else(|| Error::EncoderMissing {
+        name: ExternalEncoder::Cjxl.name().to_string(),
+    })?;
+    let original_size = fs::metadata(input)?.len();
+    // Huge JPEGs decode at a reduced size, so the file says what its size is
+    let original_dimensions = image::image_dimensions(input)?;
+    let resized = resize_for_preset(open_image(input)?, preset);
+
+    let is_jpeg = ImageFormat::from_path(input).is_ok_and(|format| format == ImageFormat::Jpeg);
+    let encoded = if is_jpeg && resized.dimensions() == original_dimensions {
+        encoders::transcode_jpeg(&cjxl, input)?
+    } else {
+        encoders::encode(ExternalEncoder::Cjxl, &cjxl, &resized, preset.image_quality)?
+    };
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+    let file_name = input.file_stem().unwrap().to_string_lossy();
+    let name = settings
+        .output_file_name(&file_name, "image")
+        .replace("{width}", &resized.width().to_string());
+
+    // Same rule as other images, but a copy keeps its own extension
+    let mut result = if encoded.len() as u64 >= original_size {
+        let extension = input.extension().unwrap_or_default().to_string_lossy();
+        let output_file = output_dir.join(format!("{}.{}", name, extension));
+        fs::copy(input, &output_file)?;
+        let mut result =
+            CompressionResult::new(original_size, original_size, &output_file, started);
+        (result.width, result.height) = (Some(original_dimensions.0), Some(original_dimensions.1));
+        result.kept_original = true;
+        result
+    } else {
+        let output_file = output_dir.join(format!("{}.jxl", name));
+        fs::write(&output_file, &encoded)?;
+        let mut result =
+            CompressionResult::new(original_size, encoded.len() as u64, &output_file, started);
+        (result.width, result.height) = (Some(resized.width()), Some(resized.height()));
+        result.codec = Some("jxl".to_string());
+        result
+    };
+    result.sha256 = checksum::write(Path::new(&result.output_path), settings.checksum_mode)?;
+    Ok(result)
+}
+
+/// Number of pages (image directories) in a TIFF.
+fn tiff_page_count(path: &Path) -> Result<usize> {
+    let mut decoder = open_tiff(path)?;
+    let mut count = 1;
+    while decoder.more_images() {
+        decoder.next_image().map_err(tiff_error)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Decodes page `index` (0-based) of a TIFF. 8- and 16-bit gray, RGB and
+/// RGBA pages are supported, like the first page `image` decodes.
+fn decode_tiff_page(path: &Path, index: usize) -> Result<DynamicImage> {
+    use image::{GrayAlphaImage, GrayImage, ImageBuffer, RgbImage, RgbaImage};
+    use tiff::decoder::DecodingResult;
+    use tiff::ColorType;
+
+    let mut decoder = open_tiff(path)?;
+    decoder.seek_to_image(index).map_err(tiff_error)?;
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color = decoder.colortype().map_err(tiff_error)?;
+
+    let page = match (color, decoder.read_image().map_err(tiff_error)?) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            GrayAlphaImage::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        _ => None,
+    };
+
+    page.ok_or_else(|| Error::Image {
+        message: format!(
+            "Page {} of {} has an unsupported pixel format ({:?})",
+            index + 1,
+            path.display(),
+            color
+        ),
+    })
+}
+
+fn open_tiff(path: &Path) -> Result<tiff::decoder::Decoder<io::BufReader<fs::File>>> {
+    let file = fs::File::open(path).map_err(|e| Error::io("Failed to open image", e))?;
+    tiff::decoder::Decoder::new(io::BufReader::new(file)).map_err(tiff_error)
+}
+
+fn tiff_error(e: tiff::TiffError) -> Error {
+    Error::Image {
+        message: e.to_string(),
+    }
+}
+
+/// Encodes an image already resized for `preset` as `compress_image` does,
+/// picking the format from the input's `extension` or by trial. Returns the
+/// format picked.
+pub(crate) fn encode_for_preset(
+    resized: &DynamicImage,
+    extension: &str,
+    preset: &Preset,
+) -> Result<(ImageFormat, Vec<u8>)> {
+    // For WebP and other already compressed formats, convert to JPEG if it
+    // would be smaller. The format trial replaces this guess with measurements.
+    let format = match extension.to_lowercase().as_str() {
+        // PNG might be better kept as PNG if it has transparency
+        "png" if resized.color().has_alpha() => ImageFormat::Png,
+        "gif" => ImageFormat::Gif,
+        _ => ImageFormat::Jpeg,
+    };
+
+    // Animated GIFs would lose their animation in any other format
+    if preset.image_format_trial && format != ImageFormat::Gif {
+        smallest_encoding(resized, preset)
+    } else {
+        Ok((format, encode_image(resized, format, preset)?))
+    }
+}
+
+/// Saves with quality optimization for the format.
+fn write_image(
+    img: &DynamicImage,
+    format: ImageFormat,
+    preset: &Preset,
+    path: &Path,
+) -> Result<()> {
+    fs::write(path, encode_image(img, format, preset)?)?;
+    Ok(())
+}
+
+fn encode_image(img: &DynamicImage, format: ImageFormat, preset: &Preset) -> Result<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => encode_jpeg_for_preset(img, preset),
+        ImageFormat::Png => encode_png(img),
+        ImageFormat::WebP => encode_webp(img, preset.image_quality),
+        ImageFormat::Avif => encode_avif(img, preset.image_quality),
+        _ => {
+            let mut buffer = io::Cursor::new(Vec::new());
+            img.write_to(&mut buffer, format)?;
+            Ok(buffer.into_inner())
+        }
+    }
+}
+
+/// Encodes `img` in each format of the preset's trial, in parallel, and
+/// returns the smallest result. All formats get the same quality number,
+/// which the three encoders map to roughly the same visual quality.
+fn smallest_encoding(img: &DynamicImage, preset: &Preset) -> Result<(ImageFormat, Vec<u8>)> {
+    let mut formats = vec![
+        if img.color().has_alpha() {
+            ImageFormat::Png
+        } else {
+            ImageFormat::Jpeg
+        },
+        ImageFormat::WebP,
+    ];
+    if preset.trial_avif {
+        formats.push(ImageFormat::Avif);
+    }
+
+    let mut encodings = formats
+        .par_iter()
+        .map(|&format| (format, encode_image(img, format, preset)))
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    // The first format always works; the others are only candidates
+    let (format, encoded) = encodings.next().unwrap();
+    let mut smallest = (format, encoded?);
+    for (format, encoded) in encodings {
+        match encoded {
+            Ok(encoded) if encoded.len() < smallest.1.len() => smallest = (format, encoded),
+            Ok(_) => {}
+            Err(e) => warn!(
+                "{} encode failed in format trial: {}",
+                image_codec_name(format),
+                e
+            ),
+        }
+    }
+    Ok(smallest)
+}
+
+/// Lossy WebP. `image` only writes lossless WebP, so this goes through
+/// cwebp or ffmpeg's libwebp when available and falls back to lossless
+/// otherwise.
+fn encode_webp(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    if let Some(encoded) = encode_external(ImageFormat::WebP, img, quality) {
+        return Ok(encoded);
+    }
+    if let Some(ffmpeg_path) = FFmpegManager::new().installed_ffmpeg_path() {
+        match encode_webp_with_ffmpeg(&ffmpeg_path, img, quality) {
+            Ok(encoded) => return Ok(encoded),
+            Err(e) => warn!("Lossy WebP encode failed, using lossless: {}", e),
+        }
+    }
+
+    let mut buffer = Vec::new();
+    to_8bit(img).write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut buffer))?;
+    Ok(buffer)
+}
+
+fn encode_webp_with_ffmpeg(ffmpeg_path: &Path, img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let mut input = Vec::new();
+    img.write_with_encoder(image::codecs::png::PngEncoder::new_with_quality(
+        &mut input,
+        image::codecs::png::CompressionType::Fast,
+        image::codecs::png::FilterType::NoFilter,
+    ))?;
+
+    let mut child = Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-f", "image2pipe", "-c:v", "png", "-i", "-"])
+        .args(["-c:v", "libwebp", "-quality", &quality.to_string()])
+        .args(["-f", "webp", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| Error::io("Failed to run ffmpeg", e))?;
+
+    // Fed from another thread, as ffmpeg may fill stdout before it has
+    // read all of stdin. A failed write shows up as a failed encode.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::io("Failed to run ffmpeg", e))?;
+    writer.join().ok();
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(Error::Image {
+            message: "ffmpeg could not encode WebP".to_string(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+fn encode_avif(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    if let Some(encoded) = encode_external(ImageFormat::Avif, img, quality) {
+        return Ok(encoded);
+    }
+    let mut buffer = Vec::new();
+    let encoder =
+        image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_SPEED, quality);
+    to_8bit(img).write_with_encoder(encoder)?;
+    Ok(buffer)
+}
+
+/// Encodes with the external encoder for `format` when one is installed.
+/// If it fails, the built-in encoder gets the job.
+fn encode_external(format: ImageFormat, img: &DynamicImage, quality: u8) -> Option<Vec<u8>> {
+    let encoder = ExternalEncoder::for_format(format)?;
+    let path = encoders::find(encoder)?;
+    encoders::encode(encoder, &path, img, quality)
+        .inspect_err(|e| {
+            warn!(
+                "{} failed, using the built-in encoder: {}",
+                encoder.name(),
+                e
+            )
+        })
+        .ok()
+}
+
+/// The WebP and AVIF encoders only take 8-bit RGB(A).
+fn to_8bit(img: &DynamicImage) -> DynamicImage {
+    if img.color().has_alpha() {
+        img.to_rgba8().into()
+    } else {
+        img.to_rgb8().into()
+    }
+}
+
+/// Creates and returns the directory outputs for `input` go to: the
+/// requested one, then the configured one, then `compressed` next to the
+/// input, plus a `YYYY/MM` subfolder with the by-date layout.
+pub(crate) fn prepare_output_dir(
+    input: &Path,
+    output_path: Option<String>,
+    settings: &Settings,
+) -> Result<PathBuf> {
+    let output_dir = output_dir_for(input, output_path, settings);
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| Error::io("Failed to create output directory", e))?;
+    Ok(output_dir)
+}
+
+/// Where the output of `input` goes, without creating anything.
+pub(crate) fn output_dir_for(
+    input: &Path,
+    output_path: Option<String>,
+    settings: &Settings,
+) -> PathBuf {
+    let mut output_dir = if let Some(dir) = output_path.or_else(|| settings.output_dir.clone()) {
+        PathBuf::from(dir)
+    } else {
+        input.parent().unwrap().join("compressed")
+    };
+    if settings.output_layout == OutputLayout::ByDate {
+        output_dir.push(capture_date::month_folder(input));
+    }
+    output_dir
+}
+
+pub(crate) fn image_codec_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Png => "png",
+        ImageFormat::Gif => "gif",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Avif => "avif",
+        ImageFormat::Bmp => "bmp",
+        _ => "unknown",
+    }
+}
+
+/// Whether the history shows contents hashing to `hash` were compressed
+/// before.
+fn already_processed(hash: &str) -> bool {
+    HistoryStore::open()
+        .and_then(|store| store.is_processed(hash))
+        .unwrap_or(false)
+}
+
+/// Whether `input` is named like the output of an earlier run.
+fn has_output_name(input: &Path, settings: &Settings) -> bool {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    settings.is_output_name(&stem)
+}
+
+/// Whether the history has contents hashing to `hash` as an output of an
+/// earlier run.
+fn is_compressed_output(hash: &str) -> bool {
+    HistoryStore::open()
+        .and_then(|store| store.is_output(hash))
+        .unwrap_or(false)
+}
+
+/// Records a finished job in the history, then encrypts its outputs and
+/// sends them wherever settings ask.
+pub fn finish_job(
+    input_path: &str,
+    media_type: &str,
+    preset: &Preset,
+    settings: &Settings,
+    started: Instant,
+    result: Result<CompressionResult>,
+) -> Result<CompressionResult> {
+    let job_id = record_history(input_path, media_type, preset, started, &result);
+    let result = encryption::encrypt_outputs(input_path, result, &settings.encryption)?;
+    let result = s3::upload_outputs(result, &settings.upload)?;
+    let result = destination::upload_outputs(result, &settings.destination)?;
+
+    if let (Some(id), Some(url)) = (job_id, &result.remote_url) {
+        HistoryStore::open()
+            .and_then(|store| store.set_remote_url(id, url))
+            .ok();
+    }
+    Ok(result)
+}
+
+/// Writes a finished job to the history database, returning its id. History
+/// is best-effort: a broken database must never fail the compression itself.
+pub fn record_history(
+    input_path: &str,
+    media_type: &str,
+    preset: &Preset,
+    started: Instant,
+    result: &Result<CompressionResult>,
+) -> Option<i64> {
+    let original_size = match result {
+        Ok(result) => result.original_size,
+        Err(_) => fs::metadata(input_path).map(|m| m.len()).unwrap_or(0),
+    };
+    let error = result.as_ref().err().map(|e| e.to_string());
+    let input_hash = checksum::content_hash(Path::new(input_path)).ok();
+    let output_hash = result
+        .as_ref()
+        .ok()
+        .and_then(|result| checksum::content_hash(Path::new(&result.output_path)).ok());
+
+    // Keep the preset as submitted, but note when its encoder was swapped out
+    let mut settings = serde_json::to_value(preset).unwrap_or_default();
+    if let (Ok(result), Some(fields)) = (result, settings.as_object_mut()) {
+        if result.fallback_used {
+            fields.insert("fallbackUsed".to_string(), true.into());
+        }
+        if let Some(reason) = &result.fallback_reason {
+            fields.insert("fallbackReason".to_string(), reason.clone().into());
+        }
+    }
+
+    let job = NewJob {
+        input_path,
+        output_path: result.as_ref().ok().map(|r| r.output_path.as_str()),
+        media_type,
+        original_size,
+        compressed_size: result.as_ref().ok().map(|r| r.compressed_size),
+        settings: settings.to_string(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error: error.as_deref(),
+        input_hash: input_hash.as_deref(),
+        output_hash: output_hash.as_deref(),
+    };
+
+    HistoryStore::open()
+        .and_then(|store| store.record(&job))
+        .ok()
+}
+
+/// Lowest quality a size-capped preset will go to before giving up on the target.
+pub(crate) const MIN_TARGET_JPEG_QUALITY: u8 = 40;
+
+/// Opens an image within a memory budget. Only the header is read to size
+/// it up; JPEGs over `MAX_DECODE_PIXELS` are decoded downscaled by ffmpeg
+/// when it is installed.
+pub fn open_image(path: &Path) -> Result<DynamicImage> {
+    // HEIC/HEIF (iPhone photos) has no Rust decoder here, ffmpeg has one
+    if is_heif(path) {
+        let ffmpeg_path = FFmpegManager::new()
+            .installed_ffmpeg_path()
+            .ok_or(Error::FfmpegMissing)?;
+        return decode_with_ffmpeg(&ffmpeg_path, path, 0);
+    }
+
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader.format();
+    let (width, height) = reader.into_dimensions()?;
+    let pixels = width as u64 * height as u64;
+
+    if pixels <= MAX_DECODE_PIXELS {
+        return Ok(image::open(path)?);
+    }
+
+    // Each lowres step halves both sides
+    let lowres = (1..=MAX_JPEG_LOWRES).find(|step| pixels >> (2 * step) <= MAX_DECODE_PIXELS);
+    if let (Some(ImageFormat::Jpeg), Some(lowres)) = (format, lowres) {
+        if let Some(ffmpeg_path) = FFmpegManager::new().installed_ffmpeg_path() {
+            return decode_with_ffmpeg(&ffmpeg_path, path, lowres);
+        }
+    }
+
+    Err(Error::Image {
+        message: format!(
+            "{} is too large to open: {} megapixels, the limit is {}",
+            path.display(),
+            pixels / 1_000_000,
+            MAX_DECODE_PIXELS / 1_000_000
+        ),
+    })
+}
+
+fn is_heif(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| HEIF_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Decodes a still image with ffmpeg. A non-zero `lowres` decodes a JPEG at
+/// 1/2^`lowres` scale, which skips most of the work and memory of a full
+/// decode.
+pub(crate) fn decode_with_ffmpeg(
+    ffmpeg_path: &Path,
+    path: &Path,
+    lowres: u32,
+) -> Result<DynamicImage> {
+    let mut command = Command::new(ffmpeg_path);
+    command.arg("-hide_banner");
+    if lowres > 0 {
+        command.args(["-lowres", &lowres.to_string()]);
+    }
+    let output = command
+        .arg("-i")
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "bmp", "-"])
+        .output()
+        .map_err(|e| Error::io("Failed to run ffmpeg", e))?;
+
+    if !output.status.success() {
+        return Err(Error::Image {
+            message: format!("ffmpeg could not decode {}", path.display()),
+        });
+    }
+    Ok(image::load_from_memory_with_format(
+        &output.stdout,
+        ImageFormat::Bmp,
+    )?)
+}
+
+/// Downscales to the preset's limit, then sharpens if the preset asks for
+/// it and the image actually got smaller.
+pub fn resize_for_preset(img: DynamicImage, preset: &Preset) -> DynamicImage {
+    let original_width = img.width();
+    let resized = resize_to_fit(img, preset.max_image_dimension, preset.resize_filter);
+    match preset.sharpen {
+        Some(amount) if resized.width() < original_width => sharpen(&resized, amount),
+        _ => resized,
+    }
+}
+
+/// Unsharp mask: pushes each color channel away from a blurred copy by
+/// `amount`. Alpha is left alone.
+fn sharpen(img: &DynamicImage, amount: f32) -> DynamicImage {
+    let blurred = img.blur(SHARPEN_SIGMA).to_rgba8();
+    let mut sharpened = img.to_rgba8();
+    for (pixel, blurred) in sharpened.pixels_mut().zip(blurred.pixels()) {
+        for channel in 0..3 {
+            let value = pixel[channel] as f32;
+            let detail = value - blurred[channel] as f32;
+            pixel[channel] = (value + amount * detail).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    let sharpened = DynamicImage::ImageRgba8(sharpened);
+    if img.color().has_alpha() {
+        sharpened
+    } else {
+        DynamicImage::ImageRgb8(sharpened.to_rgb8())
+    }
+}
+
+/// Downscales `img` so its longest side is at most `max_dimension`; smaller
+/// images (or `None`) pass through untouched.
+pub fn resize_to_fit(
+    img: DynamicImage,
+    max_dimension: Option<u32>,
+    filter: ResizeFilter,
+) -> DynamicImage {
+    let (width, height) = img.dimensions();
+
+    match max_dimension {
+        Some(max_dimension) if width > max_dimension || height > max_dimension => {
+            let ratio = (max_dimension as f32) / (width.max(height) as f32);
+            let new_width = ((width as f32 * ratio) as u32).max(1);
+            let new_height = ((height as f32 * ratio) as u32).max(1);
+            resize_exact(&img, new_width, new_height, filter)
+        }
+        _ => img,
+    }
+}
+
+/// 8-bit RGB, RGBA and grayscale images, which covers nearly every photo, go
+/// through fast_image_resize when it's built in.
+fn resize_exact(img: &DynamicImage, width: u32, height: u32, filter: ResizeFilter) -> DynamicImage {
+    #[cfg(feature = "simd-resize")]
+    if let Some(resized) = fast_resize(img, width, height, filter) {
+        return resized;
+    }
+    let filter = match filter {
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::Nearest => FilterType::Nearest,
+    };
+    img.resize_exact(width, height, filter)
+}
+
+#[cfg(feature = "simd-resize")]
+fn fast_resize(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> Option<DynamicImage> {
+    use fast_image_resize::images::Image;
+    use fast_image_resize::{
+        FilterType as Filter, IntoImageView, ResizeAlg, ResizeOptions, Resizer,
+    };
+    use image::{GrayImage, RgbImage, RgbaImage};
+
+    if !matches!(
+        img,
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) | DynamicImage::ImageLuma8(_)
+    ) {
+        return None;
+    }
+
+    // Alpha is premultiplied during the resize so transparent edges don't
+    // pick up dark fringes
+    let mut resized = Image::new(width, height, img.pixel_type()?);
+    let algorithm = match filter {
+        ResizeFilter::Lanczos3 => ResizeAlg::Convolution(Filter::Lanczos3),
+        ResizeFilter::CatmullRom => ResizeAlg::Convolution(Filter::CatmullRom),
+        ResizeFilter::Triangle => ResizeAlg::Convolution(Filter::Bilinear),
+        ResizeFilter::Nearest => ResizeAlg::Nearest,
+    };
+    let options = ResizeOptions::new().resize_alg(algorithm);
+    Resizer::new().resize(img, &mut resized, &options).ok()?;
+
+    let buffer = resized.into_vec();
+    match img {
+        DynamicImage::ImageRgb8(_) => {
+            RgbImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8)
+        }
+        DynamicImage::ImageRgba8(_) => {
+            RgbaImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8)
+        }
+        _ => GrayImage::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+    }
+}
+
+/// Encodes at the preset's JPEG quality (85 by default, a good balance of
+/// quality and size). Size-capped presets step the quality down until the
+/// image fits.
+pub fn encode_jpeg_for_preset(img: &DynamicImage, preset: &Preset) -> Result<Vec<u8>> {
+    let rgb_image = img.to_rgb8();
+    let mut quality = preset.image_quality;
+    let mut encoded = encode_jpeg(&rgb_image, quality)?;
+
+    if let Some(target_size) = preset.target_size_bytes() {
+        while encoded.len() as u64 > target_size && quality > MIN_TARGET_JPEG_QUALITY {
+            quality = quality.saturating_sub(10).max(MIN_TARGET_JPEG_QUALITY);
+            encoded = encode_jpeg(&rgb_image, quality)?;
+        }
+    }
+
+    Ok(encoded)
+}
+
+/// Downscales and re-encodes image bytes in their own `format`, so files that
+/// are referenced by name elsewhere stay valid. `None` when the format isn't
+/// handled, the data doesn't decode, or the result isn't smaller.
+pub fn recompress_image_data(data: &[u8], format: ImageFormat, preset: &Preset) -> Option<Vec<u8>> {
+    let img = image::load_from_memory_with_format(data, format).ok()?;
+    let resized = resize_for_preset(img, preset);
+
+    let encoded = match format {
+        ImageFormat::Jpeg => encode_jpeg(&resized.to_rgb8(), preset.image_quality).ok()?,
+        ImageFormat::Png => encode_png(&resized).ok()?,
+        _ => return None,
+    };
+    (encoded.len() < data.len()).then_some(encoded)
+}
+
+/// PNG with the strongest lossless compression.
+pub fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let encoder = image::codecs::png::PngEncoder::new_with_quality(
+        &mut buffer,
+        image::codecs::png::CompressionType::Best,
+        image::codecs::png::FilterType::Adaptive,
+    );
+    img.write_with_encoder(encoder)?;
+    Ok(buffer)
+}
+
+pub fn encode_jpeg(image: &image::RgbImage, quality: u8) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality).encode_image(image)?;
+    Ok(buffer)
+}
@@ -0,0 +1,173 @@
+//! How many jobs run at once. Unless the settings fix a number, that follows
+//! the machine: video encodes already spread over several cores each, so
+//! only a few run side by side, while image, audio and document jobs mostly
+//! use one core and fill the rest. A job only starts while the free memory
+//! covers what it typically needs.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::media::MediaType;
+use crate::settings::Settings;
+
+/// Cores one video encode keeps busy.
+const CORES_PER_VIDEO_JOB: usize = 8;
+/// Cores per image, audio or document job.
+const CORES_PER_LIGHT_JOB: usize = 2;
+/// Rough peak memory of a 1080p encode, or of decoding a large photo.
+const VIDEO_JOB_MEMORY: u64 = 1_500_000_000;
+const LIGHT_JOB_MEMORY: u64 = 300_000_000;
+/// Free memory is re-read at most this often; on Windows that takes a
+/// PowerShell start.
+const MEMORY_MAX_AGE: Duration = Duration::from_secs(5);
+
+static FREE_MEMORY: Mutex<Option<(Instant, Option<u64>)>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobLimits {
+    /// Video and archive jobs at once; archives mostly hold videos.
+    pub video: usize,
+    /// Jobs of any kind at once.
+    pub total: usize,
+    /// Whether jobs also wait for free memory, as they do unless the
+    /// settings fix the concurrency.
+    pub memory_aware: bool,
+}
+
+impl JobLimits {
+    pub fn from_settings(settings: &Settings) -> Self {
+        match settings.concurrency {
+            Some(concurrency) => Self {
+                video: concurrency.max(1),
+                total: concurrency.max(1),
+                memory_aware: false,
+            },
+            None => Self::automatic(),
+        }
+    }
+
+    fn automatic() -> Self {
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        let video = (cores / CORES_PER_VIDEO_JOB).max(1);
+        Self {
+            video,
+            total: video + (cores / CORES_PER_LIGHT_JOB).max(1),
+            memory_aware: true,
+        }
+    }
+
+    /// Whether a job for `input_path` may start next to `running_video`
+    /// video jobs and `running` jobs overall, with `free_memory` bytes free.
+    pub fn allows(
+        &self,
+        input_path: &str,
+        running_video: usize,
+        running: usize,
+        free_memory: Option<u64>,
+    ) -> bool {
+        let video = is_video_job(input_path);
+        if running >= self.total || (video && running_video >= self.video) {
+            return false;
+        }
+        // The first job always starts, or a big file could never run
+        let needed = if video {
+            VIDEO_JOB_MEMORY
+        } else {
+            LIGHT_JOB_MEMORY
+        };
+        running == 0 || !self.memory_aware || free_memory.is_none_or(|free| free >= needed)
+    }
+}
+
+/// Enough workers for whatever the limits may become: automatic ones can
+/// be switched to at any time, as can a higher fixed number (up to this).
+pub fn worker_count(settings: &Settings) -> usize {
+    JobLimits::automatic()
+        .total
+        .max(settings.concurrency.unwrap_or(0))
+}
+
+pub fn is_video_job(input_path: &str) -> bool {
+    matches!(
+        MediaType::from_path(std::path::Path::new(input_path)),
+        Some(MediaType::Video | MediaType::Archive)
+    )
+}
+
+/// Memory that can be handed out without swapping, cached briefly.
+pub fn free_memory() -> Option<u64> {
+    let mut cached = FREE_MEMORY.lock().unwrap_or_else(|e| e.into_inner());
+    match *cached {
+        Some((read_at, free)) if read_at.elapsed() < MEMORY_MAX_AGE => free,
+        _ => {
+            let free = read_free_memory();
+            *cached = Some((Instant::now(), free));
+            free
+        }
+    }
+}
+
+/// `MemAvailable`, which also counts caches the kernel can drop.
+#[cfg(target_os = "linux")]
+fn read_free_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Free, inactive and speculative pages from `vm_stat`, whose header gives
+/// the page size.
+#[cfg(target_os = "macos")]
+fn read_free_memory() -> Option<u64> {
+    let output = std::process::Command::new("vm_stat").output().ok()?;
+    let stats = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stats.lines();
+    let page_size: u64 = lines
+        .next()?
+        .split("page size of ")
+        .nth(1)?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let pages: u64 = lines
+        .filter_map(|line| {
+            let (name, count) = line.split_once(':')?;
+            matches!(name, "Pages free" | "Pages inactive" | "Pages speculative")
+                .then(|| count.trim().trim_end_matches('.').parse::<u64>().ok())?
+        })
+        .sum();
+    Some(pages * page_size)
+}
+
+#[cfg(target_os = "windows")]
+fn read_free_memory() -> Option<u64> {
+    let output = std::process::Command::new("powershell")
+        .args([
+            "-NoProfile",
+            "-Command",
+            "(Get-CimInstance Win32_OperatingSystem).FreePhysicalMemory",
+        ])
+        .output()
+        .ok()?;
+    let kb: u64 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn read_free_memory() -> Option<u64> {
+    None
+}
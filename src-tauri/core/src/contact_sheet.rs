@@ -0,0 +1,632 @@
+//! Contact sheets: one JPEG with a labelled grid of thumbnails, for seeing
+//! at a glance what a folder or a long video contains.
+
+use image::imageops;
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use crate::compressor::{self, CompressionResult};
+use crate::error::{Error, Result};
+use crate::filter_graph::Filter;
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::presets::Preset;
+use crate::probe;
+use crate::settings::Settings;
+
+const COLUMNS: u32 = 4;
+/// Thumbnails are fitted into this box, keeping their aspect ratio.
+const THUMB_WIDTH: u32 = 320;
+const THUMB_HEIGHT: u32 = 240;
+const MARGIN: u32 = 12;
+/// Labels use the built-in 5x7 font at this scale.
+const TEXT_SCALE: u32 = 2;
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT * TEXT_SCALE + 8;
+/// Frames taken from a single video.
+const VIDEO_FRAMES: u32 = 16;
+/// Folders with more files than this get a sheet of the first ones only.
+const MAX_TILES: usize = 200;
+
+const BACKGROUND: Rgb<u8> = Rgb([245, 245, 245]);
+const TEXT_COLOR: Rgb<u8> = Rgb([40, 40, 40]);
+
+struct Tile {
+    image: DynamicImage,
+    label: String,
+}
+
+/// Builds a contact sheet of the images (and, with ffmpeg, videos) directly
+/// in the folder `input`, or of evenly spaced frames of the video `input`,
+/// and saves it as `<name>-contact-sheet.jpg`.
+pub fn create_contact_sheet(
+    ffmpeg_path: Option<&Path>,
+    input: &Path,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+
+    let (tiles, original_size) = if input.is_dir() {
+        folder_tiles(ffmpeg_path, input, ctx)?
+    } else if MediaType::from_path(input) == Some(MediaType::Video) {
+        let ffmpeg_path = ffmpeg_path.ok_or(Error::FfmpegMissing)?;
+        (
+            video_tiles(ffmpeg_path, input, ctx)?,
+            fs::metadata(input)?.len(),
+        )
+    } else {
+        return Err(Error::UnsupportedFormat {
+            path: input.to_string_lossy().to_string(),
+        });
+    };
+
+    if tiles.is_empty() {
+        return Err(Error::InvalidRequest {
+            message: "Nothing to put on a contact sheet".to_string(),
+        });
+    }
+
+    let sheet = render(&tiles);
+
+    let output_dir = match output_path.or_else(|| settings.output_dir.clone()) {
+        Some(dir) => PathBuf::from(dir),
+        None if input.is_dir() => input.join("compressed"),
+        None => input.parent().unwrap_or(Path::new(".")).join("compressed"),
+    };
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| Error::io("Failed to create output directory", e))?;
+
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let output_file = output_dir.join(format!("{}-contact-sheet.jpg", stem));
+    fs::write(
+        &output_file,
+        compressor::encode_jpeg_for_preset(&sheet, preset)?,
+    )?;
+
+    let compressed_size = fs::metadata(&output_file)?.len();
+    let mut result = CompressionResult::new(original_size, compressed_size, &output_file, started);
+    (result.width, result.height) = (Some(sheet.width()), Some(sheet.height()));
+    result.codec = Some("jpeg".to_string());
+    Ok(result)
+}
+
+/// One tile per image or video in `dir`, by file name. Files that fail to
+/// decode are left out rather than failing the whole sheet.
+fn folder_tiles(
+    ffmpeg_path: Option<&Path>,
+    dir: &Path,
+    ctx: &JobContext,
+) -> Result<(Vec<Tile>, u64)> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| Error::io("Failed to read folder", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    let mut tiles = Vec::new();
+    let mut total_size = 0;
+    for path in paths {
+        if tiles.len() == MAX_TILES {
+            break;
+        }
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let image = match (MediaType::from_path(&path), ffmpeg_path) {
+            (Some(MediaType::Image), _) => compressor::open_image(&path).ok(),
+            // A frame a little way in skips fade-ins and black intros
+            (Some(MediaType::Video), Some(ffmpeg_path)) => {
+                extract_frame(ffmpeg_path, &path, 1.0).ok()
+            }
+            _ => None,
+        };
+        let Some(image) = image else {
+            continue;
+        };
+
+        total_size += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        tiles.push(Tile {
+            image: thumbnail(image),
+            label: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        });
+    }
+
+    Ok((tiles, total_size))
+}
+
+/// `VIDEO_FRAMES` frames spread evenly over the video, labelled with their
+/// timestamps.
+fn video_tiles(ffmpeg_path: &Path, input: &Path, ctx: &JobContext) -> Result<Vec<Tile>> {
+    let duration = probe::probe_duration(ffmpeg_path, input)?;
+
+    let mut tiles = Vec::new();
+    for index in 0..VIDEO_FRAMES {
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        // Centered in each slice, so neither the first nor the last frame
+        let time = duration * (index as f64 + 0.5) / VIDEO_FRAMES as f64;
+        if let Ok(image) = extract_frame(ffmpeg_path, input, time) {
+            tiles.push(Tile {
+                image: thumbnail(image),
+                label: format_timestamp(time),
+            });
+        }
+    }
+    Ok(tiles)
+}
+
+fn extract_frame(ffmpeg_path: &Path, input: &Path, time: f64) -> Result<DynamicImage> {
+    let output = Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .args(["-ss", &format!("{:.3}", time)])
+        .arg("-i")
+        .arg(input)
+        .args([
+            "-frames:v",
+            "1",
+            "-vf",
+            &Filter::Fit {
+                width: THUMB_WIDTH,
+                height: THUMB_HEIGHT,
+            }
+            .render(),
+            "-f",
+            "image2pipe",
+            "-c:v",
+            "png",
+            "-",
+        ])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::FfmpegMissing,
+            _ => Error::io("Failed to run ffmpeg", e),
+        })?;
+
+    Ok(image::load_from_memory(&output.stdout)?)
+}
+
+fn thumbnail(image: DynamicImage) -> DynamicImage {
+    image.thumbnail(THUMB_WIDTH, THUMB_HEIGHT)
+}
+
+fn format_timestamp(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn render(tiles: &[Tile]) -> DynamicImage {
+    let columns = COLUMNS.min(tiles.len() as u32);
+    let rows = (tiles.len() as u32).div_ceil(COLUMNS);
+    let cell_height = THUMB_HEIGHT + LABEL_HEIGHT;
+
+    let mut sheet = RgbImage::from_pixel(
+        MARGIN + columns * (THUMB_WIDTH + MARGIN),
+        MARGIN + rows * (cell_height + MARGIN),
+        BACKGROUND,
+    );
+
+    for (index, tile) in tiles.iter().enumerate() {
+        let x = MARGIN + (index as u32 % COLUMNS) * (THUMB_WIDTH + MARGIN);
+        let y = MARGIN + (index as u32 / COLUMNS) * (cell_height + MARGIN);
+
+        let (width, height) = tile.image.dimensions();
+        imageops::overlay(
+            &mut sheet,
+            &tile.image.to_rgb8(),
+            (x + (THUMB_WIDTH - width) / 2) as i64,
+            (y + (THUMB_HEIGHT - height) / 2) as i64,
+        );
+        draw_label(&mut sheet, &tile.label, x, y + THUMB_HEIGHT + 4);
+    }
+
+    DynamicImage::ImageRgb8(sheet)
+}
+
+/// Draws `text` in upper case, cut short with `..` if it doesn't fit under
+/// a thumbnail. Characters without a glyph are drawn as `?`.
+fn draw_label(sheet: &mut RgbImage, text: &str, x: u32, y: u32) {
+    let advance = (GLYPH_WIDTH + 1) * TEXT_SCALE;
+    let max_chars = (THUMB_WIDTH / advance) as usize;
+
+    let mut chars: Vec<char> = text.to_uppercase().chars().collect();
+    if chars.len() > max_chars {
+        chars.truncate(max_chars - 2);
+        chars.extend(['.', '.']);
+    }
+
+    for (index, c) in chars.into_iter().enumerate() {
+        let rows = glyph(c);
+        let left = x + index as u32 * advance;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..TEXT_SCALE {
+                    for dx in 0..TEXT_SCALE {
+                        sheet.put_pixel(
+                            left + column * TEXT_SCALE + dx,
+                            y + row as u32 * TEXT_SCALE + dy,
+                            TEXT_COLOR,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+fn glyph(c: char) -> [u8; 7] {
+    let find = |c: char| GLYPHS.iter().find(|(glyph, _)| *glyph == c);
+    find(c)
+        .or_else(|| find('?'))
+        .map(|(_, rows)| *rows)
+        .unwrap_or_default()
+}
+
+/// 5x7 bitmaps, one row per byte with the leftmost pixel in bit 4. Enough
+/// for file names and timestamps.
+const GLYPHS: &[(char, [u8; 7])] = &[
+    (
+        'A',
+        [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'B',
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+    ),
+    (
+        'C',
+        [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        'D',
+        [
+            0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+        ],
+    ),
+    (
+        'E',
+        [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+    ),
+    (
+        'F',
+        [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+    ),
+    (
+        'G',
+        [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+    ),
+    (
+        'H',
+        [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'I',
+        [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+    ),
+    (
+        'J',
+        [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+    ),
+    (
+        'K',
+        [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+    ),
+    (
+        'L',
+        [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+    ),
+    (
+        'M',
+        [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'N',
+        [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'O',
+        [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        'P',
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+    ),
+    (
+        'Q',
+        [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+    ),
+    (
+        'R',
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+    ),
+    (
+        'S',
+        [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+    ),
+    (
+        'T',
+        [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+    ),
+    (
+        'U',
+        [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        'V',
+        [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+    ),
+    (
+        'W',
+        [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+    ),
+    (
+        'X',
+        [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'Y',
+        [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+    ),
+    (
+        'Z',
+        [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+    ),
+    (
+        '0',
+        [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '1',
+        [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+    ),
+    (
+        '2',
+        [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+    ),
+    (
+        '3',
+        [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '4',
+        [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+    ),
+    (
+        '5',
+        [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '6',
+        [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '7',
+        [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+    ),
+    (
+        '8',
+        [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '9',
+        [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+    ),
+    (
+        ' ',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '.',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+    ),
+    (
+        ',',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+        ],
+    ),
+    (
+        '-',
+        [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '_',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111,
+        ],
+    ),
+    (
+        ':',
+        [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ],
+    ),
+    (
+        '(',
+        [
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ],
+    ),
+    (
+        ')',
+        [
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ],
+    ),
+    (
+        '[',
+        [
+            0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110,
+        ],
+    ),
+    (
+        ']',
+        [
+            0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110,
+        ],
+    ),
+    (
+        '+',
+        [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+    ),
+    (
+        '&',
+        [
+            0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101,
+        ],
+    ),
+    (
+        '\'',
+        [
+            0b01100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '#',
+        [
+            0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010,
+        ],
+    ),
+    (
+        '!',
+        [
+            0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100,
+        ],
+    ),
+    (
+        '=',
+        [
+            0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '?',
+        [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100,
+        ],
+    ),
+    (
+        '/',
+        [
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ],
+    ),
+    (
+        '@',
+        [
+            0b01110, 0b10001, 0b00001, 0b01101, 0b10101, 0b10101, 0b01110,
+        ],
+    ),
+    (
+        '%',
+        [
+            0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011,
+        ],
+    ),
+];
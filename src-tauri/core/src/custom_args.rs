@@ -0,0 +1,400 @@
+//! Extra ffmpeg options for presets, for tuning the app has no setting for.
+//! They are output options only, from an allowlist: nothing that reads or
+//! writes other files, changes inputs or outputs, or runs filters, since
+//! presets get shared and imported.
+
+use crate::error::{Error, Result};
+
+/// Flags accepted, without stream specifiers (`-b:a` counts as `-b`), and
+/// whether each takes a value.
+const ALLOWED_FLAGS: &[(&str, bool)] = &[
+    // Rate control and GOP
+    ("-b", true),
+    ("-crf", true),
+    ("-cq", true),
+    ("-qp", true),
+    ("-q", true),
+    ("-qmin", true),
+    ("-qmax", true),
+    ("-maxrate", true),
+    ("-minrate", true),
+    ("-bufsize", true),
+    ("-rc", true),
+    ("-g", true),
+    ("-keyint_min", true),
+    ("-bf", true),
+    ("-refs", true),
+    ("-sc_threshold", true),
+    ("-force_key_frames", true),
+    // Encoder tuning
+    ("-preset", true),
+    ("-tune", true),
+    ("-profile", true),
+    ("-level", true),
+    ("-x264-params", true),
+    ("-x265-params", true),
+    ("-svtav1-params", true),
+    ("-aq-mode", true),
+    ("-aq-strength", true),
+    ("-psy-rd", true),
+    ("-deblock", true),
+    ("-rc-lookahead", true),
+    ("-spatial-aq", true),
+    ("-temporal-aq", true),
+    ("-multipass", true),
+    ("-b_ref_mode", true),
+    ("-cpu-used", true),
+    ("-deadline", true),
+    ("-row-mt", true),
+    ("-tile-columns", true),
+    ("-tile-rows", true),
+    ("-lag-in-frames", true),
+    ("-auto-alt-ref", true),
+    ("-threads", true),
+    // Picture and sound
+    ("-pix_fmt", true),
+    ("-r", true),
+    ("-fps_mode", true),
+    ("-colorspace", true),
+    ("-color_primaries", true),
+    ("-color_trc", true),
+    ("-color_range", true),
+    ("-ac", true),
+    ("-ar", true),
+    ("-compression_level", true),
+    // Container
+    ("-tag", true),
+    ("-movflags", true),
+    ("-metadata", true),
+    ("-map_metadata", true),
+    ("-map_chapters", true),
+    ("-max_muxing_queue_size", true),
+    ("-id3v2_version", true),
+    ("-sn", false),
+    ("-dn", false),
+];
+
+/// Keys `-x264-params` may set: rate control, GOP, motion search and
+/// color tags. Several options name files to read or write (`stats`,
+/// `qpfile`, `cqmfile`, ...), and new ones keep being added, so anything not
+/// listed is refused.
+const X264_PARAMS: &[&str] = &[
+    "crf",
+    "qp",
+    "qpmin",
+    "qpmax",
+    "qpstep",
+    "qcomp",
+    "ipratio",
+    "pbratio",
+    "chroma-qp-offset",
+    "vbv-maxrate",
+    "vbv-bufsize",
+    "vbv-init",
+    "nal-hrd",
+    "keyint",
+    "min-keyint",
+    "scenecut",
+    "open-gop",
+    "intra-refresh",
+    "bframes",
+    "b-adapt",
+    "b-bias",
+    "b-pyramid",
+    "ref",
+    "deblock",
+    "no-deblock",
+    "cabac",
+    "no-cabac",
+    "aq-mode",
+    "aq-strength",
+    "psy",
+    "no-psy",
+    "psy-rd",
+    "mbtree",
+    "no-mbtree",
+    "rc-lookahead",
+    "me",
+    "merange",
+    "subme",
+    "trellis",
+    "partitions",
+    "direct",
+    "weightb",
+    "no-weightb",
+    "weightp",
+    "8x8dct",
+    "no-8x8dct",
+    "mixed-refs",
+    "no-mixed-refs",
+    "fast-pskip",
+    "no-fast-pskip",
+    "dct-decimate",
+    "no-dct-decimate",
+    "nr",
+    "deadzone-inter",
+    "deadzone-intra",
+    "cqm",
+    "slices",
+    "threads",
+    "lookahead-threads",
+    "sliced-threads",
+    "colorprim",
+    "transfer",
+    "colormatrix",
+    "range",
+    "chromaloc",
+    "aud",
+    "force-cfr",
+    "bluray-compat",
+    "log-level",
+];
+
+/// Keys `-x265-params` may set, on the same terms as `X264_PARAMS`; x265
+/// adds `analysis-reuse-file`, `dolby-vision-rpu` and more that take paths.
+const X265_PARAMS: &[&str] = &[
+    "crf",
+    "qp",
+    "qpmin",
+    "qpmax",
+    "qcomp",
+    "ipratio",
+    "pbratio",
+    "cbqpoffs",
+    "crqpoffs",
+    "vbv-maxrate",
+    "vbv-bufsize",
+    "vbv-init",
+    "hrd",
+    "keyint",
+    "min-keyint",
+    "scenecut",
+    "hist-scenecut",
+    "open-gop",
+    "no-open-gop",
+    "bframes",
+    "b-adapt",
+    "b-pyramid",
+    "b-intra",
+    "ref",
+    "limit-refs",
+    "deblock",
+    "no-deblock",
+    "sao",
+    "no-sao",
+    "selective-sao",
+    "aq-mode",
+    "aq-strength",
+    "psy-rd",
+    "psy-rdoq",
+    "rdoq-level",
+    "cutree",
+    "no-cutree",
+    "rc-lookahead",
+    "me",
+    "merange",
+    "subme",
+    "rd",
+    "ctu",
+    "min-cu-size",
+    "max-tu-size",
+    "tu-intra-depth",
+    "tu-inter-depth",
+    "limit-modes",
+    "rect",
+    "no-rect",
+    "amp",
+    "no-amp",
+    "early-skip",
+    "rskip",
+    "fast-intra",
+    "tskip",
+    "weightp",
+    "weightb",
+    "strong-intra-smoothing",
+    "no-strong-intra-smoothing",
+    "nr-intra",
+    "nr-inter",
+    "lossless",
+    "pools",
+    "frame-threads",
+    "wpp",
+    "pmode",
+    "pme",
+    "colorprim",
+    "transfer",
+    "colormatrix",
+    "range",
+    "chromaloc",
+    "hdr10",
+    "hdr10-opt",
+    "master-display",
+    "max-cll",
+    "repeat-headers",
+    "aud",
+    "level-idc",
+    "high-tier",
+    "info",
+    "no-info",
+    "log-level",
+];
+
+/// Keys `-svtav1-params` may set, on the same terms as `X264_PARAMS`.
+const SVTAV1_PARAMS: &[&str] = &[
+    "crf",
+    "qp",
+    "min-qp",
+    "max-qp",
+    "rc",
+    "tbr",
+    "mbr",
+    "buf-sz",
+    "keyint",
+    "irefresh-type",
+    "scd",
+    "lookahead",
+    "hierarchical-levels",
+    "pred-struct",
+    "tune",
+    "aq-mode",
+    "enable-variance-boost",
+    "variance-boost-strength",
+    "variance-octile",
+    "enable-qm",
+    "qm-min",
+    "qm-max",
+    "sharpness",
+    "enable-tf",
+    "tf-strength",
+    "enable-overlays",
+    "scm",
+    "film-grain",
+    "film-grain-denoise",
+    "fast-decode",
+    "enable-cdef",
+    "enable-restoration",
+    "enable-dlf",
+    "tile-columns",
+    "tile-rows",
+    "lp",
+    "color-primaries",
+    "transfer-characteristics",
+    "matrix-coefficients",
+    "color-range",
+    "chroma-sample-position",
+    "mastering-display",
+    "content-light",
+];
+
+/// Checks `args` as flag/value pairs (or lone flags) against the allowlist.
+pub fn validate(args: &[String]) -> Result<()> {
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let base = flag.split(':').next().unwrap_or(flag);
+        let Some(&(_, takes_value)) = ALLOWED_FLAGS.iter().find(|(name, _)| *name == base) else {
+            return Err(Error::invalid_settings(format!(
+                "ffmpeg option {} isn't allowed in presets",
+                flag
+            )));
+        };
+        if !takes_value {
+            continue;
+        }
+        let Some(value) = args.next() else {
+            return Err(Error::invalid_settings(format!(
+                "ffmpeg option {} needs a value",
+                flag
+            )));
+        };
+        let allowed_params = match base {
+            "-x264-params" => X264_PARAMS,
+            "-x265-params" => X265_PARAMS,
+            "-svtav1-params" => SVTAV1_PARAMS,
+            _ => continue,
+        };
+        if let Some(key) = value
+            .split(':')
+            .filter(|param| !param.is_empty())
+            .filter_map(|param| param.split('=').next())
+            .find(|key| !allowed_params.contains(key))
+        {
+            return Err(Error::invalid_settings(format!(
+                "{} key {} isn't allowed in presets",
+                flag, key
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_allowed_flags() {
+        assert!(validate(&[]).is_ok());
+        assert!(validate(&args(&["-crf", "20", "-b:a", "96k", "-sn"])).is_ok());
+        assert!(validate(&args(&["-tag:v", "hvc1", "-movflags", "+faststart"])).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert!(validate(&args(&["-i", "other.mp4"])).is_err());
+        assert!(validate(&args(&["-vf", "scale=640:-2"])).is_err());
+        assert!(validate(&args(&["-filter_complex", "[0:v]null"])).is_err());
+        assert!(validate(&args(&["-passlogfile", "/tmp/log"])).is_err());
+        assert!(validate(&args(&["-y"])).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        assert!(validate(&args(&["-crf"])).is_err());
+        assert!(validate(&args(&["-b:v", "2M", "-maxrate"])).is_err());
+    }
+
+    #[test]
+    fn lone_flags_take_no_value() {
+        // `-i` would otherwise be swallowed as the value of `-sn`
+        assert!(validate(&args(&["-sn", "-i", "other.mp4"])).is_err());
+    }
+
+    #[test]
+    fn accepts_allowed_encoder_params() {
+        assert!(validate(&args(&["-x264-params", "aq-mode=3:no-mbtree=1:ref=4"])).is_ok());
+        assert!(validate(&args(&["-x265-params", "sao=0:psy-rd=2.0"])).is_ok());
+        assert!(validate(&args(&["-svtav1-params", "tune=0:enable-overlays=1"])).is_ok());
+        assert!(validate(&args(&["-x264-params", "no-mbtree:"])).is_ok());
+    }
+
+    #[test]
+    fn rejects_encoder_params_naming_files() {
+        for (flag, params) in [
+            ("-x264-params", "stats=/tmp/x264.log"),
+            ("-x264-params", "aq-mode=3:cqmfile=/etc/passwd"),
+            ("-x264-params", "qpfile=frames.txt"),
+            ("-x265-params", "analysis-reuse-file=/tmp/analysis"),
+            ("-x265-params", "dolby-vision-rpu=rpu.bin"),
+            ("-x265-params", "csv=/tmp/x265.csv"),
+            ("-svtav1-params", "fgs-table=grain.tbl"),
+        ] {
+            assert!(
+                validate(&args(&[flag, params])).is_err(),
+                "{} {}",
+                flag,
+                params
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_encoder_params() {
+        assert!(validate(&args(&["-x264-params", "some-future-option=1"])).is_err());
+        // Each encoder has its own keys
+        assert!(validate(&args(&["-x264-params", "sao=0"])).is_err());
+    }
+}
@@ -0,0 +1,441 @@
+//! Delivers finished outputs to a NAS or web server over SFTP or FTP.
+//!
+//! SFTP goes through the system's OpenSSH `sftp`, which ships with macOS,
+//! Linux and Windows 10+, so it logs in with the user's ssh keys and agent
+//! and checks `known_hosts` as usual. FTP is spoken directly.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::compressor::CompressionResult;
+use crate::error::{Error, Result};
+use crate::settings::{DestinationProtocol, DestinationSettings};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest a stalled transfer is waited on before the attempt fails.
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+/// Wait before the first retry, doubled for each one after.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// FTP progress is reported about this often.
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Progress of one file's upload, sent to listeners as it goes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadProgress {
+    pub file: String,
+    /// Where the file is going, e.g. `sftp://nas/media/clip.mp4`.
+    pub destination: String,
+    /// Bytes sent so far. FTP reports it every `PROGRESS_STEP`; SFTP only
+    /// reports the start (0) and the end, since `sftp` shows no progress
+    /// without a terminal.
+    pub sent: u64,
+    pub total: u64,
+    /// 1 for the first try.
+    pub attempt: u32,
+    pub done: bool,
+}
+
+type Listener = Arc<dyn Fn(&UploadProgress) + Send + Sync>;
+
+static LISTENERS: Mutex<Vec<Listener>> = Mutex::new(Vec::new());
+
+/// Registers a callback for upload progress. Listeners run on the uploading
+/// thread, so they should return quickly.
+pub fn subscribe(listener: impl Fn(&UploadProgress) + Send + Sync + 'static) {
+    LISTENERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Arc::new(listener));
+}
+
+fn notify(progress: &UploadProgress) {
+    let listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for listener in listeners {
+        listener(progress);
+    }
+}
+
+/// When a destination is set up, uploads every file `result` wrote into its
+/// remote directory, retrying failed files. The main output's remote
+/// location becomes `remote_url` unless an earlier upload already set one.
+pub fn upload_outputs(
+    mut result: CompressionResult,
+    settings: &DestinationSettings,
+) -> Result<CompressionResult> {
+    if !settings.enabled {
+        return Ok(result);
+    }
+    if settings.host.trim().is_empty() {
+        return Err(Error::invalid_settings(
+            "The upload destination is on but has no host",
+        ));
+    }
+
+    let mut urls = Vec::new();
+    for file in result.output_files() {
+        urls.push(upload_with_retries(Path::new(file), settings)?);
+    }
+    if result.remote_url.is_none() {
+        result.remote_url = urls.into_iter().next();
+    }
+    Ok(result)
+}
+
+fn upload_with_retries(file: &Path, settings: &DestinationSettings) -> Result<String> {
+    let name = file
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let remote_dir = settings.remote_dir.trim().trim_end_matches('/');
+    let remote_path = if remote_dir.is_empty() {
+        name
+    } else {
+        format!("{}/{}", remote_dir, name)
+    };
+    let url = format!(
+        "{}://{}/{}",
+        settings.protocol.scheme(),
+        authority(settings),
+        remote_path.trim_start_matches('/')
+    );
+    let total = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+
+    let mut attempt = 1;
+    loop {
+        let report = |sent: u64, done: bool| {
+            notify(&UploadProgress {
+                file: file.to_string_lossy().to_string(),
+                destination: url.clone(),
+                sent,
+                total,
+                attempt,
+                done,
+            })
+        };
+        report(0, false);
+
+        let uploaded = match settings.protocol {
+            DestinationProtocol::Sftp => sftp_put(file, &remote_path, settings),
+            DestinationProtocol::Ftp => ftp_put(file, &remote_path, settings, &report),
+        };
+        match uploaded {
+            Ok(()) => {
+                report(total, true);
+                return Ok(url);
+            }
+            Err(e @ (Error::Network { .. } | Error::Io { .. })) if attempt <= settings.retries => {
+                warn!("Upload of {} failed, retrying: {}", file.display(), e);
+                thread::sleep(RETRY_DELAY * 2u32.pow(attempt - 1));
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(Error::Network {
+                    message: format!("Failed to upload {} to {}: {}", file.display(), url, e),
+                })
+            }
+        }
+    }
+}
+
+fn authority(settings: &DestinationSettings) -> String {
+    let host = settings.host.trim();
+    match settings.port {
+        Some(port) if port != settings.protocol.default_port() => format!("{}:{}", host, port),
+        _ => host.to_string(),
+    }
+}
+
+/// Uploads with `sftp -b -`, creating missing directories on the way. The
+/// leading `-` makes sftp carry on when a directory already exists. There
+/// is no progress in between: sftp only prints it to a terminal.
+fn sftp_put(file: &Path, remote_path: &str, settings: &DestinationSettings) -> Result<()> {
+    let mut script = String::new();
+    let mut dir = String::new();
+    let parents: Vec<&str> = remote_path.split('/').collect();
+    for component in &parents[..parents.len() - 1] {
+        dir.push_str(component);
+        if !component.is_empty() {
+            script.push_str(&format!("-mkdir {}\n", sftp_quote(&dir)));
+        }
+        dir.push('/');
+    }
+    script.push_str(&format!(
+        "put {} {}\n",
+        sftp_quote(&file.to_string_lossy()),
+        sftp_quote(remote_path)
+    ));
+
+    let mut command = Command::new("sftp");
+    command.args([
+        "-b",
+        "-",
+        // Never stop to ask for a password or host confirmation nobody sees
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        &format!("ConnectTimeout={}", CONNECT_TIMEOUT.as_secs()),
+        "-o",
+        &format!("ServerAliveInterval={}", IO_TIMEOUT.as_secs() / 4),
+        "-o",
+        "ServerAliveCountMax=4",
+    ]);
+    if let Some(port) = settings.port {
+        command.args(["-P", &port.to_string()]);
+    }
+    if let Some(key_path) = settings.key_path.as_deref().filter(|path| !path.is_empty()) {
+        command.args(["-i", key_path]);
+    }
+    let target = match settings.username.trim() {
+        "" => settings.host.trim().to_string(),
+        username => format!("{}@{}", username, settings.host.trim()),
+    };
+    command.arg(target);
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::platform("sftp was not found; install OpenSSH"),
+            _ => Error::io("Failed to start sftp", e),
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(script.as_bytes())
+            .map_err(|e| Error::io("Failed to send commands to sftp", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::io("sftp failed", e))?;
+    if !output.status.success() {
+        // The cause, e.g. "Connection refused", comes before sftp's own
+        // "Connection closed"
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = stderr
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        return Err(Error::Network {
+            message: if lines.is_empty() {
+                format!("sftp failed with {}", output.status)
+            } else {
+                lines.join("; ")
+            },
+        });
+    }
+    Ok(())
+}
+
+/// Quotes an argument for an sftp batch file.
+fn sftp_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn ftp_put(
+    file: &Path,
+    remote_path: &str,
+    settings: &DestinationSettings,
+    report: &dyn Fn(u64, bool),
+) -> Result<()> {
+    let mut contents = File::open(file).map_err(|e| Error::io("Failed to read output", e))?;
+    let mut ftp = Ftp::connect(settings.host.trim(), settings.port.unwrap_or(21))?;
+
+    let username = match settings.username.trim() {
+        "" => "anonymous",
+        username => username,
+    };
+    let (code, reply) = ftp.command(&format!("USER {}", username))?;
+    if code == 331 {
+        let password = settings.password.as_deref().unwrap_or_default();
+        ftp.expect(&format!("PASS {}", password), &[230, 202])?;
+    } else if code != 230 {
+        return Err(ftp_error("Login", code, &reply));
+    }
+    ftp.expect("TYPE I", &[200])?;
+
+    // Fails harmlessly for directories that already exist
+    let mut dir = String::new();
+    let parents: Vec<&str> = remote_path.split('/').collect();
+    for component in &parents[..parents.len() - 1] {
+        dir.push_str(component);
+        if !component.is_empty() {
+            ftp.command(&format!("MKD {}", dir))?;
+        }
+        dir.push('/');
+    }
+
+    let mut data = ftp.open_data()?;
+    ftp.expect(&format!("STOR {}", remote_path), &[125, 150])?;
+
+    let mut buffer = vec![0; 64 * 1024];
+    let mut sent = 0;
+    let mut reported = 0;
+    loop {
+        let read = contents
+            .read(&mut buffer)
+            .map_err(|e| Error::io("Failed to read output", e))?;
+        if read == 0 {
+            break;
+        }
+        data.write_all(&buffer[..read])
+            .map_err(|e| Error::io("Upload interrupted", e))?;
+        sent += read as u64;
+        if sent - reported >= PROGRESS_STEP {
+            report(sent, false);
+            reported = sent;
+        }
+    }
+    // Closing the data connection is what tells the server the file ended
+    drop(data);
+    ftp.read_reply_expecting("STOR", &[226, 250])?;
+    ftp.command("QUIT").ok();
+    Ok(())
+}
+
+/// Just enough of an FTP client to log in and store files, using passive
+/// mode so it works from behind NAT.
+struct Ftp {
+    control: BufReader<TcpStream>,
+}
+
+impl Ftp {
+    fn connect(host: &str, port: u16) -> Result<Self> {
+        let address = (host, port)
+            .to_socket_addrs()
+            .map_err(|e| Error::io("Failed to resolve FTP host", e))?
+            .next()
+            .ok_or_else(|| Error::Network {
+                message: format!("Could not resolve {}", host),
+            })?;
+        let stream = connect(&address)?;
+        let mut ftp = Self {
+            control: BufReader::new(stream),
+        };
+        ftp.read_reply_expecting("Connecting", &[220])?;
+        Ok(ftp)
+    }
+
+    fn command(&mut self, command: &str) -> Result<(u16, String)> {
+        self.control
+            .get_mut()
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(|e| Error::io("FTP connection lost", e))?;
+        self.read_reply()
+    }
+
+    fn expect(&mut self, command: &str, codes: &[u16]) -> Result<String> {
+        let (code, text) = self.command(command)?;
+        if !codes.contains(&code) {
+            // Don't echo the password back in the error
+            let verb = command.split(' ').next().unwrap_or(command);
+            return Err(ftp_error(verb, code, &text));
+        }
+        Ok(text)
+    }
+
+    fn read_reply_expecting(&mut self, context: &str, codes: &[u16]) -> Result<String> {
+        let (code, text) = self.read_reply()?;
+        if !codes.contains(&code) {
+            return Err(ftp_error(context, code, &text));
+        }
+        Ok(text)
+    }
+
+    /// Reads one reply, including every line of a multi-line `123-` one.
+    fn read_reply(&mut self) -> Result<(u16, String)> {
+        let mut text = String::new();
+        let mut line = String::new();
+        // Code of a multi-line reply, which ends at a `123 ` line
+        let mut multi_line: Option<String> = None;
+        loop {
+            line.clear();
+            let read = self
+                .control
+                .read_line(&mut line)
+                .map_err(|e| Error::io("FTP connection lost", e))?;
+            if read == 0 {
+                return Err(Error::Network {
+                    message: "The FTP server closed the connection".to_string(),
+                });
+            }
+            text.push_str(&line);
+
+            let code = line.get(..3).unwrap_or_default();
+            let separator = line.as_bytes().get(3).copied();
+            match &multi_line {
+                None if separator == Some(b'-') => multi_line = Some(code.to_string()),
+                Some(first) if first != code || separator != Some(b' ') => {}
+                _ => {
+                    let code = code.parse().map_err(|_| Error::Network {
+                        message: format!("Unexpected FTP reply: {}", line.trim_end()),
+                    })?;
+                    return Ok((code, text.trim_end().to_string()));
+                }
+            }
+        }
+    }
+
+    /// Opens a passive data connection, EPSV first and PASV for older
+    /// servers. The control connection's address is used rather than the one
+    /// PASV reports, which is often a private address behind NAT.
+    fn open_data(&mut self) -> Result<TcpStream> {
+        let peer = self
+            .control
+            .get_ref()
+            .peer_addr()
+            .map_err(|e| Error::io("FTP connection lost", e))?;
+
+        let (code, text) = self.command("EPSV")?;
+        let port = if code == 229 {
+            text.split('|').nth(3).and_then(|port| port.parse().ok())
+        } else {
+            let text = self.expect("PASV", &[227])?;
+            let numbers: Vec<u16> = text
+                .split(['(', ')'])
+                .nth(1)
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|number| number.trim().parse().ok())
+                .collect();
+            (numbers.len() == 6).then(|| numbers[4] * 256 + numbers[5])
+        };
+        let port: u16 = port.ok_or_else(|| Error::Network {
+            message: format!("Unexpected passive mode reply: {}", text),
+        })?;
+
+        let mut address = peer;
+        address.set_port(port);
+        connect(&address)
+    }
+}
+
+fn connect(address: &std::net::SocketAddr) -> Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(address, CONNECT_TIMEOUT)
+        .map_err(|e| Error::io("Failed to connect to FTP server", e))?;
+    stream.set_read_timeout(Some(IO_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(IO_TIMEOUT)).ok();
+    Ok(stream)
+}
+
+/// FTP replies in the 500s are permanent, so retrying won't help; 400s are
+/// worth another try.
+fn ftp_error(context: &str, code: u16, text: &str) -> Error {
+    let message = format!("{} failed: {}", context, text.trim());
+    if (500..600).contains(&code) {
+        Error::InvalidRequest { message }
+    } else {
+        Error::Network { message }
+    }
+}
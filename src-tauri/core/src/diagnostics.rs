@@ -0,0 +1,182 @@
+//! Single-file bundle of everything useful for a bug report: recent logs,
+//! what the installed ffmpeg can do, the settings, and the last few jobs.
+
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::history::HistoryStore;
+use crate::paths;
+use crate::settings::SettingsManager;
+
+/// Most recent per-job ffmpeg logs to include.
+const JOB_LOG_LIMIT: usize = 20;
+const JOB_RECORD_LIMIT: u32 = 50;
+const REDACTED: &str = "<redacted>";
+
+/// Writes the bundle to `path` as a zip archive.
+pub fn export(path: &Path) -> Result<()> {
+    let file = File::create(path).map_err(|e| Error::io("Failed to create diagnostics file", e))?;
+    let mut zip = ZipWriter::new(file);
+
+    add(&mut zip, "report.txt", system_report().as_bytes())?;
+    add(&mut zip, "settings.json", settings_json().as_bytes())?;
+    add(&mut zip, "jobs.json", jobs_json().as_bytes())?;
+
+    for log in app_logs().into_iter().chain(recent_job_logs()) {
+        // Logs are optional; skip any that vanished or can't be read
+        let Ok(contents) = fs::read(&log) else {
+            continue;
+        };
+        let Ok(relative) = log.strip_prefix(paths::logs_dir()) else {
+            continue;
+        };
+        let name = Path::new("logs").join(relative);
+        add(
+            &mut zip,
+            &name.to_string_lossy().replace('\\', "/"),
+            &contents,
+        )?;
+    }
+
+    zip.finish().map_err(zip_error)?;
+    Ok(())
+}
+
+fn add(zip: &mut ZipWriter<File>, name: &str, contents: &[u8]) -> Result<()> {
+    zip.start_file(name, SimpleFileOptions::default())
+        .map_err(zip_error)?;
+    zip.write_all(contents)
+        .map_err(|e| Error::io("Failed to write diagnostics file", e))
+}
+
+fn zip_error(error: zip::result::ZipError) -> Error {
+    match error {
+        zip::result::ZipError::Io(error) => Error::io("Failed to write diagnostics file", error),
+        error => Error::Io {
+            message: format!("Failed to write diagnostics file: {}", error),
+        },
+    }
+}
+
+fn system_report() -> String {
+    let mut report = format!(
+        "Media Compressor {}\nOS: {} ({})\n\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+
+    match FFmpegManager::new().installed_ffmpeg_path() {
+        Some(ffmpeg_path) => {
+            for args in [
+                &["-version"][..],
+                &["-hide_banner", "-encoders"],
+                &["-hide_banner", "-hwaccels"],
+            ] {
+                report.push_str(&format!("$ ffmpeg {}\n", args.join(" ")));
+                match Command::new(&ffmpeg_path).args(args).output() {
+                    Ok(output) => report.push_str(&String::from_utf8_lossy(&output.stdout)),
+                    Err(e) => report.push_str(&format!("Failed to run ffmpeg: {}\n", e)),
+                }
+                report.push('\n');
+            }
+        }
+        None => report.push_str("ffmpeg: not installed\n"),
+    }
+
+    report
+}
+
+/// Settings with secrets removed and the home directory replaced by `~`.
+fn settings_json() -> String {
+    let mut settings = SettingsManager::new().load();
+    if !settings.http_api.token.is_empty() {
+        settings.http_api.token = REDACTED.to_string();
+    }
+    if settings
+        .webhook
+        .url
+        .as_ref()
+        .is_some_and(|url| !url.is_empty())
+    {
+        settings.webhook.url = Some(REDACTED.to_string());
+    }
+
+    if settings.batch_zip.password.is_some() {
+        settings.batch_zip.password = Some(REDACTED.to_string());
+    }
+    if settings.encryption.passphrase.is_some() {
+        settings.encryption.passphrase = Some(REDACTED.to_string());
+    }
+    if !settings.upload.secret_access_key.is_empty() {
+        settings.upload.secret_access_key = REDACTED.to_string();
+    }
+    if settings.destination.password.is_some() {
+        settings.destination.password = Some(REDACTED.to_string());
+    }
+
+    let mut value = serde_json::to_value(&settings).unwrap_or(Value::Null);
+    anonymize_paths(&mut value);
+    serde_json::to_string_pretty(&value).unwrap_or_default()
+}
+
+fn jobs_json() -> String {
+    let records = HistoryStore::open()
+        .and_then(|store| store.recent(JOB_RECORD_LIMIT))
+        .unwrap_or_default();
+
+    let mut value = serde_json::to_value(&records).unwrap_or(Value::Null);
+    anonymize_paths(&mut value);
+    serde_json::to_string_pretty(&value).unwrap_or_default()
+}
+
+fn anonymize_paths(value: &mut Value) {
+    let Some(home) = dirs::home_dir().map(|home| home.to_string_lossy().to_string()) else {
+        return;
+    };
+    if home.is_empty() {
+        return;
+    }
+
+    match value {
+        Value::String(text) => *text = text.replace(&home, "~"),
+        Value::Array(items) => items.iter_mut().for_each(anonymize_paths),
+        Value::Object(fields) => fields.values_mut().for_each(anonymize_paths),
+        _ => {}
+    }
+}
+
+/// The app log and its rotated predecessors.
+fn app_logs() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(paths::logs_dir()) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    logs.sort();
+    logs
+}
+
+fn recent_job_logs() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(paths::job_logs_dir()) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+
+    // Names start with a timestamp, so the last ones are the newest
+    logs.sort();
+    let skip = logs.len().saturating_sub(JOB_LOG_LIMIT);
+    logs.split_off(skip)
+}
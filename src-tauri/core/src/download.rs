@@ -0,0 +1,213 @@
+//! Compressing media shared through direct links: the file is downloaded
+//! into the staging area, compressed like a local file, then removed.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::compressor::{self, CompressionResult};
+use crate::error::{Error, Result};
+use crate::history::HistoryStore;
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::paths;
+use crate::presets::Preset;
+use crate::settings::Settings;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UrlOptions {
+    pub preset: Option<String>,
+    /// Where the output goes; the output directory in settings, then
+    /// `Downloads/compressed`, when not given.
+    pub output_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    pub downloaded: u64,
+    /// From `Content-Length`; `None` when the server doesn't say.
+    pub total: Option<u64>,
+}
+
+/// Downloads `url`, compresses it, and deletes the download whatever the
+/// outcome. The history records the URL as the job's input.
+pub async fn compress_from_url(
+    url: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+    on_progress: impl Fn(&DownloadProgress),
+) -> Result<CompressionResult> {
+    let download = Download::fetch(url, ctx, on_progress).await?;
+
+    // The download's own folder is about to disappear
+    let output_path = output_path
+        .or_else(|| settings.output_dir.clone())
+        .or_else(|| {
+            dirs::download_dir().map(|dir| dir.join("compressed").to_string_lossy().to_string())
+        });
+    let input_path = download.path.to_string_lossy().to_string();
+    let result = compressor::compress_file(&input_path, output_path, settings, preset, ctx).await;
+
+    if let Ok(store) = HistoryStore::open() {
+        store.rename_input(&input_path, url).ok();
+    }
+    result
+}
+
+/// A downloaded file, deleted along with its folder when dropped.
+struct Download {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Download {
+    async fn fetch(
+        url: &str,
+        ctx: &JobContext,
+        on_progress: impl Fn(&DownloadProgress),
+    ) -> Result<Self> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| Error::InvalidRequest {
+            message: format!("Invalid URL {}: {}", url, e),
+        })?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(Error::InvalidRequest {
+                message: format!("Only http and https links can be downloaded: {}", url),
+            });
+        }
+
+        let mut response = reqwest::get(parsed.clone()).await?;
+        if !response.status().is_success() {
+            return Err(Error::Network {
+                message: format!(
+                    "Download failed, server responded with {}",
+                    response.status()
+                ),
+            });
+        }
+
+        let name = file_name(&parsed, &response).ok_or_else(|| Error::UnsupportedFormat {
+            path: url.to_string(),
+        })?;
+        let dir = paths::staging_dir()
+            .join("downloads")
+            .join(format!("{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).map_err(|e| Error::io("Failed to create download folder", e))?;
+        // From here on the folder is cleaned up however the download ends
+        let download = Self {
+            path: dir.join(name),
+            dir,
+        };
+
+        let total = response.content_length();
+        let mut file =
+            File::create(&download.path).map_err(|e| Error::io("Failed to save download", e))?;
+        let mut downloaded = 0;
+        on_progress(&DownloadProgress { downloaded, total });
+        while let Some(chunk) = response.chunk().await? {
+            if ctx.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            file.write_all(&chunk)
+                .map_err(|e| Error::io("Failed to save download", e))?;
+            downloaded += chunk.len() as u64;
+            on_progress(&DownloadProgress { downloaded, total });
+        }
+
+        Ok(download)
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// A name with an extension the pipelines know, from the URL's last path
+/// segment or else the `Content-Type`. Only types in `media` are mapped.
+fn file_name(url: &reqwest::Url, response: &reqwest::Response) -> Option<String> {
+    let segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(percent_decode)
+        .unwrap_or_default();
+    // Names are used on disk, so nothing that could leave the folder
+    let segment: String = segment
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if MediaType::from_path(Path::new(&segment)).is_some() {
+        return Some(segment);
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?;
+    let extension = extension_for(content_type.split(';').next()?.trim())?;
+    let stem = Path::new(&segment)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .filter(|stem| !stem.is_empty() && !stem.starts_with('.'))
+        .unwrap_or_else(|| "download".to_string());
+    Some(format!("{}.{}", stem, extension))
+}
+
+fn extension_for(content_type: &str) -> Option<&'static str> {
+    Some(match content_type.to_ascii_lowercase().as_str() {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        "image/heic" => "heic",
+        "image/tiff" => "tiff",
+        "image/bmp" => "bmp",
+        "image/svg+xml" => "svg",
+        "video/mp4" => "mp4",
+        "video/quicktime" => "mov",
+        "video/x-matroska" => "mkv",
+        "video/x-msvideo" => "avi",
+        "audio/mpeg" => "mp3",
+        "audio/wav" | "audio/x-wav" => "wav",
+        "audio/flac" => "flac",
+        "audio/mp4" | "audio/x-m4a" => "m4a",
+        "audio/ogg" => "ogg",
+        "application/zip" => "zip",
+        _ => return None,
+    })
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
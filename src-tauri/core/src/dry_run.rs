@@ -0,0 +1,277 @@
+//! Dry runs: what a batch would do to each file, worked out from settings
+//! and probes without encoding or writing anything, so a thousand-file run
+//! can be checked before it starts.
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::compressor::{self, is_lossless_video_codec};
+use crate::custom_args;
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::presets::{self, parse_bitrate, Preset};
+use crate::probe::{self, MediaInfo};
+use crate::queue::JobRequest;
+use crate::scan::{typical_video_bitrate, JPEG_BYTES_PER_PIXEL};
+use crate::script;
+use crate::settings::{HevcPolicy, Settings};
+
+const DEFAULT_AUDIO_BITRATE: u64 = 128_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlannedAction {
+    Compress,
+    /// Copied into a new container as is; see `HevcPolicy::Remux`.
+    Remux,
+    Skip,
+    /// Would fail, e.g. because the input is missing or the script errors.
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedFile {
+    pub input_path: String,
+    pub media_type: Option<&'static str>,
+    pub action: PlannedAction,
+    /// Preset the file would get, after the pipeline script had its say.
+    pub preset: Option<String>,
+    pub output_dir: Option<String>,
+    pub original_size: Option<u64>,
+    /// Rough guess at the output size; `None` when there's nothing to go
+    /// on, as for documents and archives.
+    pub estimated_size: Option<u64>,
+    /// Why the file would be skipped or fail.
+    pub reason: Option<String>,
+}
+
+impl PlannedFile {
+    fn from_error(input_path: &str, error: Error) -> Self {
+        Self {
+            input_path: input_path.to_string(),
+            media_type: MediaType::from_path(Path::new(input_path))
+                .map(|media_type| media_type.as_str()),
+            action: if error.is_skip() {
+                PlannedAction::Skip
+            } else {
+                PlannedAction::Fail
+            },
+            preset: None,
+            output_dir: None,
+            original_size: fs::metadata(input_path).ok().map(|metadata| metadata.len()),
+            estimated_size: None,
+            reason: Some(error.to_string()),
+        }
+    }
+
+    fn is_written(&self) -> bool {
+        matches!(self.action, PlannedAction::Compress | PlannedAction::Remux)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunSummary {
+    pub files: usize,
+    /// Files that would be compressed or remuxed.
+    pub written: usize,
+    pub skipped: usize,
+    pub failing: usize,
+    /// Size of the files that would be written.
+    pub original_size: u64,
+    pub estimated_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRun {
+    pub files: Vec<PlannedFile>,
+    pub summary: DryRunSummary,
+}
+
+/// Plans `requests` in order, as the queue would run them. Outputs count
+/// toward the output budget at their estimated size.
+pub async fn plan(requests: &[JobRequest], settings: &Settings) -> DryRun {
+    // Probing only; a missing ffmpeg isn't downloaded for a dry run
+    let ffmpeg_path = FFmpegManager::new().installed_ffmpeg_path();
+    let mut files = Vec::new();
+    let mut summary = DryRunSummary::default();
+
+    for request in requests {
+        let preset = settings
+            .check_output_budget(summary.estimated_size, &request.input_path)
+            .and_then(|()| presets::resolve_preset(request.preset.as_deref(), settings));
+        let planned = match preset {
+            Ok(preset) => {
+                plan_file(
+                    &request.input_path,
+                    request.output_path.clone(),
+                    settings,
+                    &preset,
+                    ffmpeg_path.as_deref(),
+                )
+                .await
+            }
+            Err(e) => PlannedFile::from_error(&request.input_path, e),
+        };
+
+        summary.files += 1;
+        match planned.action {
+            PlannedAction::Compress | PlannedAction::Remux => summary.written += 1,
+            PlannedAction::Skip => summary.skipped += 1,
+            PlannedAction::Fail => summary.failing += 1,
+        }
+        if planned.is_written() {
+            summary.original_size += planned.original_size.unwrap_or(0);
+            summary.estimated_size += planned
+                .estimated_size
+                .or(planned.original_size)
+                .unwrap_or(0);
+        }
+        files.push(planned);
+    }
+
+    DryRun { files, summary }
+}
+
+/// Plans one file, going through the same checks `compress_file` does
+/// before it encodes.
+pub async fn plan_file(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ffmpeg_path: Option<&Path>,
+) -> PlannedFile {
+    match try_plan_file(input_path, output_path, settings, preset, ffmpeg_path).await {
+        Ok(planned) => planned,
+        Err(e) => PlannedFile::from_error(input_path, e),
+    }
+}
+
+async fn try_plan_file(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ffmpeg_path: Option<&Path>,
+) -> Result<PlannedFile> {
+    let input = Path::new(input_path);
+    let media_type = MediaType::from_path(input).ok_or_else(|| Error::UnsupportedFormat {
+        path: input_path.to_string(),
+    })?;
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+    let size = fs::metadata(input)?.len();
+
+    compressor::check_skips(input_path, settings)?;
+    let (preset, _) = script::apply(
+        input_path,
+        media_type,
+        settings,
+        preset,
+        &JobContext::default(),
+    )
+    .await?;
+    custom_args::validate(&preset.extra_ffmpeg_args)?;
+
+    let info = match (media_type, ffmpeg_path) {
+        (MediaType::Video | MediaType::Audio, Some(ffmpeg_path)) => {
+            Some(probe::probe_media(ffmpeg_path, input)?)
+        }
+        _ => None,
+    };
+
+    let mut action = PlannedAction::Compress;
+    let estimated_size = match media_type {
+        MediaType::Video => {
+            let info = info.unwrap_or_default();
+            if settings.hevc_policy != HevcPolicy::Reencode
+                && info.video_codec.as_deref() == Some("hevc")
+            {
+                if settings.hevc_policy == HevcPolicy::Skip {
+                    return Err(Error::AlreadyHevc {
+                        path: input_path.to_string(),
+                    });
+                }
+                action = PlannedAction::Remux;
+                Some(size)
+            } else {
+                estimate_video(&info, &preset)
+            }
+        }
+        MediaType::Audio => info.and_then(|info| estimate_audio(&info, &preset)),
+        MediaType::Image => estimate_image(input, size, &preset),
+        MediaType::Document | MediaType::Archive => None,
+    };
+
+    Ok(PlannedFile {
+        input_path: input_path.to_string(),
+        media_type: Some(media_type.as_str()),
+        action,
+        preset: Some(preset.name.clone()),
+        output_dir: Some(
+            compressor::output_dir_for(input, output_path, settings)
+                .to_string_lossy()
+                .to_string(),
+        ),
+        original_size: Some(size),
+        estimated_size,
+        reason: None,
+    })
+}
+
+fn estimate_video(info: &MediaInfo, preset: &Preset) -> Option<u64> {
+    // Lossless output is anyone's guess
+    if is_lossless_video_codec(&preset.video_codec) {
+        return None;
+    }
+    let duration = info.duration? / preset.speed.unwrap_or(1.0) as f64;
+    let video_bitrate = match preset.target_size_mb {
+        Some(_) => preset.target_video_bitrate(info.duration?)? as f64,
+        None => match preset.average_video_bitrate() {
+            Some(bitrate) => bitrate as f64,
+            None => {
+                let height = info.height.unwrap_or(1080);
+                typical_video_bitrate(
+                    preset
+                        .max_video_height
+                        .map_or(height, |max| height.min(max)),
+                )
+            }
+        },
+    };
+    let audio_bitrate = if preset.drops_audio() || info.audio_codec.is_none() {
+        0
+    } else {
+        parse_bitrate(&preset.audio_bitrate).unwrap_or(DEFAULT_AUDIO_BITRATE)
+    };
+    Some(((video_bitrate + audio_bitrate as f64) * duration / 8.0) as u64)
+}
+
+fn estimate_audio(info: &MediaInfo, preset: &Preset) -> Option<u64> {
+    let bitrate = parse_bitrate(&preset.audio_bitrate)?;
+    Some((bitrate as f64 * info.duration? / 8.0) as u64)
+}
+
+/// Images that wouldn't shrink keep the original, so they never grow.
+fn estimate_image(input: &Path, size: u64, preset: &Preset) -> Option<u64> {
+    // Reads only the header
+    let (width, height) = image::image_dimensions(input).ok()?;
+    let longest = width.max(height).max(1);
+    let scale = preset
+        .max_image_dimension
+        .map_or(1.0, |max| (max as f64 / longest as f64).min(1.0));
+    let estimate = (width as f64 * height as f64 * scale * scale * JPEG_BYTES_PER_PIXEL) as u64;
+    let estimate = preset
+        .target_size_bytes()
+        .map_or(estimate, |target| estimate.min(target));
+    Some(estimate.min(size))
+}
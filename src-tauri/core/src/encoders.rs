@@ -0,0 +1,373 @@
+//! Standalone image encoders used instead of the built-in ones when they are
+//! installed, either by the user or downloaded into the app's data directory
+//! the way ffmpeg is. `cwebp` makes lossy WebP without ffmpeg, `avifenc` is
+//! several times faster than the built-in AVIF encoder, `cjxl` is the only
+//! way to write JPEG XL, and `gifski` makes far better GIFs than ffmpeg.
+
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::error::{Error, Result};
+use crate::paths;
+
+/// How long a lookup is trusted, so an encoder installed while the app runs
+/// is picked up without checking on every image.
+const LOOKUP_TTL: Duration = Duration::from_secs(60);
+/// Same speed/size trade-off as the built-in AVIF encoder.
+const AVIFENC_SPEED: &str = "6";
+
+/// When an encoder was looked for, and where it was found.
+type Lookup = (Instant, Option<PathBuf>);
+
+static LOOKUPS: Mutex<Option<HashMap<ExternalEncoder, Lookup>>> = Mutex::new(None);
+/// Numbers scratch files, so concurrent encodes don't share one.
+static NEXT_SCRATCH: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalEncoder {
+    Cwebp,
+    Avifenc,
+    Cjxl,
+    Gifski,
+}
+
+impl ExternalEncoder {
+    pub const ALL: [Self; 4] = [Self::Cwebp, Self::Avifenc, Self::Cjxl, Self::Gifski];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cwebp => "cwebp",
+            Self::Avifenc => "avifenc",
+            Self::Cjxl => "cjxl",
+            Self::Gifski => "gifski",
+        }
+    }
+
+    /// The encoder for `format` among those that replace a built-in one.
+    pub fn for_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::WebP => Some(Self::Cwebp),
+            ImageFormat::Avif => Some(Self::Avifenc),
+            _ => None,
+        }
+    }
+
+    fn executable(self) -> String {
+        if cfg!(target_os = "windows") {
+            format!("{}.exe", self.name())
+        } else {
+            self.name().to_string()
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Cwebp => "webp",
+            Self::Avifenc => "avif",
+            Self::Cjxl => "jxl",
+            Self::Gifski => "gif",
+        }
+    }
+
+    fn version_arg(self) -> &'static str {
+        match self {
+            Self::Cwebp => "-version",
+            Self::Avifenc | Self::Cjxl | Self::Gifski => "--version",
+        }
+    }
+
+    /// Official static builds; libjxl has none for macOS, and gifski is
+    /// only packaged (Homebrew, winget, cargo).
+    fn download_url(self) -> Option<&'static str> {
+        match self {
+            #[cfg(target_os = "windows")]
+            Self::Cwebp => Some("https://storage.googleapis.com/downloads.webmproject.org/releases/webp/libwebp-1.4.0-windows-x64.zip"),
+            #[cfg(target_os = "macos")]
+            Self::Cwebp => Some("https://storage.googleapis.com/downloads.webmproject.org/releases/webp/libwebp-1.4.0-mac-arm64.tar.gz"),
+            #[cfg(target_os = "linux")]
+            Self::Cwebp => Some("https://storage.googleapis.com/downloads.webmproject.org/releases/webp/libwebp-1.4.0-linux-x86-64.tar.gz"),
+
+            #[cfg(target_os = "windows")]
+            Self::Avifenc => Some("https://github.com/AOMediaCodec/libavif/releases/download/v1.1.1/windows-artifacts.zip"),
+            #[cfg(target_os = "macos")]
+            Self::Avifenc => Some("https://github.com/AOMediaCodec/libavif/releases/download/v1.1.1/macOS-artifacts.zip"),
+            #[cfg(target_os = "linux")]
+            Self::Avifenc => Some("https://github.com/AOMediaCodec/libavif/releases/download/v1.1.1/linux-artifacts.zip"),
+
+            #[cfg(target_os = "windows")]
+            Self::Cjxl => Some("https://github.com/libjxl/libjxl/releases/download/v0.11.1/jxl-x64-windows-static.zip"),
+            #[cfg(target_os = "macos")]
+            Self::Cjxl => None,
+            #[cfg(target_os = "linux")]
+            Self::Cjxl => Some("https://github.com/libjxl/libjxl/releases/download/v0.11.1/jxl-linux-x86_64-static-v0.11.1.tar.gz"),
+
+            Self::Gifski => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncoderStatus {
+    pub encoder: ExternalEncoder,
+    /// The executable in use; `None` when the encoder isn't installed.
+    pub path: Option<String>,
+    /// Whether `install` can download it on this platform.
+    pub downloadable: bool,
+}
+
+/// Every supported encoder, found or not.
+pub fn status() -> Vec<EncoderStatus> {
+    ExternalEncoder::ALL
+        .into_iter()
+        .map(|encoder| EncoderStatus {
+            encoder,
+            path: find(encoder).map(|path| path.to_string_lossy().to_string()),
+            downloadable: encoder.download_url().is_some(),
+        })
+        .collect()
+}
+
+/// The copy in the app's data directory, else one on the `PATH`.
+pub fn find(encoder: ExternalEncoder) -> Option<PathBuf> {
+    let mut lookups = LOOKUPS.lock().unwrap();
+    let lookups = lookups.get_or_insert_with(HashMap::new);
+    if let Some((checked, path)) = lookups.get(&encoder) {
+        if checked.elapsed() < LOOKUP_TTL {
+            return path.clone();
+        }
+    }
+    let path = [
+        paths::encoders_dir().join(encoder.executable()),
+        PathBuf::from(encoder.executable()),
+    ]
+    .into_iter()
+    .find(|path| runs(encoder, path));
+    lookups.insert(encoder, (Instant::now(), path.clone()));
+    path
+}
+
+fn runs(encoder: ExternalEncoder, path: &Path) -> bool {
+    Command::new(path)
+        .arg(encoder.version_arg())
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Downloads `encoder` into the app's data directory, unless it is already
+/// installed.
+pub async fn install(encoder: ExternalEncoder) -> Result<PathBuf> {
+    if let Some(path) = find(encoder) {
+        return Ok(path);
+    }
+    let url = encoder
+        .download_url()
+        .ok_or_else(|| Error::EncoderInstallFailed {
+            name: encoder.name().to_string(),
+            message: "There is no download for this platform; install it with a package manager"
+                .to_string(),
+        })?;
+
+    info!("Downloading {} from {}", encoder.name(), url);
+    let bytes = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| Error::Network {
+            message: format!("Failed to download {}: {}", encoder.name(), e),
+        })?
+        .bytes()
+        .await
+        .map_err(|e| Error::Network {
+            message: format!("Failed to read download: {}", e),
+        })?;
+
+    let dir = paths::encoders_dir();
+    fs::create_dir_all(&dir).map_err(|e| Error::io("Failed to create encoders directory", e))?;
+    let path = dir.join(encoder.executable());
+    if url.ends_with(".zip") {
+        extract_zip(encoder, &bytes, &path)?;
+    } else {
+        extract_tar_gz(encoder, &bytes, &path)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .map_err(|e| Error::io("Failed to set permissions", e))?;
+    }
+
+    LOOKUPS.lock().unwrap().take();
+    if !runs(encoder, &path) {
+        fs::remove_file(&path).ok();
+        return Err(Error::EncoderInstallFailed {
+            name: encoder.name().to_string(),
+            message: "The downloaded executable doesn't run on this system".to_string(),
+        });
+    }
+    info!("Installed {} to {}", encoder.name(), path.display());
+    Ok(path)
+}
+
+fn extract_zip(encoder: ExternalEncoder, bytes: &[u8], path: &Path) -> Result<()> {
+    let mut archive =
+        zip::ZipArchive::new(io::Cursor::new(bytes)).map_err(|e| install_failed(encoder, e))?;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .map_err(|e| install_failed(encoder, e))?;
+        if is_executable_entry(encoder, Path::new(entry.name())) {
+            let mut file =
+                fs::File::create(path).map_err(|e| Error::io("Failed to create encoder", e))?;
+            io::copy(&mut entry, &mut file)
+                .map_err(|e| Error::io("Failed to extract encoder", e))?;
+            return Ok(());
+        }
+    }
+    Err(not_in_download(encoder))
+}
+
+/// `tar` has no gzip support of its own, so the system's `gzip` unpacks it.
+fn extract_tar_gz(encoder: ExternalEncoder, bytes: &[u8], path: &Path) -> Result<()> {
+    let archive_path = scratch_file("tar.gz");
+    fs::write(&archive_path, bytes).map_err(|e| Error::io("Failed to write download", e))?;
+    let output = Command::new("gzip")
+        .args(["-d", "-c"])
+        .arg(&archive_path)
+        .output();
+    fs::remove_file(&archive_path).ok();
+    let output = output.map_err(|e| Error::io("Failed to run gzip", e))?;
+    if !output.status.success() {
+        return Err(install_failed(
+            encoder,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+
+    let mut archive = tar::Archive::new(io::Cursor::new(output.stdout));
+    for entry in archive
+        .entries()
+        .map_err(|e| Error::io("Failed to read download", e))?
+    {
+        let mut entry = entry.map_err(|e| Error::io("Failed to read download", e))?;
+        let is_executable = entry
+            .path()
+            .is_ok_and(|entry_path| is_executable_entry(encoder, &entry_path));
+        if is_executable {
+            entry
+                .unpack(path)
+                .map_err(|e| Error::io("Failed to extract encoder", e))?;
+            return Ok(());
+        }
+    }
+    Err(not_in_download(encoder))
+}
+
+/// Archives put the executable in a `bin/` folder or at the top.
+fn is_executable_entry(encoder: ExternalEncoder, entry_path: &Path) -> bool {
+    entry_path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy() == encoder.executable())
+}
+
+fn install_failed(encoder: ExternalEncoder, message: impl std::fmt::Display) -> Error {
+    Error::EncoderInstallFailed {
+        name: encoder.name().to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn not_in_download(encoder: ExternalEncoder) -> Error {
+    install_failed(
+        encoder,
+        format!("The download has no {}", encoder.executable()),
+    )
+}
+
+/// Encodes `img` at `quality` (1-100, as for the built-in encoders) with the
+/// encoder at `path`.
+pub fn encode(
+    encoder: ExternalEncoder,
+    path: &Path,
+    img: &DynamicImage,
+    quality: u8,
+) -> Result<Vec<u8>> {
+    let quality = quality.to_string();
+    let args: Vec<&str> = match encoder {
+        ExternalEncoder::Cwebp => vec!["-quiet", "-mt", "-q", &quality],
+        ExternalEncoder::Avifenc => vec!["-q", &quality, "-s", AVIFENC_SPEED, "-j", "all"],
+        ExternalEncoder::Cjxl => vec!["--quiet", "-q", &quality],
+        // Takes video frames, see `gif`
+        ExternalEncoder::Gifski => {
+            return Err(Error::Image {
+                message: "gifski only makes GIFs from video".to_string(),
+            })
+        }
+    };
+    let input = scratch_file("png");
+    img.save_with_format(&input, ImageFormat::Png)?;
+    let encoded = run(encoder, path, &input, &args);
+    fs::remove_file(&input).ok();
+    encoded
+}
+
+/// Repacks a JPEG file as JPEG XL without decoding it, so the pixels stay
+/// exactly the same; typically about 20% smaller.
+pub fn transcode_jpeg(cjxl_path: &Path, input: &Path) -> Result<Vec<u8>> {
+    run(
+        ExternalEncoder::Cjxl,
+        cjxl_path,
+        input,
+        &["--quiet", "--lossless_jpeg=1"],
+    )
+}
+
+/// Runs `path` on `input` with `args`, and returns what it wrote. cwebp
+/// names its output with `-o`, the others take it after the input.
+fn run(encoder: ExternalEncoder, path: &Path, input: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = scratch_file(encoder.extension());
+    let mut command = Command::new(path);
+    command.args(args).arg(input);
+    if encoder == ExternalEncoder::Cwebp {
+        command.arg("-o");
+    }
+    let result = command.arg(&output).output();
+    let encoded = match result {
+        Ok(result) if result.status.success() => {
+            fs::read(&output).map_err(|e| Error::io("Failed to read encoder output", e))
+        }
+        Ok(result) => Err(Error::Image {
+            message: format!(
+                "{} failed: {}",
+                encoder.name(),
+                String::from_utf8_lossy(&result.stderr).trim()
+            ),
+        }),
+        Err(e) => Err(Error::io(&format!("Failed to run {}", encoder.name()), e)),
+    };
+    fs::remove_file(&output).ok();
+    encoded
+}
+
+/// A fresh path in the staging directory.
+pub(crate) fn scratch_file(extension: &str) -> PathBuf {
+    let dir = paths::staging_dir();
+    fs::create_dir_all(&dir).ok();
+    dir.join(format!(
+        "{}-{}-encoder.{}",
+        std::process::id(),
+        NEXT_SCRATCH.fetch_add(1, Ordering::Relaxed),
+        extension
+    ))
+}
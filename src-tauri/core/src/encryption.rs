@@ -0,0 +1,177 @@
+//! Optional encryption of outputs, for footage headed to cloud storage.
+//! Outputs become standard age files, so `age -d` and rage open them as well
+//! as the app's own decrypt command.
+
+use age::secrecy::SecretString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::compressor::CompressionResult;
+use crate::error::{Error, Result};
+use crate::settings::EncryptionSettings;
+
+/// Extension appended to encrypted files, after their own.
+pub const EXTENSION: &str = "age";
+
+/// Prefix of age secret keys; anything else given to decrypt is a passphrase.
+const SECRET_KEY_PREFIX: &str = "AGE-SECRET-KEY-";
+
+/// When encryption is on, replaces every file a successful `result` wrote
+/// with an encrypted `<name>.age` and points the result at those. The input
+/// itself is never touched, even when an output path names it.
+pub fn encrypt_outputs(
+    input_path: &str,
+    result: Result<CompressionResult>,
+    settings: &EncryptionSettings,
+) -> Result<CompressionResult> {
+    let mut result = result?;
+    if !settings.enabled {
+        return Ok(result);
+    }
+
+    let input = Path::new(input_path).canonicalize().ok();
+    let encrypt = |path: &mut String| -> Result<()> {
+        if Path::new(path.as_str()).canonicalize().ok() == input {
+            return Ok(());
+        }
+        let encrypted = encrypt_file(Path::new(path.as_str()), settings)?;
+        *path = encrypted.to_string_lossy().to_string();
+        Ok(())
+    };
+
+    encrypt(&mut result.output_path)?;
+    for variant in result.variants.iter_mut().chain(result.pages.iter_mut()) {
+        encrypt(&mut variant.output_path)?;
+    }
+    // The first part is the output itself, already done
+    if let Some(first) = result.parts.first_mut() {
+        *first = result.output_path.clone();
+    }
+    for part in result.parts.iter_mut().skip(1) {
+        encrypt(part)?;
+    }
+    Ok(result)
+}
+
+/// Encrypts `path` to `<path>.age` for the configured recipients, or the
+/// passphrase if there are none, then removes the plaintext.
+pub fn encrypt_file(path: &Path, settings: &EncryptionSettings) -> Result<PathBuf> {
+    let encryptor = encryptor(settings)?;
+    let mut encrypted_path = path.as_os_str().to_owned();
+    encrypted_path.push(format!(".{}", EXTENSION));
+    let encrypted_path = PathBuf::from(encrypted_path);
+
+    let written = (|| -> io::Result<()> {
+        let mut plaintext = File::open(path)?;
+        let output = BufWriter::new(File::create(&encrypted_path)?);
+        let mut writer = encryptor.wrap_output(output)?;
+        io::copy(&mut plaintext, &mut writer)?;
+        writer.finish()?.flush()
+    })();
+    if let Err(e) = written {
+        fs::remove_file(&encrypted_path).ok();
+        return Err(Error::io("Failed to encrypt output", e));
+    }
+
+    fs::remove_file(path).map_err(|e| Error::io("Failed to remove unencrypted output", e))?;
+    Ok(encrypted_path)
+}
+
+/// Decrypts an age file with `secret`, either a passphrase or an
+/// `AGE-SECRET-KEY-1…` identity, to `output` or the path without `.age`.
+/// Returns where the plaintext went.
+pub fn decrypt_file(path: &Path, secret: &str, output: Option<&Path>) -> Result<PathBuf> {
+    if !path.exists() {
+        return Err(Error::InputNotFound {
+            path: path.to_string_lossy().to_string(),
+        });
+    }
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None if path.extension().is_some_and(|ext| ext == EXTENSION) => path.with_extension(""),
+        None => {
+            return Err(Error::InvalidRequest {
+                message: format!("Not an .{} file: {}", EXTENSION, path.display()),
+            })
+        }
+    };
+
+    let failed = |message: String| Error::DecryptionFailed {
+        path: path.to_string_lossy().to_string(),
+        message,
+    };
+    let key_failed = |error: age::DecryptError| match error {
+        age::DecryptError::DecryptionFailed | age::DecryptError::NoMatchingKeys => {
+            failed("wrong passphrase or key".to_string())
+        }
+        error => failed(error.to_string()),
+    };
+    let input = BufReader::new(File::open(path).map_err(|e| Error::io("Failed to open file", e))?);
+    let decryptor = age::Decryptor::new_buffered(input).map_err(|e| failed(e.to_string()))?;
+    let mut reader = if secret.starts_with(SECRET_KEY_PREFIX) {
+        let identity: age::x25519::Identity =
+            secret
+                .trim()
+                .parse()
+                .map_err(|e: &str| Error::InvalidRequest {
+                    message: format!("Invalid age key: {}", e),
+                })?;
+        decryptor.decrypt(iter::once(&identity as &dyn age::Identity))
+    } else {
+        let identity = age::scrypt::Identity::new(SecretString::from(secret.to_string()));
+        decryptor.decrypt(iter::once(&identity as &dyn age::Identity))
+    }
+    .map_err(key_failed)?;
+
+    let written = (|| -> io::Result<()> {
+        let mut plaintext = BufWriter::new(File::create(&output)?);
+        io::copy(&mut reader, &mut plaintext)?;
+        plaintext.flush()
+    })();
+    if let Err(e) = written {
+        fs::remove_file(&output).ok();
+        // The payload is authenticated chunk by chunk, so tampering shows up here
+        return Err(if e.kind() == io::ErrorKind::InvalidData {
+            failed(e.to_string())
+        } else {
+            Error::io("Failed to write decrypted file", e)
+        });
+    }
+    Ok(output)
+}
+
+fn encryptor(settings: &EncryptionSettings) -> Result<age::Encryptor> {
+    let recipients: Vec<&str> = settings
+        .recipients
+        .iter()
+        .map(|recipient| recipient.trim())
+        .filter(|recipient| !recipient.is_empty())
+        .collect();
+    if !recipients.is_empty() {
+        let recipients = recipients
+            .into_iter()
+            .map(|recipient| {
+                recipient.parse::<age::x25519::Recipient>().map_err(|e| {
+                    Error::invalid_settings(format!("Invalid age recipient {}: {}", recipient, e))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return age::Encryptor::with_recipients(
+            recipients
+                .iter()
+                .map(|recipient| recipient as &dyn age::Recipient),
+        )
+        .map_err(|e| Error::invalid_settings(e.to_string()));
+    }
+
+    match settings.passphrase.as_deref() {
+        Some(passphrase) if !passphrase.is_empty() => Ok(age::Encryptor::with_user_passphrase(
+            SecretString::from(passphrase.to_string()),
+        )),
+        _ => Err(Error::invalid_settings(
+            "Encryption is on but neither a passphrase nor recipients are set",
+        )),
+    }
+}
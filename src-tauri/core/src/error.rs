@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+use crate::units;
+
+/// Errors returned by the engine and the app's commands. Serialized with a
+/// `kind` tag so callers can branch on the failure instead of parsing text,
+/// e.g. `{"kind": "ffmpegMissing"}` or `{"kind": "encodeFailed", "stderrTail": "..."}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Error {
+    InputNotFound {
+        path: String,
+    },
+    UnsupportedFormat {
+        path: String,
+    },
+    FfmpegMissing,
+    FfmpegInstallFailed {
+        message: String,
+    },
+    /// An external encoder a preset needs isn't installed.
+    EncoderMissing {
+        name: String,
+    },
+    EncoderInstallFailed {
+        name: String,
+        message: String,
+    },
+    EncodeFailed {
+        /// Last lines ffmpeg printed, which usually say why it failed.
+        stderr_tail: String,
+        /// Full command line and output of the failed run.
+        log_path: Option<String>,
+    },
+    /// ffmpeg could not report the duration a size-capped encode needs.
+    UnknownDuration {
+        path: String,
+    },
+    DiskFull,
+    Cancelled,
+    /// A lossless encode decoded to different frames than its source.
+    VerificationFailed {
+        path: String,
+    },
+    /// Identical contents were already compressed, per the job history.
+    AlreadyProcessed {
+        path: String,
+    },
+    /// The video half of a Live Photo, skipped because settings drop those.
+    LivePhotoMotion {
+        path: String,
+    },
+    /// Already HEVC, skipped because settings leave HEVC videos alone.
+    AlreadyHevc {
+        path: String,
+    },
+    /// Written by an earlier run; compressing it again only stacks losses.
+    CompressedOutput {
+        path: String,
+    },
+    /// Not started because the batch's outputs already fill the output
+    /// budget in settings.
+    OutputBudgetExceeded {
+        path: String,
+        budget: u64,
+    },
+    /// Smaller than the minimum input size in settings.
+    BelowMinimumSize {
+        path: String,
+        size: u64,
+    },
+    /// Left out by the pipeline script in settings.
+    ScriptSkipped {
+        path: String,
+        reason: String,
+    },
+    /// The pipeline script failed or returned something unusable.
+    ScriptFailed {
+        message: String,
+    },
+    /// Wrong passphrase or key, or the encrypted file was damaged.
+    DecryptionFailed {
+        path: String,
+        message: String,
+    },
+    InvalidSettings {
+        message: String,
+    },
+    InvalidRequest {
+        message: String,
+    },
+    UnknownPreset {
+        name: String,
+    },
+    UnknownJob {
+        id: u64,
+    },
+    JobAlreadyFinished {
+        id: u64,
+    },
+    UnknownBatch {
+        id: u64,
+    },
+    JobNotQueued {
+        id: u64,
+    },
+    Network {
+        message: String,
+    },
+    Io {
+        message: String,
+    },
+    Database {
+        message: String,
+    },
+    Image {
+        message: String,
+    },
+    /// An OS integration (file manager, clipboard, power management, shell
+    /// registration) failed.
+    Platform {
+        message: String,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Wraps an I/O error with what was being attempted, keeping a full disk
+    /// recognizable.
+    pub fn io(context: &str, error: io::Error) -> Self {
+        if is_disk_full(&error) {
+            return Error::DiskFull;
+        }
+        Error::Io {
+            message: format!("{}: {}", context, error),
+        }
+    }
+
+    pub fn database(context: &str, error: rusqlite::Error) -> Self {
+        Error::Database {
+            message: format!("{}: {}", context, error),
+        }
+    }
+
+    pub fn invalid_settings(message: impl Into<String>) -> Self {
+        Error::InvalidSettings {
+            message: message.into(),
+        }
+    }
+
+    pub fn platform(message: impl fmt::Display) -> Self {
+        Error::Platform {
+            message: message.to_string(),
+        }
+    }
+
+    /// Whether the input was deliberately left alone rather than failing.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Error::AlreadyProcessed { .. }
+                | Error::LivePhotoMotion { .. }
+                | Error::AlreadyHevc { .. }
+                | Error::CompressedOutput { .. }
+                | Error::BelowMinimumSize { .. }
+                | Error::OutputBudgetExceeded { .. }
+                | Error::ScriptSkipped { .. }
+        )
+    }
+}
+
+fn is_disk_full(error: &io::Error) -> bool {
+    // ENOSPC on Unix, ERROR_DISK_FULL / ERROR_HANDLE_DISK_FULL on Windows
+    error.kind() == io::ErrorKind::StorageFull
+        || matches!(error.raw_os_error(), Some(28) if cfg!(unix))
+        || matches!(error.raw_os_error(), Some(39 | 112) if cfg!(windows))
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InputNotFound { path } => write!(f, "Input file does not exist: {}", path),
+            Error::UnsupportedFormat { path } => write!(f, "Unsupported file type: {}", path),
+            Error::FfmpegMissing => write!(
+                f,
+                "ffmpeg is not installed. Please install ffmpeg to compress videos."
+            ),
+            Error::FfmpegInstallFailed { message } => {
+                write!(f, "Failed to install FFmpeg: {}", message)
+            }
+            Error::EncoderMissing { name } => write!(f, "{} is not installed", name),
+            Error::EncoderInstallFailed { name, message } => {
+                write!(f, "Failed to install {}: {}", name, message)
+            }
+            Error::EncodeFailed { stderr_tail, .. } => {
+                write!(f, "Video compression failed: {}", stderr_tail)
+            }
+            Error::UnknownDuration { path } => {
+                write!(f, "Could not determine video duration: {}", path)
+            }
+            Error::DiskFull => write!(f, "The disk is full"),
+            Error::Cancelled => write!(f, "Compression cancelled"),
+            Error::VerificationFailed { path } => write!(
+                f,
+                "Lossless output does not match the source frame for frame: {}",
+                path
+            ),
+            Error::AlreadyProcessed { path } => {
+                write!(
+                    f,
+                    "Skipped, an identical file was already compressed: {}",
+                    path
+                )
+            }
+            Error::LivePhotoMotion { path } => {
+                write!(f, "Skipped the video half of a Live Photo: {}", path)
+            }
+            Error::AlreadyHevc { path } => write!(f, "Skipped, already HEVC: {}", path),
+            Error::CompressedOutput { path } => {
+                write!(f, "Skipped, an output of an earlier run: {}", path)
+            }
+            Error::OutputBudgetExceeded { path, budget } => write!(
+                f,
+                "Skipped, the batch's outputs already fill the {} budget: {}",
+                units::format_size(*budget),
+                path
+            ),
+            Error::BelowMinimumSize { path, size } => write!(
+                f,
+                "Skipped, smaller than the minimum size ({}): {}",
+                units::format_size(*size),
+                path
+            ),
+            Error::ScriptSkipped { path, reason } => {
+                write!(f, "Skipped by the pipeline script ({}): {}", reason, path)
+            }
+            Error::ScriptFailed { message } => write!(f, "The pipeline script failed: {}", message),
+            Error::DecryptionFailed { path, message } => {
+                write!(f, "Could not decrypt {}: {}", path, message)
+            }
+            Error::InvalidSettings { message } | Error::InvalidRequest { message } => {
+                write!(f, "{}", message)
+            }
+            Error::UnknownPreset { name } => write!(f, "Unknown preset: {}", name),
+            Error::UnknownJob { id } => write!(f, "Unknown job: {}", id),
+            Error::JobAlreadyFinished { id } => write!(f, "Job {} has already finished", id),
+            Error::UnknownBatch { id } => write!(f, "Unknown batch: {}", id),
+            Error::JobNotQueued { id } => write!(f, "Job {} is no longer queued", id),
+            Error::Network { message }
+            | Error::Io { message }
+            | Error::Database { message }
+            | Error::Image { message }
+            | Error::Platform { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        if is_disk_full(&error) {
+            return Error::DiskFull;
+        }
+        Error::Io {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(error) => error.into(),
+            error => Error::Image {
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Database {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Network {
+            message: error.to_string(),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Output drives formatted FAT32, as most USB sticks and SD cards for TVs
+//! and car stereos are, can't hold a file of 4 GiB or more. Videos too big
+//! for one are split into playable parts instead of failing mid-copy.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compressor;
+use crate::error::{Error, Result};
+use crate::job::JobContext;
+use crate::probe;
+
+/// Largest file FAT32 can store.
+pub const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+/// Size parts are aimed at. Cuts land on keyframes and bitrate varies
+/// along a video, so this leaves room on both counts.
+const PART_TARGET_SIZE: u64 = 3_600_000_000;
+
+/// Splits tried, each with parts a quarter shorter than the last, before
+/// giving up.
+const MAX_SPLIT_ATTEMPTS: u32 = 3;
+
+/// File system names of FAT12/16/32 as Linux, macOS and Windows report them.
+const FAT_NAMES: &[&str] = &["vfat", "msdos", "fat", "fat32"];
+
+/// Whether files in `dir` are limited to 4 GiB. `false` when the file
+/// system can't be determined.
+pub fn has_4gb_limit(dir: &Path) -> bool {
+    let Ok(dir) = dir.canonicalize() else {
+        return false;
+    };
+    filesystem_name(&dir).is_some_and(|name| FAT_NAMES.contains(&name.to_lowercase().as_str()))
+}
+
+/// File system of the longest mount point containing `dir`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn filesystem_name(dir: &Path) -> Option<String> {
+    mounts()?
+        .into_iter()
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, name)| name)
+}
+
+/// Mount points and their file systems, from `/proc/self/mounts`, which
+/// escapes spaces and the like as octal (`\040`).
+#[cfg(target_os = "linux")]
+fn mounts() -> Option<Vec<(PathBuf, String)>> {
+    let table = fs::read_to_string("/proc/self/mounts").ok()?;
+    Some(
+        table
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let mount_point = fields.nth(1)?;
+                let name = fields.next()?;
+                Some((PathBuf::from(unescape_octal(mount_point)), name.to_string()))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn unescape_octal(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let code = rest
+            .get(index + 1..index + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Mount points and their file systems, from lines of `mount` like
+/// `/dev/disk4s1 on /Volumes/NO NAME (msdos, local, nodev, nosuid)`.
+#[cfg(target_os = "macos")]
+fn mounts() -> Option<Vec<(PathBuf, String)>> {
+    let output = std::process::Command::new("mount").output().ok()?;
+    let table = String::from_utf8_lossy(&output.stdout);
+    Some(
+        table
+            .lines()
+            .filter_map(|line| {
+                let (_, rest) = line.split_once(" on ")?;
+                let (mount_point, options) = rest.rsplit_once(" (")?;
+                let name = options.split([',', ')']).next()?;
+                Some((PathBuf::from(mount_point), name.to_string()))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(target_os = "windows")]
+fn filesystem_name(dir: &Path) -> Option<String> {
+    let script = format!(
+        "(Get-Volume -FilePath '{}').FileSystemType",
+        dir.to_string_lossy().replace('\'', "''")
+    );
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .output()
+        .ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// Splits `video` into `<output stem>-partNNN` files next to `output_file`,
+/// each under `MAX_FILE_SIZE`, without re-encoding. Returns the parts in
+/// order.
+pub fn split(
+    ffmpeg_path: &Path,
+    video: &Path,
+    output_file: &Path,
+    ctx: &JobContext,
+) -> Result<Vec<PathBuf>> {
+    let duration = probe::probe_duration(ffmpeg_path, video)?;
+    let size = fs::metadata(video)?.len();
+    let mut part_seconds = duration * PART_TARGET_SIZE as f64 / size as f64;
+
+    for _ in 0..MAX_SPLIT_ATTEMPTS {
+        let parts = write_parts(ffmpeg_path, video, output_file, part_seconds, ctx)?;
+        let too_big = parts
+            .iter()
+            .any(|part| fs::metadata(part).is_ok_and(|meta| meta.len() > MAX_FILE_SIZE));
+        if !too_big {
+            return Ok(parts);
+        }
+        for part in &parts {
+            fs::remove_file(part).ok();
+        }
+        part_seconds *= 0.75;
+    }
+
+    Err(Error::EncodeFailed {
+        stderr_tail: format!("Could not split {} into parts under 4 GB", video.display()),
+        log_path: None,
+    })
+}
+
+fn write_parts(
+    ffmpeg_path: &Path,
+    video: &Path,
+    output_file: &Path,
+    part_seconds: f64,
+    ctx: &JobContext,
+) -> Result<Vec<PathBuf>> {
+    let stem = output_file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let extension = output_file
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let dir = output_file.parent().unwrap_or(Path::new("."));
+    let prefix = format!("{}-part", stem);
+    let pattern = dir.join(format!("{}%03d.{}", prefix, extension));
+
+    // Parts of an earlier, longer split would look like part of this one
+    for part in parts(dir, &prefix)? {
+        fs::remove_file(part).map_err(|e| Error::io("Failed to remove old part", e))?;
+    }
+
+    let mut args: Vec<String> = vec![
+        "-i".into(),
+        video.to_string_lossy().to_string(),
+        "-map".into(),
+        "0".into(),
+        "-c".into(),
+        "copy".into(),
+        "-f".into(),
+        "segment".into(),
+        "-segment_time".into(),
+        format!("{:.3}", part_seconds),
+        "-segment_start_number".into(),
+        "1".into(),
+        // Each part plays on its own
+        "-reset_timestamps".into(),
+        "1".into(),
+    ];
+    if matches!(extension.to_lowercase().as_str(), "mp4" | "mov" | "m4v") {
+        args.extend([
+            "-segment_format_options".into(),
+            "movflags=+faststart".into(),
+        ]);
+    }
+    args.extend(["-y".into(), pattern.to_string_lossy().to_string()]);
+    compressor::run_encode(
+        ffmpeg_path,
+        &args,
+        &video.to_string_lossy(),
+        output_file,
+        ctx,
+    )?;
+
+    parts(dir, &prefix)
+}
+
+/// Files in `dir` named `<prefix>NNN.*`, in order.
+fn parts(dir: &Path, prefix: &str) -> Result<Vec<PathBuf>> {
+    let mut parts: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| Error::io("Failed to read output directory", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix(prefix))
+                .is_some_and(|number| {
+                    number.len() == 3 && number.bytes().all(|b| b.is_ascii_digit())
+                })
+        })
+        .collect();
+    parts.sort();
+    Ok(parts)
+}
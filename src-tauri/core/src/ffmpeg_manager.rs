@@ -1,10 +1,13 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use dirs;
-use reqwest;
 use std::io::Write;
 
+use tracing::{error, info};
+
+use crate::error::{Error, Result};
+use crate::paths;
+
 #[cfg(target_os = "windows")]
 const FFMPEG_URL: &str = "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-win64-gpl.zip";
 #[cfg(target_os = "windows")]
@@ -25,13 +28,15 @@ pub struct FFmpegManager {
     ffmpeg_path: PathBuf,
 }
 
+impl Default for FFmpegManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FFmpegManager {
     pub fn new() -> Self {
-        let app_data_dir = dirs::data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("media-compressor");
-        
-        let ffmpeg_dir = app_data_dir.join("ffmpeg");
+        let ffmpeg_dir = paths::app_data_dir().join("ffmpeg");
         let ffmpeg_path = ffmpeg_dir.join(FFMPEG_EXECUTABLE);
         
         Self {
@@ -51,6 +56,17 @@ impl FFmpegManager {
         }
     }
     
+    /// The ffmpeg to use if one is already installed, without downloading.
+    pub fn installed_ffmpeg_path(&self) -> Option<PathBuf> {
+        if self.is_ffmpeg_available() {
+            Some(self.ffmpeg_path.clone())
+        } else if self.is_system_ffmpeg_available() {
+            Some(PathBuf::from("ffmpeg"))
+        } else {
+            None
+        }
+    }
+    
     pub fn is_ffmpeg_available(&self) -> bool {
         self.ffmpeg_path.exists() && self.test_ffmpeg(&self.ffmpeg_path)
     }
@@ -67,7 +83,7 @@ impl FFmpegManager {
             .unwrap_or(false)
     }
     
-    pub async fn ensure_ffmpeg(&self) -> Result<PathBuf, String> {
+    pub async fn ensure_ffmpeg(&self) -> Result<PathBuf> {
         if self.is_ffmpeg_available() {
             return Ok(self.ffmpeg_path.clone());
         }
@@ -76,35 +92,46 @@ impl FFmpegManager {
             return Ok(PathBuf::from("ffmpeg"));
         }
         
-        self.download_ffmpeg().await?;
+        info!("Downloading FFmpeg from {}", FFMPEG_URL);
+        if let Err(e) = self.download_ffmpeg().await {
+            error!("FFmpeg download failed: {}", e);
+            return Err(e);
+        }
+        info!("Installed FFmpeg to {}", self.ffmpeg_path.display());
         
         if self.is_ffmpeg_available() {
             Ok(self.ffmpeg_path.clone())
         } else {
-            Err("Failed to download and install FFmpeg".to_string())
+            Err(Error::FfmpegInstallFailed {
+                message: "The download did not contain an ffmpeg executable".to_string(),
+            })
         }
     }
     
-    async fn download_ffmpeg(&self) -> Result<(), String> {
+    async fn download_ffmpeg(&self) -> Result<()> {
         fs::create_dir_all(&self.ffmpeg_dir)
-            .map_err(|e| format!("Failed to create FFmpeg directory: {}", e))?;
+            .map_err(|e| Error::io("Failed to create FFmpeg directory", e))?;
         
         let temp_file = self.ffmpeg_dir.join("ffmpeg_temp.download");
         
         // Download FFmpeg
         let response = reqwest::get(FFMPEG_URL)
             .await
-            .map_err(|e| format!("Failed to download FFmpeg: {}", e))?;
+            .map_err(|e| Error::Network {
+                message: format!("Failed to download FFmpeg: {}", e),
+            })?;
         
         let bytes = response.bytes()
             .await
-            .map_err(|e| format!("Failed to read download: {}", e))?;
+            .map_err(|e| Error::Network {
+                message: format!("Failed to read download: {}", e),
+            })?;
         
         let mut file = fs::File::create(&temp_file)
-            .map_err(|e| format!("Failed to create temp file: {}", e))?;
+            .map_err(|e| Error::io("Failed to create temp file", e))?;
         
         file.write_all(&bytes)
-            .map_err(|e| format!("Failed to write temp file: {}", e))?;
+            .map_err(|e| Error::io("Failed to write temp file", e))?;
         
         // Extract based on platform
         #[cfg(target_os = "windows")]
@@ -124,39 +151,43 @@ impl FFmpegManager {
         {
             use std::os::unix::fs::PermissionsExt;
             let mut perms = fs::metadata(&self.ffmpeg_path)
-                .map_err(|e| format!("Failed to get file metadata: {}", e))?
+                .map_err(|e| Error::io("Failed to get file metadata", e))?
                 .permissions();
             perms.set_mode(0o755);
             fs::set_permissions(&self.ffmpeg_path, perms)
-                .map_err(|e| format!("Failed to set permissions: {}", e))?;
+                .map_err(|e| Error::io("Failed to set permissions", e))?;
         }
         
         Ok(())
     }
     
     #[cfg(any(target_os = "windows", target_os = "macos"))]
-    fn extract_zip(&self, archive_path: &Path) -> Result<(), String> {
+    fn extract_zip(&self, archive_path: &Path) -> Result<()> {
         use zip::ZipArchive;
         
         let file = fs::File::open(archive_path)
-            .map_err(|e| format!("Failed to open archive: {}", e))?;
+            .map_err(|e| Error::io("Failed to open archive", e))?;
         
         let mut archive = ZipArchive::new(file)
-            .map_err(|e| format!("Failed to read archive: {}", e))?;
+            .map_err(|e| Error::FfmpegInstallFailed {
+                message: format!("Failed to read archive: {}", e),
+            })?;
         
         for i in 0..archive.len() {
             let mut file = archive.by_index(i)
-                .map_err(|e| format!("Failed to extract file: {}", e))?;
+                .map_err(|e| Error::FfmpegInstallFailed {
+                    message: format!("Failed to extract file: {}", e),
+                })?;
             
             let file_name = file.name();
             
             // Look for ffmpeg executable
             if file_name.ends_with(FFMPEG_EXECUTABLE) || file_name.ends_with("ffmpeg") {
                 let mut outfile = fs::File::create(&self.ffmpeg_path)
-                    .map_err(|e| format!("Failed to create ffmpeg file: {}", e))?;
+                    .map_err(|e| Error::io("Failed to create ffmpeg file", e))?;
                 
                 std::io::copy(&mut file, &mut outfile)
-                    .map_err(|e| format!("Failed to extract ffmpeg: {}", e))?;
+                    .map_err(|e| Error::io("Failed to extract ffmpeg", e))?;
                 
                 break;
             }
@@ -166,8 +197,7 @@ impl FFmpegManager {
     }
     
     #[cfg(target_os = "linux")]
-    fn extract_tar_xz(&self, archive_path: &Path) -> Result<(), String> {
-        use flate2::read::GzDecoder;
+    fn extract_tar_xz(&self, archive_path: &Path) -> Result<()> {
         use tar::Archive;
         use std::process::Command;
         
@@ -175,32 +205,34 @@ impl FFmpegManager {
         let tar_path = self.ffmpeg_dir.join("ffmpeg.tar");
         
         Command::new("xz")
-            .args(&["-d", "-c"])
+            .args(["-d", "-c"])
             .arg(archive_path)
             .output()
-            .map_err(|e| format!("Failed to decompress xz: {}", e))
+            .map_err(|e| Error::io("Failed to decompress xz", e))
             .and_then(|output| {
                 if output.status.success() {
                     fs::write(&tar_path, output.stdout)
-                        .map_err(|e| format!("Failed to write tar file: {}", e))
+                        .map_err(|e| Error::io("Failed to write tar file", e))
                 } else {
-                    Err("Failed to decompress xz file".to_string())
+                    Err(Error::FfmpegInstallFailed {
+                        message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                    })
                 }
             })?;
         
         // Extract tar
         let tar_file = fs::File::open(&tar_path)
-            .map_err(|e| format!("Failed to open tar file: {}", e))?;
+            .map_err(|e| Error::io("Failed to open tar file", e))?;
         
         let mut archive = Archive::new(tar_file);
         
-        for entry in archive.entries().map_err(|e| format!("Failed to read tar: {}", e))? {
-            let mut entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-            let path = entry.path().map_err(|e| format!("Failed to get path: {}", e))?;
+        for entry in archive.entries().map_err(|e| Error::io("Failed to read tar", e))? {
+            let mut entry = entry.map_err(|e| Error::io("Failed to read entry", e))?;
+            let path = entry.path().map_err(|e| Error::io("Failed to get path", e))?;
             
             if path.ends_with("ffmpeg") {
                 entry.unpack(&self.ffmpeg_path)
-                    .map_err(|e| format!("Failed to extract ffmpeg: {}", e))?;
+                    .map_err(|e| Error::io("Failed to extract ffmpeg", e))?;
                 break;
             }
         }
@@ -0,0 +1,228 @@
+//! Video filter graphs built from typed stages instead of pasted strings.
+//! A graph is a chain of filters on the first input, optionally finished by
+//! a watermark overlay from a second input, and renders to the `-vf` or
+//! `-filter_complex` options ffmpeg needs for it.
+
+use std::path::{Path, PathBuf};
+
+use crate::presets::{ColorRange, ColorSpace, Denoise, Watermark, WatermarkPosition};
+use crate::probe::Crop;
+
+/// Mild spatial/temporal smoothing that keeps detail.
+const HQDN3D_FILTER: &str = "hqdn3d=2:1.5:3:2.25";
+const NLMEANS_FILTER: &str = "nlmeans=s=3";
+/// HDR to SDR through linear light with zscale, Hable's curve keeping
+/// highlight detail. Ends in limited-range BT.709 `yuv420p`.
+const TONEMAP_FILTERS: &str = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+     tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+
+/// One stage of a chain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Crop(Crop),
+    /// Shrinks to at most this height, keeping the aspect ratio with an
+    /// even width. Smaller videos pass through.
+    MaxHeight(u32),
+    /// Shrinks to at most this width with Lanczos, which keeps small
+    /// outputs like GIFs sharp.
+    MaxWidth(u32),
+    /// Scales to exactly this height, keeping the aspect ratio.
+    Height(u32),
+    /// Scales down to fit inside the box, keeping the aspect ratio.
+    Fit {
+        width: u32,
+        height: u32,
+    },
+    /// Converts the color matrix and range; `Source` leaves either as is.
+    Color {
+        space: ColorSpace,
+        range: ColorRange,
+    },
+    /// Tone maps HDR (PQ or HLG) to SDR BT.709.
+    Tonemap,
+    /// Drops or duplicates frames to this constant rate.
+    Fps(f64),
+    Denoise(Denoise),
+    /// Luma-only unsharp mask over 5x5 pixels with this strength.
+    Sharpen(f32),
+    /// Draws subtitles from `path`, or from its subtitle stream `track`.
+    Subtitles {
+        path: PathBuf,
+        track: Option<u32>,
+    },
+    /// Speeds playback up by this factor (below 1 slows it down).
+    Speed(f32),
+    /// A filter as ffmpeg writes it, for anything not covered above.
+    Custom(String),
+}
+
+impl Filter {
+    pub fn render(&self) -> String {
+        match self {
+            Self::Crop(crop) => crop.filter(),
+            Self::MaxHeight(height) => format!("scale=-2:min(ih\\,{})", height),
+            Self::MaxWidth(width) => format!("scale=min(iw\\,{}):-2:flags=lanczos", width),
+            Self::Height(height) => format!("scale=-2:{}", height),
+            Self::Fit { width, height } => format!(
+                "scale={}:{}:force_original_aspect_ratio=decrease",
+                width, height
+            ),
+            Self::Color { space, range } => {
+                let mut options = Vec::new();
+                if *space == ColorSpace::Bt709 {
+                    options.push("out_color_matrix=bt709");
+                }
+                match range {
+                    ColorRange::Limited => options.push("out_range=tv"),
+                    ColorRange::Full => options.push("out_range=pc"),
+                    ColorRange::Source => {}
+                }
+                if options.is_empty() {
+                    return "null".to_string();
+                }
+                format!("scale={}", options.join(":"))
+            }
+            Self::Tonemap => TONEMAP_FILTERS.to_string(),
+            Self::Fps(rate) => format!("fps={}", rate),
+            Self::Denoise(Denoise::Light) => HQDN3D_FILTER.to_string(),
+            Self::Denoise(Denoise::Strong) => NLMEANS_FILTER.to_string(),
+            Self::Sharpen(amount) => format!("unsharp=5:5:{}:5:5:0", amount),
+            Self::Subtitles { path, track: None } => {
+                format!("subtitles={}", escape_filter_value(path))
+            }
+            Self::Subtitles {
+                path,
+                track: Some(track),
+            } => format!("subtitles={}:si={}", escape_filter_value(path), track),
+            Self::Speed(speed) => format!("setpts=PTS/{}", speed),
+            Self::Custom(filter) => filter.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FilterGraph {
+    filters: Vec<Filter>,
+    /// The input the watermark comes from, and how it is placed.
+    overlay: Option<(usize, Watermark)>,
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `filter` after the ones already in the chain.
+    pub fn push(&mut self, filter: Filter) {
+        self.filters.push(filter);
+    }
+
+    /// Overlays input `input` on the filtered video as `watermark` says.
+    pub fn overlay(&mut self, input: usize, watermark: Watermark) {
+        self.overlay = Some((input, watermark));
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// Whether the graph leaves the picture as decoded.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.overlay.is_none()
+    }
+
+    /// The filters joined into one chain, `null` when there are none.
+    pub fn chain(&self) -> String {
+        if self.filters.is_empty() {
+            return "null".to_string();
+        }
+        self.filters
+            .iter()
+            .map(Filter::render)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// ffmpeg output options applying the graph: a `-vf` chain, or with an
+    /// overlay a `-filter_complex` whose video and the first input's audio
+    /// are mapped explicitly, since a graph with two inputs needs that.
+    pub fn args(&self) -> Vec<String> {
+        match &self.overlay {
+            Some((input, watermark)) => vec![
+                "-filter_complex".into(),
+                overlay_graph(&self.chain(), *input, watermark),
+                "-map".into(),
+                "[v]".into(),
+                "-map".into(),
+                "0:a?".into(),
+            ],
+            None if !self.filters.is_empty() => vec!["-vf".into(), self.chain()],
+            None => Vec::new(),
+        }
+    }
+
+    /// A `-filter_complex` running the chain once, then splitting its frames
+    /// into one branch per graph in `branches`, output as `[v0]`, `[v1]`,
+    /// and so on. Overlays on the branches are ignored.
+    pub fn split(&self, branches: &[FilterGraph]) -> String {
+        let mut graph = "[0:v]".to_string();
+        if !self.filters.is_empty() {
+            graph.push_str(&self.chain());
+            graph.push(',');
+        }
+        graph.push_str(&format!("split={}", branches.len()));
+        for index in 0..branches.len() {
+            graph.push_str(&format!("[s{}]", index));
+        }
+        for (index, branch) in branches.iter().enumerate() {
+            graph.push_str(&format!(";[s{}]{}[v{}]", index, branch.chain(), index));
+        }
+        graph
+    }
+}
+
+/// Runs `chain` on the video (input 0), then overlays the watermark
+/// (`input`) scaled relative to the result. The output pad is `[v]`.
+fn overlay_graph(chain: &str, input: usize, watermark: &Watermark) -> String {
+    // Keep a small gap from the edges, proportional to the frame
+    let (x, y) = match watermark.position {
+        WatermarkPosition::TopLeft => ("W*0.02", "H*0.02"),
+        WatermarkPosition::TopRight => ("W-w-W*0.02", "H*0.02"),
+        WatermarkPosition::BottomLeft => ("W*0.02", "H-h-H*0.02"),
+        WatermarkPosition::BottomRight => ("W-w-W*0.02", "H-h-H*0.02"),
+        WatermarkPosition::Center => ("(W-w)/2", "(H-h)/2"),
+    };
+
+    format!(
+        "[0:v]{chain}[base];\
+         [{input}:v]format=rgba,colorchannelmixer=aa={opacity}[mark];\
+         [mark][base]scale2ref=w=main_w*{scale}:h=ow/a[mark][base];\
+         [base][mark]overlay={x}:{y}[v]",
+        opacity = watermark.opacity,
+        scale = watermark.scale,
+    )
+}
+
+/// Escapes a path for use as a filter option inside a filter graph, which
+/// takes two rounds: one for the option parser and one for the graph parser.
+fn escape_filter_value(path: &Path) -> String {
+    // Forward slashes work on Windows too and need no escaping
+    let value = path.to_string_lossy().replace('\\', "/");
+
+    let mut option = String::new();
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            option.push('\\');
+        }
+        option.push(c);
+    }
+
+    let mut graph = String::new();
+    for c in option.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph.push('\\');
+        }
+        graph.push(c);
+    }
+    graph
+}
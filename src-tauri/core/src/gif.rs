@@ -0,0 +1,251 @@
+//! GIFs from video clips. gifski picks a palette per frame and dithers
+//! across frames, which beats ffmpeg by a wide margin; without it ffmpeg
+//! builds one palette for the clip and dithers with it.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use crate::compressor::{self, prepare_output_dir, CompressionResult};
+use crate::encoders::{self, ExternalEncoder};
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::filter_graph::{Filter, FilterGraph};
+use crate::job::JobContext;
+use crate::probe;
+use crate::settings::Settings;
+
+/// GIF frame delays are in hundredths of a second, and most browsers slow
+/// down anything faster than 50 fps.
+const MAX_FPS: u32 = 50;
+/// Longest clip, in frames; GIFs that long are already tens of megabytes.
+const MAX_FRAMES: f64 = 1500.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GifOptions {
+    pub fps: u32,
+    /// Widest the GIF gets; smaller videos keep their width.
+    pub width: u32,
+    /// Where the clip starts in the video, in seconds.
+    pub start_secs: f64,
+    /// Clip length in seconds; `None` runs to the end of the video.
+    pub duration_secs: Option<f64>,
+    /// gifski quality (1-100); lower is smaller and noisier.
+    pub quality: u8,
+    /// Dithering for ffmpeg's palette, when gifski isn't installed.
+    pub dither: GifDither,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            fps: 15,
+            width: 480,
+            start_secs: 0.0,
+            duration_secs: None,
+            quality: 90,
+            dither: GifDither::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GifDither {
+    /// Flat color bands, but the smallest files.
+    None,
+    /// An ordered pattern that compresses well and doesn't crawl between
+    /// frames.
+    Bayer,
+    FloydSteinberg,
+    /// Error diffusion that is lighter than Floyd-Steinberg; ffmpeg's
+    /// default.
+    #[default]
+    Sierra,
+}
+
+impl GifDither {
+    fn filter_option(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Bayer => "bayer:bayer_scale=3",
+            Self::FloydSteinberg => "floyd_steinberg",
+            Self::Sierra => "sierra2_4a",
+        }
+    }
+}
+
+/// Turns a clip of `input_path` into `<name>.gif`, with gifski when it is
+/// installed and ffmpeg otherwise.
+pub async fn make_gif(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    options: &GifOptions,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+    // Without a length, the clip runs to the end of the video
+    let clip_secs = options.duration_secs.or_else(|| {
+        probe::probe_media(&ffmpeg_path, input)
+            .ok()
+            .and_then(|info| info.duration)
+            .map(|duration| (duration - options.start_secs).max(0.0))
+    });
+    validate(options, clip_secs)?;
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+    let name = settings.output_file_name(&input.file_stem().unwrap().to_string_lossy(), "video");
+    let output_file = output_dir.join(format!("{}.gif", name));
+
+    let mut graph = FilterGraph::new();
+    graph.push(Filter::Fps(options.fps as f64));
+    graph.push(Filter::MaxWidth(options.width));
+
+    // Input seeking, so only the clip is decoded
+    let mut args = Vec::new();
+    if options.start_secs > 0.0 {
+        args.extend(["-ss".to_string(), format!("{:.3}", options.start_secs)]);
+    }
+    if let Some(duration) = options.duration_secs {
+        args.extend(["-t".to_string(), format!("{:.3}", duration)]);
+    }
+    args.extend(["-i".to_string(), input_path.to_string()]);
+
+    match encoders::find(ExternalEncoder::Gifski) {
+        Some(gifski) => {
+            let frames_dir = encoders::scratch_file("frames");
+            let made = extract_frames(&ffmpeg_path, args, &graph, &frames_dir, input_path, ctx)
+                .and_then(|frames| run_gifski(&gifski, &frames, &output_file, options, ctx));
+            fs::remove_dir_all(&frames_dir).ok();
+            made?;
+        }
+        None => {
+            // One palette from the whole clip, then every frame dithered to it
+            let filters = format!(
+                "{},split[a][b];[a]palettegen=stats_mode=diff[p];[b][p]paletteuse=dither={}",
+                graph.chain(),
+                options.dither.filter_option()
+            );
+            args.extend(["-vf".to_string(), filters]);
+            args.extend(["-loop", "0", "-y"].map(String::from));
+            args.push(output_file.to_string_lossy().to_string());
+            compressor::run_encode(&ffmpeg_path, &args, input_path, &output_file, ctx)?;
+        }
+    }
+
+    let mut result = CompressionResult::new(
+        fs::metadata(input)?.len(),
+        fs::metadata(&output_file)?.len(),
+        &output_file,
+        started,
+    );
+    if let Ok((width, height)) = image::image_dimensions(&output_file) {
+        (result.width, result.height) = (Some(width), Some(height));
+    }
+    result.codec = Some("gif".to_string());
+    Ok(result)
+}
+
+fn validate(options: &GifOptions, clip_secs: Option<f64>) -> Result<()> {
+    if options.fps == 0 || options.fps > MAX_FPS {
+        return Err(invalid(format!(
+            "GIF frame rate must be between 1 and {} fps",
+            MAX_FPS
+        )));
+    }
+    if options.width < 16 {
+        return Err(invalid("GIF width must be at least 16 pixels"));
+    }
+    if options.quality == 0 || options.quality > 100 {
+        return Err(invalid("GIF quality must be between 1 and 100"));
+    }
+    if options.start_secs < 0.0 {
+        return Err(invalid("The clip can't start before the video"));
+    }
+    match clip_secs {
+        Some(duration) if duration <= 0.0 => Err(invalid("The clip must be longer than 0 seconds")),
+        Some(duration) if duration * options.fps as f64 > MAX_FRAMES => Err(invalid(format!(
+            "The clip is too long for a GIF; keep it under {} frames",
+            MAX_FRAMES
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Writes the clip's frames as PNGs into `frames_dir` and returns them in
+/// order.
+fn extract_frames(
+    ffmpeg_path: &Path,
+    mut args: Vec<String>,
+    graph: &FilterGraph,
+    frames_dir: &Path,
+    input_path: &str,
+    ctx: &JobContext,
+) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(frames_dir).map_err(|e| Error::io("Failed to create frames folder", e))?;
+    let pattern = frames_dir.join("frame%05d.png");
+    args.extend(graph.args());
+    args.push(pattern.to_string_lossy().to_string());
+    compressor::run_encode(ffmpeg_path, &args, input_path, &pattern, ctx)?;
+
+    let mut frames: Vec<PathBuf> = fs::read_dir(frames_dir)
+        .map_err(|e| Error::io("Failed to read frames", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    frames.sort();
+    if frames.is_empty() {
+        return Err(invalid(
+            "The clip has no frames; check its start and length",
+        ));
+    }
+    Ok(frames)
+}
+
+fn run_gifski(
+    gifski_path: &Path,
+    frames: &[PathBuf],
+    output_file: &Path,
+    options: &GifOptions,
+    ctx: &JobContext,
+) -> Result<()> {
+    if ctx.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    ctx.writing(output_file);
+    let output = Command::new(gifski_path)
+        .args(["--quiet", "--fps", &options.fps.to_string()])
+        .args(["--quality", &options.quality.to_string()])
+        .arg("-o")
+        .arg(output_file)
+        .args(frames)
+        .output()
+        .map_err(|e| Error::io("Failed to run gifski", e))?;
+    if !output.status.success() {
+        fs::remove_file(output_file).ok();
+        return Err(Error::Image {
+            message: format!(
+                "gifski failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidRequest {
+        message: message.into(),
+    }
+}
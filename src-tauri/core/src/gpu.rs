@@ -0,0 +1,170 @@
+//! GPUs that hardware encodes can be put on. ffmpeg lets an encode pick
+//! its device for NVENC (`-gpu`) and, on Linux, for Quick Sync (a DRM
+//! render node); VideoToolbox, AMF and Media Foundation always use the
+//! system's choice, so only NVIDIA and Intel GPUs are listed.
+
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+use crate::settings::{GpuSettings, HardwareDecoding};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareEncoder {
+    Nvenc,
+    Qsv,
+}
+
+impl HardwareEncoder {
+    /// The encoder family behind an ffmpeg codec name like `hevc_nvenc`;
+    /// `None` for software encoders and those without device selection.
+    pub fn of_codec(codec: &str) -> Option<Self> {
+        if codec.ends_with("_nvenc") {
+            Some(Self::Nvenc)
+        } else if codec.ends_with("_qsv") {
+            Some(Self::Qsv)
+        } else {
+            None
+        }
+    }
+
+    fn id_prefix(self) -> &'static str {
+        match self {
+            Self::Nvenc => "nvenc:",
+            Self::Qsv => "qsv:",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GpuDevice {
+    /// What `GpuSettings::devices` stores, e.g. `nvenc:1` or
+    /// `qsv:/dev/dri/renderD129`.
+    pub id: String,
+    pub name: String,
+    pub encoder: HardwareEncoder,
+}
+
+/// GPUs found on this machine, NVIDIA ones in `nvidia-smi` order first.
+pub fn list_devices() -> Vec<GpuDevice> {
+    let mut devices = nvidia_devices();
+    devices.extend(intel_devices());
+    devices
+}
+
+/// The GPUs `settings` allow for `encoder`; empty when none is chosen and
+/// the driver decides.
+pub fn selected_devices(settings: &GpuSettings, encoder: HardwareEncoder) -> Vec<&str> {
+    settings
+        .devices
+        .iter()
+        .map(String::as_str)
+        .filter(|id| id.starts_with(encoder.id_prefix()))
+        .collect()
+}
+
+/// Where an encode with `codec` goes when nothing else decided: the first
+/// chosen GPU for its encoder.
+pub fn default_device<'a>(settings: &'a GpuSettings, codec: &str) -> Option<&'a str> {
+    let encoder = HardwareEncoder::of_codec(codec)?;
+    selected_devices(settings, encoder).first().copied()
+}
+
+/// ffmpeg options that put an encode with `codec` on `device`, placed after
+/// `-c:v`. Nothing when the device is for another encoder family.
+pub fn device_args(codec: &str, device: &str) -> Vec<String> {
+    match HardwareEncoder::of_codec(codec) {
+        Some(HardwareEncoder::Nvenc) => device
+            .strip_prefix(HardwareEncoder::Nvenc.id_prefix())
+            .map(|index| vec!["-gpu".to_string(), index.to_string()])
+            .unwrap_or_default(),
+        // A global option, so its position doesn't matter
+        Some(HardwareEncoder::Qsv) => device
+            .strip_prefix(HardwareEncoder::Qsv.id_prefix())
+            .map(|node| vec!["-qsv_device".to_string(), node.to_string()])
+            .unwrap_or_default(),
+        None => Vec::new(),
+    }
+}
+
+/// ffmpeg input options that decode the source on the GPU, placed before
+/// its `-i`. NVDEC and VAAPI use the encode's GPU when it is of their kind.
+pub fn decode_args(decoding: HardwareDecoding, device: Option<&str>) -> Vec<String> {
+    let (hwaccel, device) = match decoding {
+        HardwareDecoding::Off => return Vec::new(),
+        HardwareDecoding::Auto => ("auto", None),
+        HardwareDecoding::VideoToolbox => ("videotoolbox", None),
+        HardwareDecoding::Nvdec => (
+            "cuda",
+            device.and_then(|device| device.strip_prefix(HardwareEncoder::Nvenc.id_prefix())),
+        ),
+        // The render node Quick Sync encodes on decodes just as well
+        HardwareDecoding::Vaapi => (
+            "vaapi",
+            device.and_then(|device| device.strip_prefix(HardwareEncoder::Qsv.id_prefix())),
+        ),
+    };
+    let mut args = vec!["-hwaccel".to_string(), hwaccel.to_string()];
+    if let Some(device) = device {
+        args.extend(["-hwaccel_device".to_string(), device.to_string()]);
+    }
+    args
+}
+
+/// `-gpu` takes the same index `nvidia-smi` reports.
+fn nvidia_devices() -> Vec<GpuDevice> {
+    let Ok(output) = Command::new("nvidia-smi")
+        .args(["--query-gpu=index,name", "--format=csv,noheader"])
+        .output()
+    else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (index, name) = line.split_once(',')?;
+            Some(GpuDevice {
+                id: format!("{}{}", HardwareEncoder::Nvenc.id_prefix(), index.trim()),
+                name: name.trim().to_string(),
+                encoder: HardwareEncoder::Nvenc,
+            })
+        })
+        .collect()
+}
+
+/// Render nodes whose PCI vendor is Intel.
+#[cfg(target_os = "linux")]
+fn intel_devices() -> Vec<GpuDevice> {
+    const INTEL_VENDOR_ID: &str = "0x8086";
+
+    let Ok(entries) = std::fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|node| node.starts_with("renderD"))
+        .filter(|node| {
+            std::fs::read_to_string(format!("/sys/class/drm/{}/device/vendor", node))
+                .is_ok_and(|vendor| vendor.trim() == INTEL_VENDOR_ID)
+        })
+        .collect();
+    nodes.sort();
+    nodes
+        .into_iter()
+        .map(|node| GpuDevice {
+            id: format!("{}/dev/dri/{}", HardwareEncoder::Qsv.id_prefix(), node),
+            name: format!("Intel GPU ({})", node),
+            encoder: HardwareEncoder::Qsv,
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn intel_devices() -> Vec<GpuDevice> {
+    Vec::new()
+}
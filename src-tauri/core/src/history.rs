@@ -0,0 +1,485 @@
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+use crate::paths;
+
+const HISTORY_DB: &str = "history.db";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRecord {
+    pub id: i64,
+    pub input_path: String,
+    pub output_path: Option<String>,
+    pub media_type: String,
+    pub original_size: u64,
+    pub compressed_size: Option<u64>,
+    /// JSON snapshot of the preset the job ran with.
+    pub settings: String,
+    pub duration_ms: u64,
+    /// `completed` or `failed`.
+    pub status: String,
+    pub error: Option<String>,
+    /// Unix timestamp (seconds) when the job finished.
+    pub created_at: i64,
+    /// BLAKE3 of the input, for recognizing files that were already processed.
+    pub input_hash: Option<String>,
+    /// BLAKE3 of the output, for recognizing outputs fed back in as inputs.
+    pub output_hash: Option<String>,
+    /// Where the output was uploaded, when uploads are on.
+    pub remote_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Statistics {
+    pub total_files: u64,
+    pub failed_files: u64,
+    pub total_original_bytes: u64,
+    pub total_compressed_bytes: u64,
+    pub total_bytes_saved: u64,
+    pub by_media_type: Vec<MediaTypeStatistics>,
+    /// Daily savings (UTC), oldest first.
+    pub over_time: Vec<SavingsPoint>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaTypeStatistics {
+    pub media_type: String,
+    pub files: u64,
+    pub bytes_saved: u64,
+    /// Average compressed/original size ratio; lower is better.
+    pub average_ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavingsPoint {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub files: u64,
+    pub bytes_saved: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// A finished job about to be written to the history.
+pub struct NewJob<'a> {
+    pub input_path: &'a str,
+    pub output_path: Option<&'a str>,
+    pub media_type: &'a str,
+    pub original_size: u64,
+    pub compressed_size: Option<u64>,
+    pub settings: String,
+    pub duration_ms: u64,
+    pub error: Option<&'a str>,
+    pub input_hash: Option<&'a str>,
+    pub output_hash: Option<&'a str>,
+}
+
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    pub fn open() -> Result<Self> {
+        let data_dir = paths::app_data_dir();
+        std::fs::create_dir_all(&data_dir)
+            .map_err(|e| Error::io("Failed to create data directory", e))?;
+
+        let conn = Connection::open(data_dir.join(HISTORY_DB))
+            .map_err(|e| Error::database("Failed to open history database", e))?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                input_path TEXT NOT NULL,
+                output_path TEXT,
+                media_type TEXT NOT NULL,
+                original_size INTEGER NOT NULL,
+                compressed_size INTEGER,
+                settings TEXT NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                error TEXT,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS jobs_input_path ON jobs (input_path);
+            CREATE INDEX IF NOT EXISTS jobs_created_at ON jobs (created_at);",
+        )
+        .map_err(|e| Error::database("Failed to initialize history database", e))?;
+
+        migrate(&conn).map_err(|e| Error::database("Failed to upgrade history database", e))?;
+
+        Ok(Self { conn })
+    }
+
+    pub fn record(&self, job: &NewJob) -> Result<i64> {
+        let status = if job.error.is_some() {
+            "failed"
+        } else {
+            "completed"
+        };
+
+        self.conn
+            .execute(
+                "INSERT INTO jobs (input_path, output_path, media_type, original_size,
+                    compressed_size, settings, duration_ms, status, error, created_at, input_hash,
+                    output_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    job.input_path,
+                    job.output_path,
+                    job.media_type,
+                    job.original_size as i64,
+                    job.compressed_size.map(|size| size as i64),
+                    job.settings,
+                    job.duration_ms as i64,
+                    status,
+                    job.error,
+                    unix_now(),
+                    job.input_hash,
+                    job.output_hash,
+                ],
+            )
+            .map_err(|e| Error::database("Failed to record job", e))?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Most recent jobs first.
+    pub fn recent(&self, limit: u32) -> Result<Vec<JobRecord>> {
+        self.query(
+            "SELECT * FROM jobs ORDER BY created_at DESC, id DESC LIMIT ?1",
+            params![limit],
+        )
+    }
+
+    /// Every recorded job for one input file, most recent first.
+    pub fn for_input(&self, input_path: &str) -> Result<Vec<JobRecord>> {
+        self.query(
+            "SELECT * FROM jobs WHERE input_path = ?1 ORDER BY created_at DESC, id DESC",
+            params![input_path],
+        )
+    }
+
+    /// Every recorded job, oldest first.
+    pub fn all(&self) -> Result<Vec<JobRecord>> {
+        self.query("SELECT * FROM jobs ORDER BY created_at, id", [])
+    }
+
+    /// Writes the full history to `path`, returning the number of jobs exported.
+    pub fn export(&self, path: &Path, format: ExportFormat) -> Result<usize> {
+        let records = self.all()?;
+
+        let contents = match format {
+            ExportFormat::Json => {
+                serde_json::to_string_pretty(&records).map_err(|e| Error::Io {
+                    message: format!("Failed to serialize history: {}", e),
+                })?
+            }
+            ExportFormat::Csv => records_to_csv(&records),
+        };
+
+        fs::write(path, contents).map_err(|e| Error::io("Failed to write export", e))?;
+        Ok(records.len())
+    }
+
+    /// Input hashes of every job that completed successfully.
+    pub fn processed_hashes(&self) -> Result<HashSet<String>> {
+        let map_err = |e| Error::database("Failed to query history", e);
+        self.conn
+            .prepare(
+                "SELECT DISTINCT input_hash FROM jobs
+                 WHERE status = 'completed' AND input_hash IS NOT NULL",
+            )
+            .map_err(map_err)?
+            .query_map([], |row| row.get(0))
+            .map_err(map_err)?
+            .collect::<rusqlite::Result<HashSet<String>>>()
+            .map_err(map_err)
+    }
+
+    /// Output hashes of every job that completed successfully.
+    pub fn output_hashes(&self) -> Result<HashSet<String>> {
+        let map_err = |e| Error::database("Failed to query history", e);
+        self.conn
+            .prepare(
+                "SELECT DISTINCT output_hash FROM jobs
+                 WHERE status = 'completed' AND output_hash IS NOT NULL",
+            )
+            .map_err(map_err)?
+            .query_map([], |row| row.get(0))
+            .map_err(map_err)?
+            .collect::<rusqlite::Result<HashSet<String>>>()
+            .map_err(map_err)
+    }
+
+    /// Points jobs recorded for `old_path` at `new_path`, e.g. the URL a
+    /// temporary download came from.
+    pub fn rename_input(&self, old_path: &str, new_path: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE jobs SET input_path = ?1 WHERE input_path = ?2",
+                params![new_path, old_path],
+            )
+            .map_err(|e| Error::database("Failed to update history", e))?;
+        Ok(())
+    }
+
+    pub fn set_remote_url(&self, id: i64, url: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE jobs SET remote_url = ?1 WHERE id = ?2",
+                params![url, id],
+            )
+            .map_err(|e| Error::database("Failed to record upload", e))?;
+        Ok(())
+    }
+
+    pub fn is_output(&self, hash: &str) -> Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM jobs WHERE status = 'completed' AND output_hash = ?1)",
+                [hash],
+                |row| row.get(0),
+            )
+            .map_err(|e| Error::database("Failed to query history", e))
+    }
+
+    pub fn is_processed(&self, input_hash: &str) -> Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM jobs WHERE status = 'completed' AND input_hash = ?1)",
+                [input_hash],
+                |row| row.get(0),
+            )
+            .map_err(|e| Error::database("Failed to query history", e))
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM jobs", [])
+            .map_err(|e| Error::database("Failed to clear history", e))?;
+        Ok(())
+    }
+
+    /// Lifetime totals over completed jobs. Outputs that came out larger than
+    /// the input count as zero savings rather than negative.
+    pub fn statistics(&self) -> Result<Statistics> {
+        let map_err = |e| Error::database("Failed to compute statistics", e);
+
+        let (total_files, total_original_bytes, total_compressed_bytes, total_bytes_saved) = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*),
+                        COALESCE(SUM(original_size), 0),
+                        COALESCE(SUM(compressed_size), 0),
+                        COALESCE(SUM(MAX(original_size - compressed_size, 0)), 0)
+                 FROM jobs WHERE status = 'completed'",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)? as u64,
+                        row.get::<_, i64>(1)? as u64,
+                        row.get::<_, i64>(2)? as u64,
+                        row.get::<_, i64>(3)? as u64,
+                    ))
+                },
+            )
+            .map_err(map_err)?;
+
+        let failed_files = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM jobs WHERE status = 'failed'",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(map_err)? as u64;
+
+        let by_media_type = self
+            .conn
+            .prepare(
+                "SELECT media_type,
+                        COUNT(*),
+                        COALESCE(SUM(MAX(original_size - compressed_size, 0)), 0),
+                        COALESCE(AVG(CASE WHEN original_size > 0
+                            THEN CAST(compressed_size AS REAL) / original_size END), 0)
+                 FROM jobs WHERE status = 'completed'
+                 GROUP BY media_type ORDER BY media_type",
+            )
+            .map_err(map_err)?
+            .query_map([], |row| {
+                Ok(MediaTypeStatistics {
+                    media_type: row.get(0)?,
+                    files: row.get::<_, i64>(1)? as u64,
+                    bytes_saved: row.get::<_, i64>(2)? as u64,
+                    average_ratio: row.get(3)?,
+                })
+            })
+            .map_err(map_err)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(map_err)?;
+
+        let over_time = self
+            .conn
+            .prepare(
+                "SELECT date(created_at, 'unixepoch') AS day,
+                        COUNT(*),
+                        COALESCE(SUM(MAX(original_size - compressed_size, 0)), 0)
+                 FROM jobs WHERE status = 'completed'
+                 GROUP BY day ORDER BY day",
+            )
+            .map_err(map_err)?
+            .query_map([], |row| {
+                Ok(SavingsPoint {
+                    date: row.get(0)?,
+                    files: row.get::<_, i64>(1)? as u64,
+                    bytes_saved: row.get::<_, i64>(2)? as u64,
+                })
+            })
+            .map_err(map_err)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(map_err)?;
+
+        Ok(Statistics {
+            total_files,
+            failed_files,
+            total_original_bytes,
+            total_compressed_bytes,
+            total_bytes_saved,
+            by_media_type,
+            over_time,
+        })
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<JobRecord>> {
+        let mut statement = self
+            .conn
+            .prepare(sql)
+            .map_err(|e| Error::database("Failed to query history", e))?;
+
+        let records = statement
+            .query_map(params, job_from_row)
+            .map_err(|e| Error::database("Failed to query history", e))?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(|e| Error::database("Failed to read history", e))?;
+
+        Ok(records)
+    }
+}
+
+/// Brings databases created by older versions up to the current schema.
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let has_input_hash = conn
+        .prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = 'input_hash'")?
+        .exists([])?;
+    if !has_input_hash {
+        conn.execute_batch("ALTER TABLE jobs ADD COLUMN input_hash TEXT")?;
+    }
+    let has_output_hash = conn
+        .prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = 'output_hash'")?
+        .exists([])?;
+    if !has_output_hash {
+        conn.execute_batch("ALTER TABLE jobs ADD COLUMN output_hash TEXT")?;
+    }
+    let has_remote_url = conn
+        .prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = 'remote_url'")?
+        .exists([])?;
+    if !has_remote_url {
+        conn.execute_batch("ALTER TABLE jobs ADD COLUMN remote_url TEXT")?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS jobs_input_hash ON jobs (input_hash);
+         CREATE INDEX IF NOT EXISTS jobs_output_hash ON jobs (output_hash);",
+    )
+}
+
+fn job_from_row(row: &Row) -> rusqlite::Result<JobRecord> {
+    Ok(JobRecord {
+        id: row.get("id")?,
+        input_path: row.get("input_path")?,
+        output_path: row.get("output_path")?,
+        media_type: row.get("media_type")?,
+        original_size: row.get::<_, i64>("original_size")? as u64,
+        compressed_size: row
+            .get::<_, Option<i64>>("compressed_size")?
+            .map(|size| size as u64),
+        settings: row.get("settings")?,
+        duration_ms: row.get::<_, i64>("duration_ms")? as u64,
+        status: row.get("status")?,
+        error: row.get("error")?,
+        created_at: row.get("created_at")?,
+        input_hash: row.get("input_hash")?,
+        output_hash: row.get("output_hash")?,
+        remote_url: row.get("remote_url")?,
+    })
+}
+
+fn records_to_csv(records: &[JobRecord]) -> String {
+    let mut csv = String::from(
+        "id,input_path,output_path,media_type,original_size,compressed_size,bytes_saved,\
+         settings,duration_ms,status,error,created_at,input_hash,output_hash,remote_url\n",
+    );
+
+    for record in records {
+        let bytes_saved = record
+            .compressed_size
+            .map(|size| record.original_size.saturating_sub(size).to_string())
+            .unwrap_or_default();
+
+        let fields = [
+            record.id.to_string(),
+            csv_field(&record.input_path),
+            csv_field(record.output_path.as_deref().unwrap_or("")),
+            csv_field(&record.media_type),
+            record.original_size.to_string(),
+            record
+                .compressed_size
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+            bytes_saved,
+            csv_field(&record.settings),
+            record.duration_ms.to_string(),
+            csv_field(&record.status),
+            csv_field(record.error.as_deref().unwrap_or("")),
+            record.created_at.to_string(),
+            record.input_hash.clone().unwrap_or_default(),
+            record.output_hash.clone().unwrap_or_default(),
+            csv_field(record.remote_url.as_deref().unwrap_or("")),
+        ];
+
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote, or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,199 @@
+//! Optional localhost REST API so other programs on the machine can submit
+//! jobs to the running app. Every request must carry
+//! `Authorization: Bearer <token>` with the token from the settings; a new
+//! token applies from the next request on.
+//!
+//! - `POST /jobs` with a `JobRequest` body enqueues a job
+//! - `GET /jobs` lists all jobs, `GET /jobs/{id}` returns one
+//! - `GET /queue` returns the queue status: jobs in start order, batch
+//!   progress
+//! - `POST /jobs/{id}/cancel` cancels a job
+//! - `POST /batches/{id}/cancel` cancels what's left of a batch
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use std::io::Read;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::info;
+
+use crate::error::{Error, Result};
+use crate::queue::{JobQueue, JobRequest};
+use crate::settings::HttpApiSettings;
+
+const TOKEN_LENGTH: usize = 32;
+/// Largest `POST /jobs` body accepted; a job request is a few hundred bytes.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// The server while it's running.
+struct Running {
+    server: Arc<Server>,
+    port: u16,
+    /// Checked on every request, so swapping it locks the old one out at
+    /// once. Empty once the server is stopped.
+    token: Arc<RwLock<String>>,
+}
+
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Brings the server in line with `settings`: starts it on a background
+/// thread, stops it, moves it to a new port, or swaps its token.
+pub fn apply(queue: JobQueue, settings: &HttpApiSettings) -> Result<()> {
+    let mut running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    let keep = settings.enabled && !settings.token.is_empty();
+    if let Some(current) = running.as_ref() {
+        if keep && current.port == settings.port {
+            *current.token.write().unwrap_or_else(|e| e.into_inner()) = settings.token.clone();
+            return Ok(());
+        }
+    }
+    if let Some(previous) = running.take() {
+        // Requests already accepted are refused rather than served
+        previous
+            .token
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        previous.server.unblock();
+        info!("HTTP API on port {} stopped", previous.port);
+    }
+
+    if !settings.enabled {
+        return Ok(());
+    }
+    if settings.token.is_empty() {
+        return Err(Error::invalid_settings(
+            "The HTTP API needs a token before it can be enabled",
+        ));
+    }
+
+    // Loopback only: this API is for programs on the same machine
+    let server = Server::http(("127.0.0.1", settings.port)).map_err(|e| Error::Io {
+        message: format!("Failed to start HTTP API on port {}: {}", settings.port, e),
+    })?;
+    let server = Arc::new(server);
+    let token = Arc::new(RwLock::new(settings.token.clone()));
+
+    let (listener, current_token) = (server.clone(), token.clone());
+    thread::spawn(move || {
+        for request in listener.incoming_requests() {
+            let token = current_token
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            handle(&queue, &token, request);
+        }
+    });
+
+    *running = Some(Running {
+        server,
+        port: settings.port,
+        token,
+    });
+    Ok(())
+}
+
+fn handle(queue: &JobQueue, token: &str, mut request: Request) {
+    let response = if !is_authorized(&request, token) {
+        error_response(401, "Missing or invalid token")
+    } else {
+        route(queue, &mut request)
+    };
+
+    request.respond(response).ok();
+}
+
+fn route(queue: &JobQueue, request: &mut Request) -> Response<std::io::Cursor<Vec<u8>>> {
+    let url = request.url().trim_end_matches('/').to_string();
+    let segments: Vec<&str> = url.trim_start_matches('/').split('/').collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Post, ["jobs"]) => {
+            if request
+                .body_length()
+                .is_some_and(|length| length > MAX_BODY_BYTES)
+            {
+                return error_response(413, "Request body too large");
+            }
+            // The length is only a claim, and chunked bodies have none
+            let mut body = String::new();
+            if let Err(e) = request
+                .as_reader()
+                .take(MAX_BODY_BYTES as u64 + 1)
+                .read_to_string(&mut body)
+            {
+                return error_response(400, &format!("Failed to read body: {}", e));
+            }
+            if body.len() > MAX_BODY_BYTES {
+                return error_response(413, "Request body too large");
+            }
+
+            match serde_json::from_str::<JobRequest>(&body) {
+                Ok(job_request) => json_response(201, &queue.enqueue(job_request)),
+                Err(e) => error_response(400, &format!("Invalid job request: {}", e)),
+            }
+        }
+        (Method::Get, ["jobs"]) => json_response(200, &queue.jobs()),
+        (Method::Get, ["queue"]) => json_response(200, &queue.status()),
+        (Method::Get, ["jobs", id]) => match id.parse().ok().and_then(|id| queue.job(id)) {
+            Some(job) => json_response(200, &job),
+            None => error_response(404, "Unknown job"),
+        },
+        (Method::Post, ["jobs", id, "cancel"]) => match id.parse() {
+            Ok(id) => match queue.cancel(id) {
+                Ok(job) => json_response(200, &job),
+                Err(e @ Error::UnknownJob { .. }) => error_response(404, &e.to_string()),
+                Err(e) => error_response(409, &e.to_string()),
+            },
+            Err(_) => error_response(404, "Unknown job"),
+        },
+        (Method::Post, ["batches", id, "cancel"]) => {
+            match id.parse().map(|id| queue.cancel_batch(id)) {
+                Ok(Ok(report)) => json_response(200, &report),
+                Ok(Err(e)) => error_response(404, &e.to_string()),
+                Err(_) => error_response(404, "Unknown batch"),
+            }
+        }
+        _ => error_response(404, "Not found"),
+    }
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    if token.is_empty() {
+        return false;
+    }
+    let expected = format!("Bearer {}", token);
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .map(|header| constant_time_eq(header.value.as_str().as_bytes(), expected.as_bytes()))
+        .unwrap_or(false)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(
+            Header::from_bytes("Content-Type", "application/json").expect("static header is valid"),
+        )
+}
+
+fn error_response(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
@@ -0,0 +1,136 @@
+//! Whether the computer is free for background work: nobody has touched the
+//! keyboard or mouse for a while, and other apps leave the CPU mostly idle.
+//! Both come from system tools, and are `None` where those aren't available.
+
+use std::process::Command;
+use std::time::Duration;
+
+/// Time since the last keyboard or mouse input.
+#[cfg(target_os = "linux")]
+pub fn user_idle_time() -> Option<Duration> {
+    // X11 via xprintidle (ms), else GNOME's idle monitor, which also covers
+    // Wayland and replies like `(uint64 123456,)`
+    if let Some(millis) = command_output("xprintidle", &[]) {
+        return millis.trim().parse().ok().map(Duration::from_millis);
+    }
+    let reply = command_output(
+        "gdbus",
+        &[
+            "call",
+            "--session",
+            "--dest",
+            "org.gnome.Mutter.IdleMonitor",
+            "--object-path",
+            "/org/gnome/Mutter/IdleMonitor/Core",
+            "--method",
+            "org.gnome.Mutter.IdleMonitor.GetIdletime",
+        ],
+    )?;
+    reply
+        .trim()
+        .trim_start_matches("(uint64 ")
+        .trim_end_matches(",)")
+        .parse()
+        .ok()
+        .map(Duration::from_millis)
+}
+
+/// Time since the last keyboard or mouse input, from the `HIDIdleTime`
+/// (nanoseconds) that `ioreg` reports for the HID system.
+#[cfg(target_os = "macos")]
+pub fn user_idle_time() -> Option<Duration> {
+    let output = command_output("ioreg", &["-c", "IOHIDSystem", "-d", "4"])?;
+    output
+        .lines()
+        .find_map(|line| line.split_once("\"HIDIdleTime\" = ")?.1.trim().parse().ok())
+        .map(Duration::from_nanos)
+}
+
+/// Time since the last keyboard or mouse input, from `GetLastInputInfo`.
+#[cfg(target_os = "windows")]
+pub fn user_idle_time() -> Option<Duration> {
+    const SCRIPT: &str = "Add-Type -TypeDefinition 'using System; \
+        using System.Runtime.InteropServices; \
+        public static class LastInput { \
+            [StructLayout(LayoutKind.Sequential)] struct Info { public uint Size; public uint Time; } \
+            [DllImport(\"user32.dll\")] static extern bool GetLastInputInfo(ref Info info); \
+            public static uint IdleMillis() { \
+                var info = new Info { Size = 8 }; \
+                GetLastInputInfo(ref info); \
+                return (uint)Environment.TickCount - info.Time; } }'; \
+        [LastInput]::IdleMillis()";
+    let millis = command_output("powershell", &["-NoProfile", "-Command", SCRIPT])?;
+    millis.trim().parse().ok().map(Duration::from_millis)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub fn user_idle_time() -> Option<Duration> {
+    None
+}
+
+/// Share of all cores in use, in percent, over the last second.
+#[cfg(target_os = "linux")]
+pub fn cpu_usage() -> Option<f32> {
+    // The first line of /proc/stat adds up every core's time in each state
+    fn sample() -> Option<(u64, u64)> {
+        let stat = std::fs::read_to_string("/proc/stat").ok()?;
+        let times: Vec<u64> = stat
+            .lines()
+            .next()?
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|field| field.parse().ok())
+            .collect();
+        // idle and iowait
+        let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+        Some((times.iter().sum(), idle))
+    }
+
+    let (total_before, idle_before) = sample()?;
+    std::thread::sleep(Duration::from_secs(1));
+    let (total, idle) = sample()?;
+    let elapsed = total.checked_sub(total_before).filter(|&ticks| ticks > 0)?;
+    let idled = idle.saturating_sub(idle_before);
+    Some(100.0 * elapsed.saturating_sub(idled) as f32 / elapsed as f32)
+}
+
+/// Share of all cores in use, in percent, from the recent usage `ps`
+/// reports per process.
+#[cfg(target_os = "macos")]
+pub fn cpu_usage() -> Option<f32> {
+    let output = command_output("ps", &["-A", "-o", "%cpu="])?;
+    let total: f32 = output
+        .lines()
+        .filter_map(|line| line.trim().parse::<f32>().ok())
+        .sum();
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    Some(total / cores as f32)
+}
+
+/// Share of all cores in use, in percent, averaged over the processors.
+#[cfg(target_os = "windows")]
+pub fn cpu_usage() -> Option<f32> {
+    let output = command_output(
+        "powershell",
+        &[
+            "-NoProfile",
+            "-Command",
+            "(Get-CimInstance Win32_Processor | Measure-Object -Property LoadPercentage -Average).Average",
+        ],
+    )?;
+    output.trim().parse().ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub fn cpu_usage() -> Option<f32> {
+    None
+}
+
+/// Stdout of a command that succeeded.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,88 @@
+//! Compresses a batch of images in one call, spread over every core. Image
+//! encodes are single-threaded, so one file at a time leaves most of the
+//! machine idle.
+
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::compressor::{self, CompressionResult};
+use crate::error::Error;
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::presets::Preset;
+use crate::settings::Settings;
+
+/// Outcome for one input; exactly one of `result` and `error` is set.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageBatchItem {
+    /// Position of the input in the submitted list.
+    pub index: usize,
+    pub input_path: String,
+    pub result: Option<CompressionResult>,
+    pub error: Option<Error>,
+}
+
+/// Reported as each file finishes, in completion order.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageBatchProgress {
+    /// Files finished so far, including this one.
+    pub completed: usize,
+    pub total: usize,
+    pub item: ImageBatchItem,
+}
+
+/// Compresses `input_paths` in parallel and returns their outcomes in input
+/// order. One failed file doesn't stop the others; once `ctx` is cancelled,
+/// files that haven't started fail with `Error::Cancelled`. Each goes
+/// through `compress_file` like any other job, so this has to be called
+/// from a blocking thread of the tokio runtime.
+pub fn compress_images(
+    input_paths: &[String],
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+    on_progress: impl Fn(&ImageBatchProgress) + Sync,
+) -> Vec<ImageBatchItem> {
+    let completed = AtomicUsize::new(0);
+    let runtime = tokio::runtime::Handle::current();
+
+    input_paths
+        .par_iter()
+        .enumerate()
+        .map(|(index, input_path)| {
+            let result = if ctx.is_cancelled() {
+                Err(Error::Cancelled)
+            } else if MediaType::from_path(Path::new(input_path)) != Some(MediaType::Image) {
+                Err(Error::UnsupportedFormat {
+                    path: input_path.clone(),
+                })
+            } else {
+                runtime.block_on(compressor::compress_file(
+                    input_path,
+                    output_path.clone(),
+                    settings,
+                    preset,
+                    ctx,
+                ))
+            };
+
+            let item = ImageBatchItem {
+                index,
+                input_path: input_path.clone(),
+                error: result.as_ref().err().cloned(),
+                result: result.ok(),
+            };
+            on_progress(&ImageBatchProgress {
+                completed: completed.fetch_add(1, Ordering::SeqCst) + 1,
+                total: input_paths.len(),
+                item: item.clone(),
+            });
+            item
+        })
+        .collect()
+}
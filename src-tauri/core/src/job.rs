@@ -0,0 +1,415 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info, warn};
+
+use crate::paths;
+use crate::probe;
+
+/// How often a running ffmpeg process is checked for exit or cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Lines of stderr kept in memory for error reports.
+pub const STDERR_TAIL_LINES: usize = 50;
+/// Longer lines are truncated in the tail (but not in the log).
+const MAX_TAIL_LINE_LENGTH: usize = 1000;
+/// Older job logs beyond this many are deleted when a new one is written.
+const MAX_JOB_LOGS: usize = 200;
+
+type OutputListener = Arc<dyn Fn(&Path) + Send + Sync>;
+type ProgressListener = Arc<dyn Fn(&JobProgress) + Send + Sync>;
+
+/// Where a running encode is, from ffmpeg's status line.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    /// Position reached in the output, in seconds.
+    pub time_secs: f64,
+    /// Input duration, from the header ffmpeg prints first.
+    pub duration_secs: Option<f64>,
+    /// 0-100, when the duration is known.
+    pub percent: Option<f64>,
+    pub fps: Option<f64>,
+    /// Encoding speed relative to playback, e.g. `2.5` for `2.5x`.
+    pub speed: Option<f64>,
+    /// Output bitrate so far, in kbit/s.
+    pub bitrate_kbps: Option<f64>,
+    /// Seconds left at the current speed.
+    pub eta_secs: Option<f64>,
+}
+
+/// Per-job handle shared between whoever runs a job and whoever may cancel it.
+#[derive(Clone, Default)]
+pub struct JobContext {
+    cancelled: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    on_output: Option<OutputListener>,
+    on_progress: Option<ProgressListener>,
+    gpu: Option<Arc<str>>,
+    filters: Arc<[String]>,
+}
+
+impl fmt::Debug for JobContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobContext")
+            .field("cancelled", &self.is_cancelled())
+            .field("paused", &self.is_paused())
+            .field("gpu", &self.gpu)
+            .field("filters", &self.filters)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JobContext {
+    /// Reports every output the job starts writing, so whatever a crash
+    /// leaves half-written can be found afterwards.
+    pub fn with_output_listener(
+        mut self,
+        listener: impl Fn(&Path) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_output = Some(Arc::new(listener));
+        self
+    }
+
+    /// Reports encode progress, a few times a second while ffmpeg runs.
+    /// Listeners run on the thread reading ffmpeg's output.
+    pub fn with_progress_listener(
+        mut self,
+        listener: impl Fn(&JobProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Arc::new(listener));
+        self
+    }
+
+    /// Puts the job's hardware encodes on a `gpu::GpuDevice`, by id.
+    pub fn with_gpu(mut self, device: &str) -> Self {
+        self.gpu = Some(device.into());
+        self
+    }
+
+    pub fn gpu(&self) -> Option<&str> {
+        self.gpu.as_deref()
+    }
+
+    /// Adds ffmpeg video filters to the job's encodes, after the preset's.
+    pub fn with_filters(mut self, filters: Vec<String>) -> Self {
+        self.filters = filters.into();
+        self
+    }
+
+    pub fn filters(&self) -> &[String] {
+        &self.filters
+    }
+
+    /// Called by long-running writers just before they create `path`.
+    pub fn writing(&self, path: &Path) {
+        if let Some(listener) = &self.on_output {
+            listener(path);
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Suspends the job's ffmpeg process until `resume`, where the system
+    /// allows it (not on Windows). Other work carries on.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+}
+
+pub struct FfmpegOutput {
+    pub status: ExitStatus,
+    /// The last `STDERR_TAIL_LINES` lines ffmpeg printed; the full output
+    /// goes to the job log.
+    pub stderr_tail: String,
+}
+
+/// Runs ffmpeg to completion, killing it as soon as the job is cancelled and
+/// suspending it while the job is paused. Cancellation surfaces as an
+/// `Interrupted` error. Stderr is streamed into
+/// `log` as it arrives rather than buffered, since long encodes print
+/// megabytes of progress.
+pub fn run_ffmpeg(
+    ffmpeg_path: &Path,
+    args: &[String],
+    ctx: &JobContext,
+    log: Option<&JobLog>,
+) -> io::Result<FfmpegOutput> {
+    info!("Starting ffmpeg ({})", ffmpeg_path.display());
+    debug!("ffmpeg command: {}", command_line(ffmpeg_path, args));
+
+    let mut child = Command::new(ffmpeg_path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain stderr on its own thread so a chatty encode can't fill the pipe
+    // and stall ffmpeg while we poll
+    let stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let log_file = log.and_then(|log| log.file.try_clone().ok());
+    let progress = ctx.on_progress.clone();
+    let stderr_reader = thread::spawn(move || read_stderr(stderr_pipe, log_file, progress));
+
+    let mut suspended = false;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let stderr_tail = stderr_reader.join().unwrap_or_default();
+            if status.success() {
+                info!("ffmpeg finished");
+            } else {
+                warn!("ffmpeg failed with {}", status);
+            }
+            if let Some(log) = log {
+                log.append(&format!("\nFinished with {}\n", status));
+            }
+            return Ok(FfmpegOutput {
+                status,
+                stderr_tail,
+            });
+        }
+
+        if ctx.is_cancelled() {
+            child.kill().ok();
+            child.wait().ok();
+            info!("ffmpeg cancelled");
+            if let Some(log) = log {
+                log.append("\nCancelled\n");
+            }
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Job cancelled"));
+        }
+
+        if ctx.is_paused() != suspended && set_suspended(child.id(), !suspended) {
+            suspended = !suspended;
+            info!("ffmpeg {}", if suspended { "suspended" } else { "resumed" });
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Stops or continues a process with `kill`.
+#[cfg(unix)]
+fn set_suspended(pid: u32, suspended: bool) -> bool {
+    let signal = if suspended { "-STOP" } else { "-CONT" };
+    Command::new("kill")
+        .args([signal, &pid.to_string()])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(not(unix))]
+fn set_suspended(_pid: u32, _suspended: bool) -> bool {
+    false
+}
+
+/// Copies stderr to the log, reports progress, and returns its last lines.
+/// ffmpeg redraws its progress line with `\r`, so that counts as a line
+/// break too.
+fn read_stderr(
+    mut pipe: impl Read,
+    mut log: Option<fs::File>,
+    on_progress: Option<ProgressListener>,
+) -> String {
+    let mut tail: VecDeque<String> = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut line = Vec::new();
+    let mut buffer = [0u8; 8192];
+    let mut progress = ProgressParser::default();
+
+    let mut push_line = |line: &mut Vec<u8>| {
+        if line.is_empty() {
+            return;
+        }
+        if tail.len() == STDERR_TAIL_LINES {
+            tail.pop_front();
+        }
+        let text = String::from_utf8_lossy(line).into_owned();
+        if let Some(listener) = &on_progress {
+            if let Some(progress) = progress.parse(&text) {
+                listener(&progress);
+            }
+        }
+        tail.push_back(text);
+        line.clear();
+    };
+
+    loop {
+        let read = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+
+        if let Some(file) = &mut log {
+            // A failing log must not stop us draining the pipe
+            if file.write_all(&buffer[..read]).is_err() {
+                log = None;
+            }
+        }
+
+        for &byte in &buffer[..read] {
+            match byte {
+                b'\n' | b'\r' => push_line(&mut line),
+                _ if line.len() < MAX_TAIL_LINE_LENGTH => line.push(byte),
+                _ => {}
+            }
+        }
+    }
+    push_line(&mut line);
+
+    Vec::from(tail).join("\n")
+}
+
+/// Follows ffmpeg's stderr: the input duration from its header, then a
+/// `frame= 240 fps= 58 … time=00:00:08.00 bitrate=1048.6kbits/s speed=1.93x`
+/// status line per update.
+#[derive(Default)]
+struct ProgressParser {
+    duration_secs: Option<f64>,
+}
+
+impl ProgressParser {
+    fn parse(&mut self, line: &str) -> Option<JobProgress> {
+        if let Some(rest) = line.trim_start().strip_prefix("Duration:") {
+            // The first one is the input's; later ones describe other inputs
+            if self.duration_secs.is_none() {
+                self.duration_secs = rest
+                    .split(',')
+                    .next()
+                    .and_then(|duration| probe::parse_timestamp(duration.trim()))
+                    .filter(|&duration| duration > 0.0);
+            }
+            return None;
+        }
+
+        let time_secs = probe::parse_timestamp(status_field(line, "time=")?)?.max(0.0);
+        let speed = status_field(line, "speed=")
+            .and_then(|speed| speed.trim_end_matches('x').parse().ok())
+            .filter(|&speed: &f64| speed > 0.0);
+        let remaining = self
+            .duration_secs
+            .map(|duration| (duration - time_secs).max(0.0));
+        Some(JobProgress {
+            time_secs,
+            duration_secs: self.duration_secs,
+            percent: self
+                .duration_secs
+                .map(|duration| (time_secs / duration * 100.0).min(100.0)),
+            fps: status_field(line, "fps=").and_then(|fps| fps.parse().ok()),
+            speed,
+            bitrate_kbps: status_field(line, "bitrate=")
+                .and_then(|bitrate| bitrate.trim_end_matches("kbits/s").parse().ok()),
+            eta_secs: remaining
+                .zip(speed)
+                .map(|(remaining, speed)| remaining / speed),
+        })
+    }
+}
+
+/// The value after `key` in a status line, where ffmpeg pads values with
+/// spaces, as in `fps= 58`.
+fn status_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_once(key)?.1.split_whitespace().next()
+}
+
+/// Log file holding the command line and full stderr of one ffmpeg run, so
+/// users can attach it to bug reports.
+pub struct JobLog {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl JobLog {
+    /// Creates the log under the app data dir and writes the command line.
+    /// Logging is best-effort: returns `None` if the file can't be created.
+    pub fn create(ffmpeg_path: &Path, args: &[String], input_path: &str) -> Option<Self> {
+        let dir = paths::job_logs_dir();
+        fs::create_dir_all(&dir).ok()?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or(0);
+        let stem = Path::new(input_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let path = dir.join(format!("{}-{}.log", timestamp, stem));
+
+        let mut file = fs::File::create(&path).ok()?;
+        write!(
+            file,
+            "Input: {}\nCommand: {}\n\n",
+            input_path,
+            command_line(ffmpeg_path, args)
+        )
+        .ok()?;
+
+        prune_logs(&dir);
+        Some(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&self, text: &str) {
+        (&self.file).write_all(text.as_bytes()).ok();
+    }
+}
+
+/// The invocation as a copy-pasteable shell command.
+fn command_line(ffmpeg_path: &Path, args: &[String]) -> String {
+    std::iter::once(quote_arg(&ffmpeg_path.to_string_lossy()))
+        .chain(args.iter().map(|arg| quote_arg(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quote_arg(arg: &str) -> String {
+    if arg.is_empty() || arg.contains([' ', '"', '\'', '\\']) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Log names start with a millisecond timestamp, so name order is age order.
+fn prune_logs(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut logs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+
+    if logs.len() > MAX_JOB_LOGS {
+        logs.sort();
+        for path in &logs[..logs.len() - MAX_JOB_LOGS] {
+            fs::remove_file(path).ok();
+        }
+    }
+}
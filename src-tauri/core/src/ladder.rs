@@ -0,0 +1,237 @@
+//! Bitrate ladders for adaptive streaming: one video at several stepped
+//! resolutions and bitrates. A single ffmpeg run decodes the source once and
+//! splits the frames between one encoder per rendition.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compressor::{self, prepare_output_dir};
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::filter_graph::{Filter, FilterGraph};
+use crate::job::JobContext;
+use crate::presets::{parse_bitrate, Preset};
+use crate::probe;
+use crate::settings::Settings;
+
+/// Keyframe interval when the preset sets none. Renditions get keyframes at
+/// the same times, which is where players can switch between them.
+const DEFAULT_KEYFRAME_SECONDS: f32 = 2.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rendition {
+    /// Output height; the width follows the source's aspect ratio.
+    pub height: u32,
+    /// Video bitrate, e.g. `2800k`, which peaks are held to as well.
+    pub video_bitrate: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenditionOutput {
+    pub height: u32,
+    pub width: Option<u32>,
+    pub video_bitrate: String,
+    pub output_path: String,
+    pub size: u64,
+}
+
+/// 1080p down to 360p, with bitrates close to Apple's HLS authoring
+/// guidelines for H.264.
+pub fn default_ladder() -> Vec<Rendition> {
+    [
+        (1080, "5000k"),
+        (720, "2800k"),
+        (480, "1400k"),
+        (360, "800k"),
+    ]
+    .into_iter()
+    .map(|(height, video_bitrate)| Rendition {
+        height,
+        video_bitrate: video_bitrate.to_string(),
+    })
+    .collect()
+}
+
+/// Encodes `input_path` once per rendition as `<name>-<height>p.<ext>`,
+/// with the preset's codec, speed and audio. Its filters, watermark and
+/// quality settings don't apply; renditions taller than the source are
+/// skipped rather than upscaled.
+pub async fn encode_ladder(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    renditions: &[Rendition],
+    ctx: &JobContext,
+) -> Result<Vec<RenditionOutput>> {
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+    if compressor::is_lossless_video_codec(&preset.video_codec) {
+        return Err(invalid("A bitrate ladder needs a lossy video codec"));
+    }
+    validate(renditions)?;
+
+    // Upscaling only spends bits on blur
+    let source_height = probe::probe_media(&ffmpeg_path, input)?.height;
+    let renditions: Vec<&Rendition> = renditions
+        .iter()
+        .filter(|rendition| source_height.is_none_or(|height| rendition.height <= height))
+        .collect();
+    if renditions.is_empty() {
+        return Err(invalid("The video is smaller than every rendition"));
+    }
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+    let name = settings.output_file_name(&input.file_stem().unwrap().to_string_lossy(), "video");
+    let extension = compressor::video_container(&preset.video_codec).unwrap_or("mp4");
+    let outputs: Vec<PathBuf> = renditions
+        .iter()
+        .map(|rendition| output_dir.join(format!("{}-{}p.{}", name, rendition.height, extension)))
+        .collect();
+
+    let args = ladder_args(input_path, preset, &renditions, &outputs);
+    // `run_encode` announces the first output itself
+    for output in &outputs[1..] {
+        ctx.writing(output);
+    }
+    if let Err(e) = compressor::run_encode(&ffmpeg_path, &args, input_path, &outputs[0], ctx) {
+        // A cancelled run leaves every rendition truncated
+        if matches!(e, Error::Cancelled) {
+            for output in &outputs {
+                fs::remove_file(output).ok();
+            }
+        }
+        return Err(e);
+    }
+
+    renditions
+        .iter()
+        .zip(&outputs)
+        .map(|(rendition, output)| {
+            Ok(RenditionOutput {
+                height: rendition.height,
+                // Describing the output is best-effort
+                width: probe::probe_media(&ffmpeg_path, output)
+                    .ok()
+                    .and_then(|info| info.width),
+                video_bitrate: rendition.video_bitrate.clone(),
+                output_path: output.to_string_lossy().to_string(),
+                size: fs::metadata(output)?.len(),
+            })
+        })
+        .collect()
+}
+
+fn validate(renditions: &[Rendition]) -> Result<()> {
+    if renditions.is_empty() {
+        return Err(invalid("A bitrate ladder needs at least one rendition"));
+    }
+    for (index, rendition) in renditions.iter().enumerate() {
+        // 4:2:0 video needs even dimensions
+        if rendition.height == 0 || rendition.height % 2 != 0 {
+            return Err(invalid(format!(
+                "Rendition height must be even and above 0, not {}",
+                rendition.height
+            )));
+        }
+        if parse_bitrate(&rendition.video_bitrate).is_none_or(|bitrate| bitrate == 0) {
+            return Err(invalid(format!(
+                "Invalid rendition bitrate: {}",
+                rendition.video_bitrate
+            )));
+        }
+        if renditions[..index]
+            .iter()
+            .any(|other| other.height == rendition.height)
+        {
+            return Err(invalid(format!(
+                "More than one rendition is {}p",
+                rendition.height
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The source split into one scaled stream per rendition, then one output
+/// per stream with its own encoder and bitrate.
+fn ladder_args(
+    input_path: &str,
+    preset: &Preset,
+    renditions: &[&Rendition],
+    outputs: &[PathBuf],
+) -> Vec<String> {
+    let branches: Vec<FilterGraph> = renditions
+        .iter()
+        .map(|rendition| {
+            let mut branch = FilterGraph::new();
+            branch.push(Filter::Height(rendition.height));
+            branch
+        })
+        .collect();
+
+    let keyframes = Preset {
+        keyframe_interval_secs: Some(
+            preset
+                .keyframe_interval_secs
+                .unwrap_or(DEFAULT_KEYFRAME_SECONDS),
+        ),
+        fixed_keyframes: true,
+        ..preset.clone()
+    };
+    let audio_codec = match preset.video_codec.as_str() {
+        "libvpx-vp9" => "libopus",
+        _ => "aac",
+    };
+
+    let mut args: Vec<String> = vec![
+        "-i".into(),
+        input_path.to_string(),
+        "-filter_complex".into(),
+        FilterGraph::new().split(&branches),
+    ];
+    for (index, (rendition, output)) in renditions.iter().zip(outputs).enumerate() {
+        let bitrate = parse_bitrate(&rendition.video_bitrate).unwrap_or_default();
+        args.extend([
+            "-map".into(),
+            format!("[v{}]", index),
+            "-map".into(),
+            "0:a?".into(),
+            "-c:v".into(),
+            preset.video_codec.clone(),
+            "-pix_fmt".into(),
+            "yuv420p".into(),
+            "-b:v".into(),
+            bitrate.to_string(),
+        ]);
+        args.extend(compressor::rate_cap_args(bitrate));
+        args.extend(compressor::keyframe_args(&keyframes));
+        args.extend(compressor::speed_args(preset));
+        args.extend([
+            "-c:a".into(),
+            audio_codec.into(),
+            "-b:a".into(),
+            preset.audio_bitrate.clone(),
+        ]);
+        if compressor::video_container(&preset.video_codec).is_none() {
+            args.extend(["-movflags".into(), "+faststart".into()]);
+        }
+        args.extend(["-y".into(), output.to_string_lossy().to_string()]);
+    }
+    args
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidRequest {
+        message: message.into(),
+    }
+}
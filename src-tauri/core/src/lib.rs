@@ -0,0 +1,50 @@
+//! Compression engine shared by the desktop app and `media-compressor-cli`.
+
+pub mod archive;
+pub mod batch_zip;
+pub mod capture_date;
+pub mod checksum;
+pub mod compressor;
+pub mod concurrency;
+pub mod contact_sheet;
+pub mod custom_args;
+pub mod destination;
+pub mod diagnostics;
+pub mod download;
+pub mod dry_run;
+pub mod encoders;
+pub mod encryption;
+pub mod error;
+pub mod fat32;
+pub mod ffmpeg_manager;
+pub mod filter_graph;
+pub mod gif;
+pub mod gpu;
+pub mod history;
+pub mod http_api;
+pub mod idle;
+pub mod image_batch;
+pub mod job;
+pub mod ladder;
+pub mod live_photo;
+pub mod logging;
+pub mod media;
+pub mod office;
+pub mod paths;
+pub mod pdf;
+pub mod perceptual;
+pub mod presets;
+pub mod preview;
+pub mod probe;
+pub mod queue;
+pub mod report;
+pub mod s3;
+pub mod scan;
+pub mod scenes;
+pub mod script;
+pub mod settings;
+pub mod stdio;
+pub mod streaming;
+pub mod svg;
+pub mod units;
+pub mod webhook;
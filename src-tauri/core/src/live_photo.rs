@@ -0,0 +1,64 @@
+//! iPhone Live Photos: a still (HEIC, or JPEG with "Most Compatible") and a
+//! short MOV of the same name, e.g. `IMG_1234.HEIC` and `IMG_1234.MOV`.
+//! Outputs keep the input's stem, so compressed halves stay paired by name.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STILL_EXTENSIONS: &[&str] = &["heic", "heif", "jpg", "jpeg"];
+const MOTION_EXTENSION: &str = "mov";
+
+/// Pairs up the Live Photo halves among `paths`, which should be the files
+/// of one directory. Each half maps to the other.
+pub fn pairs(paths: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    let mut stills: HashMap<(PathBuf, String), &PathBuf> = HashMap::new();
+    let mut motions = Vec::new();
+    for path in paths {
+        let Some(key) = pair_key(path) else {
+            continue;
+        };
+        match extension(path).as_deref() {
+            Some(MOTION_EXTENSION) => motions.push((key, path)),
+            Some(extension) if STILL_EXTENSIONS.contains(&extension) => {
+                stills.entry(key).or_insert(path);
+            }
+            _ => {}
+        }
+    }
+
+    let mut pairs = HashMap::new();
+    for (key, motion) in motions {
+        if let Some(still) = stills.get(&key) {
+            pairs.insert(motion.clone(), (*still).clone());
+            pairs.insert((*still).clone(), motion.clone());
+        }
+    }
+    pairs
+}
+
+/// The other half of the Live Photo `path` belongs to, if it has one.
+pub fn partner(path: &Path) -> Option<PathBuf> {
+    let parent = path.parent()?;
+    let siblings: Vec<PathBuf> = fs::read_dir(parent)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    pairs(&siblings).remove(path)
+}
+
+/// Whether `path` is the video half of a Live Photo.
+pub fn is_motion(path: &Path) -> bool {
+    extension(path).as_deref() == Some(MOTION_EXTENSION) && partner(path).is_some()
+}
+
+/// Directory and case-folded stem, since cameras and file managers don't
+/// agree on extension case.
+fn pair_key(path: &Path) -> Option<(PathBuf, String)> {
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    Some((path.parent()?.to_path_buf(), stem))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_lowercase())
+}
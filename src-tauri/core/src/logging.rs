@@ -0,0 +1,111 @@
+//! App-wide log file. Entries go to `logs/media-compressor.log` in the app
+//! data dir, which rotates by size so it never grows without bound.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use crate::error::{Error, Result};
+use crate::paths;
+
+const LOG_FILE: &str = "media-compressor.log";
+/// The log rotates once it would grow past this size.
+const MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+/// Rotated logs kept next to the live one, as `.1` (newest) to `.3`.
+const MAX_ROTATED_LOGS: usize = 3;
+
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Installs the global subscriber. Only the first call has any effect.
+pub fn init(debug: bool) -> Result<()> {
+    if LEVEL.get().is_some() {
+        return Ok(());
+    }
+
+    let writer = RotatingFile::open(paths::logs_dir().join(LOG_FILE))
+        .map_err(|e| Error::io("Failed to open log file", e))?;
+
+    let (level, handle) = reload::Layer::new(level_filter(debug));
+    let subscriber = tracing_subscriber::registry().with(level).with(
+        fmt::layer()
+            .with_ansi(false)
+            .with_target(false)
+            .with_writer(Mutex::new(writer)),
+    );
+
+    if tracing::subscriber::set_global_default(subscriber).is_ok() {
+        LEVEL.set(handle).ok();
+    }
+    Ok(())
+}
+
+/// Switches between info and debug level without restarting.
+pub fn set_debug(debug: bool) {
+    if let Some(handle) = LEVEL.get() {
+        handle.modify(|level| *level = level_filter(debug)).ok();
+    }
+}
+
+fn level_filter(debug: bool) -> LevelFilter {
+    if debug {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::INFO
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..MAX_ROTATED_LOGS).rev() {
+            fs::rename(
+                rotated_path(&self.path, index),
+                rotated_path(&self.path, index + 1),
+            )
+            .ok();
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "avi", "mov", "mkv", "wmv", "flv"];
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "aac", "wav", "flac", "ogg", "opus", "wma"];
+pub const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff", "svg", "heic", "heif",
+];
+/// Office Open XML packages whose embedded images can be recompressed.
+pub const DOCUMENT_EXTENSIONS: &[&str] = &["docx", "pptx", "xlsx"];
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MediaType {
+    Video,
+    Audio,
+    Image,
+    Document,
+    Archive,
+}
+
+impl MediaType {
+    /// Classifies a file by its extension; `None` for unsupported files.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaType::Video)
+        } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaType::Audio)
+        } else if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaType::Image)
+        } else if DOCUMENT_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaType::Document)
+        } else if ARCHIVE_EXTENSIONS.contains(&extension.as_str()) {
+            Some(MediaType::Archive)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Video => "video",
+            MediaType::Audio => "audio",
+            MediaType::Image => "image",
+            MediaType::Document => "document",
+            MediaType::Archive => "archive",
+        }
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = String;
+
+    /// Parses the names `as_str` returns.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            MediaType::Video,
+            MediaType::Audio,
+            MediaType::Image,
+            MediaType::Document,
+            MediaType::Archive,
+        ]
+        .into_iter()
+        .find(|media_type| media_type.as_str() == name)
+        .ok_or_else(|| format!("unknown media type: {}", name))
+    }
+}
+
+/// Narrows a directory walk to some media types and extensions. An empty
+/// list allows everything; files have to pass both.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MediaFilter {
+    pub media_types: Vec<MediaType>,
+    /// Extensions without the dot, in any case, e.g. `["jpg", "HEIC"]`.
+    pub extensions: Vec<String>,
+}
+
+impl MediaFilter {
+    /// Whether `path` is a supported file this filter lets through.
+    pub fn matches(&self, path: &Path) -> bool {
+        let Some(media_type) = MediaType::from_path(path) else {
+            return false;
+        };
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+        (self.media_types.is_empty() || self.media_types.contains(&media_type))
+            && (self.extensions.is_empty()
+                || self.extensions.iter().any(|allowed| {
+                    allowed
+                        .trim_start_matches('.')
+                        .eq_ignore_ascii_case(&extension)
+                }))
+    }
+}
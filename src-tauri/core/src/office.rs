@@ -0,0 +1,77 @@
+//! Shrinks photos embedded in Office Open XML documents (docx, pptx, xlsx),
+//! which are zip packages keeping their media under `<part>/media/`.
+
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::archive;
+use crate::checksum;
+use crate::compressor::{self, CompressionResult};
+use crate::error::{Error, Result};
+use crate::job::JobContext;
+use crate::presets::Preset;
+use crate::settings::Settings;
+
+/// Embedded images smaller than this are left alone; icons and bullets
+/// aren't worth re-encoding.
+const MIN_EMBEDDED_IMAGE_SIZE: u64 = 100 * 1024;
+
+pub fn compress_document(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+
+    let original_size = fs::metadata(input)?.len();
+    let output_dir = compressor::prepare_output_dir(input, output_path, settings)?;
+
+    let file_name = input.file_stem().unwrap().to_str().unwrap();
+    let extension = input
+        .extension()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or("docx");
+    let output_file = output_dir.join(format!(
+        "{}.{}",
+        settings.output_file_name(file_name, "document"),
+        extension
+    ));
+
+    archive::repack(input, &output_file, ctx, |entry| {
+        if entry.size() < MIN_EMBEDDED_IMAGE_SIZE || !is_embedded_media(entry.name()) {
+            return Ok(None);
+        }
+        archive::recompress_image_entry(entry, preset)
+    })?;
+
+    let compressed_size = fs::metadata(&output_file)?.len();
+    let mut result = if compressed_size >= original_size {
+        fs::copy(input, &output_file)?;
+        let mut result =
+            CompressionResult::new(original_size, original_size, &output_file, started);
+        result.kept_original = true;
+        result
+    } else {
+        CompressionResult::new(original_size, compressed_size, &output_file, started)
+    };
+
+    result.sha256 = checksum::write(&output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+fn is_embedded_media(name: &str) -> bool {
+    ["word/media/", "ppt/media/", "xl/media/"]
+        .iter()
+        .any(|dir| name.starts_with(dir))
+}
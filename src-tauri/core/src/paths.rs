@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+/// Per-user directory where the app keeps its own files (ffmpeg, settings, ...).
+pub fn app_data_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("media-compressor")
+}
+
+pub fn logs_dir() -> PathBuf {
+    app_data_dir().join("logs")
+}
+
+/// Downloaded image encoders, like `cjxl`.
+pub fn encoders_dir() -> PathBuf {
+    app_data_dir().join("encoders")
+}
+
+/// Scratch space for outputs that can't be written in place, on the
+/// system drive.
+pub fn staging_dir() -> PathBuf {
+    app_data_dir().join("staging")
+}
+
+/// Before/after crops made for the comparison slider.
+pub fn previews_dir() -> PathBuf {
+    app_data_dir().join("previews")
+}
+
+/// The unfinished part of the queue, kept so a later launch can resume it.
+pub fn queue_session_file() -> PathBuf {
+    app_data_dir().join("queue.json")
+}
+
+/// Where per-job ffmpeg logs are written.
+pub fn job_logs_dir() -> PathBuf {
+    logs_dir().join("jobs")
+}
@@ -0,0 +1,157 @@
+//! Assembles images into a single PDF, one page each, the way a phone
+//! "scan" of a paper document is usually shared.
+
+use image::GenericImageView;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::checksum;
+use crate::compressor::{self, CompressionResult, MIN_TARGET_JPEG_QUALITY};
+use crate::error::{Error, Result};
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::presets::Preset;
+use crate::settings::Settings;
+
+/// A4 in PDF points; landscape images get the page turned sideways.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+
+struct Page {
+    jpeg: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+/// Downscales and JPEG-encodes `inputs` with `preset`, then writes them in
+/// order as the pages of `output`. With a target size the quality of every
+/// page is lowered together until the whole document fits.
+pub fn images_to_pdf(
+    inputs: &[String],
+    output: &Path,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    let started = Instant::now();
+
+    if inputs.is_empty() {
+        return Err(Error::InvalidRequest {
+            message: "No images to put in the PDF".to_string(),
+        });
+    }
+
+    let mut original_size = 0;
+    for input in inputs {
+        let path = Path::new(input);
+        if !path.exists() {
+            return Err(Error::InputNotFound {
+                path: input.clone(),
+            });
+        }
+        if MediaType::from_path(path) != Some(MediaType::Image) {
+            return Err(Error::UnsupportedFormat {
+                path: input.clone(),
+            });
+        }
+        original_size += fs::metadata(path)?.len();
+    }
+
+    let mut quality = preset.image_quality;
+    let mut document = render(inputs, preset, quality, ctx)?;
+    if let Some(target_size) = preset.target_size_bytes() {
+        while document.len() as u64 > target_size && quality > MIN_TARGET_JPEG_QUALITY {
+            quality = quality.saturating_sub(10).max(MIN_TARGET_JPEG_QUALITY);
+            document = render(inputs, preset, quality, ctx)?;
+        }
+    }
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| Error::io("Failed to create output directory", e))?;
+    }
+    fs::write(output, &document).map_err(|e| Error::io("Failed to write PDF", e))?;
+
+    let mut result = CompressionResult::new(original_size, document.len() as u64, output, started);
+    result.codec = Some("jpeg".to_string());
+    result.sha256 = checksum::write(output, settings.checksum_mode)?;
+    Ok(result)
+}
+
+fn render(inputs: &[String], preset: &Preset, quality: u8, ctx: &JobContext) -> Result<Vec<u8>> {
+    let mut pages = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let img = compressor::resize_for_preset(compressor::open_image(Path::new(input))?, preset);
+        let (width, height) = img.dimensions();
+        pages.push(Page {
+            jpeg: compressor::encode_jpeg(&img.to_rgb8(), quality)?,
+            width,
+            height,
+        });
+    }
+    Ok(write_pdf(&pages))
+}
+
+fn write_pdf(pages: &[Page]) -> Vec<u8> {
+    let mut pdf = Pdf::new();
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    // Each page needs three objects: the page, its image and its content stream
+    let page_ids: Vec<Ref> = (0..pages.len())
+        .map(|i| Ref::new(3 + 3 * i as i32))
+        .collect();
+
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().copied())
+        .count(pages.len() as i32);
+
+    let image_name = Name(b"Im1");
+    for (page, &page_id) in pages.iter().zip(&page_ids) {
+        let image_id = Ref::new(page_id.get() + 1);
+        let content_id = Ref::new(page_id.get() + 2);
+
+        let (page_width, page_height) = if page.width > page.height {
+            (PAGE_HEIGHT, PAGE_WIDTH)
+        } else {
+            (PAGE_WIDTH, PAGE_HEIGHT)
+        };
+
+        let mut pdf_page = pdf.page(page_id);
+        pdf_page.media_box(Rect::new(0.0, 0.0, page_width, page_height));
+        pdf_page.parent(page_tree_id);
+        pdf_page.contents(content_id);
+        pdf_page.resources().x_objects().pair(image_name, image_id);
+        pdf_page.finish();
+
+        let mut image = pdf.image_xobject(image_id, &page.jpeg);
+        image.filter(Filter::DctDecode);
+        image.width(page.width as i32);
+        image.height(page.height as i32);
+        image.color_space().device_rgb();
+        image.bits_per_component(8);
+        image.finish();
+
+        // Fit the image inside the page, centered
+        let scale = (page_width / page.width as f32).min(page_height / page.height as f32);
+        let width = page.width as f32 * scale;
+        let height = page.height as f32 * scale;
+        let x = (page_width - width) / 2.0;
+        let y = (page_height - height) / 2.0;
+
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([width, 0.0, 0.0, height, x, y]);
+        content.x_object(image_name);
+        content.restore_state();
+        pdf.stream(content_id, &content.finish());
+    }
+
+    pdf.finish()
+}
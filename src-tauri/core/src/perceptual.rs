@@ -0,0 +1,32 @@
+//! Perceptual image hashes for spotting visually identical photos (burst
+//! shots, resaves at another quality or size) whose bytes differ.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// Hashes within this many differing bits are treated as the same picture.
+pub const SIMILAR_MAX_DISTANCE: u32 = 10;
+
+/// 64-bit difference hash: shrinks the image to 9x8 grayscale and records
+/// whether each pixel is brighter than its right-hand neighbour.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | (left > right) as u64;
+        }
+    }
+    hash
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+pub fn is_similar(a: u64, b: u64) -> bool {
+    distance(a, b) <= SIMILAR_MAX_DISTANCE
+}
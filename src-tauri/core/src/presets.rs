@@ -0,0 +1,820 @@
+use serde::{Deserialize, Serialize};
+
+use crate::compressor;
+use crate::custom_args;
+use crate::error::{Error, Result};
+use crate::settings::Settings;
+
+/// Fraction of the target size budgeted for the encode; the rest absorbs
+/// container overhead and rate-control overshoot.
+const TARGET_SIZE_MARGIN: f64 = 0.92;
+/// Bitrates below this produce unwatchable video, so long clips with a small
+/// target get this floor instead (and may overshoot the target).
+const MIN_VIDEO_BITRATE: u64 = 100_000;
+/// Built-ins suited to ProRes/DNxHD sources: a proxy for editing and a
+/// high-quality archive copy.
+pub const MEZZANINE_PRESETS: &[&str] = &["Editing proxy (H.264)", "Mezzanine archive (HEVC)"];
+/// Range for `Preset::speed`; ffmpeg's `atempo` handles 0.5x and up.
+const MIN_SPEED: f32 = 0.5;
+const MAX_SPEED: f32 = 100.0;
+/// Faster than this, sped-up audio is just noise and is dropped instead.
+const MAX_AUDIO_SPEED: f32 = 4.0;
+const MAX_SHARPEN: f32 = 2.0;
+/// What x264 accepts for `-profile:v`, from the most to the least widely
+/// supported.
+const H264_PROFILES: &[&str] = &["baseline", "main", "high", "high10", "high422", "high444"];
+/// Highest H.264 level, for 8K at 120 fps.
+const MAX_H264_LEVEL: f32 = 6.2;
+
+/// A named bundle of encoder parameters that can be selected per job instead
+/// of passing raw settings to every command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Preset {
+    pub name: String,
+    #[serde(default)]
+    pub builtin: bool,
+    pub video_codec: String,
+    pub video_crf: u8,
+    /// x264/x265 speed preset (`ultrafast` ... `veryslow`). SVT-AV1 also
+    /// takes its own `0` (slowest) to `13` (fastest).
+    pub encoder_preset: String,
+    pub audio_bitrate: String,
+    /// Encoder for audio-only outputs; videos get whatever their container
+    /// suits (AAC, Opus, or FLAC).
+    #[serde(default = "default_audio_codec")]
+    pub audio_codec: String,
+    /// Downmix the audio to this many channels, e.g. 2 for 5.1 sources.
+    #[serde(default)]
+    pub audio_channels: Option<u8>,
+    /// Resample the audio to this rate, in Hz.
+    #[serde(default)]
+    pub audio_sample_rate: Option<u32>,
+    /// Carry embedded cover art over to audio-only outputs whose container
+    /// can hold it (m4a, mp3, flac).
+    #[serde(default)]
+    pub keep_cover_art: bool,
+    /// Downscale videos taller than this; `None` keeps the source resolution.
+    pub max_video_height: Option<u32>,
+    pub image_quality: u8,
+    /// Downscale images whose longest side exceeds this; `None` keeps the
+    /// source resolution.
+    pub max_image_dimension: Option<u32>,
+    /// Upload limit the output has to fit under, in megabytes. When set,
+    /// videos are encoded at a computed bitrate instead of CRF and images
+    /// lower their quality until they fit.
+    #[serde(default)]
+    pub target_size_mb: Option<u32>,
+    /// Average video bitrate, e.g. `2500k`, instead of CRF. Peaks may go
+    /// well above it; the average holds.
+    #[serde(default)]
+    pub video_bitrate: Option<String>,
+    /// Peak video bitrate, e.g. `8M`, that even busy scenes stay under, for
+    /// streaming boxes and older devices. With CRF this is "constrained
+    /// quality": constant quality wherever the cap allows.
+    #[serde(default)]
+    pub max_video_bitrate: Option<String>,
+    /// Encode bitrate-based videos (`video_bitrate` or `target_size_mb`) in
+    /// two passes: the first only analyses, so the second can spend the bits
+    /// where they matter and land on the bitrate. About twice as slow.
+    #[serde(default)]
+    pub two_pass: bool,
+    /// Even out the volume of the audio track (EBU R128 `loudnorm`).
+    #[serde(default)]
+    pub normalize_loudness: bool,
+    /// Cut long silences from audio-only outputs. Never applied to video,
+    /// where it would desync picture and sound.
+    #[serde(default)]
+    pub trim_silence: bool,
+    /// x264/x265 `-tune`, e.g. `animation` for screen captures; ignored by
+    /// other encoders.
+    #[serde(default)]
+    pub tune: Option<String>,
+    /// Drop frames above this rate; lower-rate sources are left alone.
+    #[serde(default)]
+    pub max_frame_rate: Option<u32>,
+    /// Reduce sensor noise before encoding; noisy footage compresses poorly.
+    #[serde(default)]
+    pub denoise: Option<Denoise>,
+    /// Detect letterbox/pillarbox bars and crop them off.
+    #[serde(default)]
+    pub crop_black_bars: bool,
+    /// Image stamped onto every video.
+    #[serde(default)]
+    pub watermark: Option<Watermark>,
+    /// Render subtitles into the picture, for players without soft subs.
+    #[serde(default)]
+    pub burn_subtitles: Option<BurnSubtitles>,
+    /// Playback speed multiplier, e.g. `10.0` for a timelapse or `0.5` for
+    /// slow motion. Audio is time-stretched up to `MAX_AUDIO_SPEED` and
+    /// dropped beyond it.
+    #[serde(default)]
+    pub speed: Option<f32>,
+    /// SVT-AV1 film grain synthesis strength (1-50): grain is removed before
+    /// encoding and re-synthesized on playback, saving the bits it would
+    /// cost. Ignored by other encoders.
+    #[serde(default)]
+    pub film_grain: Option<u8>,
+    /// Also package the encode for streaming, next to the file output.
+    #[serde(default)]
+    pub streaming: Option<StreamingFormat>,
+    /// Force a keyframe every this many seconds, for streaming segments or
+    /// frame-accurate seeking. `None` leaves placement to the encoder.
+    #[serde(default)]
+    pub keyframe_interval_secs: Option<f32>,
+    /// Put keyframes only at the interval, not also at scene cuts, so every
+    /// GOP has the same length. x264 and x265 only.
+    #[serde(default)]
+    pub fixed_keyframes: bool,
+    /// Extra widths to write each image at, e.g. `[480, 1024, 2048]` for a
+    /// `srcset`. Widths at or above the source width are skipped.
+    #[serde(default)]
+    pub responsive_widths: Vec<u32>,
+    /// Resampling filter for downscaled images.
+    #[serde(default)]
+    pub resize_filter: ResizeFilter,
+    /// Unsharp mask strength applied after a downscale, which otherwise
+    /// looks soft; around 0.5 is subtle, 2.0 is the maximum.
+    #[serde(default)]
+    pub sharpen: Option<f32>,
+    /// x264 profile, e.g. `high`. `None` means baseline, which every
+    /// device decodes, or the lowest profile `pixel_format` allows.
+    #[serde(default)]
+    pub h264_profile: Option<String>,
+    /// x264 level, e.g. `4.1`. `None` means 3.0 with the default profile and
+    /// whatever x264 works out from the resolution and bitrate otherwise.
+    #[serde(default)]
+    pub h264_level: Option<String>,
+    /// Output pixel format as ffmpeg names it, e.g. `yuv420p10le`. `None`
+    /// means `yuv420p` for lossy codecs and the source's for lossless ones.
+    #[serde(default)]
+    pub pixel_format: Option<String>,
+    #[serde(default)]
+    pub color_space: ColorSpace,
+    #[serde(default)]
+    pub color_range: ColorRange,
+    /// Encode images as JPEG (PNG when transparent) and WebP, and keep
+    /// whichever comes out smallest, instead of picking by input format.
+    #[serde(default)]
+    pub image_format_trial: bool,
+    /// Also try AVIF in the format trial. Often the smallest, but many
+    /// times slower to encode.
+    #[serde(default)]
+    pub trial_avif: bool,
+    /// Write images as JPEG XL with `cjxl`, which has to be installed (see
+    /// `encoders`). JPEGs that aren't resized are repacked losslessly.
+    #[serde(default)]
+    pub jpeg_xl: bool,
+    /// ffmpeg output options added to video and audio encodes after the
+    /// generated ones, so they take precedence, e.g. `["-x264-params",
+    /// "aq-mode=3"]`. Limited to what `custom_args` allows.
+    #[serde(default)]
+    pub extra_ffmpeg_args: Vec<String>,
+}
+
+/// Where burned-in subtitles come from. Only text formats (SRT, ASS, WebVTT,
+/// mov_text) can be rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "camelCase")]
+pub enum BurnSubtitles {
+    /// `<video name>.srt` or `.ass` next to the input.
+    Sidecar,
+    /// The input's own subtitle stream with this index (0 = first).
+    Embedded { track: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Watermark {
+    /// PNG to overlay; transparency is respected.
+    pub path: String,
+    pub position: WatermarkPosition,
+    /// 0.0 (invisible) to 1.0 (as is).
+    pub opacity: f32,
+    /// Watermark width as a fraction of the video width.
+    pub scale: f32,
+}
+
+impl Default for Watermark {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            position: WatermarkPosition::BottomRight,
+            opacity: 0.8,
+            scale: 0.15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Denoise {
+    /// `hqdn3d`: fast, good enough for most phone footage.
+    Light,
+    /// `nlmeans`: much better on heavy low-light noise, but several times
+    /// slower than the encode itself.
+    Strong,
+}
+
+/// Color space of video outputs. Players guess when a file isn't tagged,
+/// and guess differently, which shows as shifted colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorSpace {
+    /// Whatever the source has, tags included.
+    #[default]
+    Source,
+    /// Convert to BT.709 and tag the output as BT.709, the HD standard.
+    /// HDR sources are tone mapped to SDR first.
+    Bt709,
+}
+
+/// Video levels. Phones and webcams often record full range; when that gets
+/// squeezed or left untagged, blacks turn gray and the picture looks washed
+/// out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorRange {
+    /// Keep the source's range.
+    #[default]
+    Source,
+    /// 16-235, what nearly all video uses.
+    Limited,
+    /// 0-255.
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResizeFilter {
+    /// Sharpest; best for photos.
+    #[default]
+    Lanczos3,
+    /// Slightly softer, with less ringing around hard edges in screenshots
+    /// and UI captures.
+    CatmullRom,
+    /// Bilinear. Soft, but no halos at all.
+    Triangle,
+    /// Keeps hard pixel edges, for pixel art.
+    Nearest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamingFormat {
+    /// `index.m3u8` plus segments.
+    Hls,
+    /// MPEG-DASH `manifest.mpd` plus segments.
+    Dash,
+}
+
+impl StreamingFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamingFormat::Hls => "hls",
+            StreamingFormat::Dash => "dash",
+        }
+    }
+}
+
+impl Preset {
+    /// The preset implied by the user's default settings, used when a job
+    /// doesn't name one.
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            name: "Default".to_string(),
+            video_codec: settings.video_codec.clone(),
+            video_crf: settings.video_crf,
+            audio_channels: settings.audio_channels,
+            audio_sample_rate: settings.audio_sample_rate,
+            image_quality: settings.image_quality,
+            normalize_loudness: settings.normalize_loudness,
+            trim_silence: settings.trim_silence,
+            ..base()
+        }
+    }
+
+    pub fn target_size_bytes(&self) -> Option<u64> {
+        self.target_size_mb.map(|mb| mb as u64 * 1_000_000)
+    }
+
+    /// Video bitrate in bits per second that keeps a clip of `duration`
+    /// seconds under the target size, leaving room for the audio track and
+    /// container overhead.
+    pub fn target_video_bitrate(&self, duration: f64) -> Option<u64> {
+        let target_bytes = self.target_size_bytes()?;
+        let duration = duration / self.speed.unwrap_or(1.0) as f64;
+        if duration <= 0.0 {
+            return None;
+        }
+
+        let total_bitrate = (target_bytes as f64 * 8.0 * TARGET_SIZE_MARGIN) / duration;
+        let audio_bitrate = if self.drops_audio() {
+            0.0
+        } else {
+            parse_bitrate(&self.audio_bitrate).unwrap_or(128_000) as f64
+        };
+
+        Some((total_bitrate - audio_bitrate).max(MIN_VIDEO_BITRATE as f64) as u64)
+    }
+
+    /// `video_bitrate` in bits per second.
+    pub fn average_video_bitrate(&self) -> Option<u64> {
+        self.video_bitrate.as_deref().and_then(parse_bitrate)
+    }
+
+    /// `max_video_bitrate` in bits per second.
+    pub fn peak_video_bitrate(&self) -> Option<u64> {
+        self.max_video_bitrate.as_deref().and_then(parse_bitrate)
+    }
+
+    /// Whether the speed change is too fast for audio to be worth keeping.
+    pub fn drops_audio(&self) -> bool {
+        matches!(self.speed, Some(speed) if speed > MAX_AUDIO_SPEED)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(Error::invalid_settings("Preset name must not be empty"));
+        }
+        if self.image_quality == 0 || self.image_quality > 100 {
+            return Err(Error::invalid_settings(
+                "Image quality must be between 1 and 100",
+            ));
+        }
+        // AV1 and VP9 encoders use a 0-63 scale
+        let max_crf = match self.video_codec.as_str() {
+            "libsvtav1" | "libvpx-vp9" => 63,
+            _ => 51,
+        };
+        if self.video_crf > max_crf {
+            return Err(Error::invalid_settings(format!(
+                "Video CRF must be between 0 and {}",
+                max_crf
+            )));
+        }
+        if matches!(self.keyframe_interval_secs, Some(interval) if !(interval > 0.0 && interval <= 60.0))
+        {
+            return Err(Error::invalid_settings(
+                "Keyframe interval must be between 0 and 60 seconds",
+            ));
+        }
+        if self.responsive_widths.contains(&0) {
+            return Err(Error::invalid_settings(
+                "Responsive image widths must be at least 1",
+            ));
+        }
+        if self.jpeg_xl && !self.responsive_widths.is_empty() {
+            return Err(Error::invalid_settings(
+                "JPEG XL output doesn't make responsive images",
+            ));
+        }
+        if self.jpeg_xl && self.image_format_trial {
+            return Err(Error::invalid_settings(
+                "Use either JPEG XL output or the format trial, not both",
+            ));
+        }
+        if matches!(self.sharpen, Some(amount) if !(amount > 0.0 && amount <= MAX_SHARPEN)) {
+            return Err(Error::invalid_settings(format!(
+                "Sharpening must be above 0 and at most {}",
+                MAX_SHARPEN
+            )));
+        }
+        if matches!(&self.pixel_format, Some(format) if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()))
+        {
+            return Err(Error::invalid_settings(
+                "Pixel format must be an ffmpeg pixel format name, e.g. yuv420p",
+            ));
+        }
+        if matches!(&self.h264_profile, Some(profile) if !H264_PROFILES.contains(&profile.as_str()))
+        {
+            return Err(Error::invalid_settings(format!(
+                "H.264 profile must be one of {}",
+                H264_PROFILES.join(", ")
+            )));
+        }
+        if let (Some(profile), Some(required)) = (
+            &self.h264_profile,
+            self.pixel_format.as_deref().and_then(required_h264_profile),
+        ) {
+            if self.video_codec == "libx264"
+                && h264_profile_rank(profile) < h264_profile_rank(required)
+            {
+                return Err(Error::invalid_settings(format!(
+                    "The {} pixel format needs the {} H.264 profile or above",
+                    self.pixel_format.as_deref().unwrap_or_default(),
+                    required
+                )));
+            }
+        }
+        if matches!(&self.h264_level, Some(level) if !is_h264_level(level)) {
+            return Err(Error::invalid_settings(format!(
+                "H.264 level must be between 1 and {}, e.g. 4.1",
+                MAX_H264_LEVEL
+            )));
+        }
+        if matches!(self.film_grain, Some(grain) if grain == 0 || grain > 50) {
+            return Err(Error::invalid_settings(
+                "Film grain must be between 1 and 50",
+            ));
+        }
+        if self.video_codec.trim().is_empty() {
+            return Err(Error::invalid_settings("Video codec must not be empty"));
+        }
+        if parse_bitrate(&self.audio_bitrate).is_none() {
+            return Err(Error::invalid_settings(format!(
+                "Invalid audio bitrate: {}",
+                self.audio_bitrate
+            )));
+        }
+        if self.audio_codec.trim().is_empty() {
+            return Err(Error::invalid_settings("Audio codec must not be empty"));
+        }
+        if matches!(self.audio_channels, Some(channels) if channels == 0 || channels > 8) {
+            return Err(Error::invalid_settings(
+                "Audio channels must be between 1 and 8",
+            ));
+        }
+        if matches!(self.audio_sample_rate, Some(rate) if !(8_000..=192_000).contains(&rate)) {
+            return Err(Error::invalid_settings(
+                "Audio sample rate must be between 8000 and 192000 Hz",
+            ));
+        }
+        if self.max_frame_rate == Some(0) {
+            return Err(Error::invalid_settings("Frame rate cap must be at least 1"));
+        }
+        if let Some(watermark) = &self.watermark {
+            if watermark.path.trim().is_empty() {
+                return Err(Error::invalid_settings("Watermark image must be set"));
+            }
+            if !(0.0..=1.0).contains(&watermark.opacity) {
+                return Err(Error::invalid_settings(
+                    "Watermark opacity must be between 0 and 1",
+                ));
+            }
+            if watermark.scale <= 0.0 || watermark.scale > 1.0 {
+                return Err(Error::invalid_settings(
+                    "Watermark scale must be between 0 and 1",
+                ));
+            }
+        }
+        if matches!(self.speed, Some(speed) if !(MIN_SPEED..=MAX_SPEED).contains(&speed)) {
+            return Err(Error::invalid_settings(format!(
+                "Speed must be between {}x and {}x",
+                MIN_SPEED, MAX_SPEED
+            )));
+        }
+        if self.target_size_mb == Some(0) {
+            return Err(Error::invalid_settings("Target size must be at least 1 MB"));
+        }
+        if self.target_size_mb.is_some() && compressor::is_lossless_video_codec(&self.video_codec) {
+            return Err(Error::invalid_settings(
+                "A lossless video codec can't be held to a target size",
+            ));
+        }
+        if let Some(bitrate) = &self.video_bitrate {
+            if self
+                .average_video_bitrate()
+                .is_none_or(|bitrate| bitrate == 0)
+            {
+                return Err(Error::invalid_settings(format!(
+                    "Invalid video bitrate: {}",
+                    bitrate
+                )));
+            }
+            if self.target_size_mb.is_some() {
+                return Err(Error::invalid_settings(
+                    "Set a target size or a video bitrate, not both",
+                ));
+            }
+            if compressor::is_lossless_video_codec(&self.video_codec) {
+                return Err(Error::invalid_settings(
+                    "A lossless video codec can't be held to a bitrate",
+                ));
+            }
+        }
+        if let Some(max_bitrate) = &self.max_video_bitrate {
+            let Some(peak) = self.peak_video_bitrate().filter(|&peak| peak > 0) else {
+                return Err(Error::invalid_settings(format!(
+                    "Invalid maximum video bitrate: {}",
+                    max_bitrate
+                )));
+            };
+            if compressor::is_lossless_video_codec(&self.video_codec) {
+                return Err(Error::invalid_settings(
+                    "A lossless video codec can't be held to a bitrate",
+                ));
+            }
+            if self
+                .average_video_bitrate()
+                .is_some_and(|average| average > peak)
+            {
+                return Err(Error::invalid_settings(
+                    "Video bitrate must not exceed the maximum bitrate",
+                ));
+            }
+        }
+        custom_args::validate(&self.extra_ffmpeg_args)?;
+        if self.two_pass {
+            if self.video_bitrate.is_none() && self.target_size_mb.is_none() {
+                return Err(Error::invalid_settings(
+                    "Two-pass encoding needs a video bitrate or a target size",
+                ));
+            }
+            if !compressor::supports_two_pass(&self.video_codec) {
+                return Err(Error::invalid_settings(
+                    "Two-pass encoding needs libx264 or libvpx-vp9",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn builtin_presets() -> Vec<Preset> {
+    vec![
+        Preset {
+            name: "Web".to_string(),
+            max_video_height: Some(1080),
+            image_quality: 80,
+            ..base()
+        },
+        Preset {
+            name: "Email".to_string(),
+            video_crf: 28,
+            encoder_preset: "slow".to_string(),
+            audio_bitrate: "96k".to_string(),
+            max_video_height: Some(720),
+            image_quality: 70,
+            max_image_dimension: Some(1280),
+            ..base()
+        },
+        Preset {
+            name: "Archive".to_string(),
+            video_codec: "libx265".to_string(),
+            video_crf: 22,
+            encoder_preset: "slow".to_string(),
+            audio_bitrate: "192k".to_string(),
+            image_quality: 92,
+            max_image_dimension: None,
+            ..base()
+        },
+        Preset {
+            name: "Max quality".to_string(),
+            video_crf: 18,
+            encoder_preset: "slow".to_string(),
+            audio_bitrate: "192k".to_string(),
+            image_quality: 95,
+            max_image_dimension: None,
+            ..base()
+        },
+        Preset {
+            name: "Lossless archival (FFV1)".to_string(),
+            video_codec: "ffv1".to_string(),
+            video_crf: 0,
+            audio_bitrate: "192k".to_string(),
+            audio_codec: "flac".to_string(),
+            keep_cover_art: true,
+            image_quality: 100,
+            max_image_dimension: None,
+            ..base()
+        },
+        Preset {
+            name: "Editing proxy (H.264)".to_string(),
+            video_crf: 16,
+            encoder_preset: "veryfast".to_string(),
+            audio_bitrate: "256k".to_string(),
+            max_video_height: Some(1080),
+            image_quality: 92,
+            max_image_dimension: None,
+            tune: Some("fastdecode".to_string()),
+            h264_profile: Some("high".to_string()),
+            h264_level: Some("4.1".to_string()),
+            ..base()
+        },
+        Preset {
+            name: "Mezzanine archive (HEVC)".to_string(),
+            video_codec: "libx265".to_string(),
+            video_crf: 18,
+            encoder_preset: "slow".to_string(),
+            audio_bitrate: "256k".to_string(),
+            image_quality: 92,
+            max_image_dimension: None,
+            ..base()
+        },
+        Preset {
+            name: "WebM (VP9)".to_string(),
+            video_codec: "libvpx-vp9".to_string(),
+            video_crf: 33,
+            audio_bitrate: "96k".to_string(),
+            audio_codec: "libopus".to_string(),
+            max_video_height: Some(1080),
+            image_quality: 80,
+            ..base()
+        },
+        Preset {
+            name: "AV1 (SVT)".to_string(),
+            video_codec: "libsvtav1".to_string(),
+            video_crf: 35,
+            encoder_preset: "8".to_string(),
+            max_image_dimension: None,
+            ..base()
+        },
+        Preset {
+            name: "Voice".to_string(),
+            video_crf: 28,
+            audio_bitrate: "32k".to_string(),
+            audio_codec: "libopus".to_string(),
+            audio_channels: Some(1),
+            audio_sample_rate: Some(24_000),
+            max_video_height: Some(720),
+            image_quality: 80,
+            ..base()
+        },
+        Preset {
+            name: "Music".to_string(),
+            audio_bitrate: "192k".to_string(),
+            audio_channels: Some(2),
+            keep_cover_art: true,
+            max_video_height: Some(1080),
+            ..base()
+        },
+        Preset {
+            name: "Screen recording".to_string(),
+            video_crf: 30,
+            audio_bitrate: "96k".to_string(),
+            max_video_height: Some(1080),
+            // Flat colors and sharp text, mostly static between frames
+            tune: Some("animation".to_string()),
+            max_frame_rate: Some(30),
+            h264_profile: Some("high".to_string()),
+            h264_level: Some("4.1".to_string()),
+            ..base()
+        },
+        audio_preset("Lossless audio (FLAC)", "flac", "192k"),
+        audio_preset("Opus 128k", "libopus", "128k"),
+        size_cap_preset("Discord 25 MB", 25, 720, "96k"),
+        size_cap_preset("Email 20 MB", 20, 720, "96k"),
+        size_cap_preset("WhatsApp 16 MB", 16, 480, "64k"),
+    ]
+}
+
+/// What every built-in starts from: x264 at CRF 23, AAC audio, images
+/// capped at 2048 px, and everything optional left off.
+fn base() -> Preset {
+    Preset {
+        name: String::new(),
+        builtin: true,
+        video_codec: "libx264".to_string(),
+        video_crf: 23,
+        encoder_preset: "medium".to_string(),
+        audio_bitrate: "128k".to_string(),
+        audio_codec: default_audio_codec(),
+        audio_channels: None,
+        audio_sample_rate: None,
+        keep_cover_art: false,
+        max_video_height: None,
+        image_quality: 85,
+        max_image_dimension: Some(2048),
+        target_size_mb: None,
+        video_bitrate: None,
+        max_video_bitrate: None,
+        two_pass: false,
+        normalize_loudness: false,
+        trim_silence: false,
+        tune: None,
+        max_frame_rate: None,
+        denoise: None,
+        crop_black_bars: false,
+        watermark: None,
+        burn_subtitles: None,
+        speed: None,
+        film_grain: None,
+        streaming: None,
+        keyframe_interval_secs: None,
+        fixed_keyframes: false,
+        responsive_widths: Vec::new(),
+        resize_filter: ResizeFilter::default(),
+        sharpen: None,
+        h264_profile: None,
+        h264_level: None,
+        pixel_format: None,
+        color_space: ColorSpace::default(),
+        color_range: ColorRange::default(),
+        image_format_trial: false,
+        trial_avif: false,
+        jpeg_xl: false,
+        extra_ffmpeg_args: Vec::new(),
+    }
+}
+
+/// Presets for audio files, e.g. WAV to FLAC to shrink losslessly or FLAC to
+/// Opus to shrink further at a quality loss.
+fn audio_preset(name: &str, audio_codec: &str, audio_bitrate: &str) -> Preset {
+    Preset {
+        name: name.to_string(),
+        audio_bitrate: audio_bitrate.to_string(),
+        audio_codec: audio_codec.to_string(),
+        keep_cover_art: audio_codec == "flac",
+        ..base()
+    }
+}
+
+/// Presets that target a platform's upload limit rather than a quality level.
+fn size_cap_preset(name: &str, size_mb: u32, max_height: u32, audio_bitrate: &str) -> Preset {
+    Preset {
+        name: name.to_string(),
+        audio_bitrate: audio_bitrate.to_string(),
+        max_video_height: Some(max_height),
+        target_size_mb: Some(size_mb),
+        ..base()
+    }
+}
+
+/// The lowest x264 profile that can hold `pixel_format`: 4:4:4 and 4:2:2
+/// chroma and 10-bit depth each need their own. `None` for 8-bit 4:2:0,
+/// which every profile takes.
+pub(crate) fn required_h264_profile(pixel_format: &str) -> Option<&'static str> {
+    if pixel_format.contains("444") || pixel_format.starts_with("gbr") {
+        Some("high444")
+    } else if pixel_format.contains("422") {
+        Some("high422")
+    } else if pixel_format.contains("10") {
+        Some("high10")
+    } else {
+        None
+    }
+}
+
+/// Position in `H264_PROFILES`, where each profile takes everything the ones
+/// before it do.
+fn h264_profile_rank(profile: &str) -> usize {
+    H264_PROFILES
+        .iter()
+        .position(|known| *known == profile)
+        .unwrap_or(0)
+}
+
+/// `1b` or a number like `4.1` from 1 up to the highest level.
+fn is_h264_level(level: &str) -> bool {
+    level == "1b"
+        || level
+            .parse::<f32>()
+            .is_ok_and(|level| (1.0..=MAX_H264_LEVEL).contains(&level))
+}
+
+fn default_audio_codec() -> String {
+    "aac".to_string()
+}
+
+/// Parses ffmpeg-style bitrates such as `128k` or `2M` into bits per second.
+pub fn parse_bitrate(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1_000.0),
+        'm' | 'M' => (&value[..value.len() - 1], 1_000_000.0),
+        _ => (value, 1.0),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|number| (number * multiplier) as u64)
+}
+
+/// Built-in presets followed by the user's own.
+pub fn all_presets(settings: &Settings) -> Vec<Preset> {
+    let mut presets = builtin_presets();
+    presets.extend(settings.presets.iter().cloned());
+    presets
+}
+
+/// Looks up a preset by name (case-insensitive), or falls back to the
+/// default settings when no name is given.
+pub fn resolve_preset(name: Option<&str>, settings: &Settings) -> Result<Preset> {
+    match name {
+        Some(name) => all_presets(settings)
+            .into_iter()
+            .find(|preset| preset.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::UnknownPreset {
+                name: name.to_string(),
+            }),
+        None => Ok(Preset::from_settings(settings)),
+    }
+}
+
+pub fn is_builtin_name(name: &str) -> bool {
+    builtin_presets()
+        .iter()
+        .any(|preset| preset.name.eq_ignore_ascii_case(name))
+}
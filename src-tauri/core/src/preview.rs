@@ -0,0 +1,179 @@
+//! Before/after previews: the same crop of an image as it is and as the
+//! preset would compress it, for a comparison slider before committing to a
+//! batch. Crops are saved as PNGs, so what shows is the encoder's artifacts
+//! and nothing added on top.
+
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compressor::{self, encode_for_preset, image_codec_name, resize_for_preset};
+use crate::encoders;
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::media::MediaType;
+use crate::paths;
+use crate::presets::Preset;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PreviewOptions {
+    /// Center of the crop as a fraction of the width (0.0 is the left
+    /// edge); the middle by default.
+    pub focus_x: f64,
+    pub focus_y: f64,
+    /// Side of the square crop in pixels of the compressed image; smaller
+    /// images are shown whole along that side.
+    pub size: u32,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            focus_x: 0.5,
+            focus_y: 0.5,
+            size: 400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewPair {
+    pub original_path: String,
+    pub compressed_path: String,
+    /// Where the crops come from, in pixels of the compressed image, which
+    /// is the original scaled as the preset says.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub original_size: u64,
+    /// What the whole image compresses to.
+    pub compressed_size: u64,
+    /// Output format, e.g. `jpeg` or `webp`.
+    pub format: &'static str,
+    /// Compressing wouldn't make the file smaller, so the original would be
+    /// kept and both crops are the same.
+    pub kept_original: bool,
+}
+
+/// Compresses `input_path` with `preset` in memory and saves matching crops
+/// of it before and after. Only the latest pair is kept on disk.
+pub fn preview_pair(
+    input_path: &str,
+    preset: &Preset,
+    options: &PreviewOptions,
+) -> Result<PreviewPair> {
+    let input = Path::new(input_path);
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+    let extension = input
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if MediaType::from_path(input) != Some(MediaType::Image) || extension == "svg" {
+        return Err(invalid("Only raster images can be previewed"));
+    }
+    if preset.jpeg_xl {
+        return Err(invalid("JPEG XL outputs can't be previewed"));
+    }
+
+    let original_size = fs::metadata(input)?.len();
+    let resized = resize_for_preset(compressor::open_image(input)?, preset);
+    let (format, encoded) = encode_for_preset(&resized, &extension, preset)?;
+    let kept_original = encoded.len() as u64 >= original_size;
+    let compressed = if kept_original {
+        resized.clone()
+    } else {
+        decode(&encoded, format)?
+    };
+
+    let (image_width, image_height) = resized.dimensions();
+    let width = options.size.clamp(1, image_width);
+    let height = options.size.clamp(1, image_height);
+    let x = crop_start(options.focus_x, width, image_width);
+    let y = crop_start(options.focus_y, height, image_height);
+
+    let dir = paths::previews_dir();
+    // Earlier pairs have been shown already
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).map_err(|e| Error::io("Failed to create previews folder", e))?;
+    // A new name each time, so the webview doesn't show a cached crop
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or(0);
+    let original_path = save_crop(&resized, (x, y, width, height), &dir, stamp, "before")?;
+    let compressed_path = save_crop(&compressed, (x, y, width, height), &dir, stamp, "after")?;
+
+    Ok(PreviewPair {
+        original_path: original_path.to_string_lossy().to_string(),
+        compressed_path: compressed_path.to_string_lossy().to_string(),
+        x,
+        y,
+        width,
+        height,
+        image_width,
+        image_height,
+        original_size,
+        compressed_size: if kept_original {
+            original_size
+        } else {
+            encoded.len() as u64
+        },
+        format: image_codec_name(format),
+        kept_original,
+    })
+}
+
+/// Left or top edge of a crop of `crop` pixels centered on `focus`, kept
+/// inside the image.
+fn crop_start(focus: f64, crop: u32, image: u32) -> u32 {
+    let center = focus.clamp(0.0, 1.0) * image as f64;
+    (center - crop as f64 / 2.0)
+        .round()
+        .clamp(0.0, (image - crop) as f64) as u32
+}
+
+fn decode(encoded: &[u8], format: ImageFormat) -> Result<DynamicImage> {
+    if format != ImageFormat::Avif {
+        return Ok(image::load_from_memory_with_format(encoded, format)?);
+    }
+    // `image` encodes AVIF but can't decode it
+    let ffmpeg_path = FFmpegManager::new()
+        .installed_ffmpeg_path()
+        .ok_or(Error::FfmpegMissing)?;
+    let scratch = encoders::scratch_file("avif");
+    fs::write(&scratch, encoded).map_err(|e| Error::io("Failed to write preview", e))?;
+    let decoded = compressor::decode_with_ffmpeg(&ffmpeg_path, &scratch, 0);
+    fs::remove_file(&scratch).ok();
+    decoded
+}
+
+fn save_crop(
+    img: &DynamicImage,
+    (x, y, width, height): (u32, u32, u32, u32),
+    dir: &Path,
+    stamp: u128,
+    side: &str,
+) -> Result<PathBuf> {
+    let path = dir.join(format!("{}-{}.png", stamp, side));
+    img.crop_imm(x, y, width, height)
+        .save_with_format(&path, ImageFormat::Png)?;
+    Ok(path)
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidRequest {
+        message: message.to_string(),
+    }
+}
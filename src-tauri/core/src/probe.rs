@@ -0,0 +1,497 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::{Error, Result};
+
+/// Stream information read from `ffmpeg -i`. We only ship ffmpeg (not
+/// ffprobe), so this parses the human-readable banner ffmpeg prints to
+/// stderr. Fields ffmpeg didn't report stay `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInfo {
+    /// Container duration in seconds.
+    pub duration: Option<f64>,
+    /// Overall bitrate in kb/s.
+    pub bitrate_kbps: Option<u64>,
+    pub video_codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pixel_format: Option<String>,
+    /// Transfer characteristic, e.g. `bt709`, or `smpte2084` (PQ) and
+    /// `arib-std-b67` (HLG) for HDR.
+    pub color_transfer: Option<String>,
+    pub frame_rate: Option<f64>,
+    pub audio_codec: Option<String>,
+    pub audio_sample_rate: Option<u32>,
+    /// Channel layout as ffmpeg names it, e.g. `mono`, `stereo`, `5.1(side)`.
+    pub audio_channels: Option<String>,
+    /// Whether the file embeds a still image (cover art) as a video stream.
+    pub has_cover_art: bool,
+}
+
+impl MediaInfo {
+    /// Whether the video is in an editing intermediate codec, which is huge
+    /// but high quality and deserves gentler presets than camera footage.
+    pub fn is_mezzanine(&self) -> bool {
+        self.video_codec
+            .as_deref()
+            .is_some_and(|codec| MEZZANINE_CODECS.contains(&codec))
+    }
+
+    /// Whether the video is HDR, i.e. uses the PQ or HLG transfer.
+    pub fn is_hdr(&self) -> bool {
+        self.color_transfer
+            .as_deref()
+            .is_some_and(|transfer| HDR_TRANSFERS.contains(&transfer))
+    }
+}
+
+pub fn probe_media(ffmpeg_path: &Path, input: &Path) -> Result<MediaInfo> {
+    Ok(parse_media_info(&input_banner(ffmpeg_path, input)?))
+}
+
+/// Reads the file's tags (title, artist, album, track, ...), including the
+/// ones Ogg and Opus files keep on the audio stream rather than the container.
+pub fn probe_tags(ffmpeg_path: &Path, input: &Path) -> Result<BTreeMap<String, String>> {
+    Ok(parse_tags(&input_banner(ffmpeg_path, input)?))
+}
+
+fn input_banner(ffmpeg_path: &Path, input: &Path) -> Result<String> {
+    let output = Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(input)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::FfmpegMissing,
+            _ => Error::io("Failed to run ffmpeg", e),
+        })?;
+
+    // `ffmpeg -i` without an output always exits with an error; the stream
+    // info we want is still printed to stderr.
+    Ok(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Seconds of video `detect_crop` analyzes.
+const CROP_SAMPLE_SECONDS: f64 = 20.0;
+/// ProRes, DNxHD/DNxHR and CineForm, as ffmpeg names their decoders.
+const MEZZANINE_CODECS: &[&str] = &["prores", "dnxhd", "cfhd"];
+/// PQ and HLG, as ffmpeg names them.
+const HDR_TRANSFERS: &[&str] = &["smpte2084", "arib-std-b67"];
+
+/// A `crop` filter rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Crop {
+    pub fn filter(&self) -> String {
+        format!("crop={}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Runs `cropdetect` over a sample from the middle of the video and returns
+/// the crop that removes its black bars, or `None` if it has none.
+pub fn detect_crop(ffmpeg_path: &Path, input: &Path) -> Result<Option<Crop>> {
+    let info = probe_media(ffmpeg_path, input)?;
+    // Intros and credits are often black; sample away from both ends
+    let start = info
+        .duration
+        .map(|duration| ((duration - CROP_SAMPLE_SECONDS) / 2.0).max(0.0))
+        .unwrap_or(0.0);
+
+    let output = Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .args(["-ss", &format!("{:.2}", start)])
+        .arg("-i")
+        .arg(input)
+        .args(["-t", &CROP_SAMPLE_SECONDS.to_string()])
+        .args(["-vf", "cropdetect=24:2:0", "-an", "-f", "null", "-"])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::FfmpegMissing,
+            _ => Error::io("Failed to run ffmpeg", e),
+        })?;
+
+    let crop = parse_crop(&String::from_utf8_lossy(&output.stderr));
+    Ok(crop.filter(|crop| Some(crop.width) != info.width || Some(crop.height) != info.height))
+}
+
+/// Times in seconds at which the picture changes by more than `threshold`
+/// (0-1) from one frame to the next. Decodes the whole video.
+pub fn detect_scenes(ffmpeg_path: &Path, input: &Path, threshold: f64) -> Result<Vec<f64>> {
+    let output = Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(input)
+        .args([
+            "-vf",
+            &format!("select='gt(scene,{})',showinfo", threshold),
+            "-an",
+            "-f",
+            "null",
+            "-",
+        ])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::FfmpegMissing,
+            _ => Error::io("Failed to run ffmpeg", e),
+        })?;
+
+    Ok(parse_scene_times(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// The `pts_time` of every frame `showinfo` printed.
+pub fn parse_scene_times(stderr: &str) -> Vec<f64> {
+    stderr
+        .lines()
+        .filter(|line| line.contains("Parsed_showinfo"))
+        .filter_map(|line| line.split_once("pts_time:"))
+        .filter_map(|(_, rest)| rest.split_whitespace().next()?.parse().ok())
+        .collect()
+}
+
+/// MD5 of every decoded frame of the first video stream, in order.
+pub fn frame_md5s(ffmpeg_path: &Path, input: &Path) -> Result<Vec<String>> {
+    let output = Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(input)
+        .args(["-map", "0:v:0", "-f", "framemd5", "-"])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::FfmpegMissing,
+            _ => Error::io("Failed to run ffmpeg", e),
+        })?;
+
+    // Timestamps differ between containers, so only the hashes are compared
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit(',').next())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .collect())
+}
+
+/// Bits spent in one second of a file, split by stream type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitrateSample {
+    /// Start of the second, counted from 0.
+    pub second: u32,
+    pub video_kbps: f64,
+    pub audio_kbps: f64,
+}
+
+/// Per-second bitrate of the first video and audio streams, from the sizes
+/// of their packets. Reads the whole file but decodes nothing.
+pub fn bitrate_timeline(ffmpeg_path: &Path, input: &Path) -> Result<Vec<BitrateSample>> {
+    let output = Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(input)
+        .args([
+            "-map", "0:v:0?", "-map", "0:a:0?", "-c", "copy", "-f", "framecrc", "-",
+        ])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::FfmpegMissing,
+            _ => Error::io("Failed to run ffmpeg", e),
+        })?;
+
+    Ok(parse_bitrate_timeline(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Buckets `framecrc` packet lines (`stream, dts, pts, duration, size,
+/// crc`) into seconds, using the `#tb` and `#media_type` header lines to
+/// place and classify them.
+pub fn parse_bitrate_timeline(framecrc: &str) -> Vec<BitrateSample> {
+    let mut time_bases: HashMap<usize, f64> = HashMap::new();
+    let mut audio_streams = Vec::new();
+    let mut samples: Vec<BitrateSample> = Vec::new();
+
+    for line in framecrc.lines() {
+        if let Some(header) = line.strip_prefix('#') {
+            // #tb 0: 1/12800, #media_type 1: audio
+            let Some((key, value)) = header.split_once(':') else {
+                continue;
+            };
+            let mut key = key.split_whitespace();
+            let (Some(name), Some(Ok(stream))) = (key.next(), key.next().map(str::parse)) else {
+                continue;
+            };
+            match name {
+                "tb" => {
+                    if let Some((num, den)) = value.trim().split_once('/') {
+                        if let (Ok(num), Ok(den)) = (num.parse::<f64>(), den.parse::<f64>()) {
+                            time_bases.insert(stream, num / den);
+                        }
+                    }
+                }
+                "media_type" if value.trim() == "audio" => audio_streams.push(stream),
+                _ => {}
+            }
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [stream, dts, pts, _, size, ..] = fields[..] else {
+            continue;
+        };
+        let (Ok(stream), Ok(size)) = (stream.parse::<usize>(), size.parse::<u64>()) else {
+            continue;
+        };
+        let Some(timestamp) = pts.parse::<i64>().or_else(|_| dts.parse()).ok() else {
+            continue;
+        };
+        let Some(time_base) = time_bases.get(&stream) else {
+            continue;
+        };
+
+        let second = (timestamp as f64 * time_base).max(0.0) as usize;
+        if samples.len() <= second {
+            samples.resize_with(second + 1, Default::default);
+        }
+        let kbits = size as f64 * 8.0 / 1000.0;
+        if audio_streams.contains(&stream) {
+            samples[second].audio_kbps += kbits;
+        } else {
+            samples[second].video_kbps += kbits;
+        }
+    }
+
+    for (second, sample) in samples.iter_mut().enumerate() {
+        sample.second = second as u32;
+    }
+    samples
+}
+
+/// The crop `cropdetect` suggested most often.
+pub fn parse_crop(stderr: &str) -> Option<Crop> {
+    let mut counts: HashMap<Crop, usize> = HashMap::new();
+    for line in stderr.lines() {
+        let Some((_, crop)) = line.rsplit_once("crop=") else {
+            continue;
+        };
+        let values: Vec<u32> = crop
+            .trim()
+            .split(':')
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if let [width, height, x, y] = values[..] {
+            *counts
+                .entry(Crop {
+                    width,
+                    height,
+                    x,
+                    y,
+                })
+                .or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(crop, _)| crop)
+}
+
+/// Reads the container duration in seconds.
+pub fn probe_duration(ffmpeg_path: &Path, input: &Path) -> Result<f64> {
+    probe_media(ffmpeg_path, input)?
+        .duration
+        .ok_or_else(|| Error::UnknownDuration {
+            path: input.to_string_lossy().to_string(),
+        })
+}
+
+pub fn parse_media_info(stderr: &str) -> MediaInfo {
+    let mut info = MediaInfo::default();
+
+    for line in stderr.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Duration:") {
+            // Duration: 00:01:23.45, start: 0.000000, bitrate: 1234 kb/s
+            let mut fields = rest.split(',').map(str::trim);
+            info.duration = fields.next().and_then(parse_timestamp);
+            info.bitrate_kbps = fields
+                .find_map(|field| field.strip_prefix("bitrate:"))
+                .and_then(|bitrate| bitrate.trim().strip_suffix(" kb/s"))
+                .and_then(|bitrate| bitrate.parse().ok());
+        } else if line.starts_with("Stream #") {
+            if let Some((_, video)) = line.split_once(": Video: ") {
+                if line.contains("(attached pic)") {
+                    info.has_cover_art = true;
+                } else if info.video_codec.is_none() {
+                    parse_video_stream(video, &mut info);
+                }
+            } else if let Some((_, audio)) = line.split_once(": Audio: ") {
+                if info.audio_codec.is_none() {
+                    parse_audio_stream(audio, &mut info);
+                }
+            }
+        }
+    }
+
+    info
+}
+
+/// Collects the `Metadata:` blocks of the container and its audio streams.
+/// Container tags win when both define a key.
+pub fn parse_tags(stderr: &str) -> BTreeMap<String, String> {
+    let mut tags: BTreeMap<String, String> = BTreeMap::new();
+    let mut wanted = false;
+    let mut block_indent = None;
+    let mut last_key: Option<String> = None;
+
+    for line in stderr.lines() {
+        let trimmed = line.trim();
+        let indent = line.len() - line.trim_start().len();
+
+        if let Some(header_indent) = block_indent {
+            if indent > header_indent {
+                let Some((key, value)) = trimmed.split_once(':') else {
+                    continue;
+                };
+                let (key, value) = (key.trim(), value.trim());
+                if !wanted {
+                    continue;
+                }
+                if key.is_empty() {
+                    // Multi-line values continue with an empty key
+                    if let Some(existing) = last_key.as_ref().and_then(|key| tags.get_mut(key)) {
+                        existing.push('\n');
+                        existing.push_str(value);
+                    }
+                } else if !tags.contains_key(key) {
+                    tags.insert(key.to_string(), value.to_string());
+                    last_key = Some(key.to_string());
+                } else {
+                    last_key = None;
+                }
+                continue;
+            }
+            block_indent = None;
+        }
+
+        if trimmed.starts_with("Input #") {
+            wanted = true;
+        } else if trimmed.starts_with("Stream #") {
+            wanted = trimmed.contains(": Audio: ");
+        } else if trimmed.starts_with("Chapter #") || trimmed.starts_with("Duration:") {
+            wanted = false;
+        } else if trimmed == "Metadata:" {
+            block_indent = Some(indent);
+            last_key = None;
+        }
+    }
+
+    tags
+}
+
+/// `h264 (High) (avc1 / 0x31637661), yuv420p(tv, bt709), 1920x1080 [SAR 1:1 DAR 16:9], 4000 kb/s, 29.97 fps, ...`
+fn parse_video_stream(description: &str, info: &mut MediaInfo) {
+    let fields = split_top_level(description);
+
+    info.video_codec = fields
+        .first()
+        .and_then(|codec| codec.split_whitespace().next())
+        .map(str::to_string);
+    info.pixel_format = fields.get(1).map(|format| {
+        format
+            .split(['(', ' '])
+            .next()
+            .unwrap_or(format)
+            .to_string()
+    });
+    info.color_transfer = fields.get(1).and_then(|format| color_transfer(format));
+
+    for field in &fields {
+        if let Some((width, height)) = field.split_whitespace().next().and_then(parse_size) {
+            info.width = Some(width);
+            info.height = Some(height);
+        } else if let Some(fps) = field.strip_suffix(" fps") {
+            info.frame_rate = fps.trim().parse().ok();
+        }
+    }
+}
+
+/// The transfer from a pixel format like `yuv420p10le(tv, bt2020nc/bt2020/smpte2084)`,
+/// where the colors are `matrix/primaries/transfer`. ffmpeg prints a single
+/// name instead when all three are the same.
+fn color_transfer(format: &str) -> Option<String> {
+    let (_, properties) = format.split_once('(')?;
+    properties
+        .trim_end_matches(')')
+        .split(',')
+        .map(str::trim)
+        .find_map(|property| match property.rsplit_once('/') {
+            Some((_, transfer)) => Some(transfer),
+            None => property.starts_with("bt").then_some(property),
+        })
+        .map(str::to_string)
+}
+
+/// `aac (LC) (mp4a / 0x6134706D), 48000 Hz, stereo, fltp, 128 kb/s`
+fn parse_audio_stream(description: &str, info: &mut MediaInfo) {
+    let fields = split_top_level(description);
+
+    info.audio_codec = fields
+        .first()
+        .and_then(|codec| codec.split_whitespace().next())
+        .map(str::to_string);
+
+    for (index, field) in fields.iter().enumerate() {
+        if let Some(rate) = field.strip_suffix(" Hz") {
+            info.audio_sample_rate = rate.trim().parse().ok();
+            // The channel layout always follows the sample rate
+            info.audio_channels = fields.get(index + 1).map(|layout| layout.to_string());
+        }
+    }
+}
+
+/// Splits on commas that aren't inside parentheses or brackets, since fields
+/// like `yuv420p(tv, bt709)` contain commas of their own.
+fn split_top_level(value: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+
+    for (index, c) in value.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                fields.push(value[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(value[start..].trim());
+
+    fields
+}
+
+/// Parses `1920x1080`.
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Parses `HH:MM:SS.ss` into seconds.
+pub fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut parts = timestamp.split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
@@ -0,0 +1,1240 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::compressor::{self, CompressionResult};
+use crate::concurrency::{self, JobLimits};
+use crate::error::{Error, Result};
+use crate::gpu::{self, HardwareEncoder};
+use crate::history::HistoryStore;
+use crate::idle;
+use crate::job::{JobContext, JobProgress};
+use crate::paths;
+use crate::presets::{self, Preset};
+use crate::settings::{GpuSettings, Settings, SettingsManager};
+use crate::units::format_size;
+
+pub type JobId = u64;
+pub type BatchId = u64;
+
+/// How often the idle monitor looks at user input and CPU load.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// How far the session file may lag behind the queue. Changes in between,
+/// such as a folder's worth of jobs, go out in one write.
+const SESSION_SAVE_DELAY: Duration = Duration::from_millis(250);
+
+/// What a caller submits; everything else about a job is tracked by the queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRequest {
+    pub input_path: String,
+    #[serde(default)]
+    pub output_path: Option<String>,
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+/// Queued jobs start highest priority first, and in queue order within a
+/// priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    /// Deliberately not compressed, e.g. because an identical input already
+    /// was. See `Error::is_skip`.
+    Skipped,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled | JobStatus::Skipped
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: JobId,
+    /// The batch the job was queued into: every job queued between two
+    /// times the queue is idle shares one.
+    pub batch_id: BatchId,
+    #[serde(flatten)]
+    pub request: JobRequest,
+    /// Input size at the time the job was queued.
+    pub original_size: u64,
+    pub status: JobStatus,
+    pub result: Option<CompressionResult>,
+    pub error: Option<Error>,
+    /// Wall-clock run time, set once the job finishes.
+    pub elapsed_ms: Option<u64>,
+    /// How far the current encode is, while the job runs.
+    pub progress: Option<JobProgress>,
+}
+
+/// Totals for the jobs that finished since the queue was last idle.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchSummary {
+    pub batch_id: BatchId,
+    pub files: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub skipped: usize,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub bytes_saved: u64,
+    /// Wall-clock time from the first job starting to the queue draining.
+    pub elapsed_ms: u64,
+    /// Distinct directories the outputs were written to.
+    pub output_dirs: Vec<String>,
+    /// Every file the batch wrote, in job order.
+    pub outputs: Vec<String>,
+}
+
+impl BatchSummary {
+    fn from_jobs<'a>(batch_id: BatchId, jobs: impl Iterator<Item = &'a Job>) -> Self {
+        let mut summary = BatchSummary {
+            batch_id,
+            ..BatchSummary::default()
+        };
+        for job in jobs {
+            summary.files += 1;
+            match job.status {
+                JobStatus::Completed => summary.completed += 1,
+                JobStatus::Failed => summary.failed += 1,
+                JobStatus::Cancelled => summary.cancelled += 1,
+                JobStatus::Skipped => summary.skipped += 1,
+                _ => {}
+            }
+            if let Some(result) = &job.result {
+                summary.original_size += job.original_size;
+                summary.compressed_size += result.compressed_size;
+                summary.bytes_saved += job.original_size.saturating_sub(result.compressed_size);
+
+                summary
+                    .outputs
+                    .extend(result.output_files().map(String::from));
+                if let Some(dir) = Path::new(&result.output_path).parent() {
+                    let dir = dir.to_string_lossy().to_string();
+                    if !summary.output_dirs.contains(&dir) {
+                        summary.output_dirs.push(dir);
+                    }
+                }
+            }
+        }
+        summary
+    }
+}
+
+/// Where the current batch stands, for one overall progress bar. Files
+/// count by input size, so a long video weighs more than a photo, and
+/// running ones by how far their encode is.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchProgress {
+    pub batch_id: BatchId,
+    pub files_done: usize,
+    pub files_total: usize,
+    /// Input bytes of the whole batch.
+    pub bytes_total: u64,
+    /// Input bytes processed so far.
+    pub bytes_done: u64,
+    pub bytes_saved: u64,
+    /// 0-100.
+    pub percent: f64,
+    pub elapsed_ms: u64,
+    /// Seconds left at the rate so far; `None` until something is done.
+    pub eta_secs: Option<f64>,
+}
+
+impl BatchProgress {
+    fn from_jobs<'a>(
+        batch_id: BatchId,
+        jobs: impl Iterator<Item = &'a Job>,
+        started: Option<Instant>,
+    ) -> Self {
+        let mut progress = BatchProgress {
+            batch_id,
+            ..BatchProgress::default()
+        };
+        for job in jobs {
+            progress.files_total += 1;
+            progress.bytes_total += job.original_size;
+            if job.status.is_finished() {
+                progress.files_done += 1;
+                progress.bytes_done += job.original_size;
+            } else if let Some(percent) = job.progress.as_ref().and_then(|p| p.percent) {
+                progress.bytes_done += (job.original_size as f64 * percent / 100.0) as u64;
+            }
+            if let Some(result) = &job.result {
+                progress.bytes_saved += job.original_size.saturating_sub(result.compressed_size);
+            }
+        }
+
+        progress.percent = if progress.bytes_total > 0 {
+            100.0 * progress.bytes_done as f64 / progress.bytes_total as f64
+        } else {
+            100.0 * progress.files_done as f64 / progress.files_total.max(1) as f64
+        };
+        let elapsed = started.map(|started| started.elapsed()).unwrap_or_default();
+        progress.elapsed_ms = elapsed.as_millis() as u64;
+        if progress.bytes_done > 0 && !elapsed.is_zero() {
+            let rate = progress.bytes_done as f64 / elapsed.as_secs_f64();
+            progress.eta_secs = Some((progress.bytes_total - progress.bytes_done) as f64 / rate);
+        }
+        progress
+    }
+}
+
+/// The whole queue in one go, for the frontend's queue view.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStatus {
+    /// Finished and running jobs first, then queued ones in the order they
+    /// will start.
+    pub jobs: Vec<JobStatusEntry>,
+    /// The current batch, while there is one.
+    pub batch: Option<BatchProgress>,
+    pub waiting_for_idle: bool,
+    /// How many jobs may run at once, as things stand.
+    pub concurrency: JobLimits,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatusEntry {
+    #[serde(flatten)]
+    pub job: Job,
+    /// 1 for the job that starts next; `None` unless queued.
+    pub position: Option<usize>,
+    pub settings: JobSettingsSummary,
+}
+
+/// The main parameters the job's preset will run with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobSettingsSummary {
+    /// The preset's name; `Default` for the settings' own.
+    pub preset: String,
+    pub video_codec: String,
+    pub video_crf: u8,
+    pub image_quality: u8,
+    pub max_video_height: Option<u32>,
+    pub target_size_mb: Option<u32>,
+    pub video_bitrate: Option<String>,
+    pub max_video_bitrate: Option<String>,
+    pub two_pass: bool,
+    /// Set when the job names a preset that no longer exists.
+    pub error: Option<String>,
+}
+
+impl JobSettingsSummary {
+    fn resolve(name: Option<&str>, settings: &Settings) -> Self {
+        let (preset, error) = match presets::resolve_preset(name, settings) {
+            Ok(preset) => (preset, None),
+            Err(e) => (Preset::from_settings(settings), Some(e.to_string())),
+        };
+        Self {
+            preset: name.map_or(preset.name, String::from),
+            video_codec: preset.video_codec,
+            video_crf: preset.video_crf,
+            image_quality: preset.image_quality,
+            max_video_height: preset.max_video_height,
+            target_size_mb: preset.target_size_mb,
+            video_bitrate: preset.video_bitrate,
+            max_video_bitrate: preset.max_video_bitrate,
+            two_pass: preset.two_pass,
+            error,
+        }
+    }
+}
+
+/// What `cancel_batch` did, by input path.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchCancellation {
+    pub batch_id: BatchId,
+    pub completed: Vec<String>,
+    pub failed: Vec<String>,
+    /// Deliberately not compressed, as for `JobStatus::Skipped`.
+    pub skipped: Vec<String>,
+    /// Taken out of the queue before starting, or told to stop while
+    /// running. Work that can't be interrupted, like a single image, may
+    /// still complete.
+    pub cancelled: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum QueueEvent {
+    /// A running job's encode moved on; comes several times a second.
+    Progress(Box<Job>),
+    /// The batch moved on, with each `Progress` and each finished job.
+    BatchProgress(BatchProgress),
+    JobFinished(Box<Job>),
+    /// The last queued or running job finished.
+    Drained(BatchSummary),
+    /// The batch's outputs just passed the output budget in settings.
+    OverBudget {
+        used: u64,
+        budget: u64,
+    },
+}
+
+/// A job left unfinished by an earlier launch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionJob {
+    #[serde(flatten)]
+    pub request: JobRequest,
+    /// It was running when the app quit or crashed. Whatever it had
+    /// half-written is deleted by then.
+    #[serde(default)]
+    pub interrupted: bool,
+}
+
+/// An entry of the session file.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedJob {
+    #[serde(flatten)]
+    job: SessionJob,
+    /// Unix time the job started running, if it had.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    running_since: Option<i64>,
+    /// Outputs it had started writing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    writing: Vec<PathBuf>,
+}
+
+impl SavedJob {
+    /// What's left of the job after a crash: nothing if the history shows
+    /// it finished after all, otherwise the job with its partial outputs
+    /// deleted.
+    fn recover(self) -> Option<SessionJob> {
+        let Some(running_since) = self.running_since else {
+            return Some(self.job);
+        };
+        let input_path = &self.job.request.input_path;
+
+        let finished = HistoryStore::open()
+            .and_then(|store| store.for_input(input_path))
+            .is_ok_and(|records| {
+                records
+                    .iter()
+                    .any(|record| record.created_at >= running_since)
+            });
+        if finished {
+            info!("{} finished before the app quit", input_path);
+            return None;
+        }
+
+        warn!("Job for {} was interrupted", input_path);
+        let input = Path::new(input_path).canonicalize().ok();
+        for path in &self.writing {
+            if !path.exists() || path.canonicalize().ok() == input {
+                continue;
+            }
+            match fs::remove_file(path) {
+                Ok(()) => info!("Removed partial output {}", path.display()),
+                Err(e) => warn!("Failed to remove partial output {}: {}", path.display(), e),
+            }
+        }
+        Some(SessionJob {
+            interrupted: true,
+            ..self.job
+        })
+    }
+}
+
+type Listener = Arc<dyn Fn(&QueueEvent) + Send + Sync>;
+
+struct RunningJob {
+    ctx: JobContext,
+    started: Instant,
+    /// Unix time, for matching against the history after a crash.
+    started_at: i64,
+    writing: Vec<PathBuf>,
+    /// The hardware encoder and GPU the job holds a session on.
+    gpu: Option<(HardwareEncoder, Option<String>)>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: JobId,
+    next_batch_id: BatchId,
+    /// The batch new jobs join, until the queue is next idle.
+    batch_id: Option<BatchId>,
+    /// All jobs in queue order, finished ones included so their status stays
+    /// queryable.
+    jobs: Vec<Job>,
+    running: HashMap<JobId, RunningJob>,
+    /// Jobs finished since the queue was last idle, reported on drain.
+    batch: Vec<JobId>,
+    batch_started: Option<Instant>,
+    /// Bytes the batch's outputs take so far.
+    batch_output: u64,
+    /// Unfinished jobs from the last launch, until the user resumes or
+    /// discards them.
+    previous_session: Vec<SessionJob>,
+    /// Waiting for the computer to be idle: no job starts, and running ones
+    /// are paused.
+    held_for_idle: bool,
+}
+
+/// In-process job queue shared by the app commands and the local HTTP API.
+/// Cloning is cheap and every clone refers to the same queue.
+#[derive(Clone, Default)]
+pub struct JobQueue {
+    state: Arc<Mutex<QueueState>>,
+    notify: Arc<Notify>,
+    listeners: Arc<Mutex<Vec<Listener>>>,
+    /// Saves unfinished jobs after changes, if they're saved anywhere.
+    session: Option<Arc<SessionSaver>>,
+}
+
+/// Writes the session file on a thread of its own, so changing the queue
+/// never waits on the disk.
+struct SessionSaver {
+    path: PathBuf,
+    /// Whether the queue changed since the last write.
+    dirty: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl SessionSaver {
+    fn mark_dirty(&self) {
+        *self.dirty.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.changed.notify_one();
+    }
+
+    /// Waits for changes and writes them out, at most once per
+    /// `SESSION_SAVE_DELAY`, until the queue is gone. The queue is only
+    /// locked to take a snapshot.
+    fn run(&self, state: Weak<Mutex<QueueState>>) {
+        loop {
+            {
+                let mut dirty = self.dirty.lock().unwrap_or_else(|e| e.into_inner());
+                while !*dirty {
+                    dirty = self.changed.wait(dirty).unwrap_or_else(|e| e.into_inner());
+                }
+            }
+            thread::sleep(SESSION_SAVE_DELAY);
+            *self.dirty.lock().unwrap_or_else(|e| e.into_inner()) = false;
+
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            let saved_jobs = saved_jobs(&state.lock().unwrap_or_else(|e| e.into_inner()));
+            drop(state);
+            write_session(&self.path, &saved_jobs);
+        }
+    }
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A queue that saves its queued and running jobs to the app data
+    /// directory, and picks up what the last launch left unfinished as the
+    /// previous session.
+    pub fn persistent() -> Self {
+        Self::with_session_file(paths::queue_session_file())
+    }
+
+    pub fn with_session_file(path: PathBuf) -> Self {
+        let saved: Vec<SavedJob> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        let previous_session: Vec<SessionJob> =
+            saved.into_iter().filter_map(SavedJob::recover).collect();
+        if !previous_session.is_empty() {
+            info!(
+                "{} unfinished jobs from the previous session",
+                previous_session.len()
+            );
+        }
+
+        let state = QueueState {
+            previous_session,
+            ..QueueState::default()
+        };
+        // Jobs recovered as finished drop out of the file
+        write_session(&path, &saved_jobs(&state));
+
+        let saver = Arc::new(SessionSaver {
+            path,
+            dirty: Mutex::new(false),
+            changed: Condvar::new(),
+        });
+        let queue = Self {
+            state: Arc::new(Mutex::new(state)),
+            session: Some(saver.clone()),
+            ..Self::default()
+        };
+        let state = Arc::downgrade(&queue.state);
+        thread::spawn(move || saver.run(state));
+        queue
+    }
+
+    /// Registers a callback for job and queue events. Listeners run on the
+    /// worker that produced the event, so they should return quickly.
+    pub fn subscribe(&self, listener: impl Fn(&QueueEvent) + Send + Sync + 'static) {
+        self.listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::new(listener));
+    }
+
+    pub fn enqueue(&self, request: JobRequest) -> Job {
+        let job = Self::push(&mut self.lock(), request);
+        self.session_changed();
+
+        self.notify.notify_one();
+        job
+    }
+
+    /// Queues `requests` in order, in one go rather than locking the queue
+    /// for each of a folder's files.
+    pub fn enqueue_many(&self, requests: impl IntoIterator<Item = JobRequest>) -> Vec<Job> {
+        let jobs: Vec<Job> = {
+            let mut state = self.lock();
+            requests
+                .into_iter()
+                .map(|request| Self::push(&mut state, request))
+                .collect()
+        };
+        self.session_changed();
+
+        for _ in &jobs {
+            self.notify.notify_one();
+        }
+        jobs
+    }
+
+    pub fn job(&self, id: JobId) -> Option<Job> {
+        self.lock().jobs.iter().find(|job| job.id == id).cloned()
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        self.lock().jobs.clone()
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let settings = SettingsManager::new().load();
+        let state = self.lock();
+
+        let (mut queued, started): (Vec<_>, Vec<_>) = state
+            .jobs
+            .iter()
+            .enumerate()
+            .partition(|(_, job)| job.status == JobStatus::Queued);
+        // Same order `claim_next` picks them in
+        queued.sort_by_key(|(index, job)| (std::cmp::Reverse(job.request.priority), *index));
+
+        // Many jobs share a preset, so each is only looked up once
+        let mut summaries: HashMap<Option<String>, JobSettingsSummary> = HashMap::new();
+        let mut entry = |job: &Job, position: Option<usize>| JobStatusEntry {
+            job: job.clone(),
+            position,
+            settings: summaries
+                .entry(job.request.preset.clone())
+                .or_insert_with(|| {
+                    JobSettingsSummary::resolve(job.request.preset.as_deref(), &settings)
+                })
+                .clone(),
+        };
+        let mut jobs: Vec<JobStatusEntry> =
+            started.iter().map(|(_, job)| entry(job, None)).collect();
+        jobs.extend(
+            queued
+                .iter()
+                .enumerate()
+                .map(|(position, (_, job))| entry(job, Some(position + 1))),
+        );
+
+        QueueStatus {
+            jobs,
+            batch: state.batch_id.map(|_| Self::batch_progress(&state)),
+            waiting_for_idle: state.held_for_idle,
+            concurrency: JobLimits::from_settings(&settings),
+        }
+    }
+
+    /// Jobs the last launch queued but never finished, interrupted ones
+    /// included. Empty once resumed or discarded.
+    pub fn previous_session(&self) -> Vec<SessionJob> {
+        self.lock().previous_session.clone()
+    }
+
+    /// Queues the previous session's jobs again, in their original order.
+    /// Interrupted jobs start over.
+    pub fn resume_previous_session(&self) -> Vec<Job> {
+        self.resume(|_| true)
+    }
+
+    /// Queues only the jobs a crash or quit interrupted, leaving the ones
+    /// that never started for the user to decide on.
+    pub fn resume_interrupted(&self) -> Vec<Job> {
+        self.resume(|job| job.interrupted)
+    }
+
+    fn resume(&self, pick: impl Fn(&SessionJob) -> bool) -> Vec<Job> {
+        let jobs: Vec<Job> = {
+            let mut state = self.lock();
+            let (picked, rest): (Vec<SessionJob>, Vec<SessionJob>) =
+                std::mem::take(&mut state.previous_session)
+                    .into_iter()
+                    .partition(|job| pick(job));
+            state.previous_session = rest;
+            let jobs = picked
+                .into_iter()
+                .map(|job| Self::push(&mut state, job.request))
+                .collect();
+            self.session_changed();
+            jobs
+        };
+
+        for _ in &jobs {
+            self.notify.notify_one();
+        }
+        jobs
+    }
+
+    pub fn discard_previous_session(&self) {
+        let mut state = self.lock();
+        state.previous_session.clear();
+        self.session_changed();
+    }
+
+    /// Cancels a queued job outright, or signals a running one to stop.
+    pub fn cancel(&self, id: JobId) -> Result<Job> {
+        let mut state = self.lock();
+
+        if let Some(running) = state.running.get(&id) {
+            running.ctx.cancel();
+        }
+
+        let job = state
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or(Error::UnknownJob { id })?;
+
+        match job.status {
+            JobStatus::Queued => job.status = JobStatus::Cancelled,
+            JobStatus::Running => return Ok(job.clone()),
+            _ => return Err(Error::JobAlreadyFinished { id }),
+        }
+
+        let job = job.clone();
+        state.batch.push(id);
+        self.session_changed();
+        let events = Self::finished_events(&mut state, &job);
+        drop(state);
+
+        self.emit(events);
+        Ok(job)
+    }
+
+    /// Whether idle-only mode is holding the queue right now.
+    pub fn is_waiting_for_idle(&self) -> bool {
+        self.lock().held_for_idle
+    }
+
+    /// Watches for the computer going idle, per the idle settings: until it
+    /// is, nothing starts, and once the user is back, running jobs are
+    /// paused again. The CPU is only checked before (re)starting, while
+    /// the queue's own encodes are suspended.
+    pub fn start_idle_monitor(&self) {
+        // Hold from the start, so nothing slips through before the first check
+        self.hold_for_idle(SettingsManager::new().load().idle.enabled);
+
+        let queue = self.clone();
+        thread::spawn(move || loop {
+            let settings = SettingsManager::new().load().idle;
+            let hold = settings.enabled && {
+                let idle_for = idle::user_idle_time();
+                if idle_for.is_none() {
+                    debug!("Idle time unknown, going by CPU usage alone");
+                }
+                let away = idle_for.is_none_or(|idle_for| {
+                    idle_for.as_secs() >= u64::from(settings.idle_minutes) * 60
+                });
+                // Short-circuits so the CPU is only sampled when it matters
+                !away
+                    || queue.is_waiting_for_idle()
+                        && idle::cpu_usage()
+                            .is_some_and(|usage| usage > f32::from(settings.max_cpu_percent))
+            };
+            queue.hold_for_idle(hold);
+            thread::sleep(IDLE_POLL_INTERVAL);
+        });
+    }
+
+    fn hold_for_idle(&self, hold: bool) {
+        let mut state = self.lock();
+        if state.held_for_idle == hold {
+            return;
+        }
+        state.held_for_idle = hold;
+        for running in state.running.values() {
+            if hold {
+                running.ctx.pause();
+            } else {
+                running.ctx.resume();
+            }
+        }
+        let queued = state
+            .jobs
+            .iter()
+            .filter(|job| job.status == JobStatus::Queued)
+            .count();
+        drop(state);
+
+        if hold {
+            info!("Queue waiting for the computer to be idle");
+        } else {
+            info!("Queue running");
+            for _ in 0..queued {
+                self.notify.notify_one();
+            }
+        }
+    }
+
+    pub fn set_priority(&self, id: JobId, priority: Priority) -> Result<Job> {
+        self.update_queued(id, |state, index| {
+            let job = &mut state.jobs[index];
+            job.request.priority = priority;
+            job.clone()
+        })
+    }
+
+    /// Makes a queued job the next to start: it goes ahead of every other
+    /// queued job and gets high priority, so it also overtakes those.
+    pub fn move_to_front(&self, id: JobId) -> Result<Job> {
+        self.update_queued(id, |state, index| {
+            let mut job = state.jobs.remove(index);
+            job.request.priority = Priority::High;
+            let front = state
+                .jobs
+                .iter()
+                .position(|job| job.status == JobStatus::Queued)
+                .unwrap_or(state.jobs.len());
+            state.jobs.insert(front, job.clone());
+            job
+        })
+    }
+
+    fn update_queued(
+        &self,
+        id: JobId,
+        update: impl FnOnce(&mut QueueState, usize) -> Job,
+    ) -> Result<Job> {
+        let mut state = self.lock();
+        let index = state
+            .jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or(Error::UnknownJob { id })?;
+        match state.jobs[index].status {
+            JobStatus::Queued => {}
+            JobStatus::Running => return Err(Error::JobNotQueued { id }),
+            _ => return Err(Error::JobAlreadyFinished { id }),
+        }
+
+        let job = update(&mut state, index);
+        self.session_changed();
+        Ok(job)
+    }
+
+    /// Stops the batch's running jobs and cancels the ones still queued.
+    /// Finished jobs stay as they are; the report says which those were.
+    pub fn cancel_batch(&self, batch_id: BatchId) -> Result<BatchCancellation> {
+        let mut state = self.lock();
+        if !state.jobs.iter().any(|job| job.batch_id == batch_id) {
+            return Err(Error::UnknownBatch { id: batch_id });
+        }
+
+        let mut report = BatchCancellation {
+            batch_id,
+            ..BatchCancellation::default()
+        };
+        let mut flushed = Vec::new();
+        let mut stopped = Vec::new();
+        for job in state.jobs.iter_mut().filter(|job| job.batch_id == batch_id) {
+            let input_path = job.request.input_path.clone();
+            match job.status {
+                JobStatus::Queued => {
+                    job.status = JobStatus::Cancelled;
+                    flushed.push(job.clone());
+                    report.cancelled.push(input_path);
+                }
+                JobStatus::Running => {
+                    stopped.push(job.id);
+                    report.cancelled.push(input_path);
+                }
+                JobStatus::Completed => report.completed.push(input_path),
+                JobStatus::Failed => report.failed.push(input_path),
+                JobStatus::Cancelled => report.cancelled.push(input_path),
+                JobStatus::Skipped => report.skipped.push(input_path),
+            }
+        }
+        for id in &stopped {
+            if let Some(running) = state.running.get(id) {
+                running.ctx.cancel();
+            }
+        }
+        info!(
+            "Batch {} cancelled: {} completed, {} cancelled",
+            batch_id,
+            report.completed.len(),
+            report.cancelled.len()
+        );
+
+        let mut events = Vec::new();
+        for job in &flushed {
+            state.batch.push(job.id);
+        }
+        self.session_changed();
+        for job in &flushed {
+            events.extend(Self::finished_events(&mut state, job));
+        }
+        drop(state);
+
+        self.emit(events);
+        Ok(report)
+    }
+
+    /// Processes queued jobs forever. Spawn `concurrency::worker_count` of
+    /// them; how many actually run is up to `claim_next`. Encodes block for
+    /// their whole length, so each job runs on the blocking pool and only
+    /// the waiting for work happens on the runtime.
+    pub async fn run_worker(self) {
+        loop {
+            let Some((job, ctx)) = self.claim_next() else {
+                self.notify.notified().await;
+                continue;
+            };
+
+            let id = job.id;
+            let queue = self.clone();
+            let runtime = tokio::runtime::Handle::current();
+            let ran =
+                tokio::task::spawn_blocking(move || runtime.block_on(queue.run_job(&job, &ctx)))
+                    .await;
+            if let Err(e) = ran {
+                // A panic mid-encode would otherwise leave the job running forever
+                self.finish(
+                    id,
+                    Err(Error::Io {
+                        message: format!("The job stopped unexpectedly: {}", e),
+                    }),
+                    None,
+                );
+            }
+            // What couldn't start next to this job may fit now
+            self.notify.notify_waiters();
+        }
+    }
+
+    async fn run_job(&self, job: &Job, ctx: &JobContext) {
+        let settings = SettingsManager::new().load();
+        let batch_output = self.lock().batch_output;
+        let result = match settings
+            .check_output_budget(batch_output, &job.request.input_path)
+            .and_then(|()| presets::resolve_preset(job.request.preset.as_deref(), &settings))
+        {
+            Ok(preset) => {
+                compressor::compress_file(
+                    &job.request.input_path,
+                    job.request.output_path.clone(),
+                    &settings,
+                    &preset,
+                    ctx,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        self.finish(job.id, result, settings.output_budget_bytes());
+    }
+
+    fn push(state: &mut QueueState, request: JobRequest) -> Job {
+        let original_size = fs::metadata(&request.input_path)
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        state.next_id += 1;
+        let batch_id = match state.batch_id {
+            Some(batch_id) => batch_id,
+            None => {
+                state.next_batch_id += 1;
+                state.batch_id = Some(state.next_batch_id);
+                state.next_batch_id
+            }
+        };
+        let job = Job {
+            id: state.next_id,
+            batch_id,
+            request,
+            original_size,
+            status: JobStatus::Queued,
+            result: None,
+            error: None,
+            elapsed_ms: None,
+            progress: None,
+        };
+        state.jobs.push(job.clone());
+        info!("Job {} queued: {}", job.id, job.request.input_path);
+        job
+    }
+
+    fn claim_next(&self) -> Option<(Job, JobContext)> {
+        // Re-evaluated on every claim, so the mix of running jobs counts
+        let settings = SettingsManager::new().load();
+        let limits = JobLimits::from_settings(&settings);
+        let free_memory = limits.memory_aware.then(concurrency::free_memory).flatten();
+        let mut state = self.lock();
+        if state.held_for_idle {
+            return None;
+        }
+        let running = state.running.len();
+        let running_video = state
+            .jobs
+            .iter()
+            .filter(|job| {
+                job.status == JobStatus::Running
+                    && concurrency::is_video_job(&job.request.input_path)
+            })
+            .count();
+        // Resolved once per preset rather than per job
+        let mut encoders: HashMap<Option<&str>, Option<HardwareEncoder>> = HashMap::new();
+        // `max_by_key` keeps the last of equals, hence the reversed position.
+        // A job that can't start yet lets smaller ones past it.
+        let (index, gpu) = state
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| {
+                job.status == JobStatus::Queued
+                    && limits.allows(&job.request.input_path, running_video, running, free_memory)
+            })
+            .filter_map(|(index, job)| {
+                if !concurrency::is_video_job(&job.request.input_path) {
+                    return Some((index, job, None));
+                }
+                let preset = job.request.preset.as_deref();
+                let encoder = *encoders.entry(preset).or_insert_with(|| {
+                    presets::resolve_preset(preset, &settings)
+                        .ok()
+                        .and_then(|preset| HardwareEncoder::of_codec(&preset.video_codec))
+                });
+                match encoder {
+                    Some(encoder) => Self::free_gpu(&state, &settings.gpu, encoder)
+                        .map(|device| (index, job, Some((encoder, device)))),
+                    None => Some((index, job, None)),
+                }
+            })
+            .max_by_key(|(index, job, _)| (job.request.priority, std::cmp::Reverse(*index)))
+            .map(|(index, _, gpu)| (index, gpu))?;
+        let job = &mut state.jobs[index];
+        job.status = JobStatus::Running;
+        let job = job.clone();
+
+        debug!("Job {} running", job.id);
+        let id = job.id;
+        let queue = self.clone();
+        let mut ctx = JobContext::default()
+            .with_progress_listener(move |progress| queue.report_progress(id, progress));
+        if self.session.is_some() {
+            let queue = self.clone();
+            ctx = ctx.with_output_listener(move |path| queue.track_output(id, path));
+        }
+        if let Some((_, Some(device))) = &gpu {
+            ctx = ctx.with_gpu(device);
+        }
+        let now = Instant::now();
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        state.running.insert(
+            job.id,
+            RunningJob {
+                ctx: ctx.clone(),
+                started: now,
+                started_at,
+                writing: Vec::new(),
+                gpu,
+            },
+        );
+        state.batch_started.get_or_insert(now);
+        self.session_changed();
+        Some((job, ctx))
+    }
+
+    /// A GPU with a session left for `encoder`: the least busy of the chosen
+    /// ones, or the driver's pick (`None`) when none are chosen.
+    fn free_gpu(
+        state: &QueueState,
+        settings: &GpuSettings,
+        encoder: HardwareEncoder,
+    ) -> Option<Option<String>> {
+        let sessions = |device: Option<&str>| {
+            state
+                .running
+                .values()
+                .filter_map(|running| running.gpu.as_ref())
+                .filter(|(used, used_device)| *used == encoder && used_device.as_deref() == device)
+                .count()
+        };
+        let selected = gpu::selected_devices(settings, encoder);
+        if selected.is_empty() {
+            return (sessions(None) < settings.sessions_per_device).then_some(None);
+        }
+        selected
+            .into_iter()
+            .map(|device| (sessions(Some(device)), device))
+            .filter(|(sessions, _)| *sessions < settings.sessions_per_device)
+            .min_by_key(|(sessions, _)| *sessions)
+            .map(|(_, device)| Some(device.to_string()))
+    }
+
+    fn report_progress(&self, id: JobId, progress: &JobProgress) {
+        let mut state = self.lock();
+        let Some(job) = state.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        job.progress = Some(progress.clone());
+        let job = job.clone();
+        let batch = Self::batch_progress(&state);
+        drop(state);
+
+        self.emit(vec![
+            QueueEvent::Progress(Box::new(job)),
+            QueueEvent::BatchProgress(batch),
+        ]);
+    }
+
+    fn track_output(&self, id: JobId, path: &Path) {
+        let mut state = self.lock();
+        let Some(running) = state.running.get_mut(&id) else {
+            return;
+        };
+        running.writing.push(path.to_path_buf());
+        self.session_changed();
+    }
+
+    fn finish(&self, id: JobId, result: Result<CompressionResult>, budget: Option<u64>) {
+        let mut state = self.lock();
+        let (cancelled, elapsed_ms) = state
+            .running
+            .remove(&id)
+            .map(|running| {
+                (
+                    running.ctx.is_cancelled(),
+                    running.started.elapsed().as_millis() as u64,
+                )
+            })
+            .unwrap_or((false, 0));
+
+        let output_before = state.batch_output;
+        if let Ok(result) = &result {
+            state.batch_output += result.compressed_size;
+        }
+        let Some(job) = state.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        job.elapsed_ms = Some(elapsed_ms);
+        job.progress = None;
+
+        match result {
+            Ok(result) => {
+                job.status = JobStatus::Completed;
+                job.result = Some(result);
+            }
+            Err(_) if cancelled => job.status = JobStatus::Cancelled,
+            Err(error) if error.is_skip() => {
+                job.status = JobStatus::Skipped;
+                job.error = Some(error);
+            }
+            Err(error) => {
+                job.status = JobStatus::Failed;
+                job.error = Some(error);
+            }
+        }
+
+        let job = job.clone();
+        state.batch.push(id);
+        self.session_changed();
+        let mut events = Vec::new();
+        if let Some(budget) = budget {
+            let used = state.batch_output;
+            if output_before < budget && used >= budget {
+                warn!(
+                    "Batch outputs reached {}, over the {} budget",
+                    format_size(used),
+                    format_size(budget)
+                );
+                events.push(QueueEvent::OverBudget { used, budget });
+            }
+        }
+        events.extend(Self::finished_events(&mut state, &job));
+        drop(state);
+
+        self.emit(events);
+    }
+
+    /// Events for a job that just finished, plus a drain summary if it was the
+    /// last outstanding one.
+    fn finished_events(state: &mut QueueState, job: &Job) -> Vec<QueueEvent> {
+        match &job.error {
+            Some(error) if job.status == JobStatus::Failed => {
+                warn!("Job {} failed: {}", job.id, error)
+            }
+            _ => info!("Job {} {:?}", job.id, job.status),
+        }
+
+        let mut events = vec![QueueEvent::JobFinished(Box::new(job.clone()))];
+
+        let idle = state.jobs.iter().all(|job| job.status.is_finished());
+        if !idle {
+            events.push(QueueEvent::BatchProgress(Self::batch_progress(state)));
+        } else {
+            let batch = std::mem::take(&mut state.batch);
+            state.batch_output = 0;
+            state.batch_started = None;
+            let summary = BatchSummary::from_jobs(
+                state.batch_id.take().unwrap_or_default(),
+                state.jobs.iter().filter(|job| batch.contains(&job.id)),
+            );
+            info!(
+                "Queue drained: {} completed, {} failed, {} cancelled, {} skipped",
+                summary.completed, summary.failed, summary.cancelled, summary.skipped
+            );
+            events.push(QueueEvent::Drained(summary));
+        }
+
+        events
+    }
+
+    /// The jobs that finished since the queue was last idle plus the ones
+    /// still to go.
+    fn batch_progress(state: &QueueState) -> BatchProgress {
+        BatchProgress::from_jobs(
+            state.batch_id.unwrap_or_default(),
+            state
+                .jobs
+                .iter()
+                .filter(|job| !job.status.is_finished() || state.batch.contains(&job.id)),
+            state.batch_started,
+        )
+    }
+
+    /// Has the session file rewritten shortly, if the queue keeps one.
+    fn session_changed(&self) {
+        if let Some(session) = &self.session {
+            session.mark_dirty();
+        }
+    }
+
+    fn emit(&self, events: Vec<QueueEvent>) {
+        let listeners = self
+            .listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+
+        for event in &events {
+            for listener in &listeners {
+                listener(event);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        // A panicking worker must not take the whole queue down with it
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Every unfinished job, previous session first.
+fn saved_jobs(state: &QueueState) -> Vec<SavedJob> {
+    let previous = state.previous_session.iter().map(|job| SavedJob {
+        job: job.clone(),
+        running_since: None,
+        writing: Vec::new(),
+    });
+    let current = state
+        .jobs
+        .iter()
+        .filter(|job| !job.status.is_finished())
+        .map(|job| {
+            let running = state.running.get(&job.id);
+            SavedJob {
+                job: SessionJob {
+                    request: job.request.clone(),
+                    interrupted: false,
+                },
+                running_since: running.map(|running| running.started_at),
+                writing: running
+                    .map(|running| running.writing.clone())
+                    .unwrap_or_default(),
+            }
+        });
+    previous.chain(current).collect()
+}
+
+/// Saves `saved_jobs` to `path`, or removes the file when there are none.
+fn write_session(path: &Path, saved_jobs: &[SavedJob]) {
+    let saved = if saved_jobs.is_empty() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    } else {
+        // Via a temp file, so a crash mid-write leaves the last good copy
+        let temp_path = path.with_extension("json.tmp");
+        serde_json::to_vec_pretty(saved_jobs)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&temp_path, contents)
+            })
+            .and_then(|()| fs::rename(&temp_path, path))
+    };
+    if let Err(e) = saved {
+        warn!("Failed to save the queue to {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,232 @@
+//! Reports of finished batches, saved next to their outputs for the
+//! record: every file with its sizes or why it failed, the totals, and the
+//! presets it ran with.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::batch_zip;
+use crate::compressor;
+use crate::error::{Error, Result};
+use crate::presets::{self, Preset};
+use crate::queue::{BatchSummary, Job, JobStatus};
+use crate::settings::Settings;
+use crate::units::format_size;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportFormat {
+    #[default]
+    None,
+    Json,
+    Html,
+    /// Both files, side by side.
+    Both,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchReport {
+    /// Unix time the report was made.
+    pub created_at: u64,
+    pub app_version: &'static str,
+    pub summary: BatchSummary,
+    pub files: Vec<ReportFile>,
+    /// Every preset the batch used, as it was set up when the batch
+    /// finished.
+    pub presets: Vec<Preset>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportFile {
+    pub input_path: String,
+    pub status: JobStatus,
+    pub preset: Option<String>,
+    pub original_size: u64,
+    pub compressed_size: Option<u64>,
+    /// `compressed_size / original_size`.
+    pub ratio: Option<f64>,
+    pub output_path: Option<String>,
+    pub elapsed_ms: Option<u64>,
+    /// Why the file failed or was skipped.
+    pub reason: Option<String>,
+}
+
+impl BatchReport {
+    /// Builds the report for `jobs`, the jobs of the batch `summary` covers.
+    pub fn new(summary: &BatchSummary, jobs: &[Job], settings: &Settings) -> Self {
+        let mut presets: Vec<Preset> = Vec::new();
+        let files = jobs
+            .iter()
+            .map(|job| {
+                // Presets deleted since can't be looked up any more
+                let preset = presets::resolve_preset(job.request.preset.as_deref(), settings).ok();
+                if let Some(preset) = &preset {
+                    if !presets.iter().any(|known| known.name == preset.name) {
+                        presets.push(preset.clone());
+                    }
+                }
+                ReportFile {
+                    input_path: job.request.input_path.clone(),
+                    status: job.status,
+                    preset: preset
+                        .map(|preset| preset.name)
+                        .or_else(|| job.request.preset.clone()),
+                    original_size: job.original_size,
+                    compressed_size: job.result.as_ref().map(|result| result.compressed_size),
+                    ratio: job.result.as_ref().map(|result| result.ratio),
+                    output_path: job.result.as_ref().map(|result| result.output_path.clone()),
+                    elapsed_ms: job.elapsed_ms,
+                    reason: job.error.as_ref().map(|error| error.to_string()),
+                }
+            })
+            .collect();
+
+        Self {
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            app_version: env!("CARGO_PKG_VERSION"),
+            summary: summary.clone(),
+            files,
+            presets,
+        }
+    }
+
+    /// Saves the report as `batch-<unix time>_report.json` and/or `.html`
+    /// in the directory holding the outputs, and returns the files written.
+    /// A batch without outputs gets it where its first output would have
+    /// gone.
+    pub fn write(
+        &self,
+        format: ReportFormat,
+        jobs: &[Job],
+        settings: &Settings,
+    ) -> Result<Vec<PathBuf>> {
+        let outputs: Vec<PathBuf> = self.summary.outputs.iter().map(PathBuf::from).collect();
+        let dir = match batch_zip::common_dir(&outputs.iter().collect::<Vec<_>>()) {
+            Some(dir) => dir,
+            None => {
+                let job = jobs.first().ok_or_else(|| Error::InvalidRequest {
+                    message: "The batch has no files to report on".to_string(),
+                })?;
+                let dir = compressor::output_dir_for(
+                    Path::new(&job.request.input_path),
+                    job.request.output_path.clone(),
+                    settings,
+                );
+                fs::create_dir_all(&dir)
+                    .map_err(|e| Error::io("Failed to create output directory", e))?;
+                dir
+            }
+        };
+
+        let base = format!("batch-{}_report", self.created_at);
+        let mut written = Vec::new();
+        if matches!(format, ReportFormat::Json | ReportFormat::Both) {
+            let path = dir.join(format!("{}.json", base));
+            let json = serde_json::to_string_pretty(self).map_err(|e| Error::Io {
+                message: format!("Failed to serialize report: {}", e),
+            })?;
+            fs::write(&path, json).map_err(|e| Error::io("Failed to write report", e))?;
+            written.push(path);
+        }
+        if matches!(format, ReportFormat::Html | ReportFormat::Both) {
+            let path = dir.join(format!("{}.html", base));
+            fs::write(&path, self.html()).map_err(|e| Error::io("Failed to write report", e))?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    /// A standalone page: totals, then one row per file.
+    pub fn html(&self) -> String {
+        let summary = &self.summary;
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Compression report</title>\n<style>\n\
+             body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }\n\
+             table { border-collapse: collapse; width: 100%; }\n\
+             th, td { border-bottom: 1px solid #ddd; padding: 4px 8px; text-align: left; }\n\
+             td.size { text-align: right; white-space: nowrap; }\n\
+             tr.failed td { color: #b00020; }\n\
+             tr.skipped td, tr.cancelled td { color: #777; }\n\
+             pre { background: #f5f5f5; padding: 1em; overflow-x: auto; }\n\
+             </style>\n</head>\n<body>\n<h1>Compression report</h1>\n",
+        );
+
+        html.push_str(&format!(
+            "<p>{} files: {} compressed, {} failed, {} skipped, {} cancelled.</p>\n",
+            summary.files, summary.completed, summary.failed, summary.skipped, summary.cancelled
+        ));
+        html.push_str(&format!(
+            "<p>{} &rarr; {}, saving {} ({:.1}%) in {:.1} s.</p>\n",
+            format_size(summary.original_size),
+            format_size(summary.compressed_size),
+            format_size(summary.bytes_saved),
+            if summary.original_size > 0 {
+                summary.bytes_saved as f64 * 100.0 / summary.original_size as f64
+            } else {
+                0.0
+            },
+            summary.elapsed_ms as f64 / 1000.0
+        ));
+
+        html.push_str(
+            "<table>\n<tr><th>File</th><th>Status</th><th>Preset</th><th>Before</th>\
+             <th>After</th><th>Ratio</th><th>Output or reason</th></tr>\n",
+        );
+        for file in &self.files {
+            let status = serde_json::to_value(file.status)
+                .ok()
+                .and_then(|value| value.as_str().map(String::from))
+                .unwrap_or_default();
+            html.push_str(&format!(
+                "<tr class=\"{status}\"><td>{}</td><td>{status}</td><td>{}</td>\
+                 <td class=\"size\">{}</td><td class=\"size\">{}</td>\
+                 <td class=\"size\">{}</td><td>{}</td></tr>\n",
+                escape(&file.input_path),
+                escape(file.preset.as_deref().unwrap_or_default()),
+                format_size(file.original_size),
+                file.compressed_size.map(format_size).unwrap_or_default(),
+                file.ratio
+                    .map(|ratio| format!("{:.0}%", ratio * 100.0))
+                    .unwrap_or_default(),
+                escape(
+                    file.output_path
+                        .as_deref()
+                        .or(file.reason.as_deref())
+                        .unwrap_or_default()
+                ),
+            ));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Presets</h2>\n");
+        for preset in &self.presets {
+            let json = serde_json::to_string_pretty(preset).unwrap_or_default();
+            html.push_str(&format!(
+                "<h3>{}</h3>\n<pre>{}</pre>\n",
+                escape(&preset.name),
+                escape(&json)
+            ));
+        }
+        html.push_str(&format!(
+            "<p><small>Media Compressor {}</small></p>\n</body>\n</html>\n",
+            self.app_version
+        ));
+        html
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,406 @@
+//! Uploads finished outputs to S3-compatible storage: AWS S3, Backblaze B2,
+//! Cloudflare R2, MinIO. Requests are signed with SigV4 and use path-style
+//! URLs, which all of them accept.
+
+use hmac::{Hmac, Mac};
+use reqwest::blocking::{Body, Client, Response};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::compressor::CompressionResult;
+use crate::error::{Error, Result};
+use crate::settings::UploadSettings;
+
+/// Files above this go up in parts; a single PUT tops out at 5 GiB.
+const MULTIPART_THRESHOLD: u64 = 256 * 1024 * 1024;
+const PART_SIZE: usize = 64 * 1024 * 1024;
+/// Generous, since a whole part has to make it through a home uplink.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Sent in place of a payload hash so files aren't read twice. TLS already
+/// protects the body in transit.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// When uploads are on, uploads every file `result` wrote and sets its
+/// `remote_url` to where the main output ended up.
+pub fn upload_outputs(
+    mut result: CompressionResult,
+    settings: &UploadSettings,
+) -> Result<CompressionResult> {
+    if !settings.enabled {
+        return Ok(result);
+    }
+
+    // The blocking client can't be built, used or dropped on an async
+    // runtime thread, which is where most callers are
+    let urls = thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let bucket = Bucket::new(settings)?;
+                result
+                    .output_files()
+                    .map(|file| bucket.upload(Path::new(file)))
+                    .collect::<Result<Vec<String>>>()
+            })
+            .join()
+    })
+    .map_err(|_| Error::Network {
+        message: "Upload thread panicked".to_string(),
+    })??;
+
+    result.remote_url = urls.into_iter().next();
+    Ok(result)
+}
+
+struct Bucket<'a> {
+    settings: &'a UploadSettings,
+    endpoint: Url,
+    client: Client,
+}
+
+impl<'a> Bucket<'a> {
+    fn new(settings: &'a UploadSettings) -> Result<Self> {
+        if [
+            &settings.endpoint,
+            &settings.bucket,
+            &settings.access_key_id,
+            &settings.secret_access_key,
+        ]
+        .iter()
+        .any(|value| value.trim().is_empty())
+        {
+            return Err(Error::invalid_settings(
+                "Uploads are on but the endpoint, bucket or credentials are missing",
+            ));
+        }
+
+        let endpoint = Url::parse(settings.endpoint.trim())
+            .map_err(|e| Error::invalid_settings(format!("Invalid upload endpoint: {}", e)))?;
+        if endpoint.host_str().is_none() {
+            return Err(Error::invalid_settings("The upload endpoint has no host"));
+        }
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+
+        Ok(Self {
+            settings,
+            endpoint,
+            client,
+        })
+    }
+
+    /// Uploads `file` under the configured prefix and returns its URL.
+    fn upload(&self, file: &Path) -> Result<String> {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let prefix = self.settings.prefix.trim_matches('/');
+        let key = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        let uploaded = fs::metadata(file)
+            .map_err(|e| Error::io("Failed to read output", e))
+            .and_then(|meta| {
+                if meta.len() > MULTIPART_THRESHOLD {
+                    self.put_multipart(&key, file)
+                } else {
+                    let contents =
+                        File::open(file).map_err(|e| Error::io("Failed to read output", e))?;
+                    self.send(Method::PUT, &key, &[], Body::sized(contents, meta.len()))
+                        .map(drop)
+                }
+            });
+        if let Err(e) = uploaded {
+            return Err(Error::Network {
+                message: format!("Failed to upload {}: {}", file.display(), e),
+            });
+        }
+
+        let base = match self.settings.public_url.as_deref().map(str::trim) {
+            Some(public_url) if !public_url.is_empty() => public_url.to_string(),
+            _ => format!(
+                "{}/{}",
+                self.settings.endpoint.trim().trim_end_matches('/'),
+                uri_encode(&self.settings.bucket, true)
+            ),
+        };
+        Ok(format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            uri_encode(&key, false)
+        ))
+    }
+
+    fn put_multipart(&self, key: &str, file: &Path) -> Result<()> {
+        let response = self.send(Method::POST, key, &[("uploads", "")], Body::from(""))?;
+        let upload_id = xml_value(&response.text()?, "UploadId").ok_or_else(|| Error::Network {
+            message: "The server didn't start a multipart upload".to_string(),
+        })?;
+
+        let etags = match self.upload_parts(key, file, &upload_id) {
+            Ok(etags) => etags,
+            Err(e) => {
+                // Unfinished parts are stored (and billed) until aborted
+                self.send(
+                    Method::DELETE,
+                    key,
+                    &[("uploadId", &upload_id)],
+                    Body::from(""),
+                )
+                .ok();
+                return Err(e);
+            }
+        };
+
+        let mut manifest = String::from("<CompleteMultipartUpload>");
+        for (index, etag) in etags.iter().enumerate() {
+            manifest.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                index + 1,
+                etag
+            ));
+        }
+        manifest.push_str("</CompleteMultipartUpload>");
+
+        let response = self.send(
+            Method::POST,
+            key,
+            &[("uploadId", &upload_id)],
+            Body::from(manifest),
+        )?;
+        // Completing can fail after the 200 has already been sent
+        let body = response.text()?;
+        if body.contains("<Error>") {
+            return Err(s3_error("Completing the upload failed", &body));
+        }
+        Ok(())
+    }
+
+    fn upload_parts(&self, key: &str, file: &Path, upload_id: &str) -> Result<Vec<String>> {
+        let mut contents = File::open(file).map_err(|e| Error::io("Failed to read output", e))?;
+        let mut buffer = vec![0; PART_SIZE];
+        let mut etags = Vec::new();
+
+        loop {
+            let len = read_full(&mut contents, &mut buffer)
+                .map_err(|e| Error::io("Failed to read output", e))?;
+            if len == 0 {
+                return Ok(etags);
+            }
+
+            let part_number = (etags.len() + 1).to_string();
+            let response = self.send(
+                Method::PUT,
+                key,
+                &[("partNumber", &part_number), ("uploadId", upload_id)],
+                Body::from(buffer[..len].to_vec()),
+            )?;
+            let etag = response
+                .headers()
+                .get("etag")
+                .and_then(|etag| etag.to_str().ok())
+                .ok_or_else(|| Error::Network {
+                    message: format!("The server returned no ETag for part {}", part_number),
+                })?;
+            etags.push(etag.to_string());
+        }
+    }
+
+    /// Sends a signed request for `key` in the bucket.
+    fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Body,
+    ) -> Result<Response> {
+        let host = match (self.endpoint.host_str(), self.endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => String::new(),
+        };
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.settings.bucket, true),
+            uri_encode(key, false)
+        );
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let (timestamp, date) = amz_timestamp(SystemTime::now());
+        let region = match self.settings.region.trim() {
+            "" => "us-east-1",
+            region => region,
+        };
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            path,
+            query,
+            host,
+            UNSIGNED_PAYLOAD,
+            timestamp,
+            SIGNED_HEADERS,
+            UNSIGNED_PAYLOAD
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            timestamp,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+
+        let mut signing_key = hmac(
+            format!("AWS4{}", self.settings.secret_access_key.trim()).as_bytes(),
+            &date,
+        );
+        for part in [region, "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part);
+        }
+        let signature: String = hmac(&signing_key, &string_to_sign)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let mut url = format!("{}://{}{}", self.endpoint.scheme(), host, path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let response = self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", &timestamp)
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.settings.access_key_id.trim(),
+                    scope,
+                    SIGNED_HEADERS,
+                    signature
+                ),
+            )
+            .body(body)
+            .send()?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(s3_error(
+                &format!("Server responded with {}", status),
+                &body,
+            ));
+        }
+        Ok(response)
+    }
+}
+
+fn hmac(key: &[u8], data: impl AsRef<[u8]>) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_ref());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters, as SigV4 wants.
+/// Slashes are kept in object keys, where they separate "folders".
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// `20240131T235959Z` and `20240131` in UTC.
+fn amz_timestamp(now: SystemTime) -> (String, String) {
+    let seconds = now
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = seconds % 86_400;
+    let timestamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+    (timestamp, date)
+}
+
+/// Year, month and day of a count of days since 1970-01-01, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Text of the first `<tag>` element in an S3 XML response.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].to_string())
+}
+
+fn s3_error(context: &str, body: &str) -> Error {
+    let message = match (xml_value(body, "Code"), xml_value(body, "Message")) {
+        (Some(code), Some(message)) => format!("{}: {} ({})", context, message, code),
+        (Some(code), None) => format!("{}: {}", context, code),
+        _ => context.to_string(),
+    };
+    Error::Network { message }
+}
+
+/// Fills `buffer` unless the end of the file comes first, returning how
+/// much was read.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
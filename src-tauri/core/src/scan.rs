@@ -0,0 +1,337 @@
+//! Recursive media inventory for building a selection UI over large folders.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::checksum;
+use crate::compressor;
+use crate::error::{Error, Result};
+use crate::history::HistoryStore;
+use crate::live_photo;
+use crate::media::{MediaFilter, MediaType};
+use crate::perceptual;
+use crate::presets;
+use crate::probe;
+use crate::settings::SettingsManager;
+
+/// Bytes per pixel of a typical quality-85 JPEG, used to guess image output
+/// sizes without encoding anything.
+pub(crate) const JPEG_BYTES_PER_PIXEL: f64 = 0.3;
+/// Longest side images get downscaled to by the default preset.
+const DEFAULT_MAX_IMAGE_DIMENSION: u32 = 2048;
+const DEFAULT_AUDIO_BITRATE: f64 = 128_000.0;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanEntry {
+    pub path: String,
+    pub media_type: &'static str,
+    pub size: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Video duration in seconds.
+    pub duration: Option<f64>,
+    /// Rough guess of how many bytes the default settings would save; a hint
+    /// for sorting and selection, not a promise.
+    pub estimated_savings: Option<u64>,
+    /// BLAKE3 of the contents; `None` if the file couldn't be read.
+    pub hash: Option<String>,
+    /// Earlier file in this scan with identical contents.
+    pub duplicate_of: Option<String>,
+    /// A file with identical contents was compressed before, per the history.
+    pub already_processed: bool,
+    /// Written by an earlier run, going by its name or the history.
+    pub compressed_output: bool,
+    /// Perceptual hash as 16 hex digits, computed when similar-image
+    /// detection is on.
+    pub perceptual_hash: Option<String>,
+    /// First image of the group this one looks the same as. Exact duplicates
+    /// are reported through `duplicate_of` instead.
+    pub similar_to: Option<String>,
+    /// Presets better suited than the default, e.g. for ProRes masters.
+    pub suggested_presets: Vec<&'static str>,
+    /// The other half of the Live Photo this file is part of: the MOV for a
+    /// still, the still for a MOV.
+    pub live_photo_partner: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanSummary {
+    pub files: usize,
+    pub total_size: u64,
+    pub estimated_savings: u64,
+    /// Files that are exact copies of an earlier file in the scan.
+    pub duplicates: usize,
+    pub already_processed: usize,
+    pub compressed_outputs: usize,
+    /// Images that look the same as an earlier image in the scan.
+    pub similar: usize,
+}
+
+pub struct Scanner {
+    /// Used to probe videos; without it videos are listed with size only.
+    ffmpeg_path: Option<PathBuf>,
+    /// Decode every image to group visually identical ones. Much slower than
+    /// a plain scan, so off unless asked for.
+    find_similar: bool,
+    /// Which files to list; everything supported by default.
+    filter: MediaFilter,
+}
+
+impl Scanner {
+    pub fn new(ffmpeg_path: Option<PathBuf>) -> Self {
+        Self {
+            ffmpeg_path,
+            find_similar: false,
+            filter: MediaFilter::default(),
+        }
+    }
+
+    pub fn find_similar(mut self, enabled: bool) -> Self {
+        self.find_similar = enabled;
+        self
+    }
+
+    pub fn filter(mut self, filter: MediaFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Walks `root` recursively, handing entries to `on_batch` in groups of
+    /// `batch_size` as they are found.
+    pub fn scan(
+        &self,
+        root: &Path,
+        batch_size: usize,
+        mut on_batch: impl FnMut(Vec<ScanEntry>),
+    ) -> Result<ScanSummary> {
+        if !root.exists() {
+            return Err(Error::InputNotFound {
+                path: root.to_string_lossy().to_string(),
+            });
+        }
+        if !root.is_dir() {
+            return Err(Error::InvalidRequest {
+                message: "Path is not a directory".to_string(),
+            });
+        }
+
+        // Without a readable history nothing counts as processed
+        let store = HistoryStore::open().ok();
+        let processed = store
+            .as_ref()
+            .and_then(|store| store.processed_hashes().ok())
+            .unwrap_or_default();
+        let outputs = store
+            .as_ref()
+            .and_then(|store| store.output_hashes().ok())
+            .unwrap_or_default();
+        let settings = SettingsManager::new().load();
+        let mut first_seen: HashMap<String, String> = HashMap::new();
+        // Perceptual hash and path of the first image of each similar group
+        let mut groups: Vec<(u64, String)> = Vec::new();
+
+        let mut summary = ScanSummary::default();
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut pending = vec![root.to_path_buf()];
+
+        while let Some(dir) = pending.pop() {
+            // Unreadable subdirectories are skipped rather than failing the scan
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+
+            let mut paths: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| !is_hidden(path))
+                .collect();
+            paths.sort();
+            let live_photos = live_photo::pairs(&paths);
+
+            for path in paths {
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+
+                if !self.filter.matches(&path) {
+                    continue;
+                }
+                let Some(mut entry) = self.inspect(&path) else {
+                    continue;
+                };
+                entry.live_photo_partner = live_photos
+                    .get(&path)
+                    .map(|partner| partner.to_string_lossy().to_string());
+                flag_duplicates(&mut entry, &mut first_seen, &processed);
+                entry.compressed_output = settings
+                    .is_output_name(&path.file_stem().unwrap_or_default().to_string_lossy())
+                    || entry
+                        .hash
+                        .as_ref()
+                        .is_some_and(|hash| outputs.contains(hash));
+                if entry.duplicate_of.is_none() {
+                    self.flag_similar(&mut entry, &mut groups);
+                }
+
+                summary.files += 1;
+                summary.duplicates += entry.duplicate_of.is_some() as usize;
+                summary.already_processed += entry.already_processed as usize;
+                summary.compressed_outputs += entry.compressed_output as usize;
+                summary.similar += entry.similar_to.is_some() as usize;
+                summary.total_size += entry.size;
+                summary.estimated_savings += entry.estimated_savings.unwrap_or(0);
+
+                batch.push(entry);
+                if batch.len() >= batch_size {
+                    on_batch(std::mem::take(&mut batch));
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            on_batch(batch);
+        }
+
+        Ok(summary)
+    }
+
+    pub fn inspect(&self, path: &Path) -> Option<ScanEntry> {
+        let media_type = MediaType::from_path(path)?;
+        let size = fs::metadata(path).ok()?.len();
+
+        let mut entry = ScanEntry {
+            path: path.to_string_lossy().to_string(),
+            media_type: media_type.as_str(),
+            size,
+            width: None,
+            height: None,
+            duration: None,
+            estimated_savings: None,
+            hash: checksum::content_hash(path).ok(),
+            duplicate_of: None,
+            already_processed: false,
+            compressed_output: false,
+            perceptual_hash: None,
+            similar_to: None,
+            suggested_presets: Vec::new(),
+            live_photo_partner: None,
+        };
+
+        match media_type {
+            MediaType::Image => {
+                // Reads only the header, not the pixels
+                if let Ok((width, height)) = image::image_dimensions(path) {
+                    entry.width = Some(width);
+                    entry.height = Some(height);
+                    entry.estimated_savings = Some(estimate_image_savings(size, width, height));
+                }
+            }
+            MediaType::Video => {
+                if let Some(ffmpeg_path) = &self.ffmpeg_path {
+                    if let Ok(info) = probe::probe_media(ffmpeg_path, path) {
+                        entry.width = info.width;
+                        entry.height = info.height;
+                        entry.duration = info.duration;
+                        entry.estimated_savings = info.duration.map(|duration| {
+                            estimate_video_savings(size, info.height.unwrap_or(1080), duration)
+                        });
+                        if info.is_mezzanine() {
+                            entry.suggested_presets = presets::MEZZANINE_PRESETS.to_vec();
+                        }
+                    }
+                }
+            }
+            MediaType::Audio => {
+                if let Some(ffmpeg_path) = &self.ffmpeg_path {
+                    if let Ok(info) = probe::probe_media(ffmpeg_path, path) {
+                        entry.duration = info.duration;
+                        entry.estimated_savings = info
+                            .duration
+                            .map(|duration| estimate_audio_savings(size, duration));
+                    }
+                }
+            }
+            MediaType::Document | MediaType::Archive => {}
+        }
+
+        Some(entry)
+    }
+
+    fn flag_similar(&self, entry: &mut ScanEntry, groups: &mut Vec<(u64, String)>) {
+        if !self.find_similar || entry.media_type != MediaType::Image.as_str() {
+            return;
+        }
+        let Ok(img) = compressor::open_image(Path::new(&entry.path)) else {
+            return;
+        };
+
+        let hash = perceptual::dhash(&img);
+        entry.perceptual_hash = Some(format!("{:016x}", hash));
+        match groups
+            .iter()
+            .find(|(first, _)| perceptual::is_similar(*first, hash))
+        {
+            Some((_, first_path)) => entry.similar_to = Some(first_path.clone()),
+            None => groups.push((hash, entry.path.clone())),
+        }
+    }
+}
+
+fn flag_duplicates(
+    entry: &mut ScanEntry,
+    first_seen: &mut HashMap<String, String>,
+    processed: &HashSet<String>,
+) {
+    let Some(hash) = &entry.hash else {
+        return;
+    };
+
+    entry.already_processed = processed.contains(hash);
+    match first_seen.get(hash) {
+        Some(original) => entry.duplicate_of = Some(original.clone()),
+        None => {
+            first_seen.insert(hash.clone(), entry.path.clone());
+        }
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+fn estimate_image_savings(size: u64, width: u32, height: u32) -> u64 {
+    let longest = width.max(height).max(1);
+    let scale = (DEFAULT_MAX_IMAGE_DIMENSION as f64 / longest as f64).min(1.0);
+    let pixels = width as f64 * height as f64 * scale * scale;
+    let estimated_output = (pixels * JPEG_BYTES_PER_PIXEL) as u64;
+    size.saturating_sub(estimated_output)
+}
+
+fn estimate_audio_savings(size: u64, duration: f64) -> u64 {
+    let estimated_output = (DEFAULT_AUDIO_BITRATE * duration / 8.0) as u64;
+    size.saturating_sub(estimated_output)
+}
+
+fn estimate_video_savings(size: u64, height: u32, duration: f64) -> u64 {
+    let estimated_output =
+        ((typical_video_bitrate(height) + DEFAULT_AUDIO_BITRATE) * duration / 8.0) as u64;
+    size.saturating_sub(estimated_output)
+}
+
+/// Typical CRF 23 x264 bitrate for the given frame height, in bits per
+/// second.
+pub(crate) fn typical_video_bitrate(height: u32) -> f64 {
+    match height {
+        0..=480 => 1_200_000.0,
+        481..=720 => 2_500_000.0,
+        721..=1080 => 4_500_000.0,
+        1081..=1440 => 8_000_000.0,
+        _ => 14_000_000.0,
+    }
+}
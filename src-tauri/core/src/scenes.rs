@@ -0,0 +1,119 @@
+//! Splits a long recording into one compressed clip per scene, for skimming
+//! dashcam or CCTV footage without scrubbing through hours of video.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::compressor::{self, prepare_output_dir};
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::job::JobContext;
+use crate::presets::Preset;
+use crate::probe;
+use crate::settings::Settings;
+
+/// Scene change score (0-1) above which a frame starts a new scene.
+pub const DEFAULT_SCENE_THRESHOLD: f64 = 0.4;
+/// Cuts closer together than this are merged, so a flickering light or a
+/// quick pan doesn't produce a pile of one-second clips.
+const MIN_SCENE_SECONDS: f64 = 2.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SceneClip {
+    /// 1-based position in the source.
+    pub index: usize,
+    /// Start and end in the source, in seconds.
+    pub start: f64,
+    pub end: f64,
+    pub output_path: String,
+    pub size: u64,
+}
+
+/// Detects scene changes in `input_path` and encodes each scene with
+/// `preset` as `<name>-sceneNNN.<ext>`. Size-capped presets cap every clip.
+pub async fn split_scenes(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    preset: &Preset,
+    threshold: Option<f64>,
+    ctx: &JobContext,
+) -> Result<Vec<SceneClip>> {
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    let input = Path::new(input_path);
+
+    if !input.exists() {
+        return Err(Error::InputNotFound {
+            path: input_path.to_string(),
+        });
+    }
+
+    let threshold = threshold.unwrap_or(DEFAULT_SCENE_THRESHOLD);
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(Error::InvalidRequest {
+            message: "Scene threshold must be between 0 and 1".to_string(),
+        });
+    }
+
+    let duration = probe::probe_duration(&ffmpeg_path, input)?;
+    let cuts = probe::detect_scenes(&ffmpeg_path, input, threshold)?;
+    let crop = if preset.crop_black_bars {
+        probe::detect_crop(&ffmpeg_path, input).ok().flatten()
+    } else {
+        None
+    };
+    if ctx.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+    let file_name = input.file_stem().unwrap().to_string_lossy();
+    let extension = input
+        .extension()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or("mp4");
+    let name = settings.output_file_name(&file_name, "video");
+
+    let mut clips = Vec::new();
+    for (index, (start, end)) in scene_ranges(&cuts, duration).into_iter().enumerate() {
+        let output_file = output_dir.join(format!("{}-scene{:03}.{}", name, index + 1, extension));
+        compressor::encode_range(
+            &ffmpeg_path,
+            input_path,
+            &output_file,
+            preset,
+            crop,
+            (start, end),
+            ctx,
+        )?;
+
+        clips.push(SceneClip {
+            index: index + 1,
+            start,
+            end,
+            output_path: output_file.to_string_lossy().to_string(),
+            size: fs::metadata(&output_file)?.len(),
+        });
+    }
+
+    Ok(clips)
+}
+
+/// Turns scene change times into `(start, end)` ranges covering the whole
+/// video, dropping cuts that would leave a scene shorter than
+/// `MIN_SCENE_SECONDS`.
+fn scene_ranges(cuts: &[f64], duration: f64) -> Vec<(f64, f64)> {
+    let mut ranges = Vec::new();
+    let mut start = 0.0;
+    for &cut in cuts {
+        if cut - start >= MIN_SCENE_SECONDS && duration - cut >= MIN_SCENE_SECONDS {
+            ranges.push((start, cut));
+            start = cut;
+        }
+    }
+    ranges.push((start, duration));
+    ranges
+}
@@ -0,0 +1,271 @@
+//! Pipeline scripts: a Rhai script from settings that runs before each file
+//! is compressed, for policies presets can't express, like "anything over
+//! 10 Mbps gets downscaled".
+//!
+//! The script sees the file as a `file` map: `path`, `name`, `extension`,
+//! `media_type` (`video`, `audio`, `image`, `document` or `archive`), `size` in
+//! bytes, and from probing `duration`, `bitrate_kbps`, `width`, `height`,
+//! `video_codec`, `pixel_format`, `frame_rate`, `audio_codec`,
+//! `audio_sample_rate` and `audio_channels`, any of which is `()` when
+//! unknown. It returns nothing to leave the file alone, or a map with any of:
+//!
+//! - `skip`: `true` or a reason, to leave the file out
+//! - `preset`: the name of the preset to use instead
+//! - `filters`: ffmpeg video filters to add, e.g. `["scale=-2:720"]`
+//!
+//! ```rhai
+//! if file.media_type == "video" && file.bitrate_kbps != () && file.bitrate_kbps > 10000 {
+//!     #{ filters: ["scale=-2:min(ih\\,1080)"] }
+//! }
+//! ```
+
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::ffmpeg_manager::FFmpegManager;
+use crate::job::JobContext;
+use crate::media::MediaType;
+use crate::presets::{resolve_preset, Preset};
+use crate::probe;
+use crate::settings::Settings;
+
+/// What a script asked for one file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptOverrides {
+    /// Why the file is skipped, if it is.
+    pub skip: Option<String>,
+    pub preset: Option<String>,
+    pub filters: Vec<String>,
+}
+
+/// Runs the pipeline script in `settings`, if any, on `input_path`. Returns
+/// the preset and context to compress it with, or a skip.
+pub async fn apply(
+    input_path: &str,
+    media_type: MediaType,
+    settings: &Settings,
+    preset: &Preset,
+    ctx: &JobContext,
+) -> Result<(Preset, JobContext)> {
+    let Some(source) = settings
+        .pipeline_script
+        .as_deref()
+        .filter(|source| !source.trim().is_empty())
+    else {
+        return Ok((preset.clone(), ctx.clone()));
+    };
+    // Scripts about images or documents still run without ffmpeg, just
+    // with less to go on
+    let info = match FFmpegManager::new().ensure_ffmpeg().await {
+        Ok(ffmpeg_path) => probe::probe_media(&ffmpeg_path, Path::new(input_path)).ok(),
+        Err(_) => None,
+    };
+    let overrides = run(source, input_path, media_type, info.as_ref())?;
+
+    if let Some(reason) = overrides.skip {
+        return Err(Error::ScriptSkipped {
+            path: input_path.to_string(),
+            reason,
+        });
+    }
+    let preset = match &overrides.preset {
+        Some(name) => resolve_preset(Some(name), settings)?,
+        None => preset.clone(),
+    };
+    let ctx = if overrides.filters.is_empty() {
+        ctx.clone()
+    } else {
+        ctx.clone().with_filters(overrides.filters)
+    };
+    Ok((preset, ctx))
+}
+
+#[cfg(feature = "scripting")]
+pub use engine::{check, run};
+
+#[cfg(feature = "scripting")]
+mod engine {
+    use rhai::{Dynamic, Engine, Map, Scope};
+    use std::fs;
+    use std::path::Path;
+    use tracing::info;
+
+    use super::ScriptOverrides;
+    use crate::error::{Error, Result};
+    use crate::media::MediaType;
+    use crate::probe::MediaInfo;
+
+    /// Enough for any sensible policy, and stops a runaway loop quickly.
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// Compiles `source` without running it, so mistakes show up when
+    /// settings are saved rather than on every file.
+    pub fn check(source: &str) -> Result<()> {
+        engine().compile(source).map_err(|e| {
+            Error::invalid_settings(format!("The pipeline script doesn't compile: {}", e))
+        })?;
+        Ok(())
+    }
+
+    pub fn run(
+        source: &str,
+        input_path: &str,
+        media_type: MediaType,
+        info: Option<&MediaInfo>,
+    ) -> Result<ScriptOverrides> {
+        let engine = engine();
+        let ast = engine.compile(source).map_err(|e| failed(e.to_string()))?;
+        let mut scope = Scope::new();
+        scope.push_constant("file", file_map(input_path, media_type, info));
+        let result: Dynamic = engine
+            .eval_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| failed(e.to_string()))?;
+        overrides(result)
+    }
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("Pipeline script: {}", text));
+        engine.on_debug(|text, _, _| info!("Pipeline script: {}", text));
+        engine
+    }
+
+    fn file_map(input_path: &str, media_type: MediaType, info: Option<&MediaInfo>) -> Map {
+        let path = Path::new(input_path);
+        let text = |value: Option<&str>| {
+            value.map_or(Dynamic::UNIT, |value| Dynamic::from(value.to_string()))
+        };
+        let integer =
+            |value: Option<u64>| value.map_or(Dynamic::UNIT, |value| Dynamic::from(value as i64));
+        let float = |value: Option<f64>| value.map_or(Dynamic::UNIT, Dynamic::from);
+
+        let mut file = Map::new();
+        file.insert("path".into(), Dynamic::from(input_path.to_string()));
+        file.insert(
+            "name".into(),
+            text(path.file_name().and_then(|name| name.to_str())),
+        );
+        file.insert(
+            "extension".into(),
+            text(path.extension().and_then(|extension| extension.to_str())),
+        );
+        file.insert(
+            "media_type".into(),
+            Dynamic::from(media_type.as_str().to_string()),
+        );
+        file.insert(
+            "size".into(),
+            integer(fs::metadata(path).ok().map(|metadata| metadata.len())),
+        );
+        file.insert(
+            "duration".into(),
+            float(info.and_then(|info| info.duration)),
+        );
+        file.insert(
+            "bitrate_kbps".into(),
+            integer(info.and_then(|info| info.bitrate_kbps)),
+        );
+        file.insert(
+            "width".into(),
+            integer(info.and_then(|info| info.width).map(u64::from)),
+        );
+        file.insert(
+            "height".into(),
+            integer(info.and_then(|info| info.height).map(u64::from)),
+        );
+        file.insert(
+            "video_codec".into(),
+            text(info.and_then(|info| info.video_codec.as_deref())),
+        );
+        file.insert(
+            "pixel_format".into(),
+            text(info.and_then(|info| info.pixel_format.as_deref())),
+        );
+        file.insert(
+            "frame_rate".into(),
+            float(info.and_then(|info| info.frame_rate)),
+        );
+        file.insert(
+            "audio_codec".into(),
+            text(info.and_then(|info| info.audio_codec.as_deref())),
+        );
+        file.insert(
+            "audio_sample_rate".into(),
+            integer(info.and_then(|info| info.audio_sample_rate).map(u64::from)),
+        );
+        file.insert(
+            "audio_channels".into(),
+            text(info.and_then(|info| info.audio_channels.as_deref())),
+        );
+        file
+    }
+
+    fn overrides(result: Dynamic) -> Result<ScriptOverrides> {
+        let mut overrides = ScriptOverrides::default();
+        if result.is_unit() {
+            return Ok(overrides);
+        }
+        let Some(map) = result.try_cast::<Map>() else {
+            return Err(failed("it must return a map or nothing"));
+        };
+        for (key, value) in map {
+            match key.as_str() {
+                "skip" if value.is_string() => {
+                    overrides.skip = value.into_string().ok();
+                }
+                "skip" => match value.as_bool() {
+                    Ok(true) => overrides.skip = Some("asked to by the script".to_string()),
+                    Ok(false) => {}
+                    Err(_) => return Err(failed("`skip` must be true, false or a reason")),
+                },
+                "preset" => {
+                    let name = value
+                        .into_string()
+                        .map_err(|_| failed("`preset` must be a preset name"))?;
+                    overrides.preset = Some(name);
+                }
+                "filters" => {
+                    let filters = value
+                        .into_array()
+                        .map_err(|_| failed("`filters` must be an array of filters"))?;
+                    for filter in filters {
+                        let filter = filter
+                            .into_string()
+                            .map_err(|_| failed("`filters` must be an array of filters"))?;
+                        overrides.filters.push(filter);
+                    }
+                }
+                other => return Err(failed(format!("unknown override `{}`", other))),
+            }
+        }
+        Ok(overrides)
+    }
+
+    fn failed(message: impl Into<String>) -> Error {
+        Error::ScriptFailed {
+            message: message.into(),
+        }
+    }
+}
+
+/// Without the `scripting` feature, settings with a script can't be saved
+/// and any script already in them fails its jobs.
+#[cfg(not(feature = "scripting"))]
+pub fn check(_source: &str) -> Result<()> {
+    Err(Error::invalid_settings(
+        "This build can't run pipeline scripts",
+    ))
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn run(
+    _source: &str,
+    _input_path: &str,
+    _media_type: MediaType,
+    _info: Option<&probe::MediaInfo>,
+) -> Result<ScriptOverrides> {
+    Err(Error::ScriptFailed {
+        message: "this build can't run pipeline scripts".to_string(),
+    })
+}
@@ -0,0 +1,590 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::custom_args;
+use crate::error::{Error, Result};
+use crate::paths;
+use crate::presets::Preset;
+use crate::report::ReportFormat;
+use crate::script;
+
+const SETTINGS_FILE: &str = "settings.json";
+/// The usual way to mark compressed copies, whatever the template says.
+const OUTPUT_SUFFIX: &str = "_compressed";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// Default output directory. `None` falls back to `~/Downloads/compressed`.
+    pub output_dir: Option<String>,
+    /// JPEG quality used for image outputs (1-100).
+    pub image_quality: u8,
+    /// ffmpeg video encoder, e.g. `libx264`.
+    pub video_codec: String,
+    /// Constant rate factor for video encodes.
+    pub video_crf: u8,
+    /// Number of files processed in parallel. `None` picks it from the
+    /// cores, free memory and the kinds of files queued.
+    pub concurrency: Option<usize>,
+    /// Output file name without extension. `{name}` is the input file stem,
+    /// `{type}` is `video`, `audio`, `image`, `document` or `archive`, and
+    /// `{width}` is the width of a responsive image variant.
+    pub filename_template: String,
+    /// User-defined presets. Built-ins live in `presets::builtin_presets`.
+    pub presets: Vec<Preset>,
+    /// Rhai run before each file is compressed, which can skip it or change
+    /// its preset and filters. See `script`.
+    pub pipeline_script: Option<String>,
+    pub http_api: HttpApiSettings,
+    pub webhook: WebhookSettings,
+    pub batch_zip: BatchZipSettings,
+    /// Save a report of each finished batch next to its outputs.
+    pub batch_report: ReportFormat,
+    pub encryption: EncryptionSettings,
+    pub upload: UploadSettings,
+    pub destination: DestinationSettings,
+    pub idle: IdleSettings,
+    pub gpu: GpuSettings,
+    /// Show a system notification when a batch (or one long job) finishes.
+    pub notifications: bool,
+    pub post_batch_action: PostBatchAction,
+    /// Grace period before sleep/shutdown, during which the user can cancel.
+    pub post_batch_countdown_secs: u64,
+    /// Record a SHA-256 of every output so later bit-rot can be detected.
+    pub checksum_mode: ChecksumMode,
+    /// Write debug-level entries (full ffmpeg arguments, every queue
+    /// transition) to the app log.
+    pub debug_logging: bool,
+    /// Queue jobs a crash interrupted again on the next launch without
+    /// asking; otherwise they wait in the previous session.
+    pub resume_interrupted_jobs: bool,
+    /// Audio channel count for the default preset; `None` keeps the source's.
+    pub audio_channels: Option<u8>,
+    /// Audio sample rate in Hz for the default preset; `None` keeps the
+    /// source's.
+    pub audio_sample_rate: Option<u32>,
+    /// Normalize audio loudness in the default preset.
+    pub normalize_loudness: bool,
+    /// Cut long silences from audio files in the default preset.
+    pub trim_silence: bool,
+    /// Skip inputs whose contents the history shows were already compressed.
+    pub skip_processed_inputs: bool,
+    /// Skip outputs of earlier runs, recognized by name or by the history,
+    /// so re-running on a folder doesn't compress them again.
+    pub skip_compressed_outputs: bool,
+    /// Skip the video half of Live Photos, keeping only the still.
+    pub drop_live_photo_motion: bool,
+    /// What to do with videos that are already HEVC.
+    pub hevc_policy: HevcPolicy,
+    /// How outputs are arranged inside the output directory.
+    pub output_layout: OutputLayout,
+    /// Skip inputs smaller than this many KB, which take time to compress
+    /// and often come out larger anyway.
+    pub min_input_size_kb: Option<u64>,
+    /// Total size a batch's outputs may add up to, in MB, e.g. to fit them
+    /// on a USB stick or SD card.
+    pub output_budget_mb: Option<u64>,
+    pub budget_action: BudgetAction,
+}
+
+/// What to do once the queue drains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PostBatchAction {
+    #[default]
+    None,
+    OpenFolder,
+    PlaySound,
+    Sleep,
+    Shutdown,
+}
+
+/// Most phone videos are already HEVC, and re-encoding them takes long for
+/// next to no savings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HevcPolicy {
+    /// Encode them like any other video.
+    #[default]
+    Reencode,
+    /// Leave them out of the batch.
+    Skip,
+    /// Copy the streams into a fresh file with faststart, without encoding.
+    Remux,
+}
+
+/// Decoding the source on the GPU, whatever encodes it. Frames come back
+/// to system memory, so filters and software encoders work as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HardwareDecoding {
+    #[default]
+    Off,
+    /// Whatever ffmpeg finds first.
+    Auto,
+    /// macOS.
+    VideoToolbox,
+    /// NVIDIA GPUs, through CUDA.
+    Nvdec,
+    /// Intel and AMD GPUs on Linux.
+    Vaapi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputLayout {
+    /// Everything directly in the output directory.
+    #[default]
+    Flat,
+    /// `YYYY/MM/` subfolders by capture date, for photo archives. Files
+    /// without one go to `undated/`.
+    ByDate,
+}
+
+/// What happens once a batch's outputs pass the output budget. Checked
+/// before each file starts, so the file that crosses it is still written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BudgetAction {
+    /// Skip the rest of the batch.
+    #[default]
+    Stop,
+    /// Keep going, but say so.
+    Warn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChecksumMode {
+    #[default]
+    None,
+    /// `<output>.sha256` next to each output.
+    Sidecar,
+    /// One `SHA256SUMS` file per output directory.
+    Manifest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WebhookSettings {
+    /// Endpoint that receives a JSON POST; `None` disables webhooks.
+    pub url: Option<String>,
+    /// Post after every job.
+    pub on_job: bool,
+    /// Post once the queue drains, with totals for the whole batch.
+    pub on_batch: bool,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            on_job: false,
+            on_batch: true,
+        }
+    }
+}
+
+/// Packs every output of a batch into one zip once it finishes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BatchZipSettings {
+    pub enabled: bool,
+    /// Encrypts the zip with AES-256; `None` or empty leaves it open.
+    pub password: Option<String>,
+}
+
+/// Encrypts every output with age before it is handed back.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EncryptionSettings {
+    pub enabled: bool,
+    /// age public keys (`age1…`) to encrypt to. Preferred over a passphrase,
+    /// since nothing secret has to be stored.
+    pub recipients: Vec<String>,
+    /// Used when there are no recipients.
+    pub passphrase: Option<String>,
+}
+
+/// Uploads every output to an S3-compatible bucket (S3, B2, R2, MinIO).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UploadSettings {
+    pub enabled: bool,
+    /// Service URL without the bucket, e.g. `https://s3.us-west-004.backblazeb2.com`
+    /// or `https://<account id>.r2.cloudflarestorage.com`.
+    pub endpoint: String,
+    /// Signing region; empty means `us-east-1`. R2 takes `auto`.
+    pub region: String,
+    pub bucket: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Folder in the bucket to upload into, e.g. `phone/2024`.
+    pub prefix: String,
+    /// Base of the URLs recorded in the history when the bucket is served
+    /// from elsewhere, e.g. a CDN or custom domain.
+    pub public_url: Option<String>,
+}
+
+/// Copies every output to a NAS or web server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DestinationSettings {
+    pub enabled: bool,
+    pub protocol: DestinationProtocol,
+    pub host: String,
+    /// Defaults to 22 for SFTP, 21 for FTP.
+    pub port: Option<u16>,
+    pub username: String,
+    /// FTP only. SFTP logs in with ssh keys or the agent, since OpenSSH's
+    /// sftp won't take a password non-interactively.
+    pub password: Option<String>,
+    /// Private key for SFTP, when the ssh defaults don't cover it.
+    pub key_path: Option<String>,
+    /// Directory on the server, created if missing.
+    pub remote_dir: String,
+    /// Extra attempts per file after a network failure.
+    pub retries: u32,
+}
+
+impl Default for DestinationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            protocol: DestinationProtocol::Sftp,
+            host: String::new(),
+            port: None,
+            username: String::new(),
+            password: None,
+            key_path: None,
+            remote_dir: String::new(),
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DestinationProtocol {
+    #[default]
+    Sftp,
+    Ftp,
+}
+
+impl DestinationProtocol {
+    pub fn scheme(self) -> &'static str {
+        match self {
+            DestinationProtocol::Sftp => "sftp",
+            DestinationProtocol::Ftp => "ftp",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            DestinationProtocol::Sftp => 22,
+            DestinationProtocol::Ftp => 21,
+        }
+    }
+}
+
+/// Running the queue only while the computer is otherwise unused, e.g. to
+/// work through a large library overnight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IdleSettings {
+    pub enabled: bool,
+    /// Minutes without keyboard or mouse input before jobs start.
+    pub idle_minutes: u32,
+    /// Jobs wait while other apps keep the CPU busier than this, in percent
+    /// of all cores.
+    pub max_cpu_percent: u8,
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_minutes: 5,
+            max_cpu_percent: 25,
+        }
+    }
+}
+
+/// Which GPUs hardware encoders (`*_nvenc`, `*_qsv`) run on, and how many
+/// encodes each takes at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GpuSettings {
+    /// `GpuDevice::id`s from `gpu::list_devices`. Encodes are spread over
+    /// those that fit the encoder; with none, the driver picks.
+    pub devices: Vec<String>,
+    /// Hardware encodes at once per GPU. GeForce cards only allow a few
+    /// NVENC sessions in total, across all apps.
+    pub sessions_per_device: usize,
+    pub decoding: HardwareDecoding,
+}
+
+impl Default for GpuSettings {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            sessions_per_device: 2,
+            decoding: HardwareDecoding::Off,
+        }
+    }
+}
+
+/// Local REST API for other programs on the machine. Off by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HttpApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// Bearer token clients must send; generated when the API is first enabled.
+    pub token: String,
+}
+
+impl Default for HttpApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 8765,
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            output_dir: None,
+            image_quality: 85,
+            video_codec: "libx264".to_string(),
+            video_crf: 23,
+            concurrency: None,
+            filename_template: "{name}".to_string(),
+            presets: Vec::new(),
+            pipeline_script: None,
+            http_api: HttpApiSettings::default(),
+            webhook: WebhookSettings::default(),
+            batch_zip: BatchZipSettings::default(),
+            batch_report: ReportFormat::None,
+            encryption: EncryptionSettings::default(),
+            upload: UploadSettings::default(),
+            destination: DestinationSettings::default(),
+            idle: IdleSettings::default(),
+            gpu: GpuSettings::default(),
+            notifications: true,
+            post_batch_action: PostBatchAction::None,
+            post_batch_countdown_secs: 60,
+            checksum_mode: ChecksumMode::None,
+            debug_logging: false,
+            resume_interrupted_jobs: true,
+            audio_channels: None,
+            audio_sample_rate: None,
+            normalize_loudness: false,
+            trim_silence: false,
+            skip_processed_inputs: false,
+            skip_compressed_outputs: true,
+            drop_live_photo_motion: false,
+            hevc_policy: HevcPolicy::Reencode,
+            output_layout: OutputLayout::Flat,
+            min_input_size_kb: None,
+            output_budget_mb: None,
+            budget_action: BudgetAction::Stop,
+        }
+    }
+}
+
+impl Settings {
+    pub fn output_file_name(&self, stem: &str, media_type: &str) -> String {
+        let name = self
+            .filename_template
+            .replace("{name}", stem)
+            .replace("{type}", media_type);
+
+        if name.trim().is_empty() {
+            stem.to_string()
+        } else {
+            name
+        }
+    }
+
+    pub fn output_budget_bytes(&self) -> Option<u64> {
+        self.output_budget_mb.map(|mb| mb * 1_000_000)
+    }
+
+    /// Fails when a batch whose outputs so far take `used` bytes has to stop
+    /// before compressing `input_path`.
+    pub fn check_output_budget(&self, used: u64, input_path: &str) -> Result<()> {
+        match self.output_budget_bytes() {
+            Some(budget) if used >= budget && self.budget_action == BudgetAction::Stop => {
+                Err(Error::OutputBudgetExceeded {
+                    path: input_path.to_string(),
+                    budget,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether `stem` looks like an output name: it ends in `_compressed`, or
+    /// has the text the filename template puts around `{name}`. Templates
+    /// with other placeholders there can't be matched.
+    pub fn is_output_name(&self, stem: &str) -> bool {
+        if stem.to_lowercase().ends_with(OUTPUT_SUFFIX) {
+            return true;
+        }
+        let Some((prefix, suffix)) = self.filename_template.split_once("{name}") else {
+            return false;
+        };
+        (!prefix.is_empty() || !suffix.is_empty())
+            && !prefix.contains('{')
+            && !suffix.contains('{')
+            && stem.starts_with(prefix)
+            && stem.ends_with(suffix)
+            && stem.len() > prefix.len() + suffix.len()
+    }
+
+    /// Name for the `width` pixel wide variant of a responsive image set.
+    /// Templates without `{width}` get `-<width>w` appended.
+    pub fn responsive_file_name(&self, stem: &str, width: u32) -> String {
+        let name = self.output_file_name(stem, "image");
+        if self.filename_template.contains("{width}") {
+            name.replace("{width}", &width.to_string())
+        } else {
+            format!("{}-{}w", name, width)
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.image_quality == 0 || self.image_quality > 100 {
+            return Err(Error::invalid_settings(
+                "Image quality must be between 1 and 100",
+            ));
+        }
+        // AV1 and VP9 encoders use a 0-63 scale
+        let max_crf = match self.video_codec.as_str() {
+            "libsvtav1" | "libvpx-vp9" => 63,
+            _ => 51,
+        };
+        if self.video_crf > max_crf {
+            return Err(Error::invalid_settings(format!(
+                "Video CRF must be between 0 and {}",
+                max_crf
+            )));
+        }
+        if self.concurrency == Some(0) {
+            return Err(Error::invalid_settings("Concurrency must be at least 1"));
+        }
+        if self.video_codec.trim().is_empty() {
+            return Err(Error::invalid_settings("Video codec must not be empty"));
+        }
+        if matches!(self.audio_channels, Some(channels) if channels == 0 || channels > 8) {
+            return Err(Error::invalid_settings(
+                "Audio channels must be between 1 and 8",
+            ));
+        }
+        if matches!(self.audio_sample_rate, Some(rate) if !(8_000..=192_000).contains(&rate)) {
+            return Err(Error::invalid_settings(
+                "Audio sample rate must be between 8000 and 192000 Hz",
+            ));
+        }
+        if self.idle.enabled && self.idle.idle_minutes == 0 {
+            return Err(Error::invalid_settings(
+                "Idle time must be at least 1 minute",
+            ));
+        }
+        if self.idle.max_cpu_percent == 0 || self.idle.max_cpu_percent > 100 {
+            return Err(Error::invalid_settings(
+                "Maximum CPU usage must be between 1 and 100%",
+            ));
+        }
+        if self.gpu.sessions_per_device == 0 {
+            return Err(Error::invalid_settings(
+                "Hardware encodes per GPU must be at least 1",
+            ));
+        }
+        if self.http_api.enabled && self.http_api.port == 0 {
+            return Err(Error::invalid_settings("HTTP API port must not be 0"));
+        }
+        if let Some(url) = &self.webhook.url {
+            if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(Error::invalid_settings(
+                    "Webhook URL must start with http:// or https://",
+                ));
+            }
+        }
+        if self.output_budget_mb == Some(0) {
+            return Err(Error::invalid_settings(
+                "Output budget must be at least 1 MB",
+            ));
+        }
+        if self.filename_template.contains(['/', '\\']) {
+            return Err(Error::invalid_settings(
+                "Filename template must not contain path separators",
+            ));
+        }
+        if let Some(source) = self
+            .pipeline_script
+            .as_deref()
+            .filter(|source| !source.trim().is_empty())
+        {
+            script::check(source)?;
+        }
+        // Imported presets arrive here without going through the preset editor
+        for preset in &self.presets {
+            custom_args::validate(&preset.extra_ffmpeg_args)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SettingsManager {
+    settings_path: PathBuf,
+}
+
+impl Default for SettingsManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SettingsManager {
+    pub fn new() -> Self {
+        Self {
+            settings_path: paths::app_data_dir().join(SETTINGS_FILE),
+        }
+    }
+
+    /// Loads the stored settings, falling back to defaults when the file is
+    /// missing or unreadable so a corrupt file never blocks compression.
+    pub fn load(&self) -> Settings {
+        fs::read_to_string(&self.settings_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, settings: &Settings) -> Result<()> {
+        settings.validate()?;
+
+        if let Some(parent) = self.settings_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| Error::io("Failed to create settings directory", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(settings).map_err(|e| Error::Io {
+            message: format!("Failed to serialize settings: {}", e),
+        })?;
+
+        // Write to a temp file first so a crash mid-write can't truncate the settings
+        let temp_path = self.settings_path.with_extension("json.tmp");
+        fs::write(&temp_path, contents).map_err(|e| Error::io("Failed to write settings", e))?;
+        fs::rename(&temp_path, &self.settings_path)
+            .map_err(|e| Error::io("Failed to write settings", e))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! JSON-lines protocol for driving the engine from other tools.
+//!
+//! Each line on stdin is one request, e.g.
+//! `{"id": 1, "command": "compress", "input": "/path/clip.mp4", "preset": "Web"}`.
+//! Every request gets one or more events on stdout, one JSON object per line,
+//! echoing the request `id` so callers can pipeline requests.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::compressor::{self, CompressionResult};
+use crate::error::{Error, Result};
+use crate::job::{JobContext, JobProgress};
+use crate::presets::{self, Preset};
+use crate::settings::{Settings, SettingsManager};
+
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    request: Request,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+enum Request {
+    Compress {
+        input: String,
+        output: Option<String>,
+        preset: Option<String>,
+    },
+    ListPresets,
+    GetSettings,
+    Shutdown,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+enum Event {
+    Ready {
+        version: &'static str,
+    },
+    Started {
+        id: Value,
+        input: String,
+    },
+    Progress {
+        id: Value,
+        progress: JobProgress,
+    },
+    Completed {
+        id: Value,
+        result: Box<CompressionResult>,
+    },
+    Failed {
+        id: Value,
+        error: Error,
+    },
+    Presets {
+        id: Value,
+        presets: Vec<Preset>,
+    },
+    Settings {
+        id: Value,
+        settings: Box<Settings>,
+    },
+}
+
+/// Serves requests from stdin until EOF or a `shutdown` request.
+pub async fn run() -> Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    emit(&Event::Ready {
+        version: env!("CARGO_PKG_VERSION"),
+    })?;
+
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| Error::io("Failed to read stdin", e))?
+    {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let envelope: Envelope = match serde_json::from_str(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                emit(&Event::Failed {
+                    id: Value::Null,
+                    error: Error::InvalidRequest {
+                        message: format!("Invalid request: {}", e),
+                    },
+                })?;
+                continue;
+            }
+        };
+
+        let id = envelope.id;
+        // Settings are re-read per request so edits made in the app apply
+        // without restarting the session
+        let settings = SettingsManager::new().load();
+
+        match envelope.request {
+            Request::Compress {
+                input,
+                output,
+                preset,
+            } => {
+                let preset = match presets::resolve_preset(preset.as_deref(), &settings) {
+                    Ok(preset) => preset,
+                    Err(error) => {
+                        emit(&Event::Failed { id, error })?;
+                        continue;
+                    }
+                };
+
+                emit(&Event::Started {
+                    id: id.clone(),
+                    input: input.clone(),
+                })?;
+
+                let progress_id = id.clone();
+                let ctx = JobContext::default().with_progress_listener(move |progress| {
+                    emit(&Event::Progress {
+                        id: progress_id.clone(),
+                        progress: progress.clone(),
+                    })
+                    .ok();
+                });
+                match compressor::compress_file(&input, output, &settings, &preset, &ctx).await {
+                    Ok(result) => emit(&Event::Completed {
+                        id,
+                        result: Box::new(result),
+                    })?,
+                    Err(error) => emit(&Event::Failed { id, error })?,
+                }
+            }
+            Request::ListPresets => emit(&Event::Presets {
+                id,
+                presets: presets::all_presets(&settings),
+            })?,
+            Request::GetSettings => emit(&Event::Settings {
+                id,
+                settings: Box::new(settings),
+            })?,
+            Request::Shutdown => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn emit(event: &Event) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, event).map_err(|e| Error::Io {
+        message: format!("Failed to write event: {}", e),
+    })?;
+    writeln!(stdout)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| Error::io("Failed to write event", e))
+}
@@ -0,0 +1,237 @@
+//! Streaming from a plain web server: packaging a finished encode as
+//! HLS/DASH (remuxed, not re-encoded, so the package matches the file
+//! output exactly) and checking whether a file can be streamed as-is.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::compressor;
+use crate::error::{Error, Result};
+use crate::job::JobContext;
+use crate::presets::StreamingFormat;
+use crate::probe;
+
+/// Target segment length in seconds. Segments can only start on keyframes,
+/// so real lengths follow the encode's keyframe interval; set
+/// `Preset::keyframe_interval_secs` to a divisor of this for even segments.
+const SEGMENT_SECONDS: u32 = 6;
+const SEGMENT_PREFIX: &str = "segment_";
+
+/// Codecs every major browser plays in each container, as ffmpeg names them.
+const MP4_VIDEO_CODECS: &[&str] = &["h264", "av1", "vp9"];
+const MP4_AUDIO_CODECS: &[&str] = &["aac", "mp3", "opus", "flac"];
+const WEBM_VIDEO_CODECS: &[&str] = &["vp8", "vp9", "av1"];
+const WEBM_AUDIO_CODECS: &[&str] = &["opus", "vorbis"];
+const WEB_PIXEL_FORMATS: &[&str] = &["yuv420p", "yuvj420p"];
+
+/// Writes the package for `video` into a `<name>-<format>` directory next
+/// to it and returns the path of its manifest. `codec` is the probed video
+/// codec; for HLS anything but H.264 gets fragmented MP4 segments, which is
+/// what players expect for HEVC and AV1.
+pub fn package(
+    ffmpeg_path: &Path,
+    video: &Path,
+    format: StreamingFormat,
+    codec: Option<&str>,
+    ctx: &JobContext,
+) -> Result<PathBuf> {
+    let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    let parent = video.parent().unwrap_or(Path::new("."));
+    let dir = parent.join(format!("{}-{}", stem, format.as_str()));
+
+    fs::create_dir_all(&dir).map_err(|e| Error::io("Failed to create stream directory", e))?;
+    remove_old_segments(&dir)?;
+
+    let fragmented = codec != Some("h264");
+    let mut args: Vec<String> = vec![
+        "-i".into(),
+        video.to_string_lossy().to_string(),
+        // Timecode and subtitle tracks from cameras can't be segmented
+        "-map".into(),
+        "0:v:0".into(),
+        "-map".into(),
+        "0:a?".into(),
+        "-c".into(),
+        "copy".into(),
+    ];
+
+    let manifest = match format {
+        StreamingFormat::Hls => {
+            let extension = if fragmented { "m4s" } else { "ts" };
+            args.extend([
+                "-f".into(),
+                "hls".into(),
+                "-hls_time".into(),
+                SEGMENT_SECONDS.to_string(),
+                "-hls_playlist_type".into(),
+                "vod".into(),
+                "-hls_segment_type".into(),
+                if fragmented { "fmp4" } else { "mpegts" }.into(),
+                "-hls_segment_filename".into(),
+                dir.join(format!("{}%03d.{}", SEGMENT_PREFIX, extension))
+                    .to_string_lossy()
+                    .to_string(),
+            ]);
+            dir.join("index.m3u8")
+        }
+        // DASH segments are always fragmented MP4; names are relative to
+        // the manifest
+        StreamingFormat::Dash => {
+            args.extend([
+                "-f".into(),
+                "dash".into(),
+                "-seg_duration".into(),
+                SEGMENT_SECONDS.to_string(),
+                "-use_template".into(),
+                "1".into(),
+                "-use_timeline".into(),
+                "1".into(),
+                "-init_seg_name".into(),
+                "init_$RepresentationID$.m4s".into(),
+                "-media_seg_name".into(),
+                format!("{}$RepresentationID$_$Number%05d$.m4s", SEGMENT_PREFIX),
+            ]);
+            dir.join("manifest.mpd")
+        }
+    };
+
+    args.extend(["-y".into(), manifest.to_string_lossy().to_string()]);
+    compressor::run_encode(ffmpeg_path, &args, &video.to_string_lossy(), &manifest, ctx)?;
+
+    Ok(manifest)
+}
+
+/// Deletes segments of an earlier, longer encode, which would otherwise get
+/// published alongside the new ones.
+fn remove_old_segments(dir: &Path) -> Result<()> {
+    let entries = fs::read_dir(dir).map_err(|e| Error::io("Failed to read stream directory", e))?;
+    for entry in entries.flatten() {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(SEGMENT_PREFIX)
+        {
+            fs::remove_file(entry.path())
+                .map_err(|e| Error::io("Failed to remove old segment", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Something that keeps a file from playing in a browser straight off a web
+/// server, in the order the checks run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamIssue {
+    /// Browsers play MP4 and WebM; other containers need a remux.
+    UnsupportedContainer,
+    /// The index (`moov`) sits after the media data, so playback waits for
+    /// the whole download. Fixed by re-saving with faststart, which every
+    /// MP4 this app writes has.
+    MoovAtEnd,
+    /// The video codec isn't playable in all major browsers in this
+    /// container (e.g. HEVC, or H.264 in WebM).
+    UnsupportedVideoCodec,
+    /// 10-bit or 4:2:2/4:4:4 video, which most browsers won't decode.
+    UnsupportedPixelFormat,
+    UnsupportedAudioCodec,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamabilityReport {
+    /// True when there are no issues.
+    pub streamable: bool,
+    /// Whether `moov` precedes the media data; `None` for non-MP4 files.
+    pub faststart: Option<bool>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub pixel_format: Option<String>,
+    pub issues: Vec<StreamIssue>,
+}
+
+/// Checks whether `path` can be progressively played in a browser as-is.
+pub fn verify_streamable(ffmpeg_path: &Path, path: &Path) -> Result<StreamabilityReport> {
+    let info = probe::probe_media(ffmpeg_path, path)?;
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    let mut issues = Vec::new();
+    let (video_codecs, audio_codecs, faststart) = match extension.as_str() {
+        "mp4" | "m4v" => (
+            MP4_VIDEO_CODECS,
+            MP4_AUDIO_CODECS,
+            moov_before_mdat(path).map_err(|e| Error::io("Failed to read video", e))?,
+        ),
+        "webm" => (WEBM_VIDEO_CODECS, WEBM_AUDIO_CODECS, None),
+        _ => {
+            issues.push(StreamIssue::UnsupportedContainer);
+            (&[][..], &[][..], None)
+        }
+    };
+
+    if faststart == Some(false) {
+        issues.push(StreamIssue::MoovAtEnd);
+    }
+    if !issues.contains(&StreamIssue::UnsupportedContainer) {
+        if matches!(&info.video_codec, Some(codec) if !video_codecs.contains(&codec.as_str())) {
+            issues.push(StreamIssue::UnsupportedVideoCodec);
+        }
+        if matches!(&info.pixel_format, Some(format) if !WEB_PIXEL_FORMATS.contains(&format.as_str()))
+        {
+            issues.push(StreamIssue::UnsupportedPixelFormat);
+        }
+        if matches!(&info.audio_codec, Some(codec) if !audio_codecs.contains(&codec.as_str())) {
+            issues.push(StreamIssue::UnsupportedAudioCodec);
+        }
+    }
+
+    Ok(StreamabilityReport {
+        streamable: issues.is_empty(),
+        faststart,
+        video_codec: info.video_codec,
+        audio_codec: info.audio_codec,
+        pixel_format: info.pixel_format,
+        issues,
+    })
+}
+
+/// Walks the top-level MP4 boxes and reports whether `moov` comes before
+/// `mdat`. `None` if neither is found, e.g. for a truncated file.
+fn moov_before_mdat(path: &Path) -> io::Result<Option<bool>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 8];
+
+    loop {
+        if let Err(e) = file.read_exact(&mut header) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(e),
+            };
+        }
+        match &header[4..8] {
+            b"moov" => return Ok(Some(true)),
+            b"mdat" => return Ok(Some(false)),
+            _ => {}
+        }
+
+        let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let skip = match size {
+            // Box runs to the end of the file
+            0 => return Ok(None),
+            // 64-bit size follows the type
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                u64::from_be_bytes(large).saturating_sub(16)
+            }
+            size => size.saturating_sub(8),
+        };
+        file.seek(SeekFrom::Current(skip as i64))?;
+    }
+}
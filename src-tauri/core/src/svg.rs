@@ -0,0 +1,277 @@
+//! SVG minification: editor metadata, comments and formatting whitespace are
+//! dropped and coordinates are rounded, so vector assets shrink without
+//! being rasterized. Works on the token stream, so anything not understood
+//! is passed through untouched.
+
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+use crate::checksum;
+use crate::compressor::{prepare_output_dir, CompressionResult};
+use crate::error::{Error, Result};
+use crate::job::JobContext;
+use crate::settings::Settings;
+
+/// Decimal places kept in coordinates, enough for any on-screen size of a
+/// normally scaled drawing.
+const PRECISION: usize = 3;
+
+/// Namespace prefixes of editor-only data (Inkscape, Sketch, Affinity) and
+/// of the RDF metadata block, none of which affect rendering.
+const EDITOR_PREFIXES: &[&str] = &["inkscape", "sodipodi", "sketch", "serif", "rdf", "cc", "dc"];
+
+/// Attributes holding only numbers, separators and transform names.
+const NUMERIC_ATTRIBUTES: &[&str] = &[
+    "d",
+    "points",
+    "transform",
+    "gradientTransform",
+    "patternTransform",
+    "viewBox",
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "r",
+    "rx",
+    "ry",
+    "fx",
+    "fy",
+    "width",
+    "height",
+    "stroke-width",
+    "opacity",
+    "fill-opacity",
+    "stroke-opacity",
+];
+
+/// Elements whose whitespace is content.
+const TEXT_ELEMENTS: &[&str] = &["text", "tspan", "textPath", "style", "script"];
+
+/// Minifies `input_path` into the output directory. Like other formats, the
+/// original is kept when the result isn't smaller.
+pub fn compress_svg(
+    input_path: &str,
+    output_path: Option<String>,
+    settings: &Settings,
+    ctx: &JobContext,
+) -> Result<CompressionResult> {
+    if ctx.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    let started = Instant::now();
+    let input = Path::new(input_path);
+    let source = fs::read_to_string(input).map_err(|e| Error::io("Failed to read SVG", e))?;
+    let original_size = source.len() as u64;
+
+    let minified = minify(&source).map_err(|e| Error::Image {
+        message: format!("Invalid SVG {}: {}", input_path, e),
+    })?;
+
+    let output_dir = prepare_output_dir(input, output_path, settings)?;
+    let file_name = input.file_stem().unwrap().to_string_lossy();
+    let output_file = output_dir.join(format!(
+        "{}.svg",
+        settings.output_file_name(&file_name, "image")
+    ));
+
+    let kept_original = minified.len() as u64 >= original_size;
+    if kept_original {
+        fs::copy(input, &output_file)?;
+    } else {
+        fs::write(&output_file, &minified).map_err(|e| Error::io("Failed to write SVG", e))?;
+    }
+
+    let compressed_size = fs::metadata(&output_file)?.len();
+    let mut result = CompressionResult::new(original_size, compressed_size, &output_file, started);
+    result.kept_original = kept_original;
+    result.codec = Some("svg".to_string());
+    result.sha256 = checksum::write(&output_file, settings.checksum_mode)?;
+    Ok(result)
+}
+
+fn minify(source: &str) -> std::result::Result<String, xmlparser::Error> {
+    let mut output = String::with_capacity(source.len());
+    // Open elements, to know when whitespace matters
+    let mut open: Vec<&str> = Vec::new();
+    // Depth inside an element being dropped, 0 when not dropping
+    let mut skip_depth = 0usize;
+    // Start of the DOCTYPE, kept only if it declares entities the body uses
+    let mut doctype: Option<(usize, bool)> = None;
+
+    for token in Tokenizer::from(source) {
+        match token? {
+            Token::ElementStart { prefix, local, .. } => {
+                if skip_depth > 0 || is_dropped_element(prefix.as_str(), local.as_str()) {
+                    skip_depth += 1;
+                    continue;
+                }
+                output.push('<');
+                push_name(&mut output, prefix.as_str(), local.as_str());
+                open.push(local.as_str());
+            }
+            Token::Attribute {
+                prefix,
+                local,
+                value,
+                ..
+            } => {
+                if skip_depth > 0 || is_dropped_attribute(prefix.as_str(), local.as_str()) {
+                    continue;
+                }
+                let value = value.as_str();
+                let value = if prefix.is_empty() && rounds(local.as_str(), value) {
+                    round_numbers(value)
+                } else {
+                    value.to_string()
+                };
+                let quote = if value.contains('"') { '\'' } else { '"' };
+                output.push(' ');
+                push_name(&mut output, prefix.as_str(), local.as_str());
+                output.push('=');
+                output.push(quote);
+                output.push_str(&value);
+                output.push(quote);
+            }
+            Token::ElementEnd { end, .. } => match end {
+                ElementEnd::Open if skip_depth == 0 => output.push('>'),
+                ElementEnd::Open => {}
+                ElementEnd::Empty | ElementEnd::Close(..) if skip_depth > 0 => skip_depth -= 1,
+                ElementEnd::Empty => {
+                    output.push_str("/>");
+                    open.pop();
+                }
+                ElementEnd::Close(prefix, local) => {
+                    output.push_str("</");
+                    push_name(&mut output, prefix.as_str(), local.as_str());
+                    output.push('>');
+                    open.pop();
+                }
+            },
+            Token::Text { text } if skip_depth == 0 => {
+                let text = text.as_str();
+                let in_text = open.iter().any(|name| TEXT_ELEMENTS.contains(name));
+                if in_text || !text.trim().is_empty() {
+                    output.push_str(text);
+                }
+            }
+            Token::Cdata { span, .. } if skip_depth == 0 => output.push_str(span.as_str()),
+            // Stylesheet links and the like. The XML declaration, optional
+            // for UTF-8, is a separate token and is dropped.
+            Token::ProcessingInstruction { span, .. } if skip_depth == 0 => {
+                output.push_str(span.as_str())
+            }
+            Token::DtdStart { span, .. } => doctype = Some((span.start(), false)),
+            Token::EntityDeclaration { .. } => {
+                if let Some((_, declares_entities)) = &mut doctype {
+                    *declares_entities = true;
+                }
+            }
+            Token::DtdEnd { span } => {
+                if let Some((start, true)) = doctype.take() {
+                    output.push_str(&source[start..span.end()]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(output)
+}
+
+fn push_name(output: &mut String, prefix: &str, local: &str) {
+    if !prefix.is_empty() {
+        output.push_str(prefix);
+        output.push(':');
+    }
+    output.push_str(local);
+}
+
+fn is_dropped_element(prefix: &str, local: &str) -> bool {
+    local == "metadata" || EDITOR_PREFIXES.contains(&prefix)
+}
+
+fn is_dropped_attribute(prefix: &str, local: &str) -> bool {
+    EDITOR_PREFIXES.contains(&prefix) || (prefix == "xmlns" && EDITOR_PREFIXES.contains(&local))
+}
+
+/// Whether `value` of attribute `name` can have its numbers rounded. Not
+/// paths with arcs, whose flags may be written packed against the next
+/// number (`a1 1 0 01.5 1`), which rounding would corrupt.
+fn rounds(name: &str, value: &str) -> bool {
+    NUMERIC_ATTRIBUTES.contains(&name) && !(name == "d" && value.contains(['a', 'A']))
+}
+
+/// Rounds every decimal number in `value` to `PRECISION` places, leaving
+/// integers and everything between numbers as written.
+fn round_numbers(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut output = String::with_capacity(value.len());
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let starts_number = bytes[i].is_ascii_digit()
+            || (bytes[i] == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit));
+        if !starts_number {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut decimal = false;
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || (bytes[i] == b'.' && !decimal)) {
+            decimal |= bytes[i] == b'.';
+            i += 1;
+        }
+        if matches!(bytes.get(i), Some(b'e' | b'E')) {
+            let mut exponent = i + 1;
+            if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+                exponent += 1;
+            }
+            if bytes.get(exponent).is_some_and(u8::is_ascii_digit) {
+                while bytes.get(exponent).is_some_and(u8::is_ascii_digit) {
+                    exponent += 1;
+                }
+                decimal = true;
+                i = exponent;
+            }
+        }
+        if !decimal {
+            continue;
+        }
+
+        let Ok(number) = value[start..i].parse::<f64>() else {
+            continue;
+        };
+        let formatted = format_number(number);
+        output.push_str(&value[copied..start]);
+        output.push_str(&formatted);
+        // `1.0.5` is two numbers; `1.5` would be one
+        if !formatted.contains('.') && bytes.get(i) == Some(&b'.') {
+            output.push(' ');
+        }
+        copied = i;
+    }
+
+    output.push_str(&value[copied..]);
+    output
+}
+
+/// `0.50000` becomes `.5` and `2.0` becomes `2`.
+fn format_number(number: f64) -> String {
+    let fixed = format!("{:.*}", PRECISION, number);
+    let trimmed = fixed.trim_end_matches('0').trim_end_matches('.');
+    match trimmed.strip_prefix("0.") {
+        Some(fraction) => format!(".{}", fraction),
+        None => trimmed.to_string(),
+    }
+}
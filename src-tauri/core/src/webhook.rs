@@ -0,0 +1,73 @@
+//! Posts a JSON payload to a user-configured URL when jobs or batches finish,
+//! for wiring the app into home-automation or team chat flows.
+
+use serde::Serialize;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::queue::{BatchSummary, Job, JobQueue, QueueEvent};
+use crate::settings::SettingsManager;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+#[serde(tag = "event")]
+enum Payload {
+    #[serde(rename = "job.finished")]
+    JobFinished { job: Box<Job> },
+    #[serde(rename = "batch.finished")]
+    BatchFinished { summary: BatchSummary },
+}
+
+/// Hooks webhook delivery into the queue. The webhook settings are read per
+/// event, so changing them doesn't require a restart.
+pub fn subscribe(queue: &JobQueue) {
+    queue.subscribe(|event| {
+        if matches!(
+            event,
+            QueueEvent::Progress(_) | QueueEvent::BatchProgress(_)
+        ) {
+            return;
+        }
+        let webhook = SettingsManager::new().load().webhook;
+        let Some(url) = webhook.url.filter(|url| !url.trim().is_empty()) else {
+            return;
+        };
+
+        let payload = match event {
+            QueueEvent::JobFinished(job) if webhook.on_job => {
+                Payload::JobFinished { job: job.clone() }
+            }
+            QueueEvent::Drained(summary) if webhook.on_batch => Payload::BatchFinished {
+                summary: summary.clone(),
+            },
+            _ => return,
+        };
+
+        // Delivery is fire-and-forget on its own thread: a slow endpoint must
+        // not hold up the queue, and events can come from non-async threads
+        thread::spawn(move || {
+            if let Err(e) = send(&url, &payload) {
+                warn!("Webhook delivery to {} failed: {}", url, e);
+            }
+        });
+    });
+}
+
+fn send(url: &str, payload: &Payload) -> Result<()> {
+    let response = reqwest::blocking::Client::new()
+        .post(url)
+        .timeout(REQUEST_TIMEOUT)
+        .json(payload)
+        .send()?;
+
+    if !response.status().is_success() {
+        return Err(Error::Network {
+            message: format!("Server responded with {}", response.status()),
+        });
+    }
+
+    Ok(())
+}
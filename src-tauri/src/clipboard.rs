@@ -0,0 +1,110 @@
+use arboard::{Clipboard, ImageData};
+use image::{DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use media_compressor_core::compressor;
+use media_compressor_core::error::{Error, Result};
+use media_compressor_core::presets;
+use media_compressor_core::settings::SettingsManager;
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ClipboardOptions {
+    pub preset: Option<String>,
+    /// Put the compressed image back on the clipboard.
+    pub copy_to_clipboard: bool,
+    /// Directory to also save the compressed image to.
+    pub output_path: Option<String>,
+}
+
+impl Default for ClipboardOptions {
+    fn default() -> Self {
+        Self {
+            preset: None,
+            copy_to_clipboard: true,
+            output_path: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipboardResult {
+    pub width: u32,
+    pub height: u32,
+    /// Size of the encoded image (JPEG, or PNG when it has transparency).
+    pub compressed_size: u64,
+    pub output_path: Option<String>,
+}
+
+/// Compresses the image currently on the clipboard, e.g. a screenshot about
+/// to be pasted into chat.
+pub fn compress_clipboard_image(options: ClipboardOptions) -> Result<ClipboardResult> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(options.preset.as_deref(), &settings)?;
+
+    let mut clipboard = Clipboard::new().map_err(Error::platform)?;
+    let image_data = clipboard.get_image().map_err(|_| Error::InvalidRequest {
+        message: "The clipboard does not contain an image".to_string(),
+    })?;
+
+    let rgba = RgbaImage::from_raw(
+        image_data.width as u32,
+        image_data.height as u32,
+        image_data.bytes.into_owned(),
+    )
+    .ok_or_else(|| Error::platform("The clipboard image has an unexpected size"))?;
+
+    // Screenshots usually carry an alpha channel that is fully opaque; only
+    // real transparency is worth keeping as PNG
+    let transparent = rgba.pixels().any(|pixel| pixel.0[3] < u8::MAX);
+    let resized = compressor::resize_for_preset(DynamicImage::ImageRgba8(rgba), &preset);
+
+    let (encoded, extension) = if transparent {
+        (compressor::encode_png(&resized)?, "png")
+    } else {
+        (
+            compressor::encode_jpeg_for_preset(&resized, &preset)?,
+            "jpg",
+        )
+    };
+
+    let output_path = match options.output_path {
+        Some(dir) => {
+            fs::create_dir_all(&dir)
+                .map_err(|e| Error::io("Failed to create output directory", e))?;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            let path = Path::new(&dir).join(format!("clipboard-{}.{}", timestamp, extension));
+            fs::write(&path, &encoded)?;
+            Some(path.to_string_lossy().to_string())
+        }
+        None => None,
+    };
+
+    if options.copy_to_clipboard {
+        // The clipboard holds raw pixels, so hand back the decoded result to
+        // carry over the downscale and compression artifacts
+        let compressed = image::load_from_memory(&encoded)?.to_rgba8();
+        clipboard
+            .set_image(ImageData {
+                width: compressed.width() as usize,
+                height: compressed.height() as usize,
+                bytes: Cow::Owned(compressed.into_raw()),
+            })
+            .map_err(Error::platform)?;
+    }
+
+    Ok(ClipboardResult {
+        width: resized.width(),
+        height: resized.height(),
+        compressed_size: encoded.len() as u64,
+        output_path,
+    })
+}
@@ -0,0 +1,299 @@
+//! "Compress with Media Compressor" entry in the OS file manager. Selected
+//! files are passed as launch arguments, which the single-instance plugin
+//! forwards to the running app's queue.
+
+use std::env;
+use std::path::PathBuf;
+
+use media_compressor_core::error::{Error, Result};
+
+const MENU_LABEL: &str = "Compress with Media Compressor";
+
+fn current_exe() -> Result<PathBuf> {
+    env::current_exe().map_err(|e| Error::io("Failed to locate the app executable", e))
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use std::process::Command;
+
+    const KEY_NAME: &str = "MediaCompressor";
+    /// `*` covers every file type, `Directory` lets whole folders be sent.
+    const CLASSES: [&str; 2] = ["*", "Directory"];
+
+    fn key(class: &str) -> String {
+        format!(r"HKCU\Software\Classes\{}\shell\{}", class, KEY_NAME)
+    }
+
+    fn reg(args: &[&str]) -> Result<()> {
+        let output = Command::new("reg")
+            .args(args)
+            .output()
+            .map_err(Error::platform)?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::platform(
+                String::from_utf8_lossy(&output.stderr).trim(),
+            ))
+        }
+    }
+
+    pub fn register() -> Result<()> {
+        let exe = current_exe()?.to_string_lossy().to_string();
+        let command = format!("\"{}\" \"%1\"", exe);
+
+        for class in CLASSES {
+            let key = key(class);
+            reg(&["add", &key, "/ve", "/d", MENU_LABEL, "/f"])?;
+            reg(&["add", &key, "/v", "Icon", "/d", &exe, "/f"])?;
+            reg(&[
+                "add",
+                &format!(r"{}\command", key),
+                "/ve",
+                "/d",
+                &command,
+                "/f",
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn unregister() -> Result<()> {
+        for class in CLASSES {
+            // Missing keys are fine: the entry is already gone
+            reg(&["delete", &key(class), "/f"]).ok();
+        }
+        Ok(())
+    }
+
+    pub fn is_registered() -> bool {
+        reg(&["query", &key("*")]).is_ok()
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use std::fs;
+
+    /// A Finder Quick Action (Services menu) that runs a shell script
+    /// handing the selected files to the app.
+    fn workflow_dir() -> Result<PathBuf> {
+        dirs::home_dir()
+            .map(|home| {
+                home.join("Library/Services")
+                    .join(format!("{}.workflow", MENU_LABEL))
+            })
+            .ok_or_else(|| Error::platform("Could not find the home directory"))
+    }
+
+    fn app_bundle() -> Result<PathBuf> {
+        // .../Media Compressor.app/Contents/MacOS/media-compressor
+        let exe = current_exe()?;
+        exe.ancestors()
+            .find(|path| path.extension().is_some_and(|ext| ext == "app"))
+            .map(|path| path.to_path_buf())
+            .ok_or_else(|| Error::platform("The app is not running from an .app bundle"))
+    }
+
+    pub fn register() -> Result<()> {
+        let bundle = app_bundle()?;
+        let contents = workflow_dir()?.join("Contents");
+        fs::create_dir_all(&contents)?;
+
+        let info_plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>NSServices</key>
+	<array>
+		<dict>
+			<key>NSMenuItem</key>
+			<dict><key>default</key><string>{label}</string></dict>
+			<key>NSMessage</key>
+			<string>runWorkflowAsService</string>
+			<key>NSRequiredContext</key>
+			<dict><key>NSApplicationIdentifier</key><string>com.apple.finder</string></dict>
+			<key>NSSendFileTypes</key>
+			<array><string>public.image</string><string>public.movie</string><string>public.folder</string></array>
+		</dict>
+	</array>
+</dict>
+</plist>
+"#,
+            label = MENU_LABEL
+        );
+
+        let script = format!(
+            "open -a \"{}\" --args \"$@\"",
+            bundle.to_string_lossy().replace('"', "\\\"")
+        );
+        let document = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AMApplicationBuild</key><string>521</string>
+	<key>AMApplicationVersion</key><string>2.10</string>
+	<key>AMDocumentVersion</key><string>2</string>
+	<key>actions</key>
+	<array>
+		<dict>
+			<key>action</key>
+			<dict>
+				<key>AMActionVersion</key><string>2.0.3</string>
+				<key>AMApplication</key><array><string>Automator</string></array>
+				<key>AMParameterProperties</key>
+				<dict><key>COMMAND_STRING</key><dict/><key>inputMethod</key><dict/><key>shell</key><dict/></dict>
+				<key>AMProvides</key>
+				<dict><key>Container</key><string>List</string><key>Types</key><array><string>com.apple.cocoa.string</string></array></dict>
+				<key>ActionBundlePath</key><string>/System/Library/Automator/Run Shell Script.action</string>
+				<key>ActionName</key><string>Run Shell Script</string>
+				<key>ActionParameters</key>
+				<dict>
+					<key>COMMAND_STRING</key><string>{script}</string>
+					<key>CheckedForUserDefaultShell</key><true/>
+					<key>inputMethod</key><integer>1</integer>
+					<key>shell</key><string>/bin/bash</string>
+					<key>source</key><string></string>
+				</dict>
+				<key>BundleIdentifier</key><string>com.apple.RunShellScript</string>
+				<key>CFBundleVersion</key><string>2.0.3</string>
+				<key>CanShowSelectedItemsWhenRun</key><false/>
+				<key>CanShowWhenRun</key><true/>
+				<key>Class Name</key><string>RunShellScriptAction</string>
+				<key>InputUUID</key><string>6E2D5F0C-8E4B-4C8A-9A51-0D0C3E1A7B01</string>
+				<key>OutputUUID</key><string>6E2D5F0C-8E4B-4C8A-9A51-0D0C3E1A7B02</string>
+				<key>UUID</key><string>6E2D5F0C-8E4B-4C8A-9A51-0D0C3E1A7B03</string>
+			</dict>
+		</dict>
+	</array>
+	<key>workflowMetaData</key>
+	<dict>
+		<key>serviceInputTypeIdentifier</key><string>com.apple.Automator.fileSystemObject</string>
+		<key>serviceOutputTypeIdentifier</key><string>com.apple.Automator.nothing</string>
+		<key>serviceProcessesInput</key><integer>0</integer>
+		<key>workflowTypeIdentifier</key><string>com.apple.Automator.servicesMenu</string>
+	</dict>
+</dict>
+</plist>
+"#,
+            script = xml_escape(&script)
+        );
+
+        fs::write(contents.join("Info.plist"), info_plist)?;
+        fs::write(contents.join("document.wflow"), document)?;
+
+        // Ask the system to pick up the new service without a logout
+        std::process::Command::new("/System/Library/CoreServices/pbs")
+            .arg("-update")
+            .output()
+            .ok();
+
+        Ok(())
+    }
+
+    pub fn unregister() -> Result<()> {
+        let dir = workflow_dir()?;
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_registered() -> bool {
+        workflow_dir().map(|dir| dir.exists()).unwrap_or(false)
+    }
+
+    fn xml_escape(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    const DESKTOP_FILE: &str = "media-compressor-compress.desktop";
+
+    /// Freedesktop entry that shows up under "Open With" for media files.
+    fn desktop_file() -> Result<PathBuf> {
+        dirs::data_dir()
+            .map(|dir| dir.join("applications").join(DESKTOP_FILE))
+            .ok_or_else(|| Error::platform("Could not find the data directory"))
+    }
+
+    /// Nautilus puts executables from this directory under "Scripts" in the
+    /// right-click menu.
+    fn nautilus_script() -> Result<PathBuf> {
+        dirs::data_dir()
+            .map(|dir| dir.join("nautilus/scripts").join(MENU_LABEL))
+            .ok_or_else(|| Error::platform("Could not find the data directory"))
+    }
+
+    fn write_file(path: &PathBuf, contents: &str, mode: u32) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        Ok(())
+    }
+
+    pub fn register() -> Result<()> {
+        let exe = current_exe()?.to_string_lossy().replace('"', "\\\"");
+
+        let desktop_entry = format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name={label}\n\
+             Exec=\"{exe}\" %F\n\
+             Icon=media-compressor\n\
+             MimeType=image/jpeg;image/png;image/gif;image/bmp;image/webp;image/tiff;image/svg+xml;image/heic;image/heif;video/mp4;video/quicktime;video/x-msvideo;video/x-matroska;video/x-ms-wmv;video/x-flv;inode/directory;\n\
+             NoDisplay=true\n\
+             Terminal=false\n",
+            label = MENU_LABEL,
+            exe = exe
+        );
+        write_file(&desktop_file()?, &desktop_entry, 0o644)?;
+
+        let script = format!(
+            "#!/bin/sh\nexec \"{}\" \"$@\"\n",
+            exe.replace('$', "\\$").replace('`', "\\`")
+        );
+        write_file(&nautilus_script()?, &script, 0o755)?;
+
+        // Refresh the MIME cache so "Open With" lists the entry immediately
+        if let Some(dir) = desktop_file()?.parent() {
+            std::process::Command::new("update-desktop-database")
+                .arg(dir)
+                .output()
+                .ok();
+        }
+
+        Ok(())
+    }
+
+    pub fn unregister() -> Result<()> {
+        for path in [desktop_file()?, nautilus_script()?] {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_registered() -> bool {
+        desktop_file().map(|path| path.exists()).unwrap_or(false)
+    }
+}
+
+pub use platform::{is_registered, register, unregister};
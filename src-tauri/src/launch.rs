@@ -0,0 +1,64 @@
+//! Files handed to the app on the command line ("Open with Media Compressor",
+//! drag onto the app icon) go straight into the queue. When the app is
+//! already running, the single-instance plugin forwards a second launch's
+//! arguments here instead of opening another window.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager};
+
+use media_compressor_core::media::MediaType;
+use media_compressor_core::queue::{Job, JobQueue, JobRequest, Priority};
+
+/// Enqueues the media files named in `args` (the first argument is the
+/// executable) and tells the frontend about them.
+pub fn enqueue_args(app: &AppHandle, args: &[String], cwd: &Path) -> Vec<Job> {
+    let queue = app.state::<JobQueue>();
+
+    let jobs = queue.enqueue_many(
+        args.iter()
+            .skip(1)
+            // Flags such as `--flag` aren't files
+            .filter(|arg| !arg.starts_with('-'))
+            .flat_map(|arg| media_files(&cwd.join(arg)))
+            .map(|path| JobRequest {
+                input_path: path.to_string_lossy().to_string(),
+                output_path: None,
+                preset: None,
+                priority: Priority::Normal,
+            }),
+    );
+
+    if !jobs.is_empty() {
+        app.emit("files-enqueued", &jobs).ok();
+    }
+
+    jobs
+}
+
+pub fn focus_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        window.unminimize().ok();
+        window.set_focus().ok();
+    }
+}
+
+/// A supported file, or the supported files directly inside a directory.
+fn media_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_file() && MediaType::from_path(path).is_some())
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    } else if path.is_file() && MediaType::from_path(path).is_some() {
+        vec![path.to_path_buf()]
+    } else {
+        Vec::new()
+    }
+}
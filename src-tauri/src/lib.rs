@@ -1,296 +1,590 @@
-use image::{imageops::FilterType, GenericImageView, ImageFormat};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-
-mod ffmpeg_manager;
-use ffmpeg_manager::FFmpegManager;
+use std::path::{Path, PathBuf};
+use tauri::Emitter;
+
+use media_compressor_core::batch_zip;
+use media_compressor_core::compressor::{self, CompressionResult};
+use media_compressor_core::concurrency;
+use media_compressor_core::contact_sheet;
+use media_compressor_core::destination;
+use media_compressor_core::diagnostics;
+use media_compressor_core::download::{self, DownloadProgress, UrlOptions};
+use media_compressor_core::dry_run::{self, DryRun};
+use media_compressor_core::encoders::{self, EncoderStatus, ExternalEncoder};
+use media_compressor_core::encryption;
+use media_compressor_core::error::{Error, Result};
+use media_compressor_core::ffmpeg_manager::FFmpegManager;
+use media_compressor_core::gif::{self, GifOptions};
+use media_compressor_core::gpu::{self, GpuDevice};
+use media_compressor_core::history::{ExportFormat, HistoryStore, JobRecord, Statistics};
+use media_compressor_core::http_api;
+use media_compressor_core::image_batch::{self, ImageBatchItem, ImageBatchProgress};
+use media_compressor_core::job::JobContext;
+use media_compressor_core::ladder::{self, Rendition, RenditionOutput};
+use media_compressor_core::logging;
+use media_compressor_core::media::{MediaFilter, MediaType};
+use media_compressor_core::pdf;
+use media_compressor_core::presets::{self, Preset};
+use media_compressor_core::preview::{self, PreviewOptions, PreviewPair};
+use media_compressor_core::probe::{self, BitrateSample, Crop};
+use media_compressor_core::queue::{
+    BatchCancellation, BatchId, Job, JobId, JobQueue, JobRequest, Priority, QueueEvent,
+    QueueStatus, SessionJob,
+};
+use media_compressor_core::scan::{ScanEntry, ScanSummary, Scanner};
+use media_compressor_core::scenes::{self, SceneClip};
+use media_compressor_core::settings::{Settings, SettingsManager};
+use media_compressor_core::streaming::{self, StreamabilityReport};
+use media_compressor_core::webhook;
+
+mod clipboard;
+mod context_menu;
+mod launch;
+mod notifications;
+mod post_batch;
+mod system;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct FileInfo {
     size: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CompressionResult {
-    #[serde(rename = "compressedSize")]
-    compressed_size: u64,
-}
-
 #[tauri::command]
-async fn get_file_info(path: String) -> Result<FileInfo, String> {
-    let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
+async fn get_file_info(path: String) -> Result<FileInfo> {
+    let metadata = fs::metadata(&path)?;
     Ok(FileInfo {
         size: metadata.len(),
     })
 }
 
 #[tauri::command]
-async fn create_output_dir(path: String) -> Result<(), String> {
-    fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+async fn create_output_dir(path: String) -> Result<()> {
+    fs::create_dir_all(&path).map_err(|e| Error::io("Failed to create output directory", e))?;
     Ok(())
 }
 
 #[tauri::command]
-async fn get_default_output_path() -> Result<String, String> {
+async fn get_default_output_path() -> Result<String> {
+    if let Some(dir) = SettingsManager::new().load().output_dir {
+        return Ok(dir);
+    }
+
     let home = std::env::var("HOME")
         .unwrap_or_else(|_| std::env::var("USERPROFILE").unwrap_or_else(|_| ".".to_string()));
     Ok(format!("{}/Downloads/compressed", home))
 }
 
 #[tauri::command]
-async fn open_directory(path: String) -> Result<(), String> {
-    #[cfg(target_os = "macos")]
-    {
-        Command::new("open")
-            .arg(&path)
-            .spawn()
-            .map_err(|e| e.to_string())?;
+async fn get_settings() -> Result<Settings> {
+    Ok(SettingsManager::new().load())
+}
+
+#[tauri::command]
+async fn update_settings(
+    queue: tauri::State<'_, JobQueue>,
+    mut settings: Settings,
+) -> Result<Settings> {
+    if settings.http_api.enabled && settings.http_api.token.is_empty() {
+        settings.http_api.token = http_api::generate_token();
     }
 
-    #[cfg(target_os = "windows")]
-    {
-        Command::new("explorer")
-            .arg(&path)
-            .spawn()
-            .map_err(|e| e.to_string())?;
+    SettingsManager::new().save(&settings)?;
+    logging::set_debug(settings.debug_logging);
+    http_api::apply(queue.inner().clone(), &settings.http_api)?;
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn list_presets() -> Result<Vec<Preset>> {
+    let settings = SettingsManager::new().load();
+    Ok(presets::all_presets(&settings))
+}
+
+/// GPUs the settings can put hardware encodes on.
+#[tauri::command]
+async fn list_gpus() -> Result<Vec<GpuDevice>> {
+    tauri::async_runtime::spawn_blocking(gpu::list_devices)
+        .await
+        .map_err(|e| Error::Io {
+            message: e.to_string(),
+        })
+}
+
+#[tauri::command]
+async fn save_preset(preset: Preset) -> Result<Preset> {
+    preset.validate()?;
+    if presets::is_builtin_name(&preset.name) {
+        return Err(Error::InvalidRequest {
+            message: format!("Cannot overwrite built-in preset: {}", preset.name),
+        });
     }
 
-    #[cfg(target_os = "linux")]
+    let manager = SettingsManager::new();
+    let mut settings = manager.load();
+    let preset = Preset {
+        builtin: false,
+        ..preset
+    };
+
+    match settings
+        .presets
+        .iter_mut()
+        .find(|existing| existing.name.eq_ignore_ascii_case(&preset.name))
     {
-        Command::new("xdg-open")
-            .arg(&path)
-            .spawn()
-            .map_err(|e| e.to_string())?;
+        Some(existing) => *existing = preset.clone(),
+        None => settings.presets.push(preset.clone()),
     }
 
-    Ok(())
+    manager.save(&settings)?;
+    Ok(preset)
+}
+
+#[tauri::command]
+async fn delete_preset(name: String) -> Result<()> {
+    if presets::is_builtin_name(&name) {
+        return Err(Error::InvalidRequest {
+            message: format!("Cannot delete built-in preset: {}", name),
+        });
+    }
+
+    let manager = SettingsManager::new();
+    let mut settings = manager.load();
+    let before = settings.presets.len();
+    settings
+        .presets
+        .retain(|preset| !preset.name.eq_ignore_ascii_case(&name));
+
+    if settings.presets.len() == before {
+        return Err(Error::UnknownPreset { name });
+    }
+
+    manager.save(&settings)
+}
+
+#[tauri::command]
+async fn open_directory(path: String) -> Result<()> {
+    system::open_in_file_manager(&path)
 }
 
 #[tauri::command]
 async fn compress_video(
     input_path: String,
     output_path: Option<String>,
-) -> Result<CompressionResult, String> {
-    // Ensure FFmpeg is available
-    let ffmpeg_manager = FFmpegManager::new();
-    let ffmpeg_path = ffmpeg_manager.ensure_ffmpeg().await?;
+    preset: Option<String>,
+) -> Result<CompressionResult> {
+    compress_as(MediaType::Video, input_path, output_path, preset).await
+}
+
+#[tauri::command]
+async fn compress_image(
+    input_path: String,
+    output_path: Option<String>,
+    preset: Option<String>,
+) -> Result<CompressionResult> {
+    compress_as(MediaType::Image, input_path, output_path, preset).await
+}
 
-    let input = Path::new(&input_path);
+/// Crops of an image before and after compression with `preset`, for a
+/// comparison slider.
+#[tauri::command]
+async fn preview_compression(
+    input_path: String,
+    preset: Option<String>,
+    options: Option<PreviewOptions>,
+) -> Result<PreviewPair> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        preview::preview_pair(&input_path, &preset, &options.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
+}
+
+/// Compresses a whole batch of images in parallel, reporting each file
+/// through `on_progress` as it finishes.
+#[tauri::command]
+async fn compress_images(
+    input_paths: Vec<String>,
+    output_path: Option<String>,
+    preset: Option<String>,
+    on_progress: tauri::ipc::Channel<ImageBatchProgress>,
+) -> Result<Vec<ImageBatchItem>> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        image_batch::compress_images(
+            &input_paths,
+            output_path,
+            &settings,
+            &preset,
+            &JobContext::default(),
+            |progress| {
+                on_progress.send(progress.clone()).ok();
+            },
+        )
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })
+}
 
-    if !input.exists() {
-        return Err("Input file does not exist".to_string());
+#[tauri::command]
+async fn compress_audio(
+    input_path: String,
+    output_path: Option<String>,
+    preset: Option<String>,
+) -> Result<CompressionResult> {
+    compress_as(MediaType::Audio, input_path, output_path, preset).await
+}
+
+/// Compresses a file of the type the command is for through `compress_file`,
+/// so it gets the same skips, script and argument checks as queued jobs.
+async fn compress_as(
+    media_type: MediaType,
+    input_path: String,
+    output_path: Option<String>,
+    preset: Option<String>,
+) -> Result<CompressionResult> {
+    if MediaType::from_path(Path::new(&input_path)) != Some(media_type) {
+        return Err(Error::UnsupportedFormat { path: input_path });
     }
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+
+    compressor::compress_file(
+        &input_path,
+        output_path,
+        &settings,
+        &preset,
+        &JobContext::default(),
+    )
+    .await
+}
 
-    let output_dir = if let Some(dir) = output_path {
-        Path::new(&dir).to_path_buf()
-    } else {
-        input.parent().unwrap().join("compressed")
-    };
+/// Downloads a direct link, compresses it, and deletes the download,
+/// reporting download progress through `on_progress`.
+#[tauri::command]
+async fn compress_from_url(
+    url: String,
+    options: Option<UrlOptions>,
+    on_progress: tauri::ipc::Channel<DownloadProgress>,
+) -> Result<CompressionResult> {
+    let options = options.unwrap_or_default();
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(options.preset.as_deref(), &settings)?;
+
+    download::compress_from_url(
+        &url,
+        options.output_path,
+        &settings,
+        &preset,
+        &JobContext::default(),
+        |progress| {
+            on_progress.send(progress.clone()).ok();
+        },
+    )
+    .await
+}
 
-    fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
-
-    let file_name = input.file_stem().unwrap().to_str().unwrap();
-    let extension = input
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or("mp4");
-    let output_file = output_dir.join(format!("{}.{}", file_name, extension));
-
-    let output = Command::new(&ffmpeg_path)
-        .args(&[
-            "-i",
-            input_path.as_str(),
-            "-c:v",
-            "libx264",
-            "-profile:v",
-            "baseline",
-            "-level",
-            "3.0",
-            "-pix_fmt",
-            "yuv420p",
-            "-crf",
-            "23",
-            "-preset",
-            "medium",
-            "-c:a",
-            "aac",
-            "-b:a",
-            "128k",
-            "-movflags",
-            "+faststart",
-            "-y",
-            output_file.to_str().unwrap(),
-        ])
-        .output();
-
-    match output {
-        Ok(result) => {
-            if !result.status.success() {
-                let stderr = String::from_utf8_lossy(&result.stderr);
-
-                if stderr.contains("ffmpeg: not found") || stderr.contains("command not found") {
-                    return Err(
-                        "ffmpeg is not installed. Please install ffmpeg to compress videos."
-                            .to_string(),
-                    );
-                }
+/// Tags of a media file, e.g. to check that a transcode kept them.
+#[tauri::command]
+async fn get_media_tags(path: String) -> Result<BTreeMap<String, String>> {
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    if !Path::new(&path).exists() {
+        return Err(Error::InputNotFound { path });
+    }
+    probe::probe_tags(&ffmpeg_path, Path::new(&path))
+}
 
-                return Err(format!("Video compression failed: {}", stderr));
-            }
+/// The crop that would remove the video's black bars, if it has any.
+#[tauri::command]
+async fn detect_crop(path: String) -> Result<Option<Crop>> {
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    if !Path::new(&path).exists() {
+        return Err(Error::InputNotFound { path });
+    }
+    tauri::async_runtime::spawn_blocking(move || probe::detect_crop(&ffmpeg_path, Path::new(&path)))
+        .await
+        .map_err(|e| Error::Io {
+            message: e.to_string(),
+        })?
+}
 
-            let metadata = fs::metadata(&output_file).map_err(|e| e.to_string())?;
-            Ok(CompressionResult {
-                compressed_size: metadata.len(),
-            })
-        }
-        Err(e) => {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Err(
-                    "ffmpeg is not installed. Please install ffmpeg to compress videos."
-                        .to_string(),
-                )
-            } else {
-                Err(format!("Failed to run ffmpeg: {}", e))
-            }
-        }
+/// Per-second video and audio bitrate of a file, for plotting where the
+/// bits went before and after compression.
+#[tauri::command]
+async fn get_bitrate_timeline(path: String) -> Result<Vec<BitrateSample>> {
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    if !Path::new(&path).exists() {
+        return Err(Error::InputNotFound { path });
     }
+    tauri::async_runtime::spawn_blocking(move || {
+        probe::bitrate_timeline(&ffmpeg_path, Path::new(&path))
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
 }
 
+/// Whether a video can be played in a browser straight off a web server,
+/// with the reasons when it can't.
 #[tauri::command]
-async fn compress_image(
+async fn verify_streamable(path: String) -> Result<StreamabilityReport> {
+    let ffmpeg_path = FFmpegManager::new().ensure_ffmpeg().await?;
+    if !Path::new(&path).exists() {
+        return Err(Error::InputNotFound { path });
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        streaming::verify_streamable(&ffmpeg_path, Path::new(&path))
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
+}
+
+/// Splits a video into one compressed clip per scene. `threshold` (0-1)
+/// is how different consecutive frames must be to count as a cut.
+#[tauri::command]
+async fn split_scenes(
     input_path: String,
     output_path: Option<String>,
-) -> Result<CompressionResult, String> {
-    let input = Path::new(&input_path);
-
-    if !input.exists() {
-        return Err("Input file does not exist".to_string());
-    }
+    preset: Option<String>,
+    threshold: Option<f64>,
+) -> Result<Vec<SceneClip>> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+
+    scenes::split_scenes(
+        &input_path,
+        output_path,
+        &settings,
+        &preset,
+        threshold,
+        &JobContext::default(),
+    )
+    .await
+}
 
-    // Get original file size
-    let original_size = fs::metadata(&input_path).map_err(|e| e.to_string())?.len();
+/// Encodes a video at several resolutions and bitrates in one pass over the
+/// source, for adaptive streaming. Without `renditions`, 1080p to 360p.
+#[tauri::command]
+async fn encode_bitrate_ladder(
+    input_path: String,
+    output_path: Option<String>,
+    preset: Option<String>,
+    renditions: Option<Vec<Rendition>>,
+) -> Result<Vec<RenditionOutput>> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+    let renditions = renditions.unwrap_or_else(ladder::default_ladder);
+
+    ladder::encode_ladder(
+        &input_path,
+        output_path,
+        &settings,
+        &preset,
+        &renditions,
+        &JobContext::default(),
+    )
+    .await
+}
 
-    let img = image::open(&input_path).map_err(|e| e.to_string())?;
+/// Makes a GIF of a clip from a video.
+#[tauri::command]
+async fn make_gif(
+    input_path: String,
+    output_path: Option<String>,
+    options: Option<GifOptions>,
+) -> Result<CompressionResult> {
+    let settings = SettingsManager::new().load();
+    gif::make_gif(
+        &input_path,
+        output_path,
+        &settings,
+        &options.unwrap_or_default(),
+        &JobContext::default(),
+    )
+    .await
+}
 
-    let output_dir = if let Some(dir) = output_path {
-        Path::new(&dir).to_path_buf()
+/// Saves a grid of labelled thumbnails of a folder's images and videos, or
+/// of frames from one video, as a single JPEG.
+#[tauri::command]
+async fn create_contact_sheet(
+    path: String,
+    output_path: Option<String>,
+    preset: Option<String>,
+) -> Result<CompressionResult> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+
+    // Folders of images don't need ffmpeg, so don't download it for them
+    let ffmpeg_path = if Path::new(&path).is_dir() {
+        FFmpegManager::new().installed_ffmpeg_path()
     } else {
-        input.parent().unwrap().join("compressed")
+        Some(FFmpegManager::new().ensure_ffmpeg().await?)
     };
 
-    fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
+    tauri::async_runtime::spawn_blocking(move || {
+        contact_sheet::create_contact_sheet(
+            ffmpeg_path.as_deref(),
+            Path::new(&path),
+            output_path,
+            &settings,
+            &preset,
+            &JobContext::default(),
+        )
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
+}
 
-    let file_name = input.file_stem().unwrap().to_str().unwrap();
-    let original_extension = input
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or("jpg");
+/// Compresses `input_paths` and writes them, in order, as the pages of one
+/// PDF at `output_path`.
+#[tauri::command]
+async fn images_to_pdf(
+    input_paths: Vec<String>,
+    output_path: String,
+    preset: Option<String>,
+) -> Result<CompressionResult> {
+    let settings = SettingsManager::new().load();
+    let preset = presets::resolve_preset(preset.as_deref(), &settings)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        pdf::images_to_pdf(
+            &input_paths,
+            Path::new(&output_path),
+            &settings,
+            &preset,
+            &JobContext::default(),
+        )
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
+}
 
-    let (width, height) = img.dimensions();
-    let max_dimension = 2048;
+#[tauri::command]
+async fn get_history(limit: Option<u32>) -> Result<Vec<JobRecord>> {
+    HistoryStore::open()?.recent(limit.unwrap_or(100))
+}
 
-    // Resize only if image is larger than max dimension
-    let resized = if width > max_dimension || height > max_dimension {
-        let ratio = (max_dimension as f32) / (width.max(height) as f32);
-        let new_width = (width as f32 * ratio) as u32;
-        let new_height = (height as f32 * ratio) as u32;
-        img.resize(new_width, new_height, FilterType::Lanczos3)
-    } else {
-        img
-    };
+#[tauri::command]
+async fn get_file_history(input_path: String) -> Result<Vec<JobRecord>> {
+    HistoryStore::open()?.for_input(&input_path)
+}
 
-    // For WebP and other already compressed formats, convert to JPEG if it would be smaller
-    let (output_extension, output_format) = match original_extension.to_lowercase().as_str() {
-        "webp" | "avif" => {
-            // For already efficient formats, try JPEG and see if it's smaller
-            ("jpg", ImageFormat::Jpeg)
-        }
-        "png" => {
-            // PNG might be better kept as PNG if it has transparency
-            if resized.color().has_alpha() {
-                ("png", ImageFormat::Png)
-            } else {
-                ("jpg", ImageFormat::Jpeg)
-            }
-        }
-        "gif" => ("gif", ImageFormat::Gif),
-        "bmp" => ("jpg", ImageFormat::Jpeg),
-        _ => ("jpg", ImageFormat::Jpeg),
-    };
+#[tauri::command]
+async fn get_statistics() -> Result<Statistics> {
+    HistoryStore::open()?.statistics()
+}
 
-    let output_file = output_dir.join(format!("{}.{}", file_name, output_extension));
-
-    // Save with quality optimization
-    match output_format {
-        ImageFormat::Jpeg => {
-            // Use JPEG with quality 85 for good balance of quality and size
-            let rgb_image = resized.to_rgb8();
-            let mut jpeg_encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
-                std::fs::File::create(&output_file).map_err(|e| e.to_string())?,
-                85,
-            );
-            jpeg_encoder
-                .encode_image(&rgb_image)
-                .map_err(|e| e.to_string())?;
-        }
-        ImageFormat::Png => {
-            // Use PNG with compression
-            let output = std::fs::File::create(&output_file).map_err(|e| e.to_string())?;
-            let encoder = image::codecs::png::PngEncoder::new_with_quality(
-                output,
-                image::codecs::png::CompressionType::Best,
-                image::codecs::png::FilterType::Adaptive,
-            );
-            resized
-                .write_with_encoder(encoder)
-                .map_err(|e| e.to_string())?;
-        }
-        _ => {
-            resized.save(&output_file).map_err(|e| e.to_string())?;
-        }
-    }
+#[tauri::command]
+async fn export_history(path: String, format: ExportFormat) -> Result<usize> {
+    HistoryStore::open()?.export(Path::new(&path), format)
+}
 
-    let compressed_size = fs::metadata(&output_file).map_err(|e| e.to_string())?.len();
+#[tauri::command]
+async fn clear_history() -> Result<()> {
+    HistoryStore::open()?.clear()
+}
 
-    // If compressed is larger than original, just copy the original
-    if compressed_size >= original_size {
-        fs::copy(&input_path, &output_file).map_err(|e| e.to_string())?;
-        let final_size = fs::metadata(&output_file).map_err(|e| e.to_string())?.len();
-        Ok(CompressionResult {
-            compressed_size: final_size,
-        })
-    } else {
-        Ok(CompressionResult { compressed_size })
+/// Zips logs, the ffmpeg report, anonymized settings, and recent jobs into
+/// `path` for attaching to bug reports.
+#[tauri::command]
+async fn export_diagnostics(path: String) -> Result<()> {
+    tauri::async_runtime::spawn_blocking(move || diagnostics::export(Path::new(&path)))
+        .await
+        .map_err(|e| Error::Io {
+            message: e.to_string(),
+        })?
+}
+
+/// Packs a finished batch's outputs into one zip when the setting is on.
+/// Returns the zip's path, or `None` when zipping is off.
+#[tauri::command]
+async fn zip_batch_outputs(output_paths: Vec<String>) -> Result<Option<String>> {
+    let settings = SettingsManager::new().load().batch_zip;
+    if !settings.enabled {
+        return Ok(None);
     }
+
+    let outputs: Vec<PathBuf> = output_paths.iter().map(PathBuf::from).collect();
+    tauri::async_runtime::spawn_blocking(move || {
+        batch_zip::package(&outputs, settings.password.as_deref())
+            .map(|zip| Some(zip.to_string_lossy().to_string()))
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
+}
+
+/// Decrypts an encrypted output with `secret` (a passphrase or age secret
+/// key), falling back to the passphrase in settings. Returns the path of the
+/// decrypted file.
+#[tauri::command]
+async fn decrypt_file(
+    path: String,
+    secret: Option<String>,
+    output_path: Option<String>,
+) -> Result<String> {
+    let secret = secret
+        .filter(|secret| !secret.is_empty())
+        .or(SettingsManager::new().load().encryption.passphrase)
+        .ok_or_else(|| Error::InvalidRequest {
+            message: "A passphrase or key is needed to decrypt".to_string(),
+        })?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        encryption::decrypt_file(
+            Path::new(&path),
+            &secret,
+            output_path.as_deref().map(Path::new),
+        )
+        .map(|output| output.to_string_lossy().to_string())
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
 }
 
 #[tauri::command]
-async fn get_directory_files(dir_path: String) -> Result<Vec<String>, String> {
+async fn get_directory_files(dir_path: String, filter: Option<MediaFilter>) -> Result<Vec<String>> {
     let path = Path::new(&dir_path);
 
     if !path.exists() {
-        return Err("Directory does not exist".to_string());
+        return Err(Error::InputNotFound { path: dir_path });
     }
 
     if !path.is_dir() {
-        return Err("Path is not a directory".to_string());
+        return Err(Error::InvalidRequest {
+            message: "Path is not a directory".to_string(),
+        });
     }
 
+    let filter = filter.unwrap_or_default();
     let mut files = Vec::new();
 
-    // Supported extensions
-    let video_extensions = vec!["mp4", "avi", "mov", "mkv", "wmv", "flv"];
-    let image_extensions = vec!["jpg", "jpeg", "png", "gif", "bmp", "webp"];
-
     // Read directory contents (non-recursive)
-    let entries = fs::read_dir(path).map_err(|e| e.to_string())?;
+    let entries = fs::read_dir(path)?;
 
     for entry in entries {
-        let entry = entry.map_err(|e| e.to_string())?;
+        let entry = entry?;
         let path = entry.path();
 
         // Skip directories
@@ -298,17 +592,10 @@ async fn get_directory_files(dir_path: String) -> Result<Vec<String>, String> {
             continue;
         }
 
-        // Check if file has a supported extension
-        if let Some(extension) = path.extension() {
-            if let Some(ext_str) = extension.to_str() {
-                let ext_lower = ext_str.to_lowercase();
-                if video_extensions.contains(&ext_lower.as_str())
-                    || image_extensions.contains(&ext_lower.as_str())
-                {
-                    if let Some(path_str) = path.to_str() {
-                        files.push(path_str.to_string());
-                    }
-                }
+        // Check if file has a supported extension the filter allows
+        if filter.matches(&path) {
+            if let Some(path_str) = path.to_str() {
+                files.push(path_str.to_string());
             }
         }
     }
@@ -316,35 +603,297 @@ async fn get_directory_files(dir_path: String) -> Result<Vec<String>, String> {
     Ok(files)
 }
 
+/// Number of entries sent per `on_batch` message while scanning.
+const SCAN_BATCH_SIZE: usize = 50;
+
+/// Recursively inventories media under `path`, streaming entries through
+/// `on_batch` as they're found and returning the totals at the end.
+/// `find_similar` also groups visually identical images, at the cost of
+/// decoding each one; `filter` limits the scan to some media types or
+/// extensions.
 #[tauri::command]
-async fn check_ffmpeg_status() -> Result<bool, String> {
+async fn scan_directory(
+    path: String,
+    find_similar: Option<bool>,
+    filter: Option<MediaFilter>,
+    on_batch: tauri::ipc::Channel<Vec<ScanEntry>>,
+) -> Result<ScanSummary> {
+    let scanner = Scanner::new(FFmpegManager::new().installed_ffmpeg_path())
+        .find_similar(find_similar.unwrap_or(false))
+        .filter(filter.unwrap_or_default());
+
+    tauri::async_runtime::spawn_blocking(move || {
+        scanner.scan(Path::new(&path), SCAN_BATCH_SIZE, |entries| {
+            on_batch.send(entries).ok();
+        })
+    })
+    .await
+    .map_err(|e| Error::Io {
+        message: e.to_string(),
+    })?
+}
+
+#[tauri::command]
+async fn check_ffmpeg_status() -> Result<bool> {
     let ffmpeg_manager = FFmpegManager::new();
     Ok(ffmpeg_manager.is_ffmpeg_available() || ffmpeg_manager.is_system_ffmpeg_available())
 }
 
 #[tauri::command]
-async fn download_ffmpeg() -> Result<String, String> {
+async fn download_ffmpeg() -> Result<String> {
     let ffmpeg_manager = FFmpegManager::new();
     ffmpeg_manager.ensure_ffmpeg().await?;
     Ok("FFmpeg downloaded successfully".to_string())
 }
 
+/// cwebp, avifenc and cjxl, and where each was found.
+#[tauri::command]
+async fn list_encoders() -> Result<Vec<EncoderStatus>> {
+    tauri::async_runtime::spawn_blocking(encoders::status)
+        .await
+        .map_err(|e| Error::Io {
+            message: e.to_string(),
+        })
+}
+
+#[tauri::command]
+async fn install_encoder(encoder: ExternalEncoder) -> Result<String> {
+    let path = encoders::install(encoder).await?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+async fn enqueue_jobs(
+    queue: tauri::State<'_, JobQueue>,
+    requests: Vec<JobRequest>,
+) -> Result<Vec<Job>> {
+    Ok(queue.enqueue_many(requests))
+}
+
+/// What `enqueue_jobs` would do with `requests`, without queueing or
+/// writing anything.
+#[tauri::command]
+async fn plan_jobs(requests: Vec<JobRequest>) -> Result<DryRun> {
+    let settings = SettingsManager::new().load();
+    Ok(dry_run::plan(&requests, &settings).await)
+}
+
+#[tauri::command]
+async fn get_jobs(queue: tauri::State<'_, JobQueue>) -> Result<Vec<Job>> {
+    Ok(queue.jobs())
+}
+
+/// Every job with its status, progress and settings, plus the batch as a
+/// whole, so the queue view needs one call per refresh.
+#[tauri::command]
+async fn get_queue_status(queue: tauri::State<'_, JobQueue>) -> Result<QueueStatus> {
+    Ok(queue.status())
+}
+
+#[tauri::command]
+async fn get_job(queue: tauri::State<'_, JobQueue>, id: JobId) -> Result<Job> {
+    queue.job(id).ok_or(Error::UnknownJob { id })
+}
+
+#[tauri::command]
+async fn cancel_job(queue: tauri::State<'_, JobQueue>, id: JobId) -> Result<Job> {
+    queue.cancel(id)
+}
+
+/// Jobs left unfinished when the app last quit or crashed, for the frontend
+/// to offer resuming.
+#[tauri::command]
+async fn get_previous_session(queue: tauri::State<'_, JobQueue>) -> Result<Vec<SessionJob>> {
+    Ok(queue.previous_session())
+}
+
+#[tauri::command]
+async fn resume_previous_session(queue: tauri::State<'_, JobQueue>) -> Result<Vec<Job>> {
+    Ok(queue.resume_previous_session())
+}
+
+#[tauri::command]
+async fn discard_previous_session(queue: tauri::State<'_, JobQueue>) -> Result<()> {
+    queue.discard_previous_session();
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_job_priority(
+    queue: tauri::State<'_, JobQueue>,
+    id: JobId,
+    priority: Priority,
+) -> Result<Job> {
+    queue.set_priority(id, priority)
+}
+
+/// Lets a queued job jump everything else that's waiting.
+#[tauri::command]
+async fn move_job_to_front(queue: tauri::State<'_, JobQueue>, id: JobId) -> Result<Job> {
+    queue.move_to_front(id)
+}
+
+/// Stops the batch's running job and drops its queued ones, reporting
+/// which files were done by then.
+#[tauri::command]
+async fn cancel_batch(
+    queue: tauri::State<'_, JobQueue>,
+    batch_id: BatchId,
+) -> Result<BatchCancellation> {
+    queue.cancel_batch(batch_id)
+}
+
+#[tauri::command]
+async fn cancel_post_batch_action() -> Result<bool> {
+    Ok(post_batch::cancel())
+}
+
+#[tauri::command]
+async fn compress_clipboard_image(
+    options: Option<clipboard::ClipboardOptions>,
+) -> Result<clipboard::ClipboardResult> {
+    clipboard::compress_clipboard_image(options.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn register_context_menu() -> Result<()> {
+    context_menu::register()
+}
+
+#[tauri::command]
+async fn unregister_context_menu() -> Result<()> {
+    context_menu::unregister()
+}
+
+#[tauri::command]
+async fn is_context_menu_registered() -> Result<bool> {
+    Ok(context_menu::is_registered())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tauri::Builder::default()
+    let settings = SettingsManager::new().load();
+    if let Err(e) = logging::init(settings.debug_logging) {
+        eprintln!("{}", e);
+    }
+    let queue = JobQueue::persistent();
+
+    let mut builder = tauri::Builder::default();
+
+    // Must be the first plugin so a second launch exits before doing any work
+    #[cfg(desktop)]
+    {
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            launch::enqueue_args(app, &args, Path::new(&cwd));
+            launch::focus_main_window(app);
+        }));
+    }
+
+    builder
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_notification::init())
+        .manage(queue.clone())
+        .setup(move |app| {
+            webhook::subscribe(&queue);
+            let handle = app.handle().clone();
+            destination::subscribe(move |progress| {
+                handle.emit("upload-progress", progress).ok();
+            });
+            let handle = app.handle().clone();
+            queue.subscribe(move |event| match event {
+                QueueEvent::Progress(job) => {
+                    handle.emit("job-progress", job).ok();
+                }
+                QueueEvent::BatchProgress(progress) => {
+                    handle.emit("batch-progress", progress).ok();
+                }
+                _ => {}
+            });
+            notifications::subscribe(app.handle(), &queue);
+            post_batch::subscribe(app.handle(), &queue);
+
+            if settings.resume_interrupted_jobs {
+                let jobs = queue.resume_interrupted();
+                if !jobs.is_empty() {
+                    app.emit("files-enqueued", &jobs).ok();
+                }
+            }
+
+            if let Ok(cwd) = std::env::current_dir() {
+                let args: Vec<String> = std::env::args().collect();
+                launch::enqueue_args(app.handle(), &args, &cwd);
+            }
+
+            queue.start_idle_monitor();
+            for _ in 0..concurrency::worker_count(&settings) {
+                tauri::async_runtime::spawn(queue.clone().run_worker());
+            }
+
+            if let Err(e) = http_api::apply(queue.clone(), &settings.http_api) {
+                tracing::warn!("{}", e);
+            }
+
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_file_info,
             create_output_dir,
             get_default_output_path,
+            get_settings,
+            update_settings,
+            list_presets,
+            list_gpus,
+            save_preset,
+            delete_preset,
             open_directory,
             compress_video,
             compress_image,
+            compress_images,
+            preview_compression,
+            compress_audio,
+            compress_from_url,
+            get_media_tags,
+            detect_crop,
+            split_scenes,
+            encode_bitrate_ladder,
+            make_gif,
+            verify_streamable,
+            get_bitrate_timeline,
+            images_to_pdf,
+            create_contact_sheet,
             get_directory_files,
+            scan_directory,
             check_ffmpeg_status,
-            download_ffmpeg
+            download_ffmpeg,
+            list_encoders,
+            install_encoder,
+            get_history,
+            get_file_history,
+            get_statistics,
+            export_history,
+            clear_history,
+            export_diagnostics,
+            zip_batch_outputs,
+            decrypt_file,
+            enqueue_jobs,
+            plan_jobs,
+            get_jobs,
+            get_queue_status,
+            get_job,
+            cancel_job,
+            cancel_batch,
+            set_job_priority,
+            move_job_to_front,
+            get_previous_session,
+            resume_previous_session,
+            discard_previous_session,
+            cancel_post_batch_action,
+            compress_clipboard_image,
+            register_context_menu,
+            unregister_context_menu,
+            is_context_menu_registered
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,78 @@
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+
+use media_compressor_core::queue::{BatchSummary, JobQueue, QueueEvent};
+use media_compressor_core::settings::{BudgetAction, SettingsManager};
+use media_compressor_core::units::format_size;
+
+/// A single job that runs at least this long still earns a notification;
+/// quick one-off jobs finish while the user is watching anyway.
+const LONG_JOB_MS: u64 = 60_000;
+
+/// Shows a system notification whenever the queue drains, since users tend
+/// to minimize the app during long encodes.
+pub fn subscribe(app: &AppHandle, queue: &JobQueue) {
+    let app = app.clone();
+    queue.subscribe(move |event| {
+        if matches!(
+            event,
+            QueueEvent::Progress(_) | QueueEvent::BatchProgress(_)
+        ) {
+            return;
+        }
+        let settings = SettingsManager::new().load();
+        if !settings.notifications {
+            return;
+        }
+
+        let (title, body) = match event {
+            QueueEvent::Drained(summary) => {
+                if summary.files < 2 && summary.elapsed_ms < LONG_JOB_MS {
+                    return;
+                }
+                ("Compression finished", summary_text(summary))
+            }
+            QueueEvent::OverBudget { used, budget } => (
+                "Output budget reached",
+                budget_text(*used, *budget, settings.budget_action),
+            ),
+            QueueEvent::Progress(_) | QueueEvent::BatchProgress(_) | QueueEvent::JobFinished(_) => {
+                return
+            }
+        };
+
+        app.notification()
+            .builder()
+            .title(title)
+            .body(body)
+            .show()
+            .ok();
+    });
+}
+
+fn budget_text(used: u64, budget: u64, action: BudgetAction) -> String {
+    format!(
+        "Outputs take {} of the {} budget; {}",
+        format_size(used),
+        format_size(budget),
+        match action {
+            BudgetAction::Stop => "the remaining files will be skipped",
+            BudgetAction::Warn => "compression continues",
+        }
+    )
+}
+
+fn summary_text(summary: &BatchSummary) -> String {
+    let mut text = format!(
+        "{} of {} files compressed, {} saved",
+        summary.completed,
+        summary.files,
+        format_size(summary.bytes_saved)
+    );
+
+    if summary.failed > 0 {
+        text.push_str(&format!(", {} failed", summary.failed));
+    }
+
+    text
+}
@@ -0,0 +1,177 @@
+//! Actions that run once the queue drains: write a report and zip the
+//! outputs, then open the output folder, play a sound, or put the machine
+//! to sleep / shut it down after a cancellable countdown.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tracing::{info, warn};
+
+use media_compressor_core::batch_zip;
+use media_compressor_core::error::Result;
+use media_compressor_core::queue::{BatchSummary, Job, JobQueue, QueueEvent};
+use media_compressor_core::report::{BatchReport, ReportFormat};
+use media_compressor_core::settings::{PostBatchAction, Settings, SettingsManager};
+
+use crate::system;
+
+/// Cancel flag of the countdown currently in progress, if any.
+static PENDING: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CountdownPayload {
+    action: PostBatchAction,
+    seconds_remaining: u64,
+}
+
+pub fn subscribe(app: &AppHandle, queue: &JobQueue) {
+    let app = app.clone();
+    let jobs = queue.clone();
+    queue.subscribe(move |event| {
+        let QueueEvent::Drained(summary) = event else {
+            return;
+        };
+        // Zipping and reports take a while, and this runs on the worker that
+        // finished the last job
+        let (app, queue, summary) = (app.clone(), jobs.clone(), summary.clone());
+        thread::spawn(move || run_actions(app, &queue, summary));
+    });
+}
+
+/// Everything configured to happen after `summary`'s batch, in order.
+fn run_actions(app: AppHandle, queue: &JobQueue, summary: BatchSummary) {
+    let settings = SettingsManager::new().load();
+    // Batches where everything failed are worth a record too
+    if settings.batch_report != ReportFormat::None {
+        match write_report(&summary, queue, &settings) {
+            Ok(paths) => {
+                app.emit("batch-report", paths).ok();
+            }
+            Err(e) => warn!("Writing the batch report failed: {}", e),
+        }
+    }
+    if summary.completed == 0 {
+        return;
+    }
+
+    if settings.batch_zip.enabled {
+        if let Err(e) = zip_outputs(&summary, settings.batch_zip.password.as_deref()) {
+            warn!("Zipping the batch outputs failed: {}", e);
+        }
+    }
+
+    let result = match settings.post_batch_action {
+        PostBatchAction::None => Ok(()),
+        PostBatchAction::OpenFolder => open_output_folder(&summary),
+        PostBatchAction::PlaySound => system::play_completion_sound(),
+        action @ (PostBatchAction::Sleep | PostBatchAction::Shutdown) => {
+            start_countdown(app, action, settings.post_batch_countdown_secs);
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        warn!("Post-batch action failed: {}", e);
+    }
+}
+
+/// Stops a pending sleep/shutdown. Returns whether one was pending.
+pub fn cancel() -> bool {
+    match PENDING.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::SeqCst);
+            true
+        }
+        None => false,
+    }
+}
+
+fn write_report(
+    summary: &BatchSummary,
+    queue: &JobQueue,
+    settings: &Settings,
+) -> Result<Vec<PathBuf>> {
+    let jobs: Vec<Job> = queue
+        .jobs()
+        .into_iter()
+        .filter(|job| job.batch_id == summary.batch_id)
+        .collect();
+    let paths =
+        BatchReport::new(summary, &jobs, settings).write(settings.batch_report, &jobs, settings)?;
+    for path in &paths {
+        info!("Batch report written to {}", path.display());
+    }
+    Ok(paths)
+}
+
+fn zip_outputs(summary: &BatchSummary, password: Option<&str>) -> Result<()> {
+    let outputs: Vec<PathBuf> = summary.outputs.iter().map(PathBuf::from).collect();
+    let zip = batch_zip::package(&outputs, password)?;
+    info!("Batch outputs zipped to {}", zip.display());
+    Ok(())
+}
+
+fn open_output_folder(summary: &BatchSummary) -> Result<()> {
+    match summary.output_dirs.first() {
+        Some(dir) if Path::new(dir).is_dir() => system::open_in_file_manager(dir),
+        _ => Ok(()),
+    }
+}
+
+/// Counts down on a background thread, emitting `post-batch-countdown` every
+/// second so the UI can show a cancel prompt, then runs the action.
+fn start_countdown(app: AppHandle, action: PostBatchAction, seconds: u64) {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    if let Some(previous) = PENDING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(cancelled.clone())
+    {
+        previous.store(true, Ordering::SeqCst);
+    }
+
+    thread::spawn(move || {
+        for seconds_remaining in (1..=seconds).rev() {
+            if cancelled.load(Ordering::SeqCst) {
+                app.emit("post-batch-cancelled", action).ok();
+                return;
+            }
+
+            app.emit(
+                "post-batch-countdown",
+                CountdownPayload {
+                    action,
+                    seconds_remaining,
+                },
+            )
+            .ok();
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        // Clear the pending slot, unless a newer countdown replaced us or the
+        // user cancelled during the last second
+        {
+            let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+            if cancelled.load(Ordering::SeqCst) {
+                app.emit("post-batch-cancelled", action).ok();
+                return;
+            }
+            pending.take();
+        }
+
+        let result = match action {
+            PostBatchAction::Sleep => system::sleep(),
+            PostBatchAction::Shutdown => system::shut_down(),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!("Post-batch action failed: {}", e);
+        }
+    });
+}
@@ -0,0 +1,105 @@
+use std::process::Command;
+
+use media_compressor_core::error::{Error, Result};
+
+pub fn open_in_file_manager(path: &str) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        Command::new("open")
+            .arg(path)
+            .spawn()
+            .map_err(Error::platform)?;
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        Command::new("explorer")
+            .arg(path)
+            .spawn()
+            .map_err(Error::platform)?;
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        Command::new("xdg-open")
+            .arg(path)
+            .spawn()
+            .map_err(Error::platform)?;
+    }
+
+    Ok(())
+}
+
+pub fn play_completion_sound() -> Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        Command::new("afplay")
+            .arg("/System/Library/Sounds/Glass.aiff")
+            .spawn()
+            .map_err(Error::platform)?;
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        Command::new("powershell")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "[System.Media.SystemSounds]::Asterisk.Play()",
+            ])
+            .spawn()
+            .map_err(Error::platform)?;
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        Command::new("canberra-gtk-play")
+            .args(["-i", "complete"])
+            .spawn()
+            .or_else(|_| {
+                Command::new("paplay")
+                    .arg("/usr/share/sounds/freedesktop/stereo/complete.oga")
+                    .spawn()
+            })
+            .map_err(Error::platform)?;
+    }
+
+    Ok(())
+}
+
+pub fn sleep() -> Result<()> {
+    #[cfg(target_os = "macos")]
+    run(Command::new("pmset").arg("sleepnow"))?;
+
+    #[cfg(target_os = "windows")]
+    run(Command::new("rundll32.exe").args(["powrprof.dll,SetSuspendState", "0,1,0"]))?;
+
+    #[cfg(target_os = "linux")]
+    run(Command::new("systemctl").arg("suspend"))?;
+
+    Ok(())
+}
+
+pub fn shut_down() -> Result<()> {
+    #[cfg(target_os = "macos")]
+    run(Command::new("osascript").args(["-e", "tell app \"System Events\" to shut down"]))?;
+
+    #[cfg(target_os = "windows")]
+    run(Command::new("shutdown").args(["/s", "/t", "0"]))?;
+
+    #[cfg(target_os = "linux")]
+    run(Command::new("systemctl").arg("poweroff"))?;
+
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let output = command.output().map_err(Error::platform)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::platform(
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ))
+    }
+}
@@ -1,5 +1,5 @@
 import { useState, useEffect, useCallback } from "react";
-import { invoke } from "@tauri-apps/api/core";
+import { invoke, Channel } from "@tauri-apps/api/core";
 import { open } from "@tauri-apps/plugin-dialog";
 import { getCurrentWebviewWindow } from "@tauri-apps/api/webviewWindow";
 import "./App.css";
@@ -14,6 +14,34 @@ interface FileItem {
   compressedSize?: number;
 }
 
+interface CompressionResult {
+  compressedSize: number;
+  outputPath: string;
+  variants: { outputPath: string }[];
+  pages: { outputPath: string }[];
+  parts: string[];
+}
+
+interface ImageBatchProgress {
+  completed: number;
+  total: number;
+  item: {
+    index: number;
+    inputPath: string;
+    result: CompressionResult | null;
+    error: unknown;
+  };
+}
+
+// Every file a compression wrote; split videos list their first part as
+// the output path too
+const outputFiles = (result: CompressionResult) => [
+  result.outputPath,
+  ...result.variants.map(v => v.outputPath),
+  ...result.pages.map(p => p.outputPath),
+  ...result.parts.slice(1),
+];
+
 function App() {
   const [files, setFiles] = useState<FileItem[]>([]);
   const [isDragging, setIsDragging] = useState(false);
@@ -109,7 +137,7 @@ function App() {
       const name = path.split('/').pop() || path.split('\\').pop() || '';
       const extension = name.split('.').pop()?.toLowerCase();
       const isVideo = ['mp4', 'avi', 'mov', 'mkv', 'wmv', 'flv'].includes(extension || '');
-      const isImage = ['jpg', 'jpeg', 'png', 'gif', 'bmp', 'webp'].includes(extension || '');
+      const isImage = ['jpg', 'jpeg', 'png', 'gif', 'bmp', 'webp', 'tif', 'tiff', 'svg', 'heic', 'heif'].includes(extension || '');
       
       if (isVideo || isImage) {
         try {
@@ -202,6 +230,54 @@ function App() {
     }
   };
 
+  // Image-only batches go to the backend in one call, which spreads them
+  // over every core instead of compressing one at a time
+  const compressImageBatch = async (pending: { file: FileItem; index: number }[], outputs: string[]) => {
+    setCurrentProcessingFile(`${pending.length} images`);
+    setFiles(prev => prev.map((file, i) =>
+      pending.some(p => p.index === i) ? { ...file, status: 'processing' } : file
+    ));
+
+    const onProgress = new Channel<ImageBatchProgress>();
+    onProgress.onmessage = ({ item }) => {
+      const { file, index } = pending[item.index];
+      setFiles(prev => {
+        const updated = [...prev];
+        updated[index] = item.result
+          ? { ...updated[index], status: 'completed', progress: 100, compressedSize: item.result.compressedSize }
+          : { ...updated[index], status: 'error' };
+        return updated;
+      });
+
+      if (item.result) {
+        outputs.push(...outputFiles(item.result));
+        setProcessedCount(prev => prev + 1);
+      } else {
+        console.error(`Failed to compress ${file.name}:`, item.error);
+      }
+    };
+
+    try {
+      await invoke('compress_images', {
+        inputPaths: pending.map(p => p.file.path),
+        outputPath: outputPath || undefined,
+        onProgress
+      });
+    } catch (error) {
+      console.error('Failed to compress images:', error);
+    }
+  };
+
+  // Packs the batch into one zip when that's turned on in settings
+  const zipOutputs = async (outputs: string[]) => {
+    if (outputs.length === 0) return;
+    try {
+      await invoke<string | null>('zip_batch_outputs', { outputPaths: outputs });
+    } catch (error) {
+      console.error('Failed to zip outputs:', error);
+    }
+  };
+
   const compressFiles = async () => {
     if (files.length === 0) return;
     
@@ -220,6 +296,18 @@ function App() {
     
     setIsProcessing(true);
     setProcessedCount(0);
+
+    const outputs: string[] = [];
+    const pending = files
+      .map((file, index) => ({ file, index }))
+      .filter(({ file }) => file.status !== 'completed');
+    if (pending.length > 1 && pending.every(({ file }) => file.type === 'image')) {
+      await compressImageBatch(pending, outputs);
+      await zipOutputs(outputs);
+      setIsProcessing(false);
+      setCurrentProcessingFile("");
+      return;
+    }
     
     for (let i = 0; i < files.length; i++) {
       const file = files[i];
@@ -234,7 +322,7 @@ function App() {
       });
       
       try {
-        const result = await invoke<{ compressedSize: number }>(
+        const result = await invoke<CompressionResult>(
           file.type === 'video' ? 'compress_video' : 'compress_image',
           { 
             inputPath: file.path,
@@ -253,6 +341,7 @@ function App() {
           return updated;
         });
         
+        outputs.push(...outputFiles(result));
         setProcessedCount(prev => prev + 1);
       } catch (error) {
         setFiles(prev => {
@@ -264,6 +353,7 @@ function App() {
       }
     }
     
+    await zipOutputs(outputs);
     setIsProcessing(false);
     setCurrentProcessingFile("");
   };